@@ -0,0 +1,786 @@
+/*
+ * The runtime linked into Crabby programs compiled to C. Its behaviour,
+ * down to error messages and number formatting, follows the interpreter.
+ */
+#include "crabby_runtime.h"
+
+#include <math.h>
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+struct CrString {
+    long refs;
+    size_t len;
+    char bytes[];
+};
+
+typedef struct {
+    int symbol;
+    CrValue value;
+} CrBinding;
+
+struct CrEnv {
+    long refs;
+    CrEnv *parent;
+    CrBinding *bindings;
+    size_t len;
+    size_t cap;
+};
+
+/* A growable byte buffer for building strings */
+typedef struct {
+    char *bytes;
+    size_t len;
+    size_t cap;
+} CrBuffer;
+
+static const char *const *cr_symbols;
+
+void cr_init(const char *const *symbols) {
+    cr_symbols = symbols;
+}
+
+int cr_exit(void) {
+    return fflush(stdout) == 0 ? 0 : 1;
+}
+
+static void *cr_alloc(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        fputs("Error: out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+/* Reports a runtime error the way the interpreter does, and exits */
+void cr_error(const char *format, ...) {
+    char message[1024];
+    va_list args;
+
+    va_start(args, format);
+    vsnprintf(message, sizeof message, format, args);
+    va_end(args);
+
+    fflush(stdout);
+    fprintf(stderr, "Error: Runtime error: %s\n", message);
+    exit(1);
+}
+
+static void buffer_push(CrBuffer *buffer, const char *bytes, size_t len) {
+    if (len == 0) {
+        return;
+    }
+    if (buffer->len + len > buffer->cap) {
+        size_t cap = buffer->cap * 2 + len + 16;
+        char *grown = realloc(buffer->bytes, cap);
+        if (grown == NULL) {
+            cr_error("Out of memory");
+        }
+        buffer->bytes = grown;
+        buffer->cap = cap;
+    }
+    memcpy(buffer->bytes + buffer->len, bytes, len);
+    buffer->len += len;
+}
+
+static void buffer_puts(CrBuffer *buffer, const char *text) {
+    buffer_push(buffer, text, strlen(text));
+}
+
+static void buffer_char(CrBuffer *buffer, uint32_t c) {
+    char bytes[4];
+    size_t len;
+    if (c < 0x80) {
+        bytes[0] = (char)c;
+        len = 1;
+    } else if (c < 0x800) {
+        bytes[0] = (char)(0xC0 | (c >> 6));
+        bytes[1] = (char)(0x80 | (c & 0x3F));
+        len = 2;
+    } else if (c < 0x10000) {
+        bytes[0] = (char)(0xE0 | (c >> 12));
+        bytes[1] = (char)(0x80 | ((c >> 6) & 0x3F));
+        bytes[2] = (char)(0x80 | (c & 0x3F));
+        len = 3;
+    } else {
+        bytes[0] = (char)(0xF0 | (c >> 18));
+        bytes[1] = (char)(0x80 | ((c >> 12) & 0x3F));
+        bytes[2] = (char)(0x80 | ((c >> 6) & 0x3F));
+        bytes[3] = (char)(0x80 | (c & 0x3F));
+        len = 4;
+    }
+    buffer_push(buffer, bytes, len);
+}
+
+static CrValue buffer_finish(CrBuffer *buffer) {
+    CrValue value = cr_string(buffer->bytes, buffer->len);
+    free(buffer->bytes);
+    return value;
+}
+
+/* The number of characters in UTF-8 text */
+static size_t char_count(const char *bytes, size_t len) {
+    size_t count = 0, i;
+    for (i = 0; i < len; i++) {
+        if (((unsigned char)bytes[i] & 0xC0) != 0x80) {
+            count++;
+        }
+    }
+    return count;
+}
+
+/* ---- Values ---- */
+
+CrValue cr_none(void) {
+    CrValue value;
+    value.tag = CR_NONE;
+    value.as.integer = 0;
+    return value;
+}
+
+CrValue cr_integer(int64_t integer) {
+    CrValue value;
+    value.tag = CR_INTEGER;
+    value.as.integer = integer;
+    return value;
+}
+
+static CrValue cr_float(double number) {
+    CrValue value;
+    value.tag = CR_FLOAT;
+    value.as.number = number;
+    return value;
+}
+
+CrValue cr_float_bits(uint64_t bits) {
+    double number;
+    memcpy(&number, &bits, sizeof number);
+    return cr_float(number);
+}
+
+CrValue cr_string(const char *bytes, size_t len) {
+    CrValue value;
+    CrString *string = cr_alloc(sizeof(CrString) + len + 1);
+    string->refs = 1;
+    string->len = len;
+    if (len > 0) {
+        memcpy(string->bytes, bytes, len);
+    }
+    string->bytes[len] = '\0';
+    value.tag = CR_STRING;
+    value.as.string = string;
+    return value;
+}
+
+CrValue cr_closure(const char *name, CrCode code, CrEnv *env) {
+    CrValue value;
+    CrClosure *closure = cr_alloc(sizeof(CrClosure));
+    closure->refs = 1;
+    closure->name = name;
+    closure->code = code;
+    closure->env = env;
+    env->refs++;
+    value.tag = CR_FUNCTION;
+    value.as.closure = closure;
+    return value;
+}
+
+CrValue cr_builtin(const CrBuiltin *builtin) {
+    CrValue value;
+    value.tag = CR_BUILTIN;
+    value.as.builtin = builtin;
+    return value;
+}
+
+/* Another reference to a value the caller keeps */
+static CrValue cr_copy(const CrValue *value) {
+    if (value->tag == CR_STRING) {
+        value->as.string->refs++;
+    } else if (value->tag == CR_FUNCTION) {
+        value->as.closure->refs++;
+    }
+    return *value;
+}
+
+void cr_release(CrValue value) {
+    if (value.tag == CR_STRING) {
+        if (--value.as.string->refs == 0) {
+            free(value.as.string);
+        }
+    } else if (value.tag == CR_FUNCTION) {
+        CrClosure *closure = value.as.closure;
+        if (--closure->refs == 0) {
+            cr_env_release(closure->env);
+            free(closure);
+        }
+    }
+}
+
+static const char *type_name(const CrValue *value) {
+    switch (value->tag) {
+    case CR_NONE: return "none";
+    case CR_INTEGER: return "integer";
+    case CR_FLOAT: return "float";
+    case CR_STRING: return "string";
+    default: return "function";
+    }
+}
+
+/* Floats print like Rust's `{}`: the shortest digits that read back as the
+   same number, never in exponent notation */
+static void write_float(CrBuffer *out, double x) {
+    char text[40], digits[20];
+    int precision, exponent, n = 0, i;
+    const char *c;
+
+    if (isnan(x)) {
+        buffer_puts(out, "NaN");
+        return;
+    }
+    if (isinf(x)) {
+        buffer_puts(out, x > 0 ? "inf" : "-inf");
+        return;
+    }
+    if (x == 0) {
+        buffer_puts(out, signbit(x) ? "-0" : "0");
+        return;
+    }
+
+    for (precision = 0; precision < 17; precision++) {
+        snprintf(text, sizeof text, "%.*e", precision, x);
+        if (strtod(text, NULL) == x) {
+            break;
+        }
+    }
+
+    c = text;
+    if (*c == '-') {
+        buffer_puts(out, "-");
+        c++;
+    }
+    for (; *c != 'e'; c++) {
+        if (*c != '.') {
+            digits[n++] = *c;
+        }
+    }
+    exponent = atoi(c + 1);
+    while (n > 1 && digits[n - 1] == '0') {
+        n--;
+    }
+
+    if (exponent >= 0) {
+        for (i = 0; i < n || i <= exponent; i++) {
+            if (i == exponent + 1) {
+                buffer_puts(out, ".");
+            }
+            buffer_push(out, i < n ? &digits[i] : "0", 1);
+        }
+    } else {
+        buffer_puts(out, "0.");
+        for (i = 0; i < -exponent - 1; i++) {
+            buffer_puts(out, "0");
+        }
+        buffer_push(out, digits, (size_t)n);
+    }
+}
+
+static void write_value(CrBuffer *out, const CrValue *value) {
+    char text[32];
+    switch (value->tag) {
+    case CR_NONE:
+        buffer_puts(out, "none");
+        break;
+    case CR_INTEGER:
+        snprintf(text, sizeof text, "%lld", (long long)value->as.integer);
+        buffer_puts(out, text);
+        break;
+    case CR_FLOAT:
+        write_float(out, value->as.number);
+        break;
+    case CR_STRING:
+        buffer_push(out, value->as.string->bytes, value->as.string->len);
+        break;
+    case CR_FUNCTION:
+        if (value->as.closure->name[0] == '\0') {
+            buffer_puts(out, "<lambda>");
+        } else {
+            buffer_puts(out, "<function ");
+            buffer_puts(out, value->as.closure->name);
+            buffer_puts(out, ">");
+        }
+        break;
+    case CR_BUILTIN:
+        buffer_puts(out, "<builtin ");
+        buffer_puts(out, value->as.builtin->name);
+        buffer_puts(out, ">");
+        break;
+    }
+}
+
+/* ---- Environments ---- */
+
+CrEnv *cr_env_new(CrEnv *parent) {
+    CrEnv *env = cr_alloc(sizeof(CrEnv));
+    env->refs = 1;
+    env->parent = parent;
+    env->bindings = NULL;
+    env->len = 0;
+    env->cap = 0;
+    if (parent != NULL) {
+        parent->refs++;
+    }
+    return env;
+}
+
+void cr_env_release(CrEnv *env) {
+    while (env != NULL && --env->refs == 0) {
+        CrEnv *parent = env->parent;
+        size_t i;
+        for (i = 0; i < env->len; i++) {
+            cr_release(env->bindings[i].value);
+        }
+        free(env->bindings);
+        free(env);
+        env = parent;
+    }
+}
+
+void cr_define(CrEnv *env, int symbol, CrValue value) {
+    size_t i;
+    for (i = 0; i < env->len; i++) {
+        if (env->bindings[i].symbol == symbol) {
+            CrValue old = env->bindings[i].value;
+            env->bindings[i].value = value;
+            cr_release(old);
+            return;
+        }
+    }
+    if (env->len == env->cap) {
+        size_t cap = env->cap * 2 + 4;
+        CrBinding *grown = realloc(env->bindings, cap * sizeof(CrBinding));
+        if (grown == NULL) {
+            cr_error("Out of memory");
+        }
+        env->bindings = grown;
+        env->cap = cap;
+    }
+    env->bindings[env->len].symbol = symbol;
+    env->bindings[env->len].value = value;
+    env->len++;
+}
+
+CrValue cr_lookup(CrEnv *env, int symbol) {
+    for (; env != NULL; env = env->parent) {
+        size_t i;
+        for (i = 0; i < env->len; i++) {
+            if (env->bindings[i].symbol == symbol) {
+                return cr_copy(&env->bindings[i].value);
+            }
+        }
+    }
+    cr_error("Undefined variable: %s", cr_symbols[symbol]);
+    return cr_none();
+}
+
+/* ---- Operators ---- */
+
+int cr_truthy(CrValue value) {
+    int truthy = !(value.tag == CR_NONE || (value.tag == CR_INTEGER && value.as.integer == 0));
+    cr_release(value);
+    return truthy;
+}
+
+CrValue cr_negate(CrValue value) {
+    if (value.tag == CR_INTEGER) {
+        if (value.as.integer == INT64_MIN) {
+            cr_error("Integer overflow");
+        }
+        return cr_integer(-value.as.integer);
+    }
+    if (value.tag == CR_FLOAT) {
+        return cr_float(-value.as.number);
+    }
+    cr_error("Cannot negate a non-numeric value");
+    return cr_none();
+}
+
+CrValue cr_not(CrValue value) {
+    return cr_integer(!cr_truthy(value));
+}
+
+static CrValue float_binary(CrBinaryOp op, double l, double r);
+
+static CrValue integer_binary(CrBinaryOp op, int64_t l, int64_t r) {
+    int64_t remainder;
+
+    switch (op) {
+    case CR_ADD:
+        if ((r > 0 && l > INT64_MAX - r) || (r < 0 && l < INT64_MIN - r)) {
+            cr_error("Integer overflow");
+        }
+        return cr_integer(l + r);
+    case CR_SUB:
+        if ((r < 0 && l > INT64_MAX + r) || (r > 0 && l < INT64_MIN + r)) {
+            cr_error("Integer overflow");
+        }
+        return cr_integer(l - r);
+    case CR_MUL:
+        if (l != 0 && r != 0) {
+            int64_t product = (int64_t)((uint64_t)l * (uint64_t)r);
+            if ((l == -1 && r == INT64_MIN) || (r == -1 && l == INT64_MIN) || product / r != l) {
+                cr_error("Integer overflow");
+            }
+            return cr_integer(product);
+        }
+        return cr_integer(0);
+    case CR_DIV:
+        return float_binary(op, (double)l, (double)r);
+    case CR_FLOOR_DIV:
+        if (r == 0) {
+            cr_error("Division by zero");
+        }
+        if (l == INT64_MIN && r == -1) {
+            cr_error("Integer overflow");
+        }
+        /* C truncates towards zero; floor instead */
+        return cr_integer(l / r - (l % r != 0 && (l < 0) != (r < 0)));
+    case CR_MOD:
+        if (r == 0) {
+            cr_error("Modulo by zero");
+        }
+        if (r == -1) {
+            return cr_integer(0);
+        }
+        remainder = l % r;
+        return cr_integer(remainder != 0 && (remainder < 0) != (r < 0) ? remainder + r : remainder);
+    case CR_EQ: return cr_integer(l == r);
+    case CR_NE: return cr_integer(l != r);
+    case CR_LT: return cr_integer(l < r);
+    case CR_GT: return cr_integer(l > r);
+    case CR_LE: return cr_integer(l <= r);
+    case CR_GE: return cr_integer(l >= r);
+    default: break;
+    }
+    cr_error("Invalid operation between integer and integer");
+    return cr_none();
+}
+
+static CrValue float_binary(CrBinaryOp op, double l, double r) {
+    switch (op) {
+    case CR_ADD: return cr_float(l + r);
+    case CR_SUB: return cr_float(l - r);
+    case CR_MUL: return cr_float(l * r);
+    case CR_DIV:
+        if (r == 0.0) {
+            cr_error("Division by zero");
+        }
+        return cr_float(l / r);
+    case CR_FLOOR_DIV:
+        if (r == 0.0) {
+            cr_error("Division by zero");
+        }
+        return cr_float(floor(l / r));
+    case CR_MOD:
+        if (r == 0.0) {
+            cr_error("Modulo by zero");
+        }
+        return cr_float(l - r * floor(l / r));
+    case CR_EQ: return cr_integer(l == r);
+    case CR_NE: return cr_integer(l != r);
+    case CR_LT: return cr_integer(l < r);
+    case CR_GT: return cr_integer(l > r);
+    case CR_LE: return cr_integer(l <= r);
+    case CR_GE: return cr_integer(l >= r);
+    case CR_DOT: cr_error("Cannot use dot operator with numbers");
+    }
+    return cr_none();
+}
+
+static CrValue string_binary(CrBinaryOp op, const CrString *l, const CrString *r) {
+    size_t shorter = l->len < r->len ? l->len : r->len;
+    int order = memcmp(l->bytes, r->bytes, shorter);
+    CrBuffer buffer = {NULL, 0, 0};
+
+    if (order == 0) {
+        order = (l->len > r->len) - (l->len < r->len);
+    }
+    switch (op) {
+    case CR_ADD:
+    case CR_DOT:
+        buffer_push(&buffer, l->bytes, l->len);
+        if (op == CR_DOT) {
+            buffer_puts(&buffer, ".");
+        }
+        buffer_push(&buffer, r->bytes, r->len);
+        return buffer_finish(&buffer);
+    case CR_EQ: return cr_integer(order == 0);
+    case CR_NE: return cr_integer(order != 0);
+    case CR_LT: return cr_integer(order < 0);
+    case CR_GT: return cr_integer(order > 0);
+    case CR_LE: return cr_integer(order <= 0);
+    case CR_GE: return cr_integer(order >= 0);
+    default: break;
+    }
+    cr_error("Invalid operation between string and string");
+    return cr_none();
+}
+
+static int is_number(const CrValue *value) {
+    return value->tag == CR_INTEGER || value->tag == CR_FLOAT;
+}
+
+static double as_float(const CrValue *value) {
+    return value->tag == CR_INTEGER ? (double)value->as.integer : value->as.number;
+}
+
+CrValue cr_binary(CrBinaryOp op, CrValue left, CrValue right) {
+    CrValue result;
+
+    if (left.tag == CR_INTEGER && right.tag == CR_INTEGER) {
+        return integer_binary(op, left.as.integer, right.as.integer);
+    }
+    if (is_number(&left) && is_number(&right)) {
+        return float_binary(op, as_float(&left), as_float(&right));
+    }
+
+    if (left.tag == CR_STRING && right.tag == CR_STRING) {
+        result = string_binary(op, left.as.string, right.as.string);
+    } else if (op == CR_ADD && (left.tag == CR_STRING || right.tag == CR_STRING)) {
+        CrBuffer buffer = {NULL, 0, 0};
+        write_value(&buffer, &left);
+        write_value(&buffer, &right);
+        result = buffer_finish(&buffer);
+    } else if (op == CR_EQ || op == CR_NE) {
+        /* Values of different types are never equal */
+        int equal = left.tag == CR_NONE && right.tag == CR_NONE;
+        result = cr_integer(op == CR_EQ ? equal : !equal);
+    } else {
+        cr_error("Invalid operation between %s and %s", type_name(&left), type_name(&right));
+        return cr_none();
+    }
+
+    cr_release(left);
+    cr_release(right);
+    return result;
+}
+
+CrValue cr_range(CrValue count) {
+    if (count.tag != CR_INTEGER) {
+        cr_error("Range argument must be an integer");
+    }
+    return count;
+}
+
+int64_t cr_loop_count(CrValue count) {
+    if (count.tag != CR_INTEGER) {
+        cr_error("Loop count must be an integer");
+    }
+    return count.as.integer;
+}
+
+int64_t cr_iterator_count(CrValue iterable) {
+    if (iterable.tag != CR_INTEGER) {
+        cr_error("Iterator must be a range");
+    }
+    return iterable.as.integer;
+}
+
+/* ---- Calls ---- */
+
+void cr_check_arity(const char *name, int expected, int got) {
+    if (expected != got) {
+        cr_error("%s expects %d arguments, got %d", name[0] == '\0' ? "Lambda" : name, expected, got);
+    }
+}
+
+CrValue cr_call(CrValue callee, int argc, CrValue *argv) {
+    CrValue result;
+    switch (callee.tag) {
+    case CR_FUNCTION:
+        result = callee.as.closure->code(callee.as.closure, argc, argv);
+        cr_release(callee);
+        return result;
+    case CR_BUILTIN:
+        if (callee.as.builtin->arity >= 0) {
+            cr_check_arity(callee.as.builtin->name, callee.as.builtin->arity, argc);
+        }
+        return callee.as.builtin->code(argc, argv);
+    default:
+        cr_error("Value of type %s is not callable", type_name(&callee));
+        return cr_none();
+    }
+}
+
+/* ---- Strings ---- */
+
+CrValue cr_to_string(CrValue value) {
+    CrBuffer buffer = {NULL, 0, 0};
+    if (value.tag == CR_STRING) {
+        return value;
+    }
+    write_value(&buffer, &value);
+    cr_release(value);
+    return buffer_finish(&buffer);
+}
+
+/* Rust's `{:e}`: no `+` and no leading zeros in the exponent */
+static void write_exponent(CrBuffer *out, int precision, double x) {
+    char text[64];
+    char *e;
+    snprintf(text, sizeof text, "%.*e", precision, x);
+    e = strchr(text, 'e');
+    if (e == NULL) {
+        buffer_puts(out, text);
+        return;
+    }
+    buffer_push(out, text, (size_t)(e - text));
+    snprintf(text, sizeof text, "e%d", atoi(e + 1));
+    buffer_puts(out, text);
+}
+
+static void write_fill(CrBuffer *out, uint32_t fill, size_t count) {
+    size_t i;
+    for (i = 0; i < count; i++) {
+        buffer_char(out, fill);
+    }
+}
+
+CrValue cr_format(CrValue value, const CrFormatSpec *spec) {
+    CrBuffer body = {NULL, 0, 0}, out = {NULL, 0, 0};
+    char text[80];
+    int numeric = is_number(&value);
+    size_t len, padding;
+    CrAlign align;
+
+    if (value.tag == CR_INTEGER && (spec->kind == 0 || spec->kind == 'd')) {
+        if (spec->precision >= 0) {
+            snprintf(text, sizeof text, "%.*f", (int)spec->precision, (double)value.as.integer);
+        } else {
+            snprintf(text, sizeof text, "%lld", (long long)value.as.integer);
+        }
+        buffer_puts(&body, text);
+    } else if (value.tag == CR_INTEGER && (spec->kind == 'x' || spec->kind == 'X' || spec->kind == 'o')) {
+        const char *format = spec->kind == 'x' ? "%llx" : spec->kind == 'X' ? "%llX" : "%llo";
+        snprintf(text, sizeof text, format, (unsigned long long)value.as.integer);
+        buffer_puts(&body, text);
+    } else if (value.tag == CR_INTEGER && spec->kind == 'b') {
+        uint64_t bits = (uint64_t)value.as.integer;
+        int i, started = 0;
+        for (i = 63; i >= 0; i--) {
+            if ((bits >> i) & 1) {
+                started = 1;
+            }
+            if (started || i == 0) {
+                buffer_puts(&body, (bits >> i) & 1 ? "1" : "0");
+            }
+        }
+    } else if (value.tag == CR_FLOAT && spec->kind == 0) {
+        if (spec->precision >= 0) {
+            snprintf(text, sizeof text, "%.*f", (int)spec->precision, value.as.number);
+            buffer_puts(&body, text);
+        } else {
+            write_float(&body, value.as.number);
+        }
+    } else if (numeric && (spec->kind == 'f' || spec->kind == 'e' || spec->kind == '%')) {
+        double x = as_float(&value);
+        int precision = spec->precision >= 0 ? (int)spec->precision : 6;
+        if (spec->kind == 'e') {
+            write_exponent(&body, precision, x);
+        } else {
+            snprintf(text, sizeof text, "%.*f", precision, spec->kind == '%' ? x * 100.0 : x);
+            buffer_puts(&body, text);
+            if (spec->kind == '%') {
+                buffer_puts(&body, "%");
+            }
+        }
+    } else if (numeric || (spec->kind != 0 && spec->kind != 's')) {
+        cr_error("Format type '%c' is not valid for a value of type %s", spec->kind, type_name(&value));
+    } else if (value.tag == CR_STRING && spec->precision >= 0) {
+        const CrString *string = value.as.string;
+        size_t end = 0;
+        long chars = 0;
+        while (end < string->len) {
+            if (((unsigned char)string->bytes[end] & 0xC0) != 0x80 && chars++ == spec->precision) {
+                break;
+            }
+            end++;
+        }
+        buffer_push(&body, string->bytes, end);
+    } else {
+        write_value(&body, &value);
+    }
+    cr_release(value);
+
+    if (spec->sign && numeric && (body.len == 0 || body.bytes[0] != '-')) {
+        buffer_puts(&out, "+");
+    }
+    buffer_push(&out, body.bytes, body.len);
+    free(body.bytes);
+
+    len = char_count(out.bytes, out.len);
+    if (spec->width < 0 || len >= (size_t)spec->width) {
+        return buffer_finish(&out);
+    }
+    padding = (size_t)spec->width - len;
+
+    if (spec->zero_pad && spec->align == CR_ALIGN_NONE && numeric) {
+        CrBuffer padded = {NULL, 0, 0};
+        size_t sign = out.len > 0 && (out.bytes[0] == '+' || out.bytes[0] == '-');
+        buffer_push(&padded, out.bytes, sign);
+        write_fill(&padded, '0', padding);
+        buffer_push(&padded, out.bytes + sign, out.len - sign);
+        free(out.bytes);
+        return buffer_finish(&padded);
+    }
+
+    align = spec->align != CR_ALIGN_NONE ? spec->align : numeric ? CR_ALIGN_RIGHT : CR_ALIGN_LEFT;
+    body = out;
+    out.bytes = NULL;
+    out.len = out.cap = 0;
+    if (align == CR_ALIGN_RIGHT) {
+        write_fill(&out, spec->fill, padding);
+    } else if (align == CR_ALIGN_CENTER) {
+        write_fill(&out, spec->fill, padding / 2);
+    }
+    buffer_push(&out, body.bytes, body.len);
+    if (align == CR_ALIGN_LEFT) {
+        write_fill(&out, spec->fill, padding);
+    } else if (align == CR_ALIGN_CENTER) {
+        write_fill(&out, spec->fill, padding - padding / 2);
+    }
+    free(body.bytes);
+    return buffer_finish(&out);
+}
+
+CrValue cr_concat(int count, CrValue *parts) {
+    CrBuffer buffer = {NULL, 0, 0};
+    int i;
+    for (i = 0; i < count; i++) {
+        write_value(&buffer, &parts[i]);
+        cr_release(parts[i]);
+    }
+    return buffer_finish(&buffer);
+}
+
+/* ---- Builtins ---- */
+
+static CrValue builtin_print(int argc, CrValue *argv) {
+    CrBuffer buffer = {NULL, 0, 0};
+    int i;
+    for (i = 0; i < argc; i++) {
+        if (i > 0) {
+            buffer_puts(&buffer, " ");
+        }
+        write_value(&buffer, &argv[i]);
+        cr_release(argv[i]);
+    }
+    buffer_puts(&buffer, "\n");
+    fwrite(buffer.bytes, 1, buffer.len, stdout);
+    free(buffer.bytes);
+    return cr_none();
+}
+
+const CrBuiltin cr_print = {"print", -1, builtin_print};
@@ -0,0 +1,127 @@
+/*
+ * The runtime linked into Crabby programs compiled to C.
+ *
+ * Values are reference counted. Every function here that takes a CrValue
+ * takes ownership of it, and every CrValue returned belongs to the caller,
+ * so generated code never has to retain or release by hand except to drop
+ * a value it does not use. As in the interpreter, a function stored in the
+ * scope it closes over keeps that scope alive until the program exits.
+ */
+#ifndef CRABBY_RUNTIME_H
+#define CRABBY_RUNTIME_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum {
+    CR_NONE,
+    CR_INTEGER,
+    CR_FLOAT,
+    CR_STRING,
+    CR_FUNCTION,
+    CR_BUILTIN
+} CrTag;
+
+typedef struct CrString CrString;
+typedef struct CrClosure CrClosure;
+typedef struct CrEnv CrEnv;
+typedef struct CrBuiltin CrBuiltin;
+
+typedef struct {
+    CrTag tag;
+    union {
+        int64_t integer;
+        double number;
+        CrString *string;
+        CrClosure *closure;
+        const CrBuiltin *builtin;
+    } as;
+} CrValue;
+
+/* A compiled function body; owns the `argc` values in `argv` */
+typedef CrValue (*CrCode)(CrClosure *self, int argc, CrValue *argv);
+
+struct CrClosure {
+    long refs;
+    const char *name;
+    CrCode code;
+    CrEnv *env;
+};
+
+struct CrBuiltin {
+    const char *name;
+    /* -1 accepts any number of arguments */
+    int arity;
+    CrValue (*code)(int argc, CrValue *argv);
+};
+
+typedef enum {
+    CR_ADD,
+    CR_SUB,
+    CR_MUL,
+    CR_DIV,
+    CR_EQ,
+    CR_NE,
+    CR_LT,
+    CR_GT,
+    CR_LE,
+    CR_GE,
+    CR_DOT,
+    CR_FLOOR_DIV,
+    CR_MOD
+} CrBinaryOp;
+
+typedef enum {
+    CR_ALIGN_NONE,
+    CR_ALIGN_LEFT,
+    CR_ALIGN_RIGHT,
+    CR_ALIGN_CENTER
+} CrAlign;
+
+/* An f-string spec; `width` and `precision` are -1 and `kind` is 0 when absent */
+typedef struct {
+    uint32_t fill;
+    CrAlign align;
+    int sign;
+    int zero_pad;
+    long width;
+    long precision;
+    char kind;
+} CrFormatSpec;
+
+/* Symbol names, indexed by the numbers generated code uses for variables */
+void cr_init(const char *const *symbols);
+int cr_exit(void);
+void cr_error(const char *format, ...);
+
+CrValue cr_none(void);
+CrValue cr_integer(int64_t value);
+CrValue cr_float_bits(uint64_t bits);
+CrValue cr_string(const char *bytes, size_t len);
+CrValue cr_closure(const char *name, CrCode code, CrEnv *env);
+CrValue cr_builtin(const CrBuiltin *builtin);
+void cr_release(CrValue value);
+
+CrEnv *cr_env_new(CrEnv *parent);
+void cr_env_release(CrEnv *env);
+void cr_define(CrEnv *env, int symbol, CrValue value);
+CrValue cr_lookup(CrEnv *env, int symbol);
+
+int cr_truthy(CrValue value);
+CrValue cr_negate(CrValue value);
+CrValue cr_not(CrValue value);
+CrValue cr_binary(CrBinaryOp op, CrValue left, CrValue right);
+CrValue cr_range(CrValue count);
+int64_t cr_loop_count(CrValue count);
+int64_t cr_iterator_count(CrValue iterable);
+
+void cr_check_arity(const char *name, int expected, int got);
+CrValue cr_call(CrValue callee, int argc, CrValue *argv);
+
+CrValue cr_to_string(CrValue value);
+CrValue cr_format(CrValue value, const CrFormatSpec *spec);
+CrValue cr_concat(int count, CrValue *parts);
+
+extern const CrBuiltin cr_print;
+
+#endif
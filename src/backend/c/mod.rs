@@ -0,0 +1,522 @@
+//! Ahead-of-time compilation to C. Each Crabby function becomes a C function
+//! working on dynamic `CrValue`s from a small runtime, `crabby_runtime.c`,
+//! which the generated file is compiled and linked with. Variables live in
+//! runtime environments just as in the interpreter, so scoping and closures
+//! behave the same.
+
+use crate::ir::{
+    self, BinaryOp, Expr, FormatAlign, FormatPart, FormatSpec, FunctionDecl, Stmt, UnaryOp,
+};
+use crate::utils::CrabbyError;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+pub const RUNTIME_HEADER: &str = include_str!("crabby_runtime.h");
+pub const RUNTIME_SOURCE: &str = include_str!("crabby_runtime.c");
+
+/// Builtins the C runtime provides, with the name of their `CrBuiltin`
+const BUILTINS: &[(&str, &str)] = &[("print", "cr_print")];
+
+/// Translates a checked module into a C program
+pub fn emit_c(module: &ir::Module) -> Result<String, CrabbyError> {
+    let mut generator = Generator::default();
+    for (name, _) in BUILTINS {
+        generator.symbol(name);
+    }
+    generator.check_names(module)?;
+
+    let mut script = Writer::new();
+    for statement in &module.body {
+        generator.statement(&mut script, statement)?;
+    }
+
+    let mut out =
+        String::from("/* Generated by crabby emit-c */\n#include \"crabby_runtime.h\"\n\n");
+    out.push_str("static const char *const crabby_symbols[] = {\n");
+    for (i, name) in generator.symbols.iter().enumerate() {
+        let _ = writeln!(out, "    {}, /* {} */", c_string(name), i);
+    }
+    out.push_str("};\n\n");
+
+    for (name, _) in &generator.functions {
+        let _ = writeln!(
+            out,
+            "static CrValue {}(CrClosure *self, int argc, CrValue *argv);",
+            name
+        );
+    }
+    for (_, definition) in &generator.functions {
+        out.push('\n');
+        out.push_str(definition);
+    }
+
+    out.push_str("\nstatic void crabby_script(CrEnv *env) {\n    CrValue last = cr_none();\n");
+    out.push_str(&script.code);
+    out.push_str("    cr_release(last);\n}\n\nint main(void) {\n");
+    out.push_str("    CrEnv *builtins, *globals;\n    cr_init(crabby_symbols);\n");
+    out.push_str("    builtins = cr_env_new(NULL);\n");
+    for (name, builtin) in BUILTINS {
+        let _ = writeln!(
+            out,
+            "    cr_define(builtins, {}, cr_builtin(&{}));",
+            generator.symbol(name),
+            builtin
+        );
+    }
+    out.push_str("    globals = cr_env_new(builtins);\n    cr_env_release(builtins);\n");
+    out.push_str("    crabby_script(globals);\n    cr_env_release(globals);\n");
+    out.push_str("    return cr_exit();\n}\n");
+    Ok(out)
+}
+
+/// Compiles a module to a native executable at `output` with the system C
+/// compiler, `$CC` or `cc`
+pub fn build_native(module: &ir::Module, output: &Path) -> Result<(), CrabbyError> {
+    let program = emit_c(module)?;
+    let io_error =
+        |e: std::io::Error| CrabbyError::CompileError(format!("Failed to write C sources: {}", e));
+
+    let dir = std::env::temp_dir().join(format!("crabby_native_{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(io_error)?;
+    let sources = [
+        ("crabby_runtime.h", RUNTIME_HEADER),
+        ("crabby_runtime.c", RUNTIME_SOURCE),
+        ("program.c", program.as_str()),
+    ];
+    for (name, contents) in sources {
+        fs::write(dir.join(name), contents).map_err(io_error)?;
+    }
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let result = Command::new(&compiler)
+        .args(["-std=c99", "-O2", "-o"])
+        .arg(output)
+        .arg(dir.join("program.c"))
+        .arg(dir.join("crabby_runtime.c"))
+        .arg("-lm")
+        .output();
+    let _ = fs::remove_dir_all(&dir);
+
+    match result {
+        Ok(result) if result.status.success() => Ok(()),
+        Ok(result) => Err(CrabbyError::CompileError(format!(
+            "C compiler failed:\n{}",
+            String::from_utf8_lossy(&result.stderr)
+        ))),
+        Err(e) => Err(CrabbyError::CompileError(format!(
+            "Could not run C compiler '{}': {}",
+            compiler, e
+        ))),
+    }
+}
+
+/// The body of one C function being generated
+struct Writer {
+    code: String,
+    indent: usize,
+    temps: usize,
+}
+
+impl Writer {
+    fn new() -> Self {
+        Self {
+            code: String::new(),
+            indent: 1,
+            temps: 0,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.code.push_str("    ");
+        }
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn open(&mut self, line: &str) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    fn close(&mut self, line: &str) {
+        self.indent -= 1;
+        self.line(line);
+    }
+
+    /// Declares a fresh temporary holding `value`
+    fn temp(&mut self, value: &str) -> String {
+        let name = self.fresh();
+        self.line(&format!("CrValue {} = {};", name, value));
+        name
+    }
+
+    fn fresh(&mut self) -> String {
+        self.temps += 1;
+        format!("t{}", self.temps)
+    }
+
+    /// Replaces the value of the statement run last, which a function
+    /// returns when it ends without `return`
+    fn set_last(&mut self, value: &str) {
+        self.line("cr_release(last);");
+        self.line(&format!("last = {};", value));
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    symbols: Vec<String>,
+    symbol_ids: HashMap<String, usize>,
+    /// Generated functions: their C names and definitions
+    functions: Vec<(String, String)>,
+}
+
+impl Generator {
+    fn symbol(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.symbol_ids.get(name) {
+            return id;
+        }
+        self.symbols.push(name.to_string());
+        self.symbol_ids
+            .insert(name.to_string(), self.symbols.len() - 1);
+        self.symbols.len() - 1
+    }
+
+    /// Rejects programs using builtins the C runtime does not have
+    fn check_names(&self, module: &ir::Module) -> Result<(), CrabbyError> {
+        let builtins = BUILTINS.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        match super::unbound_name(module, &builtins) {
+            Some(name) => Err(CrabbyError::CompileError(format!(
+                "'{}' is not available in programs compiled to C",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn function(&mut self, decl: &FunctionDecl) -> Result<String, CrabbyError> {
+        let name = format!("crabby_fn_{}", self.functions.len());
+        // Reserve the slot so nested functions get later numbers
+        self.functions.push((name.clone(), String::new()));
+        let index = self.functions.len() - 1;
+
+        let mut body = Writer::new();
+        body.line("CrEnv *env;");
+        body.line("CrValue last = cr_none();");
+        body.line(&format!(
+            "cr_check_arity(self->name, {}, argc);",
+            decl.params.len()
+        ));
+        body.line("env = cr_env_new(self->env);");
+        for (i, param) in decl.params.iter().enumerate() {
+            let symbol = self.symbol(param);
+            body.line(&format!("cr_define(env, {}, argv[{}]);", symbol, i));
+        }
+        if decl.params.is_empty() {
+            body.line("(void)argv;");
+        }
+        for statement in &decl.body {
+            self.statement(&mut body, statement)?;
+        }
+        body.line("cr_env_release(env);");
+        body.line("return last;");
+
+        self.functions[index].1 = format!(
+            "static CrValue {}(CrClosure *self, int argc, CrValue *argv) {{\n{}}}\n",
+            name, body.code
+        );
+        Ok(name)
+    }
+
+    fn block(&mut self, w: &mut Writer, statements: &[Stmt]) -> Result<(), CrabbyError> {
+        for statement in statements {
+            self.statement(w, statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, w: &mut Writer, statement: &Stmt) -> Result<(), CrabbyError> {
+        w.open("{");
+        match statement {
+            Stmt::Let { name, value, .. } => {
+                let value = self.expression(w, value)?;
+                let symbol = self.symbol(name);
+                w.line(&format!("cr_define(env, {}, {});", symbol, value));
+                w.set_last("cr_none()");
+            }
+            Stmt::Function(decl) => {
+                let function = self.function(decl)?;
+                let symbol = self.symbol(&decl.name);
+                w.line(&format!(
+                    "cr_define(env, {}, cr_closure({}, {}, env));",
+                    symbol,
+                    c_string(&decl.name),
+                    function
+                ));
+                w.set_last("cr_none()");
+            }
+            Stmt::Return(expr) => {
+                let value = self.expression(w, expr)?;
+                w.line("cr_release(last);");
+                w.line("cr_env_release(env);");
+                w.line(&format!("return {};", value));
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.expression(w, condition)?;
+                w.set_last("cr_none()");
+                w.open(&format!("if (cr_truthy({})) {{", condition));
+                self.block(w, then_branch)?;
+                w.close("} else {");
+                w.indent += 1;
+                self.block(w, else_branch)?;
+                w.close("}");
+            }
+            Stmt::While { condition, body } => {
+                w.open("for (;;) {");
+                let condition = self.expression(w, condition)?;
+                w.line(&format!("if (!cr_truthy({})) break;", condition));
+                self.block(w, body)?;
+                w.close("}");
+                w.set_last("cr_none()");
+            }
+            Stmt::Loop { count, body } => {
+                let count = self.expression(w, count)?;
+                let (n, i) = (w.fresh(), w.fresh());
+                w.line(&format!("int64_t {} = cr_loop_count({}), {};", n, count, i));
+                w.open(&format!("for ({i} = 0; {i} < {n}; {i}++) {{", i = i, n = n));
+                self.block(w, body)?;
+                w.close("}");
+                w.set_last("cr_none()");
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                let iterable = self.expression(w, iterable)?;
+                let (n, i) = (w.fresh(), w.fresh());
+                w.line(&format!(
+                    "int64_t {} = cr_iterator_count({}), {};",
+                    n, iterable, i
+                ));
+                w.open(&format!("for ({i} = 0; {i} < {n}; {i}++) {{", i = i, n = n));
+                let symbol = self.symbol(variable);
+                w.line(&format!("cr_define(env, {}, cr_integer({}));", symbol, i));
+                self.block(w, body)?;
+                w.close("}");
+                w.set_last("cr_none()");
+            }
+            Stmt::Import { .. } => {
+                return Err(CrabbyError::CompileError(
+                    "Imports are not supported in programs compiled to C yet".to_string(),
+                ));
+            }
+            Stmt::Expr(expr) => {
+                let value = self.expression(w, expr)?;
+                w.set_last(&value);
+            }
+        }
+        w.close("}");
+        Ok(())
+    }
+
+    /// Emits code evaluating `expression` into a new temporary, and returns
+    /// the temporary's name
+    fn expression(&mut self, w: &mut Writer, expression: &Expr) -> Result<String, CrabbyError> {
+        Ok(match expression {
+            Expr::Integer(n) if *n == i64::MIN => w.temp("cr_integer(INT64_MIN)"),
+            Expr::Integer(n) => w.temp(&format!("cr_integer(INT64_C({}))", n)),
+            Expr::Float(f) => w.temp(&format!("cr_float_bits(UINT64_C(0x{:016x}))", f.to_bits())),
+            Expr::String(s) => w.temp(&format!("cr_string({}, {})", c_string(s), s.len())),
+            Expr::Variable(name) => {
+                let symbol = self.symbol(name);
+                w.temp(&format!("cr_lookup(env, {})", symbol))
+            }
+            Expr::Range(count) => {
+                let count = self.expression(w, count)?;
+                w.temp(&format!("cr_range({})", count))
+            }
+            Expr::Unary { op, operand } => {
+                let operand = self.expression(w, operand)?;
+                let function = match op {
+                    UnaryOp::Neg => "cr_negate",
+                    UnaryOp::Not => "cr_not",
+                };
+                w.temp(&format!("{}({})", function, operand))
+            }
+            Expr::Binary {
+                left,
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => {
+                let left = self.expression(w, left)?;
+                let result = w.fresh();
+                w.line(&format!("CrValue {};", result));
+                let (test, short) = match op {
+                    BinaryOp::And => ("!cr_truthy", "0"),
+                    _ => ("cr_truthy", "1"),
+                };
+                w.open(&format!("if ({}({})) {{", test, left));
+                w.line(&format!("{} = cr_integer({});", result, short));
+                w.close("} else {");
+                w.indent += 1;
+                let right = self.expression(w, right)?;
+                w.line(&format!("{} = cr_integer(cr_truthy({}));", result, right));
+                w.close("}");
+                result
+            }
+            Expr::Binary { left, op, right } => {
+                let left = self.expression(w, left)?;
+                let right = self.expression(w, right)?;
+                w.temp(&format!(
+                    "cr_binary({}, {}, {})",
+                    binary_op(*op),
+                    left,
+                    right
+                ))
+            }
+            Expr::Call { callee, args } => {
+                let symbol = self.symbol(callee);
+                let function = w.temp(&format!("cr_lookup(env, {})", symbol));
+                self.call(w, &function, args)?
+            }
+            Expr::CallValue { callee, args } => {
+                let function = self.expression(w, callee)?;
+                self.call(w, &function, args)?
+            }
+            Expr::List(_) => return Err(unsupported("Lists are")),
+            Expr::Dict(_) => return Err(unsupported("Dicts are")),
+            Expr::Keywords(_) => return Err(unsupported("Keyword arguments are")),
+            Expr::Index { .. } => return Err(unsupported("Indexing is")),
+            Expr::Member { .. } => return Err(unsupported("Member access is")),
+            Expr::Lambda(decl) => {
+                let function = self.function(decl)?;
+                w.temp(&format!("cr_closure(\"\", {}, env)", function))
+            }
+            Expr::Format(parts) => {
+                let mut strings = Vec::with_capacity(parts.len());
+                for part in parts {
+                    strings.push(match part {
+                        FormatPart::Literal(text) => {
+                            w.temp(&format!("cr_string({}, {})", c_string(text), text.len()))
+                        }
+                        FormatPart::Value { expr, spec: None } => {
+                            let value = self.expression(w, expr)?;
+                            w.temp(&format!("cr_to_string({})", value))
+                        }
+                        FormatPart::Value {
+                            expr,
+                            spec: Some(spec),
+                        } => {
+                            let value = self.expression(w, expr)?;
+                            let spec_name = w.fresh();
+                            w.line(&format!(
+                                "static const CrFormatSpec {} = {};",
+                                spec_name,
+                                format_spec(spec)
+                            ));
+                            w.temp(&format!("cr_format({}, &{})", value, spec_name))
+                        }
+                    });
+                }
+                let parts = self.array(w, &strings);
+                w.temp(&format!("cr_concat({}, {})", strings.len(), parts))
+            }
+        })
+    }
+
+    /// Emits a call of the value in the temporary `function`
+    fn call(&mut self, w: &mut Writer, function: &str, args: &[Expr]) -> Result<String, CrabbyError> {
+        let args = args
+            .iter()
+            .map(|arg| self.expression(w, arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let argv = self.array(w, &args);
+        Ok(w.temp(&format!("cr_call({}, {}, {})", function, args.len(), argv)))
+    }
+
+    /// Declares a `CrValue` array of the given temporaries, or returns `NULL`
+    /// when there are none
+    fn array(&mut self, w: &mut Writer, values: &[String]) -> String {
+        if values.is_empty() {
+            return "NULL".to_string();
+        }
+        let name = w.fresh();
+        w.line(&format!("CrValue {}[] = {{{}}};", name, values.join(", ")));
+        name
+    }
+}
+
+fn unsupported(what: &str) -> CrabbyError {
+    CrabbyError::CompileError(format!("{} not supported in programs compiled to C yet", what))
+}
+
+fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "CR_ADD",
+        BinaryOp::Sub => "CR_SUB",
+        BinaryOp::Mul => "CR_MUL",
+        BinaryOp::Div => "CR_DIV",
+        BinaryOp::FloorDiv => "CR_FLOOR_DIV",
+        BinaryOp::Mod => "CR_MOD",
+        BinaryOp::Eq => "CR_EQ",
+        BinaryOp::Ne => "CR_NE",
+        BinaryOp::Lt => "CR_LT",
+        BinaryOp::Gt => "CR_GT",
+        BinaryOp::Le => "CR_LE",
+        BinaryOp::Ge => "CR_GE",
+        BinaryOp::Dot => "CR_DOT",
+        // Short-circuited before reaching here
+        BinaryOp::And | BinaryOp::Or => "CR_EQ",
+    }
+}
+
+fn format_spec(spec: &FormatSpec) -> String {
+    let align = match spec.align {
+        None => "CR_ALIGN_NONE",
+        Some(FormatAlign::Left) => "CR_ALIGN_LEFT",
+        Some(FormatAlign::Right) => "CR_ALIGN_RIGHT",
+        Some(FormatAlign::Center) => "CR_ALIGN_CENTER",
+    };
+    let optional = |value: Option<usize>| value.map_or(-1, |value| value as i64);
+    let kind = spec
+        .kind
+        .filter(char::is_ascii)
+        .map_or(0, |kind| kind as u32);
+    format!(
+        "{{{}, {}, {}, {}, {}, {}, {}}}",
+        spec.fill as u32,
+        align,
+        spec.sign as u8,
+        spec.zero_pad as u8,
+        optional(spec.width),
+        optional(spec.precision),
+        kind
+    )
+}
+
+/// A C string literal for arbitrary UTF-8 text
+fn c_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in text.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'?' => literal.push_str("\\?"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
@@ -0,0 +1,124 @@
+//! Ahead-of-time backends, which turn checked IR into programs for other
+//! toolchains instead of running it
+
+pub mod c;
+pub mod wasm;
+
+use crate::ir::{self, Expr, FormatPart, FunctionDecl, Stmt};
+use std::collections::HashSet;
+
+/// The first name a module refers to that it never binds and that is not
+/// one of `builtins`, which a backend without the full set of globals
+/// cannot compile
+fn unbound_name(module: &ir::Module, builtins: &[&str]) -> Option<String> {
+    let mut bound = HashSet::new();
+    let mut used = Vec::new();
+    names(&module.body, &mut bound, &mut used);
+    used.into_iter()
+        .find(|name| !bound.contains(name) && !builtins.contains(&name.as_str()))
+}
+
+/// Collects the names a program binds and the names it refers to
+fn names(statements: &[Stmt], bound: &mut HashSet<String>, used: &mut Vec<String>) {
+    for statement in statements {
+        match statement {
+            Stmt::Let { name, value, .. } => {
+                bound.insert(name.clone());
+                expression_names(value, bound, used);
+            }
+            Stmt::Function(decl) => {
+                bound.insert(decl.name.clone());
+                function_names(decl, bound, used);
+            }
+            Stmt::Return(expr) | Stmt::Expr(expr) => expression_names(expr, bound, used),
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                expression_names(condition, bound, used);
+                names(then_branch, bound, used);
+                names(else_branch, bound, used);
+            }
+            Stmt::While {
+                condition: expr,
+                body,
+            }
+            | Stmt::Loop { count: expr, body } => {
+                expression_names(expr, bound, used);
+                names(body, bound, used);
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                bound.insert(variable.clone());
+                expression_names(iterable, bound, used);
+                names(body, bound, used);
+            }
+            Stmt::Import { name, .. } => {
+                bound.insert(name.clone());
+            }
+        }
+    }
+}
+
+fn function_names(decl: &FunctionDecl, bound: &mut HashSet<String>, used: &mut Vec<String>) {
+    bound.extend(decl.params.iter().cloned());
+    names(&decl.body, bound, used);
+}
+
+fn expression_names(expression: &Expr, bound: &mut HashSet<String>, used: &mut Vec<String>) {
+    match expression {
+        Expr::Integer(_) | Expr::Float(_) | Expr::String(_) => {}
+        Expr::Variable(name) => used.push(name.clone()),
+        Expr::Range(inner)
+        | Expr::Unary { operand: inner, .. }
+        | Expr::Member { object: inner, .. } => expression_names(inner, bound, used),
+        Expr::Binary { left, right, .. }
+        | Expr::Index {
+            object: left,
+            index: right,
+        } => {
+            expression_names(left, bound, used);
+            expression_names(right, bound, used);
+        }
+        Expr::CallValue { callee, args } => {
+            expression_names(callee, bound, used);
+            for arg in args {
+                expression_names(arg, bound, used);
+            }
+        }
+        Expr::List(elements) => {
+            for element in elements {
+                expression_names(element, bound, used);
+            }
+        }
+        Expr::Dict(entries) => {
+            for (key, value) in entries {
+                expression_names(key, bound, used);
+                expression_names(value, bound, used);
+            }
+        }
+        Expr::Keywords(arguments) => {
+            for (_, value) in arguments {
+                expression_names(value, bound, used);
+            }
+        }
+        Expr::Call { callee, args } => {
+            used.push(callee.clone());
+            for arg in args {
+                expression_names(arg, bound, used);
+            }
+        }
+        Expr::Lambda(decl) => function_names(decl, bound, used),
+        Expr::Format(parts) => {
+            for part in parts {
+                if let FormatPart::Value { expr, .. } = part {
+                    expression_names(expr, bound, used);
+                }
+            }
+        }
+    }
+}
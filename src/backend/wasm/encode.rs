@@ -0,0 +1,455 @@
+//! Just enough of the WebAssembly binary format to write the modules the
+//! backend generates: MVP instructions only, so any engine can load them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValType {
+    fn byte(self) -> u8 {
+        match self {
+            ValType::I32 => 0x7F,
+            ValType::I64 => 0x7E,
+            ValType::F64 => 0x7C,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub result: Option<ValType>,
+}
+
+/// The instructions the backend uses. Loads and stores carry their offset
+/// and use natural alignment.
+#[derive(Debug, Clone, Copy)]
+pub enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If(Option<ValType>),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    CallIndirect(u32),
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    I32Load(u32),
+    I64Load(u32),
+    F64Load(u32),
+    I32Load8U(u32),
+    I32Store(u32),
+    I64Store(u32),
+    F64Store(u32),
+    I32Store8(u32),
+    MemorySize,
+    MemoryGrow,
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtS,
+    I32GtU,
+    I32LeS,
+    I32LeU,
+    I32GeS,
+    I32GeU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64GtS,
+    I64LeS,
+    I64GeS,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32And,
+    I32Or,
+    I32Shl,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Xor,
+    F64Neg,
+    F64Floor,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    I32WrapI64,
+    I64ExtendI32S,
+    I64ExtendI32U,
+    F64ConvertI64S,
+}
+
+impl Instr {
+    fn encode(self, out: &mut Vec<u8>) {
+        use Instr::*;
+        let memory = |out: &mut Vec<u8>, opcode: u8, align: u32, offset: u32| {
+            out.push(opcode);
+            unsigned(out, align as u64);
+            unsigned(out, offset as u64);
+        };
+        let opcode = match self {
+            Unreachable => 0x00,
+            Block => return out.extend([0x02, 0x40]),
+            Loop => return out.extend([0x03, 0x40]),
+            If(None) => return out.extend([0x04, 0x40]),
+            If(Some(ty)) => return out.extend([0x04, ty.byte()]),
+            Else => 0x05,
+            End => 0x0B,
+            Br(depth) => return op_index(out, 0x0C, depth),
+            BrIf(depth) => return op_index(out, 0x0D, depth),
+            Return => 0x0F,
+            Call(function) => return op_index(out, 0x10, function),
+            CallIndirect(ty) => {
+                op_index(out, 0x11, ty);
+                return out.push(0x00);
+            }
+            Drop => 0x1A,
+            Select => 0x1B,
+            LocalGet(local) => return op_index(out, 0x20, local),
+            LocalSet(local) => return op_index(out, 0x21, local),
+            LocalTee(local) => return op_index(out, 0x22, local),
+            GlobalGet(global) => return op_index(out, 0x23, global),
+            GlobalSet(global) => return op_index(out, 0x24, global),
+            I32Load(offset) => return memory(out, 0x28, 2, offset),
+            I64Load(offset) => return memory(out, 0x29, 3, offset),
+            F64Load(offset) => return memory(out, 0x2B, 3, offset),
+            I32Load8U(offset) => return memory(out, 0x2D, 0, offset),
+            I32Store(offset) => return memory(out, 0x36, 2, offset),
+            I64Store(offset) => return memory(out, 0x37, 3, offset),
+            F64Store(offset) => return memory(out, 0x39, 3, offset),
+            I32Store8(offset) => return memory(out, 0x3A, 0, offset),
+            MemorySize => return out.extend([0x3F, 0x00]),
+            MemoryGrow => return out.extend([0x40, 0x00]),
+            I32Const(value) => {
+                out.push(0x41);
+                return signed(out, value as i64);
+            }
+            I64Const(value) => {
+                out.push(0x42);
+                return signed(out, value);
+            }
+            F64Const(value) => {
+                out.push(0x44);
+                return out.extend(value.to_le_bytes());
+            }
+            I32Eqz => 0x45,
+            I32Eq => 0x46,
+            I32Ne => 0x47,
+            I32LtS => 0x48,
+            I32LtU => 0x49,
+            I32GtS => 0x4A,
+            I32GtU => 0x4B,
+            I32LeS => 0x4C,
+            I32LeU => 0x4D,
+            I32GeS => 0x4E,
+            I32GeU => 0x4F,
+            I64Eqz => 0x50,
+            I64Eq => 0x51,
+            I64Ne => 0x52,
+            I64LtS => 0x53,
+            I64GtS => 0x55,
+            I64LeS => 0x57,
+            I64GeS => 0x59,
+            F64Eq => 0x61,
+            F64Ne => 0x62,
+            F64Lt => 0x63,
+            F64Gt => 0x64,
+            F64Le => 0x65,
+            F64Ge => 0x66,
+            I32Add => 0x6A,
+            I32Sub => 0x6B,
+            I32Mul => 0x6C,
+            I32And => 0x71,
+            I32Or => 0x72,
+            I32Shl => 0x74,
+            I32ShrU => 0x76,
+            I64Add => 0x7C,
+            I64Sub => 0x7D,
+            I64Mul => 0x7E,
+            I64DivS => 0x7F,
+            I64DivU => 0x80,
+            I64RemS => 0x81,
+            I64RemU => 0x82,
+            I64And => 0x83,
+            I64Xor => 0x85,
+            F64Neg => 0x9A,
+            F64Floor => 0x9C,
+            F64Add => 0xA0,
+            F64Sub => 0xA1,
+            F64Mul => 0xA2,
+            F64Div => 0xA3,
+            I32WrapI64 => 0xA7,
+            I64ExtendI32S => 0xAC,
+            I64ExtendI32U => 0xAD,
+            F64ConvertI64S => 0xB9,
+        };
+        out.push(opcode);
+    }
+}
+
+fn op_index(out: &mut Vec<u8>, opcode: u8, index: u32) {
+    out.push(opcode);
+    unsigned(out, index as u64);
+}
+
+/// A function body under construction
+pub struct Function {
+    pub ty: FuncType,
+    locals: Vec<ValType>,
+    code: Vec<Instr>,
+}
+
+impl Function {
+    pub fn new(params: &[ValType], result: Option<ValType>) -> Self {
+        Self {
+            ty: FuncType {
+                params: params.to_vec(),
+                result,
+            },
+            locals: Vec::new(),
+            code: Vec::new(),
+        }
+    }
+
+    /// Declares a new local, returning its index
+    pub fn local(&mut self, ty: ValType) -> u32 {
+        self.locals.push(ty);
+        (self.ty.params.len() + self.locals.len() - 1) as u32
+    }
+
+    pub fn emit(&mut self, code: impl IntoIterator<Item = Instr>) {
+        self.code.extend(code);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut groups: Vec<(u32, ValType)> = Vec::new();
+        for &ty in &self.locals {
+            match groups.last_mut() {
+                Some((count, last)) if *last == ty => *count += 1,
+                _ => groups.push((1, ty)),
+            }
+        }
+
+        let mut body = Vec::new();
+        unsigned(&mut body, groups.len() as u64);
+        for (count, ty) in groups {
+            unsigned(&mut body, count as u64);
+            body.push(ty.byte());
+        }
+        for instr in &self.code {
+            instr.encode(&mut body);
+        }
+        Instr::End.encode(&mut body);
+        body
+    }
+}
+
+pub enum ExportKind {
+    Function,
+    Memory,
+}
+
+/// Everything that goes into a module, in index order
+#[derive(Default)]
+pub struct Module {
+    /// Types given the first indices, so `call_indirect` can refer to them
+    pub types: Vec<FuncType>,
+    /// Imported functions: module, field and type
+    pub imports: Vec<(String, String, FuncType)>,
+    pub functions: Vec<Function>,
+    /// Function indices placed in the table, starting at 0
+    pub table: Vec<u32>,
+    pub memory_pages: u32,
+    /// Mutable `i32` globals with their initial values
+    pub globals: Vec<i32>,
+    pub exports: Vec<(String, ExportKind, u32)>,
+    pub start: Option<u32>,
+    /// Bytes placed at address 0 of memory
+    pub data: Vec<u8>,
+}
+
+impl Module {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut types = self.types.clone();
+        let mut type_index = |ty: &FuncType| match types.iter().position(|known| known == ty) {
+            Some(index) => index as u32,
+            None => {
+                types.push(ty.clone());
+                (types.len() - 1) as u32
+            }
+        };
+        let import_types = self
+            .imports
+            .iter()
+            .map(|(_, _, ty)| type_index(ty))
+            .collect::<Vec<_>>();
+        let function_types = self
+            .functions
+            .iter()
+            .map(|function| type_index(&function.ty))
+            .collect::<Vec<_>>();
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+
+        section(&mut out, 1, types.len(), |s| {
+            for ty in &types {
+                s.push(0x60);
+                unsigned(s, ty.params.len() as u64);
+                s.extend(ty.params.iter().map(|param| param.byte()));
+                match ty.result {
+                    Some(result) => s.extend([1, result.byte()]),
+                    None => s.push(0),
+                }
+            }
+        });
+        section(&mut out, 2, self.imports.len(), |s| {
+            for ((module, field, _), ty) in self.imports.iter().zip(&import_types) {
+                name(s, module);
+                name(s, field);
+                s.push(0x00);
+                unsigned(s, *ty as u64);
+            }
+        });
+        section(&mut out, 3, function_types.len(), |s| {
+            for ty in &function_types {
+                unsigned(s, *ty as u64);
+            }
+        });
+        section(&mut out, 4, 1, |s| {
+            s.extend([0x70, 0x01]);
+            unsigned(s, self.table.len() as u64);
+            unsigned(s, self.table.len() as u64);
+        });
+        section(&mut out, 5, 1, |s| {
+            s.push(0x00);
+            unsigned(s, self.memory_pages as u64);
+        });
+        section(&mut out, 6, self.globals.len(), |s| {
+            for &initial in &self.globals {
+                s.extend([ValType::I32.byte(), 0x01]);
+                Instr::I32Const(initial).encode(s);
+                Instr::End.encode(s);
+            }
+        });
+        section(&mut out, 7, self.exports.len(), |s| {
+            for (export, kind, index) in &self.exports {
+                name(s, export);
+                s.push(match kind {
+                    ExportKind::Function => 0x00,
+                    ExportKind::Memory => 0x02,
+                });
+                unsigned(s, *index as u64);
+            }
+        });
+        if let Some(start) = self.start {
+            let mut s = Vec::new();
+            unsigned(&mut s, start as u64);
+            raw_section(&mut out, 8, &s);
+        }
+        section(&mut out, 9, 1, |s| {
+            s.push(0x00);
+            Instr::I32Const(0).encode(s);
+            Instr::End.encode(s);
+            unsigned(s, self.table.len() as u64);
+            for &function in &self.table {
+                unsigned(s, function as u64);
+            }
+        });
+        section(&mut out, 10, self.functions.len(), |s| {
+            for function in &self.functions {
+                let body = function.encode();
+                unsigned(s, body.len() as u64);
+                s.extend(body);
+            }
+        });
+        section(&mut out, 11, 1, |s| {
+            s.push(0x00);
+            Instr::I32Const(0).encode(s);
+            Instr::End.encode(s);
+            unsigned(s, self.data.len() as u64);
+            s.extend(&self.data);
+        });
+        out
+    }
+}
+
+/// Writes a section holding `count` entries, leaving it out when empty
+fn section(out: &mut Vec<u8>, id: u8, count: usize, entries: impl FnOnce(&mut Vec<u8>)) {
+    if count == 0 {
+        return;
+    }
+    let mut contents = Vec::new();
+    unsigned(&mut contents, count as u64);
+    entries(&mut contents);
+    raw_section(out, id, &contents);
+}
+
+fn raw_section(out: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    out.push(id);
+    unsigned(out, contents.len() as u64);
+    out.extend(contents);
+}
+
+fn name(out: &mut Vec<u8>, name: &str) {
+    unsigned(out, name.len() as u64);
+    out.extend(name.as_bytes());
+}
+
+/// Unsigned LEB128
+fn unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128
+fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
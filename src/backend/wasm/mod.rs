@@ -0,0 +1,505 @@
+//! Ahead-of-time compilation to WebAssembly. The generated module carries
+//! its own small runtime for dynamic values (see `runtime.rs`) and needs
+//! only three functions from its host, all imported from `crabby`:
+//!
+//! - `print(address: i32, len: i32)` writes UTF-8 output
+//! - `error(address: i32, len: i32)` reports a runtime error, after which
+//!   the module traps
+//! - `format_float(value: f64, address: i32) -> i32` writes the float as
+//!   Rust's `{}` formats it to memory (never more than 512 bytes) and
+//!   returns the length
+//!
+//! The top-level code runs as the module's start function. Every top-level
+//! `pub def` is exported under its own name, taking and returning values as
+//! `i32` handles, which hosts build and take apart with the exported
+//! `crabby_*` functions.
+
+mod encode;
+mod runtime;
+
+use crate::ir::{self, BinaryOp, Expr, FormatPart, FunctionDecl, Stmt, UnaryOp};
+use crate::utils::CrabbyError;
+use Instr::*;
+use ValType::{I32, I64};
+use encode::{ExportKind, Function, Instr, ValType};
+use runtime::{Data, Rt, op};
+use std::collections::HashMap;
+
+pub use runtime::{BUILTIN_TAG, FLOAT_TAG, FUNCTION_TAG, INTEGER_TAG, NONE_TAG, STRING_TAG};
+
+/// Runtime functions exported for hosts to work with values
+const API: &[(&str, Rt)] = &[
+    ("crabby_alloc", Rt::Alloc),
+    ("crabby_none", Rt::None),
+    ("crabby_int", Rt::Int),
+    ("crabby_float", Rt::Float),
+    ("crabby_string", Rt::String),
+    ("crabby_tag", Rt::Tag),
+    ("crabby_int_value", Rt::IntValue),
+    ("crabby_float_value", Rt::FloatValue),
+    ("crabby_string_ptr", Rt::StringPtr),
+    ("crabby_string_len", Rt::StringLen),
+];
+
+/// Index of the first generated function, after the imports and runtime
+const FIRST_FUNCTION: u32 = 3 + Rt::ALL.len() as u32;
+
+/// Translates a checked module into a WebAssembly binary
+pub fn emit_wasm(module: &ir::Module) -> Result<Vec<u8>, CrabbyError> {
+    if let Some(name) = super::unbound_name(module, &["print"]) {
+        return Err(CrabbyError::CompileError(format!(
+            "'{}' is not available in programs compiled to WebAssembly",
+            name
+        )));
+    }
+    let exported = exported_functions(module)?;
+
+    let mut generator = Generator::default();
+    let print = generator.symbol("print");
+
+    let mut start = Body::new(Function::new(&[], None), false);
+    let env = start.env;
+    start
+        .f
+        .emit([I32Const(0), Rt::EnvNew.call(), LocalSet(env)]);
+    start
+        .f
+        .emit([LocalGet(env), I32Const(print), I32Const(0), I32Const(print)]);
+    start.f.emit([Rt::Builtin.call(), Rt::Define.call()]);
+    start
+        .f
+        .emit([LocalGet(env), Rt::EnvNew.call(), LocalTee(env)]);
+    start.f.emit([GlobalSet(runtime::GLOBALS)]);
+    for statement in &module.body {
+        generator.statement(&mut start, statement)?;
+    }
+
+    let wrappers = exported
+        .iter()
+        .map(|(name, arity)| generator.wrapper(name, *arity))
+        .collect::<Vec<_>>();
+
+    let mut data = generator.data;
+    let symbols = data.names(generator.symbols.iter().map(String::as_str));
+    let mut functions = runtime::functions(&mut data, symbols);
+    data.align(8);
+    let heap = data.len();
+
+    let generated = generator.functions.len() as u32;
+    let mut table = vec![Rt::Print.index()];
+    table.extend((0..generated).map(|i| FIRST_FUNCTION + i));
+
+    let mut exports = vec![("memory".to_string(), ExportKind::Memory, 0)];
+    for (name, rt) in API {
+        exports.push((name.to_string(), ExportKind::Function, rt.index()));
+    }
+    for (i, (name, _)) in exported.iter().enumerate() {
+        let index = FIRST_FUNCTION + generated + i as u32;
+        exports.push((name.to_string(), ExportKind::Function, index));
+    }
+
+    functions.extend(generator.functions.into_iter().flatten());
+    functions.extend(wrappers);
+    functions.push(start.f);
+    let start_index = 3 + functions.len() as u32 - 1;
+
+    let module = encode::Module {
+        types: vec![runtime::closure_type()],
+        imports: runtime::imports(),
+        functions,
+        table,
+        memory_pages: (heap / 65536 + 1) as u32,
+        globals: vec![heap as i32, 0],
+        exports,
+        start: Some(start_index),
+        data: data.into_bytes(),
+    };
+    Ok(module.encode())
+}
+
+/// The top-level `pub def`s with their arity, checked against the names the
+/// module exports itself
+fn exported_functions(module: &ir::Module) -> Result<Vec<(&str, usize)>, CrabbyError> {
+    let mut exported: Vec<(&str, usize)> = Vec::new();
+    for statement in &module.body {
+        let Stmt::Function(decl) = statement else {
+            continue;
+        };
+        if !decl.public {
+            continue;
+        }
+        if decl.name == "memory" || API.iter().any(|(name, _)| *name == decl.name) {
+            return Err(CrabbyError::CompileError(format!(
+                "'{}' is reserved in programs compiled to WebAssembly",
+                decl.name
+            )));
+        }
+        // A redefinition is exported with the signature it ends up with
+        match exported.iter_mut().find(|(name, _)| *name == decl.name) {
+            Some(entry) => entry.1 = decl.params.len(),
+            None => exported.push((&decl.name, decl.params.len())),
+        }
+    }
+    Ok(exported)
+}
+
+/// A function being generated, with the locals holding its environment and
+/// the value of the statement run last
+struct Body {
+    f: Function,
+    env: u32,
+    last: u32,
+    /// False in the start function, which has no result to return
+    returns: bool,
+}
+
+impl Body {
+    fn new(mut f: Function, returns: bool) -> Self {
+        let env = f.local(I32);
+        let last = f.local(I32);
+        f.emit([I32Const(runtime::NONE), LocalSet(last)]);
+        Self {
+            f,
+            env,
+            last,
+            returns,
+        }
+    }
+
+    fn set_last_none(&mut self) {
+        self.f.emit([I32Const(runtime::NONE), LocalSet(self.last)]);
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    data: Data,
+    symbols: Vec<String>,
+    symbol_ids: HashMap<String, i32>,
+    /// Functions generated so far; slots are reserved before their bodies
+    /// are generated so nested functions come after
+    functions: Vec<Option<Function>>,
+}
+
+impl Generator {
+    fn symbol(&mut self, name: &str) -> i32 {
+        if let Some(&id) = self.symbol_ids.get(name) {
+            return id;
+        }
+        self.symbols.push(name.to_string());
+        let id = self.symbols.len() as i32 - 1;
+        self.symbol_ids.insert(name.to_string(), id);
+        id
+    }
+
+    /// Generates a Crabby function, returning its table slot
+    fn function(&mut self, decl: &FunctionDecl) -> Result<i32, CrabbyError> {
+        let index = self.functions.len();
+        self.functions.push(None);
+
+        let mut body = Body::new(Function::new(&[I32, I32, I32], Some(I32)), true);
+        let env = body.env;
+        body.f
+            .emit([LocalGet(0), I32Load(12), I32Const(decl.params.len() as i32)]);
+        body.f.emit([LocalGet(1), Rt::CheckArity.call()]);
+        body.f
+            .emit([LocalGet(0), I32Load(8), Rt::EnvNew.call(), LocalSet(env)]);
+        for (i, param) in decl.params.iter().enumerate() {
+            let symbol = self.symbol(param);
+            body.f.emit([LocalGet(env), I32Const(symbol), LocalGet(2)]);
+            body.f.emit([I32Load(4 * i as u32), Rt::Define.call()]);
+        }
+        for statement in &decl.body {
+            self.statement(&mut body, statement)?;
+        }
+        body.f.emit([LocalGet(body.last)]);
+
+        self.functions[index] = Some(body.f);
+        // Slot 0 holds `print`
+        Ok(index as i32 + 1)
+    }
+
+    /// An export calling the global `name` with `arity` value handles
+    fn wrapper(&mut self, name: &str, arity: usize) -> Function {
+        let mut f = Function::new(&vec![I32; arity], Some(I32));
+        let symbol = self.symbol(name);
+        let args = f.local(I32);
+        if arity > 0 {
+            f.emit([I32Const(4 * arity as i32), Rt::Alloc.call(), LocalSet(args)]);
+        }
+        for i in 0..arity {
+            f.emit([LocalGet(args), LocalGet(i as u32), I32Store(4 * i as u32)]);
+        }
+        f.emit([
+            GlobalGet(runtime::GLOBALS),
+            I32Const(symbol),
+            Rt::Lookup.call(),
+        ]);
+        f.emit([I32Const(arity as i32), LocalGet(args), Rt::Call.call()]);
+        f
+    }
+
+    fn block(&mut self, body: &mut Body, statements: &[Stmt]) -> Result<(), CrabbyError> {
+        for statement in statements {
+            self.statement(body, statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, body: &mut Body, statement: &Stmt) -> Result<(), CrabbyError> {
+        let env = body.env;
+        match statement {
+            Stmt::Let { name, value, .. } => {
+                let symbol = self.symbol(name);
+                body.f.emit([LocalGet(env), I32Const(symbol)]);
+                self.expression(body, value)?;
+                body.f.emit([Rt::Define.call()]);
+                body.set_last_none();
+            }
+            Stmt::Function(decl) => {
+                let slot = self.function(decl)?;
+                let symbol = self.symbol(&decl.name);
+                body.f.emit([
+                    LocalGet(env),
+                    I32Const(symbol),
+                    I32Const(slot),
+                    LocalGet(env),
+                ]);
+                body.f
+                    .emit([I32Const(symbol), Rt::Closure.call(), Rt::Define.call()]);
+                body.set_last_none();
+            }
+            Stmt::Return(expr) => {
+                self.expression(body, expr)?;
+                if !body.returns {
+                    body.f.emit([Drop]);
+                }
+                body.f.emit([Return]);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(body, condition)?;
+                body.f.emit([Rt::Truthy.call()]);
+                body.set_last_none();
+                body.f.emit([If(None)]);
+                self.block(body, then_branch)?;
+                body.f.emit([Else]);
+                self.block(body, else_branch)?;
+                body.f.emit([End]);
+            }
+            Stmt::While {
+                condition,
+                body: statements,
+            } => {
+                body.f.emit([Block, Loop]);
+                self.expression(body, condition)?;
+                body.f.emit([Rt::Truthy.call(), I32Eqz, BrIf(1)]);
+                self.block(body, statements)?;
+                body.f.emit([Br(0), End, End]);
+                body.set_last_none();
+            }
+            Stmt::Loop {
+                count,
+                body: statements,
+            } => {
+                self.expression(body, count)?;
+                body.f.emit([Rt::LoopCount.call()]);
+                self.counted(body, None, statements)?;
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body: statements,
+            } => {
+                self.expression(body, iterable)?;
+                body.f.emit([Rt::IterCount.call()]);
+                let symbol = self.symbol(variable);
+                self.counted(body, Some(symbol), statements)?;
+            }
+            Stmt::Import { .. } => {
+                return Err(CrabbyError::CompileError(
+                    "Imports are not supported in programs compiled to WebAssembly yet".to_string(),
+                ));
+            }
+            Stmt::Expr(expr) => {
+                self.expression(body, expr)?;
+                body.f.emit([LocalSet(body.last)]);
+            }
+        }
+        Ok(())
+    }
+
+    /// A loop running as many times as the `i64` on the stack says, binding
+    /// the iteration number to `variable` when there is one
+    fn counted(
+        &mut self,
+        body: &mut Body,
+        variable: Option<i32>,
+        statements: &[Stmt],
+    ) -> Result<(), CrabbyError> {
+        let (count, i) = (body.f.local(I64), body.f.local(I64));
+        body.f
+            .emit([LocalSet(count), I64Const(0), LocalSet(i), Block, Loop]);
+        body.f.emit([LocalGet(i), LocalGet(count), I64GeS, BrIf(1)]);
+        if let Some(symbol) = variable {
+            body.f
+                .emit([LocalGet(body.env), I32Const(symbol), LocalGet(i)]);
+            body.f.emit([Rt::Int.call(), Rt::Define.call()]);
+        }
+        self.block(body, statements)?;
+        body.f.emit([
+            LocalGet(i),
+            I64Const(1),
+            I64Add,
+            LocalSet(i),
+            Br(0),
+            End,
+            End,
+        ]);
+        body.set_last_none();
+        Ok(())
+    }
+
+    /// Emits code leaving the value of `expression` on the stack
+    fn expression(&mut self, body: &mut Body, expression: &Expr) -> Result<(), CrabbyError> {
+        match expression {
+            Expr::Integer(n) => body.f.emit([I32Const(self.data.integer(*n))]),
+            Expr::Float(x) => body.f.emit([I32Const(self.data.float(*x))]),
+            Expr::String(s) => body.f.emit([I32Const(self.data.string(s))]),
+            Expr::Variable(name) => {
+                let symbol = self.symbol(name);
+                body.f
+                    .emit([LocalGet(body.env), I32Const(symbol), Rt::Lookup.call()]);
+            }
+            Expr::Range(count) => {
+                self.expression(body, count)?;
+                body.f.emit([Rt::Range.call()]);
+            }
+            Expr::Unary { op, operand } => {
+                self.expression(body, operand)?;
+                body.f.emit([match op {
+                    UnaryOp::Neg => Rt::Negate.call(),
+                    UnaryOp::Not => Rt::Not.call(),
+                }]);
+            }
+            Expr::Binary {
+                left,
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => {
+                self.expression(body, left)?;
+                body.f.emit([Rt::Truthy.call(), If(Some(I32))]);
+                if *op == BinaryOp::Or {
+                    body.f.emit([I32Const(runtime::TRUE), Else]);
+                }
+                self.expression(body, right)?;
+                body.f.emit([Rt::Truthy.call(), Rt::Bool.call()]);
+                if *op == BinaryOp::And {
+                    body.f.emit([Else, I32Const(runtime::FALSE)]);
+                }
+                body.f.emit([End]);
+            }
+            Expr::Binary { left, op, right } => {
+                body.f.emit([I32Const(binary_op(*op))]);
+                self.expression(body, left)?;
+                self.expression(body, right)?;
+                body.f.emit([Rt::Binary.call()]);
+            }
+            Expr::Call { callee, args } => {
+                let symbol = self.symbol(callee);
+                body.f
+                    .emit([LocalGet(body.env), I32Const(symbol), Rt::Lookup.call()]);
+                self.call(body, args)?;
+            }
+            Expr::CallValue { callee, args } => {
+                self.expression(body, callee)?;
+                self.call(body, args)?;
+            }
+            Expr::List(_) => return Err(unsupported("Lists are")),
+            Expr::Dict(_) => return Err(unsupported("Dicts are")),
+            Expr::Keywords(_) => return Err(unsupported("Keyword arguments are")),
+            Expr::Index { .. } => return Err(unsupported("Indexing is")),
+            Expr::Member { .. } => return Err(unsupported("Member access is")),
+            Expr::Lambda(decl) => {
+                let slot = self.function(decl)?;
+                body.f
+                    .emit([I32Const(slot), LocalGet(body.env), I32Const(-1)]);
+                body.f.emit([Rt::Closure.call()]);
+            }
+            Expr::Format(parts) => {
+                let buf = body.f.local(I32);
+                body.f.emit([Rt::BufNew.call(), LocalSet(buf)]);
+                for part in parts {
+                    match part {
+                        FormatPart::Literal(text) => {
+                            body.f.emit(runtime::push(buf, self.data.text(text)));
+                        }
+                        FormatPart::Value { expr, spec: None } => {
+                            body.f.emit([LocalGet(buf)]);
+                            self.expression(body, expr)?;
+                            body.f.emit([Rt::WriteValue.call()]);
+                        }
+                        FormatPart::Value { spec: Some(_), .. } => {
+                            return Err(CrabbyError::CompileError(
+                                "Format specs are not supported in programs compiled to WebAssembly yet"
+                                    .to_string(),
+                            ));
+                        }
+                    }
+                }
+                body.f.emit([LocalGet(buf), Rt::BufFinish.call()]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the value on top of the stack with `args`
+    fn call(&mut self, body: &mut Body, args: &[Expr]) -> Result<(), CrabbyError> {
+        let (function, argv) = (body.f.local(I32), body.f.local(I32));
+        body.f.emit([LocalSet(function)]);
+        if !args.is_empty() {
+            body.f
+                .emit([I32Const(4 * args.len() as i32), Rt::Alloc.call()]);
+            body.f.emit([LocalSet(argv)]);
+        }
+        for (i, arg) in args.iter().enumerate() {
+            body.f.emit([LocalGet(argv)]);
+            self.expression(body, arg)?;
+            body.f.emit([I32Store(4 * i as u32)]);
+        }
+        body.f
+            .emit([LocalGet(function), I32Const(args.len() as i32)]);
+        body.f.emit([LocalGet(argv), Rt::Call.call()]);
+        Ok(())
+    }
+}
+
+fn unsupported(what: &str) -> CrabbyError {
+    CrabbyError::CompileError(format!(
+        "{} not supported in programs compiled to WebAssembly yet",
+        what
+    ))
+}
+
+fn binary_op(op: BinaryOp) -> i32 {
+    match op {
+        BinaryOp::Add => op::ADD,
+        BinaryOp::Sub => op::SUB,
+        BinaryOp::Mul => op::MUL,
+        BinaryOp::Div => op::DIV,
+        BinaryOp::FloorDiv => op::FLOOR_DIV,
+        BinaryOp::Mod => op::MOD,
+        BinaryOp::Eq => op::EQ,
+        BinaryOp::Ne => op::NE,
+        BinaryOp::Lt => op::LT,
+        BinaryOp::Gt => op::GT,
+        BinaryOp::Le => op::LE,
+        BinaryOp::Ge => op::GE,
+        BinaryOp::Dot => op::DOT,
+        // Short-circuited before reaching here
+        BinaryOp::And | BinaryOp::Or => op::EQ,
+    }
+}
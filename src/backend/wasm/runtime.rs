@@ -0,0 +1,1152 @@
+//! The runtime every generated module carries, written directly in Wasm.
+//!
+//! Values are pointers to 16-byte boxes in linear memory: a tag at offset 0
+//! and the payload after it. Integers and floats keep their number at
+//! offset 8; strings their length at 4 and a pointer to their bytes at 8;
+//! functions their table slot at 4, their environment at 8 and their name's
+//! symbol (-1 for lambdas) at 12. Values are immutable, so literals and
+//! booleans are boxes in the data segment shared by everyone. Memory comes
+//! from a bump allocator and is never freed, which suits the short-lived
+//! calls the module is meant for.
+
+use super::encode::{FuncType, Function, Instr, ValType};
+use Instr::*;
+use ValType::{F64, I32, I64};
+
+pub const NONE_TAG: i32 = 0;
+pub const INTEGER_TAG: i32 = 1;
+pub const FLOAT_TAG: i32 = 2;
+pub const STRING_TAG: i32 = 3;
+pub const FUNCTION_TAG: i32 = 4;
+pub const BUILTIN_TAG: i32 = 5;
+
+/// The shared `none` box
+pub const NONE: i32 = 16;
+pub const FALSE: i32 = 32;
+pub const TRUE: i32 = 48;
+/// Where numbers are formatted before being copied into a string
+const SCRATCH: i32 = 64;
+const SCRATCH_END: i32 = SCRATCH + 512;
+
+/// The bump allocator's next free address
+pub const HEAP: u32 = 0;
+/// The environment of the module's globals
+pub const GLOBALS: u32 = 1;
+
+/// Functions imported from the host, under the module name `crabby`
+pub const PRINT: u32 = 0;
+const ERROR: u32 = 1;
+const FORMAT_FLOAT: u32 = 2;
+
+/// The signature of every Crabby function: the closure being called, the
+/// argument count and a pointer to the arguments
+pub fn closure_type() -> FuncType {
+    FuncType {
+        params: vec![I32, I32, I32],
+        result: Some(I32),
+    }
+}
+
+pub fn imports() -> Vec<(String, String, FuncType)> {
+    let import = |name: &str, params: &[ValType], result| {
+        let ty = FuncType {
+            params: params.to_vec(),
+            result,
+        };
+        ("crabby".to_string(), name.to_string(), ty)
+    };
+    vec![
+        // Writes UTF-8 output
+        import("print", &[I32, I32], None),
+        // Reports a runtime error; the module traps right after
+        import("error", &[I32, I32], None),
+        // Writes a float as Rust's `{}` would into memory, returning its length
+        import("format_float", &[F64, I32], Some(I32)),
+    ]
+}
+
+/// Binary operators, numbered as `Rt::Binary` expects them
+pub mod op {
+    pub const ADD: i32 = 0;
+    pub const SUB: i32 = 1;
+    pub const MUL: i32 = 2;
+    pub const DIV: i32 = 3;
+    pub const EQ: i32 = 4;
+    pub const NE: i32 = 5;
+    pub const LT: i32 = 6;
+    pub const GT: i32 = 7;
+    pub const LE: i32 = 8;
+    pub const GE: i32 = 9;
+    pub const DOT: i32 = 10;
+    pub const FLOOR_DIV: i32 = 11;
+    pub const MOD: i32 = 12;
+}
+
+/// Runtime functions, in the order they follow the imports
+#[derive(Debug, Clone, Copy)]
+pub enum Rt {
+    Alloc,
+    Copy,
+    FailStatic,
+    Fail,
+    BufNew,
+    BufPush,
+    BufFinish,
+    WriteSymbol,
+    WriteTypeName,
+    WriteInt,
+    WriteFloat,
+    WriteValue,
+    None,
+    Int,
+    Float,
+    String,
+    Closure,
+    Builtin,
+    Bool,
+    EnvNew,
+    Define,
+    Lookup,
+    Truthy,
+    Negate,
+    Not,
+    IsNumber,
+    AsFloat,
+    Binary,
+    IntBinary,
+    FloatBinary,
+    StringBinary,
+    Compare,
+    Range,
+    LoopCount,
+    IterCount,
+    CheckArity,
+    Call,
+    Print,
+    Tag,
+    IntValue,
+    FloatValue,
+    StringPtr,
+    StringLen,
+}
+
+impl Rt {
+    pub const ALL: [Rt; 43] = [
+        Rt::Alloc,
+        Rt::Copy,
+        Rt::FailStatic,
+        Rt::Fail,
+        Rt::BufNew,
+        Rt::BufPush,
+        Rt::BufFinish,
+        Rt::WriteSymbol,
+        Rt::WriteTypeName,
+        Rt::WriteInt,
+        Rt::WriteFloat,
+        Rt::WriteValue,
+        Rt::None,
+        Rt::Int,
+        Rt::Float,
+        Rt::String,
+        Rt::Closure,
+        Rt::Builtin,
+        Rt::Bool,
+        Rt::EnvNew,
+        Rt::Define,
+        Rt::Lookup,
+        Rt::Truthy,
+        Rt::Negate,
+        Rt::Not,
+        Rt::IsNumber,
+        Rt::AsFloat,
+        Rt::Binary,
+        Rt::IntBinary,
+        Rt::FloatBinary,
+        Rt::StringBinary,
+        Rt::Compare,
+        Rt::Range,
+        Rt::LoopCount,
+        Rt::IterCount,
+        Rt::CheckArity,
+        Rt::Call,
+        Rt::Print,
+        Rt::Tag,
+        Rt::IntValue,
+        Rt::FloatValue,
+        Rt::StringPtr,
+        Rt::StringLen,
+    ];
+
+    pub fn index(self) -> u32 {
+        3 + self as u32
+    }
+
+    pub fn call(self) -> Instr {
+        Call(self.index())
+    }
+}
+
+/// The data segment, starting at address 0 with the fixed boxes and scratch
+/// space the runtime uses
+pub struct Data {
+    bytes: Vec<u8>,
+}
+
+impl Data {
+    pub fn new() -> Self {
+        let mut bytes = vec![0; SCRATCH_END as usize];
+        for (address, value) in [(FALSE, 0u8), (TRUE, 1)] {
+            bytes[address as usize] = INTEGER_TAG as u8;
+            bytes[address as usize + 8] = value;
+        }
+        Self { bytes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn align(&mut self, alignment: usize) {
+        while !self.bytes.len().is_multiple_of(alignment) {
+            self.bytes.push(0);
+        }
+    }
+
+    /// Places raw bytes, returning their address and length
+    pub fn text(&mut self, text: &str) -> (i32, i32) {
+        let address = self.bytes.len() as i32;
+        self.bytes.extend(text.as_bytes());
+        (address, text.len() as i32)
+    }
+
+    fn boxed(&mut self, tag: i32, payload: [u8; 12]) -> i32 {
+        self.align(8);
+        let address = self.bytes.len() as i32;
+        self.bytes.extend(tag.to_le_bytes());
+        self.bytes.extend(payload);
+        address
+    }
+
+    pub fn integer(&mut self, value: i64) -> i32 {
+        let mut payload = [0; 12];
+        payload[4..].copy_from_slice(&value.to_le_bytes());
+        self.boxed(INTEGER_TAG, payload)
+    }
+
+    pub fn float(&mut self, value: f64) -> i32 {
+        let mut payload = [0; 12];
+        payload[4..].copy_from_slice(&value.to_le_bytes());
+        self.boxed(FLOAT_TAG, payload)
+    }
+
+    pub fn string(&mut self, value: &str) -> i32 {
+        let (address, len) = self.text(value);
+        let mut payload = [0; 12];
+        payload[..4].copy_from_slice(&len.to_le_bytes());
+        payload[4..8].copy_from_slice(&address.to_le_bytes());
+        self.boxed(STRING_TAG, payload)
+    }
+
+    /// A table of (address, length) pairs for `names`, returning its address
+    pub fn names<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) -> i32 {
+        let entries = names
+            .into_iter()
+            .map(|name| self.text(name))
+            .collect::<Vec<_>>();
+        self.align(4);
+        let address = self.bytes.len() as i32;
+        for (name, len) in entries {
+            self.bytes.extend(name.to_le_bytes());
+            self.bytes.extend(len.to_le_bytes());
+        }
+        address
+    }
+}
+
+impl Default for Data {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Appends static text to the buffer in `buf`
+pub fn push(buf: u32, (address, len): (i32, i32)) -> [Instr; 4] {
+    [
+        LocalGet(buf),
+        I32Const(address),
+        I32Const(len),
+        Rt::BufPush.call(),
+    ]
+}
+
+/// Fails with a fixed message
+fn fail(data: &mut Data, message: &str) -> [Instr; 3] {
+    let (address, len) = data.text(message);
+    [I32Const(address), I32Const(len), Rt::FailStatic.call()]
+}
+
+/// Builds every runtime function; `symbols` is the address of the symbol
+/// name table
+pub fn functions(data: &mut Data, symbols: i32) -> Vec<Function> {
+    let type_names = data.names(["none", "integer", "float", "string", "function", "function"]);
+    Rt::ALL
+        .iter()
+        .map(|&rt| function(rt, data, symbols, type_names))
+        .collect()
+}
+
+fn function(rt: Rt, data: &mut Data, symbols: i32, type_names: i32) -> Function {
+    let tag_is = |value: u32, tag: i32| [LocalGet(value), I32Load(0), I32Const(tag), I32Eq];
+    let op_is = |tag: i32| [LocalGet(0), I32Const(tag), I32Eq];
+
+    let mut f;
+    match rt {
+        Rt::Alloc => {
+            f = Function::new(&[I32], Some(I32));
+            let address = f.local(I32);
+            let memory_end = [MemorySize, I32Const(16), I32Shl];
+            f.emit([GlobalGet(HEAP), I32Const(7), I32Add, I32Const(-8), I32And]);
+            f.emit([LocalTee(address), LocalGet(0), I32Add, GlobalSet(HEAP)]);
+            f.emit([Block, GlobalGet(HEAP)]);
+            f.emit(memory_end);
+            f.emit([I32LeU, BrIf(0), GlobalGet(HEAP)]);
+            f.emit(memory_end);
+            f.emit([I32Sub, I32Const(0xFFFF), I32Add, I32Const(16), I32ShrU]);
+            f.emit([MemoryGrow, I32Const(-1), I32Ne, BrIf(0)]);
+            f.emit(fail(data, "Out of memory"));
+            f.emit([End, LocalGet(address)]);
+        }
+        Rt::Copy => {
+            // (destination, source, length)
+            f = Function::new(&[I32, I32, I32], None);
+            f.emit([Block, Loop, LocalGet(2), I32Eqz, BrIf(1)]);
+            f.emit([LocalGet(0), LocalGet(1), I32Load8U(0), I32Store8(0)]);
+            for local in [0, 1] {
+                f.emit([LocalGet(local), I32Const(1), I32Add, LocalSet(local)]);
+            }
+            f.emit([
+                LocalGet(2),
+                I32Const(1),
+                I32Sub,
+                LocalSet(2),
+                Br(0),
+                End,
+                End,
+            ]);
+        }
+        Rt::FailStatic => {
+            f = Function::new(&[I32, I32], None);
+            f.emit([LocalGet(0), LocalGet(1), Call(ERROR), Unreachable]);
+        }
+        Rt::Fail => {
+            // Fails with the text in a buffer
+            f = Function::new(&[I32], None);
+            f.emit([LocalGet(0), I32Load(0), LocalGet(0), I32Load(4)]);
+            f.emit([Rt::FailStatic.call()]);
+        }
+        Rt::BufNew => {
+            // A growable buffer: bytes at 0, length at 4, capacity at 8.
+            // Fresh memory is always zeroed.
+            f = Function::new(&[], Some(I32));
+            f.emit([I32Const(12), Rt::Alloc.call()]);
+        }
+        Rt::BufPush => {
+            // (buffer, address, length)
+            f = Function::new(&[I32, I32, I32], None);
+            let (needed, capacity, grown) = (f.local(I32), f.local(I32), f.local(I32));
+            f.emit([
+                LocalGet(0),
+                I32Load(4),
+                LocalGet(2),
+                I32Add,
+                LocalSet(needed),
+            ]);
+            f.emit([
+                Block,
+                LocalGet(needed),
+                LocalGet(0),
+                I32Load(8),
+                I32LeU,
+                BrIf(0),
+            ]);
+            f.emit([
+                LocalGet(0),
+                I32Load(8),
+                I32Const(2),
+                I32Mul,
+                LocalGet(needed),
+                I32Add,
+            ]);
+            f.emit([I32Const(16), I32Add, LocalTee(capacity), Rt::Alloc.call()]);
+            f.emit([LocalSet(grown), LocalGet(grown), LocalGet(0), I32Load(0)]);
+            f.emit([LocalGet(0), I32Load(4), Rt::Copy.call()]);
+            f.emit([LocalGet(0), LocalGet(grown), I32Store(0)]);
+            f.emit([LocalGet(0), LocalGet(capacity), I32Store(8), End]);
+            f.emit([LocalGet(0), I32Load(0), LocalGet(0), I32Load(4), I32Add]);
+            f.emit([LocalGet(1), LocalGet(2), Rt::Copy.call()]);
+            f.emit([LocalGet(0), LocalGet(needed), I32Store(4)]);
+        }
+        Rt::BufFinish => {
+            f = Function::new(&[I32], Some(I32));
+            f.emit([
+                LocalGet(0),
+                I32Load(0),
+                LocalGet(0),
+                I32Load(4),
+                Rt::String.call(),
+            ]);
+        }
+        Rt::WriteSymbol | Rt::WriteTypeName => {
+            // (buffer, symbol) or (buffer, value)
+            f = Function::new(&[I32, I32], None);
+            let (table, index) = match rt {
+                Rt::WriteSymbol => (symbols, vec![LocalGet(1)]),
+                _ => (type_names, vec![LocalGet(1), I32Load(0)]),
+            };
+            f.emit([LocalGet(0)]);
+            for offset in [0, 4] {
+                f.emit(index.iter().copied());
+                f.emit([
+                    I32Const(8),
+                    I32Mul,
+                    I32Const(table),
+                    I32Add,
+                    I32Load(offset),
+                ]);
+            }
+            f.emit([Rt::BufPush.call()]);
+        }
+        Rt::WriteInt => {
+            // Digits are written backwards from the end of the scratch space
+            f = Function::new(&[I32, I64], None);
+            let (cursor, magnitude) = (f.local(I32), f.local(I64));
+            f.emit([LocalGet(1), I64Const(0), I64LtS, If(Some(I64))]);
+            f.emit([I64Const(0), LocalGet(1), I64Sub, Else, LocalGet(1), End]);
+            f.emit([LocalSet(magnitude), I32Const(SCRATCH_END), LocalSet(cursor)]);
+            f.emit([
+                Loop,
+                LocalGet(cursor),
+                I32Const(1),
+                I32Sub,
+                LocalTee(cursor),
+            ]);
+            f.emit([LocalGet(magnitude), I64Const(10), I64RemU, I32WrapI64]);
+            f.emit([I32Const(b'0' as i32), I32Add, I32Store8(0)]);
+            f.emit([
+                LocalGet(magnitude),
+                I64Const(10),
+                I64DivU,
+                LocalTee(magnitude),
+            ]);
+            f.emit([I64Eqz, I32Eqz, BrIf(0), End]);
+            f.emit([LocalGet(1), I64Const(0), I64LtS, If(None)]);
+            f.emit([LocalGet(cursor), I32Const(1), I32Sub, LocalTee(cursor)]);
+            f.emit([I32Const(b'-' as i32), I32Store8(0), End]);
+            f.emit([
+                LocalGet(0),
+                LocalGet(cursor),
+                I32Const(SCRATCH_END),
+                LocalGet(cursor),
+            ]);
+            f.emit([I32Sub, Rt::BufPush.call()]);
+        }
+        Rt::WriteFloat => {
+            f = Function::new(&[I32, F64], None);
+            f.emit([
+                LocalGet(0),
+                I32Const(SCRATCH),
+                LocalGet(1),
+                I32Const(SCRATCH),
+            ]);
+            f.emit([Call(FORMAT_FLOAT), Rt::BufPush.call()]);
+        }
+        Rt::WriteValue => {
+            // (buffer, value): appends the value as `print` shows it
+            f = Function::new(&[I32, I32], None);
+            f.emit(tag_is(1, NONE_TAG));
+            f.emit([If(None)]);
+            f.emit(push(0, data.text("none")));
+            f.emit([Return, End]);
+            f.emit(tag_is(1, INTEGER_TAG));
+            f.emit([
+                If(None),
+                LocalGet(0),
+                LocalGet(1),
+                I64Load(8),
+                Rt::WriteInt.call(),
+            ]);
+            f.emit([Return, End]);
+            f.emit(tag_is(1, FLOAT_TAG));
+            f.emit([
+                If(None),
+                LocalGet(0),
+                LocalGet(1),
+                F64Load(8),
+                Rt::WriteFloat.call(),
+            ]);
+            f.emit([Return, End]);
+            f.emit(tag_is(1, STRING_TAG));
+            f.emit([
+                If(None),
+                LocalGet(0),
+                LocalGet(1),
+                I32Load(8),
+                LocalGet(1),
+                I32Load(4),
+            ]);
+            f.emit([Rt::BufPush.call(), Return, End]);
+            f.emit(tag_is(1, FUNCTION_TAG));
+            f.emit([
+                If(None),
+                LocalGet(1),
+                I32Load(12),
+                I32Const(-1),
+                I32Eq,
+                If(None),
+            ]);
+            f.emit(push(0, data.text("<lambda>")));
+            f.emit([Return, End]);
+            f.emit(push(0, data.text("<function ")));
+            f.emit([Else]);
+            f.emit(push(0, data.text("<builtin ")));
+            f.emit([
+                End,
+                LocalGet(0),
+                LocalGet(1),
+                I32Load(12),
+                Rt::WriteSymbol.call(),
+            ]);
+            f.emit(push(0, data.text(">")));
+        }
+        Rt::None => {
+            f = Function::new(&[], Some(I32));
+            f.emit([I32Const(NONE)]);
+        }
+        Rt::Int | Rt::Float => {
+            let (ty, tag, store) = match rt {
+                Rt::Int => (I64, INTEGER_TAG, I64Store(8)),
+                _ => (F64, FLOAT_TAG, F64Store(8)),
+            };
+            f = Function::new(&[ty], Some(I32));
+            let value = f.local(I32);
+            f.emit([
+                I32Const(16),
+                Rt::Alloc.call(),
+                LocalTee(value),
+                I32Const(tag),
+            ]);
+            f.emit([
+                I32Store(0),
+                LocalGet(value),
+                LocalGet(0),
+                store,
+                LocalGet(value),
+            ]);
+        }
+        Rt::String | Rt::Closure | Rt::Builtin => {
+            // String: (address, length). Closure: (slot, environment, name).
+            // Builtin: (slot, name).
+            let (tag, offsets): (i32, &[u32]) = match rt {
+                Rt::String => (STRING_TAG, &[8, 4]),
+                Rt::Closure => (FUNCTION_TAG, &[4, 8, 12]),
+                _ => (BUILTIN_TAG, &[4, 12]),
+            };
+            f = Function::new(&[I32, I32, I32][..offsets.len()], Some(I32));
+            let value = f.local(I32);
+            f.emit([
+                I32Const(16),
+                Rt::Alloc.call(),
+                LocalTee(value),
+                I32Const(tag),
+            ]);
+            f.emit([I32Store(0)]);
+            for (param, &offset) in offsets.iter().enumerate() {
+                f.emit([LocalGet(value), LocalGet(param as u32), I32Store(offset)]);
+            }
+            f.emit([LocalGet(value)]);
+        }
+        Rt::Bool => {
+            f = Function::new(&[I32], Some(I32));
+            f.emit([I32Const(TRUE), I32Const(FALSE), LocalGet(0), Select]);
+        }
+        Rt::EnvNew => {
+            // An environment: parent at 0, bindings at 4, their count at 8
+            // and capacity at 12. A binding is a symbol and a value.
+            f = Function::new(&[I32], Some(I32));
+            let env = f.local(I32);
+            f.emit([I32Const(16), Rt::Alloc.call(), LocalTee(env), LocalGet(0)]);
+            f.emit([I32Store(0), LocalGet(env)]);
+        }
+        Rt::Define => {
+            // (environment, symbol, value)
+            f = Function::new(&[I32, I32, I32], None);
+            let (i, bindings, len, capacity, grown) = (
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+                f.local(I32),
+            );
+            let binding = [LocalGet(bindings), LocalGet(i), I32Const(8), I32Mul, I32Add];
+            f.emit([LocalGet(0), I32Load(4), LocalSet(bindings)]);
+            f.emit([LocalGet(0), I32Load(8), LocalSet(len)]);
+            f.emit([Block, Loop, LocalGet(i), LocalGet(len), I32GeU, BrIf(1)]);
+            f.emit(binding);
+            f.emit([I32Load(0), LocalGet(1), I32Eq, If(None)]);
+            f.emit(binding);
+            f.emit([LocalGet(2), I32Store(4), Return, End]);
+            f.emit([
+                LocalGet(i),
+                I32Const(1),
+                I32Add,
+                LocalSet(i),
+                Br(0),
+                End,
+                End,
+            ]);
+            f.emit([LocalGet(len), LocalGet(0), I32Load(12), I32Eq, If(None)]);
+            f.emit([
+                LocalGet(0),
+                I32Load(12),
+                I32Const(2),
+                I32Mul,
+                I32Const(4),
+                I32Add,
+            ]);
+            f.emit([LocalTee(capacity), I32Const(8), I32Mul, Rt::Alloc.call()]);
+            f.emit([
+                LocalSet(grown),
+                LocalGet(grown),
+                LocalGet(bindings),
+                LocalGet(len),
+            ]);
+            f.emit([I32Const(8), I32Mul, Rt::Copy.call()]);
+            f.emit([LocalGet(0), LocalGet(grown), I32Store(4)]);
+            f.emit([LocalGet(0), LocalGet(capacity), I32Store(12)]);
+            f.emit([LocalGet(grown), LocalSet(bindings), End]);
+            f.emit(binding);
+            f.emit([LocalGet(1), I32Store(0)]);
+            f.emit(binding);
+            f.emit([LocalGet(2), I32Store(4)]);
+            f.emit([LocalGet(0), LocalGet(len), I32Const(1), I32Add, I32Store(8)]);
+        }
+        Rt::Lookup => {
+            // (environment, symbol), walking out through the parents
+            f = Function::new(&[I32, I32], Some(I32));
+            let (i, buf) = (f.local(I32), f.local(I32));
+            let binding = [
+                LocalGet(0),
+                I32Load(4),
+                LocalGet(i),
+                I32Const(8),
+                I32Mul,
+                I32Add,
+            ];
+            f.emit([Block, Loop, LocalGet(0), I32Eqz, BrIf(1)]);
+            f.emit([I32Const(0), LocalSet(i)]);
+            f.emit([
+                Block,
+                Loop,
+                LocalGet(i),
+                LocalGet(0),
+                I32Load(8),
+                I32GeU,
+                BrIf(1),
+            ]);
+            f.emit(binding);
+            f.emit([I32Load(0), LocalGet(1), I32Eq, If(None)]);
+            f.emit(binding);
+            f.emit([I32Load(4), Return, End]);
+            f.emit([
+                LocalGet(i),
+                I32Const(1),
+                I32Add,
+                LocalSet(i),
+                Br(0),
+                End,
+                End,
+            ]);
+            f.emit([LocalGet(0), I32Load(0), LocalSet(0), Br(0), End, End]);
+            f.emit([Rt::BufNew.call(), LocalSet(buf)]);
+            f.emit(push(buf, data.text("Undefined variable: ")));
+            f.emit([LocalGet(buf), LocalGet(1), Rt::WriteSymbol.call()]);
+            f.emit([LocalGet(buf), Rt::Fail.call(), Unreachable]);
+        }
+        Rt::Truthy => {
+            // `none` and 0 are false
+            f = Function::new(&[I32], Some(I32));
+            f.emit(tag_is(0, NONE_TAG));
+            f.emit([If(None), I32Const(0), Return, End]);
+            f.emit(tag_is(0, INTEGER_TAG));
+            f.emit([
+                If(None),
+                LocalGet(0),
+                I64Load(8),
+                I64Const(0),
+                I64Ne,
+                Return,
+                End,
+            ]);
+            f.emit([I32Const(1)]);
+        }
+        Rt::Negate => {
+            f = Function::new(&[I32], Some(I32));
+            f.emit(tag_is(0, INTEGER_TAG));
+            f.emit([
+                If(None),
+                LocalGet(0),
+                I64Load(8),
+                I64Const(i64::MIN),
+                I64Eq,
+                If(None),
+            ]);
+            f.emit(fail(data, "Integer overflow"));
+            f.emit([
+                End,
+                I64Const(0),
+                LocalGet(0),
+                I64Load(8),
+                I64Sub,
+                Rt::Int.call(),
+            ]);
+            f.emit([Return, End]);
+            f.emit(tag_is(0, FLOAT_TAG));
+            f.emit([If(None), LocalGet(0), F64Load(8), F64Neg, Rt::Float.call()]);
+            f.emit([Return, End]);
+            f.emit(fail(data, "Cannot negate a non-numeric value"));
+            f.emit([Unreachable]);
+        }
+        Rt::Not => {
+            f = Function::new(&[I32], Some(I32));
+            f.emit([LocalGet(0), Rt::Truthy.call(), I32Eqz, Rt::Bool.call()]);
+        }
+        Rt::IsNumber => {
+            f = Function::new(&[I32], Some(I32));
+            f.emit(tag_is(0, INTEGER_TAG));
+            f.emit(tag_is(0, FLOAT_TAG));
+            f.emit([I32Or]);
+        }
+        Rt::AsFloat => {
+            f = Function::new(&[I32], Some(F64));
+            f.emit(tag_is(0, INTEGER_TAG));
+            f.emit([If(Some(F64)), LocalGet(0), I64Load(8), F64ConvertI64S]);
+            f.emit([Else, LocalGet(0), F64Load(8), End]);
+        }
+        Rt::Binary => {
+            // (operator, left, right)
+            f = Function::new(&[I32, I32, I32], Some(I32));
+            let buf = f.local(I32);
+            let both_none = [
+                LocalGet(1),
+                I32Load(0),
+                LocalGet(2),
+                I32Load(0),
+                I32Or,
+                I32Eqz,
+            ];
+
+            f.emit(tag_is(1, INTEGER_TAG));
+            f.emit(tag_is(2, INTEGER_TAG));
+            f.emit([I32And, If(None), LocalGet(0), LocalGet(1), I64Load(8)]);
+            f.emit([LocalGet(2), I64Load(8), Rt::IntBinary.call(), Return, End]);
+
+            f.emit([
+                LocalGet(1),
+                Rt::IsNumber.call(),
+                LocalGet(2),
+                Rt::IsNumber.call(),
+            ]);
+            f.emit([
+                I32And,
+                If(None),
+                LocalGet(0),
+                LocalGet(1),
+                Rt::AsFloat.call(),
+            ]);
+            f.emit([
+                LocalGet(2),
+                Rt::AsFloat.call(),
+                Rt::FloatBinary.call(),
+                Return,
+                End,
+            ]);
+
+            f.emit(tag_is(1, STRING_TAG));
+            f.emit(tag_is(2, STRING_TAG));
+            f.emit([I32And, If(None), LocalGet(0), LocalGet(1), LocalGet(2)]);
+            f.emit([Rt::StringBinary.call(), Return, End]);
+
+            // A string added to anything else joins their printed forms
+            f.emit(op_is(op::ADD));
+            f.emit(tag_is(1, STRING_TAG));
+            f.emit(tag_is(2, STRING_TAG));
+            f.emit([I32Or, I32And, If(None), Rt::BufNew.call(), LocalTee(buf)]);
+            f.emit([
+                LocalGet(1),
+                Rt::WriteValue.call(),
+                LocalGet(buf),
+                LocalGet(2),
+            ]);
+            f.emit([Rt::WriteValue.call(), LocalGet(buf), Rt::BufFinish.call()]);
+            f.emit([Return, End]);
+
+            // Values of different types are never equal
+            f.emit(op_is(op::EQ));
+            f.emit([If(None)]);
+            f.emit(both_none);
+            f.emit([Rt::Bool.call(), Return, End]);
+            f.emit(op_is(op::NE));
+            f.emit([If(None)]);
+            f.emit(both_none);
+            f.emit([I32Eqz, Rt::Bool.call(), Return, End]);
+
+            f.emit([Rt::BufNew.call(), LocalSet(buf)]);
+            f.emit(push(buf, data.text("Invalid operation between ")));
+            f.emit([LocalGet(buf), LocalGet(1), Rt::WriteTypeName.call()]);
+            f.emit(push(buf, data.text(" and ")));
+            f.emit([LocalGet(buf), LocalGet(2), Rt::WriteTypeName.call()]);
+            f.emit([LocalGet(buf), Rt::Fail.call(), Unreachable]);
+        }
+        Rt::IntBinary => {
+            // (operator, left, right), checking for overflow like Rust's
+            // `checked_*` operations
+            f = Function::new(&[I32, I64, I64], Some(I32));
+            let result = f.local(I64);
+            let overflow = fail(data, "Integer overflow");
+
+            f.emit(op_is(op::ADD));
+            f.emit([If(None), LocalGet(1), LocalGet(2), I64Add, LocalSet(result)]);
+            f.emit([
+                LocalGet(1),
+                LocalGet(result),
+                I64Xor,
+                LocalGet(2),
+                LocalGet(result),
+            ]);
+            f.emit([I64Xor, I64And, I64Const(0), I64LtS, If(None)]);
+            f.emit(overflow);
+            f.emit([End, LocalGet(result), Rt::Int.call(), Return, End]);
+
+            f.emit(op_is(op::SUB));
+            f.emit([If(None), LocalGet(1), LocalGet(2), I64Sub, LocalSet(result)]);
+            f.emit([
+                LocalGet(1),
+                LocalGet(2),
+                I64Xor,
+                LocalGet(1),
+                LocalGet(result),
+            ]);
+            f.emit([I64Xor, I64And, I64Const(0), I64LtS, If(None)]);
+            f.emit(overflow);
+            f.emit([End, LocalGet(result), Rt::Int.call(), Return, End]);
+
+            f.emit(op_is(op::MUL));
+            f.emit([
+                If(None),
+                LocalGet(1),
+                I64Eqz,
+                LocalGet(2),
+                I64Eqz,
+                I32Or,
+                If(None),
+            ]);
+            f.emit([I64Const(0), Rt::Int.call(), Return, End]);
+            f.emit([
+                LocalGet(1),
+                I64Const(-1),
+                I64Eq,
+                LocalGet(2),
+                I64Const(i64::MIN),
+            ]);
+            f.emit([I64Eq, I32And, LocalGet(2), I64Const(-1), I64Eq, LocalGet(1)]);
+            f.emit([I64Const(i64::MIN), I64Eq, I32And, I32Or, If(None)]);
+            f.emit(overflow);
+            f.emit([End, LocalGet(1), LocalGet(2), I64Mul, LocalTee(result)]);
+            f.emit([LocalGet(2), I64DivS, LocalGet(1), I64Ne, If(None)]);
+            f.emit(overflow);
+            f.emit([End, LocalGet(result), Rt::Int.call(), Return, End]);
+
+            // Division always gives a float
+            f.emit(op_is(op::DIV));
+            f.emit([If(None), LocalGet(0), LocalGet(1), F64ConvertI64S]);
+            f.emit([LocalGet(2), F64ConvertI64S, Rt::FloatBinary.call()]);
+            f.emit([Return, End]);
+
+            // Both round towards -infinity: a remainder whose sign differs
+            // from the divisor's moves the truncated result one step
+            let signs_differ = [LocalGet(result), LocalGet(2), I64Xor, I64Const(0), I64LtS];
+            f.emit(op_is(op::FLOOR_DIV));
+            f.emit([If(None), LocalGet(2), I64Eqz, If(None)]);
+            f.emit(fail(data, "Division by zero"));
+            f.emit([End, LocalGet(1), I64Const(i64::MIN), I64Eq, LocalGet(2)]);
+            f.emit([I64Const(-1), I64Eq, I32And, If(None)]);
+            f.emit(overflow);
+            f.emit([End, LocalGet(1), LocalGet(2), I64RemS, LocalSet(result)]);
+            f.emit([LocalGet(1), LocalGet(2), I64DivS]);
+            f.emit([LocalGet(result), I64Eqz, I32Eqz]);
+            f.emit(signs_differ);
+            f.emit([I32And, I64ExtendI32U, I64Sub, Rt::Int.call(), Return, End]);
+
+            f.emit(op_is(op::MOD));
+            f.emit([If(None), LocalGet(2), I64Eqz, If(None)]);
+            f.emit(fail(data, "Modulo by zero"));
+            f.emit([End, LocalGet(1), LocalGet(2), I64RemS, LocalSet(result)]);
+            f.emit([LocalGet(result), I64Eqz, I32Eqz]);
+            f.emit(signs_differ);
+            f.emit([I32And, If(None), LocalGet(result), LocalGet(2), I64Add]);
+            f.emit([LocalSet(result), End, LocalGet(result), Rt::Int.call()]);
+            f.emit([Return, End]);
+
+            for (code, compare) in [
+                (op::EQ, I64Eq),
+                (op::NE, I64Ne),
+                (op::LT, I64LtS),
+                (op::GT, I64GtS),
+                (op::LE, I64LeS),
+                (op::GE, I64GeS),
+            ] {
+                f.emit(op_is(code));
+                f.emit([If(None), LocalGet(1), LocalGet(2), compare, Rt::Bool.call()]);
+                f.emit([Return, End]);
+            }
+            f.emit(fail(data, "Invalid operation between integer and integer"));
+            f.emit([Unreachable]);
+        }
+        Rt::FloatBinary => {
+            f = Function::new(&[I32, F64, F64], Some(I32));
+            for (code, arithmetic) in [(op::ADD, F64Add), (op::SUB, F64Sub), (op::MUL, F64Mul)] {
+                f.emit(op_is(code));
+                f.emit([
+                    If(None),
+                    LocalGet(1),
+                    LocalGet(2),
+                    arithmetic,
+                    Rt::Float.call(),
+                ]);
+                f.emit([Return, End]);
+            }
+            f.emit(op_is(op::DIV));
+            f.emit([If(None), LocalGet(2), F64Const(0.0), F64Eq, If(None)]);
+            f.emit(fail(data, "Division by zero"));
+            f.emit([
+                End,
+                LocalGet(1),
+                LocalGet(2),
+                F64Div,
+                Rt::Float.call(),
+                Return,
+                End,
+            ]);
+
+            f.emit(op_is(op::FLOOR_DIV));
+            f.emit([If(None), LocalGet(2), F64Const(0.0), F64Eq, If(None)]);
+            f.emit(fail(data, "Division by zero"));
+            f.emit([End, LocalGet(1), LocalGet(2), F64Div, F64Floor]);
+            f.emit([Rt::Float.call(), Return, End]);
+
+            f.emit(op_is(op::MOD));
+            f.emit([If(None), LocalGet(2), F64Const(0.0), F64Eq, If(None)]);
+            f.emit(fail(data, "Modulo by zero"));
+            f.emit([End, LocalGet(1), LocalGet(2), LocalGet(1), LocalGet(2)]);
+            f.emit([F64Div, F64Floor, F64Mul, F64Sub, Rt::Float.call()]);
+            f.emit([Return, End]);
+
+            for (code, compare) in [
+                (op::EQ, F64Eq),
+                (op::NE, F64Ne),
+                (op::LT, F64Lt),
+                (op::GT, F64Gt),
+                (op::LE, F64Le),
+                (op::GE, F64Ge),
+            ] {
+                f.emit(op_is(code));
+                f.emit([If(None), LocalGet(1), LocalGet(2), compare, Rt::Bool.call()]);
+                f.emit([Return, End]);
+            }
+            f.emit(fail(data, "Cannot use dot operator with numbers"));
+            f.emit([Unreachable]);
+        }
+        Rt::StringBinary => {
+            f = Function::new(&[I32, I32, I32], Some(I32));
+            let (buf, order) = (f.local(I32), f.local(I32));
+            f.emit(op_is(op::ADD));
+            f.emit(op_is(op::DOT));
+            f.emit([
+                I32Or,
+                If(None),
+                Rt::BufNew.call(),
+                LocalTee(buf),
+                LocalGet(1),
+            ]);
+            f.emit([Rt::WriteValue.call()]);
+            f.emit(op_is(op::DOT));
+            f.emit([If(None)]);
+            f.emit(push(buf, data.text(".")));
+            f.emit([End, LocalGet(buf), LocalGet(2), Rt::WriteValue.call()]);
+            f.emit([LocalGet(buf), Rt::BufFinish.call(), Return, End]);
+
+            f.emit([
+                LocalGet(1),
+                LocalGet(2),
+                Rt::Compare.call(),
+                LocalSet(order),
+            ]);
+            for (code, compare) in [
+                (op::EQ, I32Eq),
+                (op::NE, I32Ne),
+                (op::LT, I32LtS),
+                (op::GT, I32GtS),
+                (op::LE, I32LeS),
+                (op::GE, I32GeS),
+            ] {
+                f.emit(op_is(code));
+                f.emit([
+                    If(None),
+                    LocalGet(order),
+                    I32Const(0),
+                    compare,
+                    Rt::Bool.call(),
+                ]);
+                f.emit([Return, End]);
+            }
+            f.emit(fail(data, "Invalid operation between string and string"));
+            f.emit([Unreachable]);
+        }
+        Rt::Compare => {
+            // Orders two strings bytewise, returning -1, 0 or 1
+            f = Function::new(&[I32, I32], Some(I32));
+            let (shorter, i, a, b) = (f.local(I32), f.local(I32), f.local(I32), f.local(I32));
+            let lens = [LocalGet(0), I32Load(4), LocalGet(1), I32Load(4)];
+            f.emit(lens);
+            f.emit(lens);
+            f.emit([I32LtU, Select, LocalSet(shorter)]);
+            f.emit([Block, Loop, LocalGet(i), LocalGet(shorter), I32GeU, BrIf(1)]);
+            for (string, byte) in [(0, a), (1, b)] {
+                f.emit([
+                    LocalGet(string),
+                    I32Load(8),
+                    LocalGet(i),
+                    I32Add,
+                    I32Load8U(0),
+                ]);
+                f.emit([LocalSet(byte)]);
+            }
+            f.emit([
+                LocalGet(a),
+                LocalGet(b),
+                I32Ne,
+                If(None),
+                I32Const(-1),
+                I32Const(1),
+            ]);
+            f.emit([LocalGet(a), LocalGet(b), I32LtU, Select, Return, End]);
+            f.emit([
+                LocalGet(i),
+                I32Const(1),
+                I32Add,
+                LocalSet(i),
+                Br(0),
+                End,
+                End,
+            ]);
+            f.emit(lens);
+            f.emit([I32GtU]);
+            f.emit(lens);
+            f.emit([I32LtU, I32Sub]);
+        }
+        Rt::Range | Rt::LoopCount | Rt::IterCount => {
+            let (result, message) = match rt {
+                Rt::Range => (I32, "Range argument must be an integer"),
+                Rt::LoopCount => (I64, "Loop count must be an integer"),
+                _ => (I64, "Iterator must be a range"),
+            };
+            f = Function::new(&[I32], Some(result));
+            f.emit(tag_is(0, INTEGER_TAG));
+            f.emit([I32Eqz, If(None)]);
+            f.emit(fail(data, message));
+            f.emit([End, LocalGet(0)]);
+            if result == I64 {
+                f.emit([I64Load(8)]);
+            }
+        }
+        Rt::CheckArity => {
+            // (name symbol, expected, got)
+            f = Function::new(&[I32, I32, I32], None);
+            let buf = f.local(I32);
+            f.emit([LocalGet(1), LocalGet(2), I32Eq, If(None), Return, End]);
+            f.emit([Rt::BufNew.call(), LocalSet(buf)]);
+            f.emit([LocalGet(0), I32Const(-1), I32Eq, If(None)]);
+            f.emit(push(buf, data.text("Lambda")));
+            f.emit([
+                Else,
+                LocalGet(buf),
+                LocalGet(0),
+                Rt::WriteSymbol.call(),
+                End,
+            ]);
+            f.emit(push(buf, data.text(" expects ")));
+            f.emit([
+                LocalGet(buf),
+                LocalGet(1),
+                I64ExtendI32S,
+                Rt::WriteInt.call(),
+            ]);
+            f.emit(push(buf, data.text(" arguments, got ")));
+            f.emit([
+                LocalGet(buf),
+                LocalGet(2),
+                I64ExtendI32S,
+                Rt::WriteInt.call(),
+            ]);
+            f.emit([LocalGet(buf), Rt::Fail.call()]);
+        }
+        Rt::Call => {
+            // (callee, argument count, arguments)
+            f = Function::new(&[I32, I32, I32], Some(I32));
+            let buf = f.local(I32);
+            f.emit(tag_is(0, FUNCTION_TAG));
+            f.emit(tag_is(0, BUILTIN_TAG));
+            f.emit([I32Or, If(None), LocalGet(0), LocalGet(1), LocalGet(2)]);
+            f.emit([LocalGet(0), I32Load(4), CallIndirect(0), Return, End]);
+            f.emit([Rt::BufNew.call(), LocalSet(buf)]);
+            f.emit(push(buf, data.text("Value of type ")));
+            f.emit([LocalGet(buf), LocalGet(0), Rt::WriteTypeName.call()]);
+            f.emit(push(buf, data.text(" is not callable")));
+            f.emit([LocalGet(buf), Rt::Fail.call(), Unreachable]);
+        }
+        Rt::Print => {
+            // The `print` builtin, called like any Crabby function
+            f = Function::new(&[I32, I32, I32], Some(I32));
+            let buf = f.local(I32);
+            f.emit([LocalGet(0), I32Load(12), I32Const(1), LocalGet(1)]);
+            f.emit([Rt::CheckArity.call(), Rt::BufNew.call(), LocalTee(buf)]);
+            f.emit([LocalGet(2), I32Load(0), Rt::WriteValue.call()]);
+            f.emit(push(buf, data.text("\n")));
+            f.emit([
+                LocalGet(buf),
+                I32Load(0),
+                LocalGet(buf),
+                I32Load(4),
+                Call(PRINT),
+            ]);
+            f.emit([I32Const(NONE)]);
+        }
+        Rt::Tag | Rt::IntValue | Rt::FloatValue | Rt::StringPtr | Rt::StringLen => {
+            // Accessors for hosts
+            let (result, load) = match rt {
+                Rt::Tag => (I32, I32Load(0)),
+                Rt::IntValue => (I64, I64Load(8)),
+                Rt::FloatValue => (F64, F64Load(8)),
+                Rt::StringPtr => (I32, I32Load(8)),
+                _ => (I32, I32Load(4)),
+            };
+            f = Function::new(&[I32], Some(result));
+            f.emit([LocalGet(0), load]);
+        }
+    }
+    f
+}
@@ -0,0 +1,706 @@
+use crate::bytecode::{Capture, CompiledModule, Constant, Export, FunctionProto, Op};
+use crate::ir::{self, BinaryOp, Expr, FormatPart, FunctionDecl, Stmt};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Compiles a checked module into the prototype for its top level. Top-level
+/// names are globals; everything bound inside a function gets a slot, or a
+/// cell when a nested function captures it.
+pub fn compile_module(module: &ir::Module) -> CompiledModule {
+    let mut compiler = BytecodeCompiler {
+        functions: vec![FunctionState::new("<script>", true)],
+    };
+    compiler.tail_block(&module.body);
+    let state = compiler
+        .functions
+        .pop()
+        .unwrap_or_else(|| FunctionState::new("<script>", true));
+    CompiledModule {
+        proto: Rc::new(state.proto),
+        exports: module_exports(module),
+    }
+}
+
+/// The top-level `let` and `def` bindings of a module
+pub fn module_exports(module: &ir::Module) -> Vec<Export> {
+    module
+        .body
+        .iter()
+        .filter_map(|statement| match statement {
+            Stmt::Let { name, public, .. } => Some(Export {
+                name: name.clone(),
+                public: *public,
+            }),
+            Stmt::Function(decl) => Some(Export {
+                name: decl.name.clone(),
+                public: decl.public,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+enum Access {
+    Local(u32),
+    Cell(u32),
+    Upvalue(u32),
+    Global(u32),
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    /// The top level of a module, whose names are all globals
+    script: bool,
+    slots: HashMap<String, u32>,
+    cells: HashMap<String, u32>,
+    upvalues: HashMap<String, u32>,
+    /// Locals assigned on every path to the current instruction
+    assigned: HashSet<String>,
+    /// String constants already in the pool, so names are stored once
+    strings: HashMap<String, u32>,
+}
+
+impl FunctionState {
+    fn new(name: &str, script: bool) -> Self {
+        Self {
+            proto: FunctionProto {
+                name: name.to_string(),
+                ..FunctionProto::default()
+            },
+            script,
+            slots: HashMap::new(),
+            cells: HashMap::new(),
+            upvalues: HashMap::new(),
+            assigned: HashSet::new(),
+            strings: HashMap::new(),
+        }
+    }
+}
+
+struct BytecodeCompiler {
+    /// The function being compiled and the ones enclosing it, innermost last
+    functions: Vec<FunctionState>,
+}
+
+impl BytecodeCompiler {
+    fn state(&mut self) -> &mut FunctionState {
+        let last = self.functions.len() - 1;
+        &mut self.functions[last]
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.state().proto.code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.state().proto.code.len() as u32
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.state().proto.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::JumpIfTrue(t) | Op::JumpIfNotNone(t) => {
+                *t = target
+            }
+            Op::ForIter { exit, .. } => *exit = target,
+            _ => {}
+        }
+    }
+
+    fn constant(&mut self, constant: Constant) -> u32 {
+        let constants = &mut self.state().proto.constants;
+        constants.push(constant);
+        (constants.len() - 1) as u32
+    }
+
+    fn string(&mut self, s: &str) -> u32 {
+        if let Some(&index) = self.state().strings.get(s) {
+            return index;
+        }
+        let index = self.constant(Constant::String(Rc::from(s)));
+        self.state().strings.insert(s.to_string(), index);
+        index
+    }
+
+    fn alloc_slots(&mut self, count: u32) -> u32 {
+        let proto = &mut self.state().proto;
+        let first = proto.slots;
+        proto.slots += count;
+        first
+    }
+
+    fn resolve(&mut self, name: &str) -> Access {
+        let state = self.state();
+        if let Some(&slot) = state.slots.get(name) {
+            return Access::Local(slot);
+        }
+        if let Some(&cell) = state.cells.get(name) {
+            return Access::Cell(cell);
+        }
+
+        let depth = self.functions.len() - 1;
+        match self.resolve_upvalue(depth, name) {
+            Some(index) => Access::Upvalue(index),
+            None => Access::Global(self.string(name)),
+        }
+    }
+
+    /// Finds `name` in the functions enclosing `depth`, threading it through
+    /// each intermediate closure as an upvalue
+    fn resolve_upvalue(&mut self, depth: usize, name: &str) -> Option<u32> {
+        if self.functions[depth].script {
+            return None;
+        }
+        if let Some(&index) = self.functions[depth].upvalues.get(name) {
+            return Some(index);
+        }
+
+        let parent = &self.functions[depth - 1];
+        let capture = if let Some(&cell) = parent.cells.get(name) {
+            Capture::Cell(cell)
+        } else {
+            Capture::Upvalue(self.resolve_upvalue(depth - 1, name)?)
+        };
+
+        let state = &mut self.functions[depth];
+        state.proto.captures.push(capture);
+        let index = (state.proto.captures.len() - 1) as u32;
+        state.upvalues.insert(name.to_string(), index);
+        Some(index)
+    }
+
+    fn load(&mut self, name: &str) {
+        let access = self.resolve(name);
+        let local = matches!(access, Access::Local(_) | Access::Cell(_));
+        self.emit_load(access);
+
+        // Like the interpreter, a local read before its first assignment sees
+        // the binding outside the function
+        if local && !self.state().assigned.contains(name) {
+            let to_end = self.emit(Op::JumpIfNotNone(0));
+            self.emit(Op::Pop);
+            let depth = self.functions.len() - 1;
+            let outer = match self.resolve_upvalue(depth, name) {
+                Some(index) => Access::Upvalue(index),
+                None => Access::Global(self.string(name)),
+            };
+            self.emit_load(outer);
+            self.patch(to_end);
+        }
+    }
+
+    fn emit_load(&mut self, access: Access) {
+        let op = match access {
+            Access::Local(slot) => Op::LoadLocal(slot),
+            Access::Cell(cell) => Op::LoadCell(cell),
+            Access::Upvalue(index) => Op::LoadUpvalue(index),
+            Access::Global(name) => Op::LoadGlobal(name),
+        };
+        self.emit(op);
+    }
+
+    /// Stores into a binding of the current function; bindings never live in
+    /// an enclosing function, so there is no upvalue store
+    fn store(&mut self, name: &str) {
+        let op = match self.resolve(name) {
+            Access::Local(slot) => Op::StoreLocal(slot),
+            Access::Cell(cell) => Op::StoreCell(cell),
+            Access::Upvalue(_) | Access::Global(_) => Op::StoreGlobal(self.string(name)),
+        };
+        self.emit(op);
+        self.state().assigned.insert(name.to_string());
+    }
+
+    /// Compiles a block that may not run, so its assignments are forgotten
+    /// afterwards
+    fn conditional_block(&mut self, statements: &[Stmt]) {
+        let assigned = self.state().assigned.clone();
+        self.block(statements);
+        self.state().assigned = assigned;
+    }
+
+    /// Compiles a body whose final statement provides its value, matching the
+    /// interpreter: a trailing expression, or a trailing `if` whose taken
+    /// branch ends in one
+    fn tail_block(&mut self, statements: &[Stmt]) {
+        let Some((last, rest)) = statements.split_last() else {
+            self.emit(Op::PushNone);
+            self.emit(Op::Return);
+            return;
+        };
+
+        for statement in rest {
+            self.statement(statement);
+        }
+
+        match last {
+            Stmt::Expr(expr) | Stmt::Return(expr) => {
+                self.expression(expr);
+                self.emit(Op::Return);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                let assigned = self.state().assigned.clone();
+                self.tail_block(then_branch);
+                self.state().assigned = assigned;
+                self.patch(to_else);
+                self.tail_block(else_branch);
+            }
+            other => {
+                self.statement(other);
+                self.emit(Op::PushNone);
+                self.emit(Op::Return);
+            }
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Let { name, value, .. } => {
+                self.expression(value);
+                self.store(name);
+            }
+            Stmt::Function(decl) => {
+                self.closure(decl);
+                self.store(&decl.name);
+            }
+            Stmt::Return(expr) => {
+                self.expression(expr);
+                self.emit(Op::Return);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expression(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.conditional_block(then_branch);
+                if else_branch.is_empty() {
+                    self.patch(to_else);
+                } else {
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_else);
+                    self.conditional_block(else_branch);
+                    self.patch(to_end);
+                }
+            }
+            Stmt::While { condition, body } => {
+                let start = self.here();
+                self.expression(condition);
+                let to_end = self.emit(Op::JumpIfFalse(0));
+                self.conditional_block(body);
+                self.emit(Op::Jump(start));
+                self.patch(to_end);
+            }
+            Stmt::Loop { count, body } => {
+                let counter = self.counted_loop(count, Some("Loop count must be an integer"));
+                let start = self.here();
+                let to_end = self.emit(Op::ForIter { counter, exit: 0 });
+                self.emit(Op::Pop);
+                self.conditional_block(body);
+                self.emit(Op::Jump(start));
+                self.patch(to_end);
+            }
+            Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+            } => {
+                let counter = self.counted_loop(iterable, None);
+                let start = self.here();
+                let to_end = self.emit(Op::ForIter { counter, exit: 0 });
+                let assigned = self.state().assigned.clone();
+                self.store(variable);
+                self.block(body);
+                self.state().assigned = assigned;
+                self.emit(Op::Jump(start));
+                self.patch(to_end);
+            }
+            Stmt::Import { name, source } => {
+                let name_index = self.string(name);
+                let source = match source {
+                    Some(source) => self.string(source),
+                    None => u32::MAX,
+                };
+                self.emit(Op::Import {
+                    name: name_index,
+                    source,
+                });
+                self.store(name);
+            }
+            Stmt::Expr(expr) => {
+                self.expression(expr);
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    /// Sets up the counter and limit slots of a loop over `0..limit`, failing
+    /// with `message` unless the limit is an integer. Without a message the
+    /// limit may also be a list, which `ForIter` checks.
+    fn counted_loop(&mut self, limit: &Expr, message: Option<&str>) -> u32 {
+        let counter = self.alloc_slots(2);
+        self.expression(limit);
+        if let Some(message) = message {
+            let message = self.string(message);
+            self.emit(Op::ExpectInteger(message));
+        }
+        self.emit(Op::StoreLocal(counter + 1));
+        let zero = self.constant(Constant::Integer(0));
+        self.emit(Op::Constant(zero));
+        self.emit(Op::StoreLocal(counter));
+        counter
+    }
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Integer(n) => {
+                let index = self.constant(Constant::Integer(*n));
+                self.emit(Op::Constant(index));
+            }
+            Expr::Float(f) => {
+                let index = self.constant(Constant::Float(*f));
+                self.emit(Op::Constant(index));
+            }
+            Expr::String(s) => {
+                let index = self.string(s);
+                self.emit(Op::Constant(index));
+            }
+            Expr::Variable(name) => self.load(name),
+            Expr::Range(count) => {
+                self.expression(count);
+                let message = self.string("Range argument must be an integer");
+                self.emit(Op::ExpectInteger(message));
+            }
+            Expr::Unary { op, operand } => {
+                self.expression(operand);
+                self.emit(Op::Unary(*op));
+            }
+            Expr::Binary {
+                left,
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => {
+                // Short-circuit to 0 for `&&` and 1 for `||`
+                self.expression(left);
+                let short = match op {
+                    BinaryOp::And => self.emit(Op::JumpIfFalse(0)),
+                    _ => self.emit(Op::JumpIfTrue(0)),
+                };
+                self.expression(right);
+                self.emit(Op::Truthy);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch(short);
+                let value = self.constant(Constant::Integer((*op == BinaryOp::Or) as i64));
+                self.emit(Op::Constant(value));
+                self.patch(to_end);
+            }
+            Expr::Binary { left, op, right } => {
+                if let Expr::Variable(name) = &**left
+                    && let Some(constant) = numeric_constant(right)
+                    && self.state().assigned.contains(name)
+                    && let Access::Local(slot) = self.resolve(name)
+                {
+                    let constant = self.constant(constant);
+                    self.emit(Op::BinaryLocalConst { op: *op, slot, constant });
+                    return;
+                }
+
+                self.expression(left);
+                self.expression(right);
+                self.emit(Op::Binary(*op));
+            }
+            Expr::Call { callee, args } => {
+                let global = match self.resolve(callee) {
+                    Access::Global(name) => Some(name),
+                    _ => None,
+                };
+                if global.is_none() {
+                    self.load(callee);
+                }
+                for arg in args {
+                    self.expression(arg);
+                }
+
+                let argc = args.len() as u32;
+                match global {
+                    Some(name) => self.emit(Op::CallGlobal { name, argc }),
+                    None => self.emit(Op::Call(argc)),
+                };
+            }
+            Expr::CallValue { callee, args } => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(Op::Call(args.len() as u32));
+            }
+            Expr::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::MakeList(elements.len() as u32));
+            }
+            Expr::Dict(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.emit(Op::MakeDict(entries.len() as u32));
+            }
+            Expr::Keywords(arguments) => {
+                for (name, value) in arguments {
+                    let name = self.string(name);
+                    self.emit(Op::Constant(name));
+                    self.expression(value);
+                }
+                self.emit(Op::MakeKeywords(arguments.len() as u32));
+            }
+            Expr::Index { object, index } => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(Op::Index);
+            }
+            Expr::Member { object, name } => {
+                self.expression(object);
+                let name = self.string(name);
+                self.emit(Op::Member(name));
+            }
+            Expr::Lambda(decl) => self.closure(decl),
+            Expr::Format(parts) => {
+                for part in parts {
+                    match part {
+                        FormatPart::Literal(text) => {
+                            let index = self.string(text);
+                            self.emit(Op::Constant(index));
+                        }
+                        FormatPart::Value { expr, spec } => {
+                            self.expression(expr);
+                            let spec = spec.as_ref().map(|spec| {
+                                let specs = &mut self.state().proto.specs;
+                                specs.push(spec.clone());
+                                (specs.len() - 1) as u32
+                            });
+                            self.emit(Op::Format(spec));
+                        }
+                    }
+                }
+                self.emit(Op::Concat(parts.len() as u32));
+            }
+        }
+    }
+
+    /// Compiles a nested function and emits the instruction creating its closure
+    fn closure(&mut self, decl: &FunctionDecl) {
+        let mut state = FunctionState::new(&decl.name, false);
+        state.proto.arity = decl.params.len() as u32;
+        state.assigned.extend(decl.params.iter().cloned());
+
+        let captured = captured_by_nested(&decl.body);
+        let mut locals = decl.params.clone();
+        bindings(&decl.body, &mut locals);
+
+        for (index, name) in locals.into_iter().enumerate() {
+            let is_param = index < decl.params.len();
+            // Parameters keep their argument slot even when they move to a cell
+            let slot = if is_param || !captured.contains(&name) {
+                state.proto.slots += 1;
+                Some(state.proto.slots - 1)
+            } else {
+                None
+            };
+
+            if captured.contains(&name) {
+                let cell = state.proto.cells;
+                state.proto.cells += 1;
+                if let (true, Some(slot)) = (is_param, slot) {
+                    state.proto.param_cells.push((slot, cell));
+                }
+                state.cells.insert(name, cell);
+            } else if let Some(slot) = slot {
+                state.slots.insert(name, slot);
+            }
+        }
+
+        self.functions.push(state);
+        self.tail_block(&decl.body);
+        let state = self
+            .functions
+            .pop()
+            .unwrap_or_else(|| FunctionState::new(&decl.name, false));
+
+        let index = self.constant(Constant::Function(Rc::new(state.proto)));
+        self.emit(Op::Closure(index));
+    }
+}
+
+fn numeric_constant(expression: &Expr) -> Option<Constant> {
+    match expression {
+        Expr::Integer(n) => Some(Constant::Integer(*n)),
+        Expr::Float(f) => Some(Constant::Float(*f)),
+        _ => None,
+    }
+}
+
+/// Appends the names `statements` bind, in order of first appearance, looking
+/// into nested blocks but not into function bodies
+fn bindings(statements: &[Stmt], names: &mut Vec<String>) {
+    fn add(names: &mut Vec<String>, name: &String) {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+
+    for statement in statements {
+        match statement {
+            Stmt::Let { name, .. } | Stmt::Import { name, .. } => add(names, name),
+            Stmt::Function(decl) => add(names, &decl.name),
+            Stmt::ForIn { variable, body, .. } => {
+                add(names, variable);
+                bindings(body, names);
+            }
+            Stmt::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                bindings(then_branch, names);
+                bindings(else_branch, names);
+            }
+            Stmt::While { body, .. } | Stmt::Loop { body, .. } => bindings(body, names),
+            Stmt::Return(_) | Stmt::Expr(_) => {}
+        }
+    }
+}
+
+/// Names a function may need from outside itself: everything it refers to
+/// except its parameters, since a local read before its first assignment
+/// falls back to the enclosing binding
+fn free_variables(decl: &FunctionDecl) -> HashSet<String> {
+    let mut references = HashSet::new();
+    walk_statements(&decl.body, true, &mut references);
+    for param in &decl.params {
+        references.remove(param);
+    }
+    references
+}
+
+/// Names that functions nested directly in `body` need from outside themselves
+fn captured_by_nested(body: &[Stmt]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    walk_statements(body, false, &mut names);
+    names
+}
+
+/// Collects the free variables of nested functions and, when `direct` is set,
+/// the names referenced directly as well
+fn walk_statements(statements: &[Stmt], direct: bool, out: &mut HashSet<String>) {
+    for statement in statements {
+        match statement {
+            Stmt::Function(decl) => out.extend(free_variables(decl)),
+            Stmt::Let { value: expr, .. } | Stmt::Return(expr) | Stmt::Expr(expr) => {
+                walk_expression(expr, direct, out)
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                walk_expression(condition, direct, out);
+                walk_statements(then_branch, direct, out);
+                walk_statements(else_branch, direct, out);
+            }
+            Stmt::While {
+                condition: expr,
+                body,
+            }
+            | Stmt::Loop { count: expr, body }
+            | Stmt::ForIn {
+                iterable: expr,
+                body,
+                ..
+            } => {
+                walk_expression(expr, direct, out);
+                walk_statements(body, direct, out);
+            }
+            Stmt::Import { .. } => {}
+        }
+    }
+}
+
+fn walk_expression(expression: &Expr, direct: bool, out: &mut HashSet<String>) {
+    match expression {
+        Expr::Integer(_) | Expr::Float(_) | Expr::String(_) => {}
+        Expr::Variable(name) => {
+            if direct {
+                out.insert(name.clone());
+            }
+        }
+        Expr::Range(operand)
+        | Expr::Unary { operand, .. }
+        | Expr::Member {
+            object: operand, ..
+        } => walk_expression(operand, direct, out),
+        Expr::Binary { left, right, .. }
+        | Expr::Index {
+            object: left,
+            index: right,
+        } => {
+            walk_expression(left, direct, out);
+            walk_expression(right, direct, out);
+        }
+        Expr::CallValue { callee, args } => {
+            walk_expression(callee, direct, out);
+            for arg in args {
+                walk_expression(arg, direct, out);
+            }
+        }
+        Expr::List(elements) => {
+            for element in elements {
+                walk_expression(element, direct, out);
+            }
+        }
+        Expr::Dict(entries) => {
+            for (key, value) in entries {
+                walk_expression(key, direct, out);
+                walk_expression(value, direct, out);
+            }
+        }
+        Expr::Keywords(arguments) => {
+            for (_, value) in arguments {
+                walk_expression(value, direct, out);
+            }
+        }
+        Expr::Call { callee, args } => {
+            if direct {
+                out.insert(callee.clone());
+            }
+            for arg in args {
+                walk_expression(arg, direct, out);
+            }
+        }
+        Expr::Lambda(decl) => out.extend(free_variables(decl)),
+        Expr::Format(parts) => {
+            for part in parts {
+                if let FormatPart::Value { expr, .. } = part {
+                    walk_expression(expr, direct, out);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Bytecode for the stack VM. Each function compiles to a `FunctionProto`
+//! holding its instructions and constant pool; local variables live in
+//! numbered slots, and variables captured by closures live in shared cells.
+
+mod compiler;
+mod serialize;
+
+pub use compiler::{compile_module, module_exports};
+pub use serialize::{cache_dir, cache_path, decode, encode, modified, read_stamp, SourceStamp, FORMAT_VERSION};
+
+use crate::ir::{BinaryOp, FormatSpec, UnaryOp};
+use std::rc::Rc;
+
+/// One VM instruction. Jump targets are absolute instruction indices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Push `constants[i]`
+    Constant(u32),
+    PushNone,
+    Pop,
+    LoadLocal(u32),
+    StoreLocal(u32),
+    /// Locals captured by a closure are kept in cells rather than slots
+    LoadCell(u32),
+    StoreCell(u32),
+    LoadUpvalue(u32),
+    /// Globals are looked up by the name in `constants[i]`
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    /// `LoadLocal(slot)`, `Constant(constant)` and `Binary(op)` in one
+    BinaryLocalConst {
+        op: BinaryOp,
+        slot: u32,
+        constant: u32,
+    },
+    /// Replace the top of the stack with `1` or `0` by its truthiness
+    Truthy,
+    Jump(u32),
+    /// Pop the condition and jump when it is falsy
+    JumpIfFalse(u32),
+    /// Pop the condition and jump when it is truthy
+    JumpIfTrue(u32),
+    /// Jump, keeping the value, unless the top of the stack is `none`. Guards
+    /// reads of locals that may not be assigned yet.
+    JumpIfNotNone(u32),
+    /// Fail with the message in `constants[i]` unless the top of the stack is
+    /// an integer
+    ExpectInteger(u32),
+    /// Push the counter in slot `counter` and increment it while it is below
+    /// the limit in slot `counter + 1`; otherwise jump to `exit`. When that
+    /// slot holds a list or bytes, push the element at the counter instead.
+    ForIter {
+        counter: u32,
+        exit: u32,
+    },
+    /// Call the callee below `argc` arguments
+    Call(u32),
+    /// `LoadGlobal(name)` and `Call(argc)` in one, with the arguments alone on
+    /// the stack
+    CallGlobal {
+        name: u32,
+        argc: u32,
+    },
+    Return,
+    /// Create a closure over the function prototype in `constants[i]`
+    Closure(u32),
+    /// Import `constants[name]` from the path in `constants[source]`, or from
+    /// the standard library when `source` is `u32::MAX`
+    Import {
+        name: u32,
+        source: u32,
+    },
+    /// Render the top of the stack as a string, with `specs[i]` when given
+    Format(Option<u32>),
+    /// Concatenate the top `n` strings
+    Concat(u32),
+    /// Replace the top `n` values with a list of them
+    MakeList(u32),
+    /// Replace the top `2 * n` values, alternating keys and values, with a
+    /// dict of them
+    MakeDict(u32),
+    /// Like `MakeDict`, but for the keyword arguments passed to a call
+    MakeKeywords(u32),
+    /// Pop an index and the value below it, and push the element there
+    Index,
+    /// Replace the top of the stack with its member named by `constants[i]`
+    Member(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Integer(i64),
+    Float(f64),
+    String(Rc<str>),
+    Function(Rc<FunctionProto>),
+}
+
+/// Where a closure finds one of its upvalues when it is created
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    /// A cell of the enclosing function's frame
+    Cell(u32),
+    /// An upvalue of the enclosing closure
+    Upvalue(u32),
+}
+
+/// A compiled function, or the top level of a module
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: u32,
+    /// Slots for parameters, locals and loop temporaries
+    pub slots: u32,
+    pub cells: u32,
+    /// Parameters that closures capture: `(slot, cell)` pairs copied on entry
+    pub param_cells: Vec<(u32, u32)>,
+    pub captures: Vec<Capture>,
+    pub code: Vec<Op>,
+    pub constants: Vec<Constant>,
+    pub specs: Vec<FormatSpec>,
+}
+
+/// A top-level binding of a module, which importers may see when it is public
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub name: String,
+    pub public: bool,
+}
+
+/// A whole module: its top-level code and the bindings it exports
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledModule {
+    pub proto: Rc<FunctionProto>,
+    pub exports: Vec<Export>,
+}
@@ -0,0 +1,685 @@
+//! The `.crabc` file format: a fixed header followed by the encoded module.
+//!
+//! | bytes | field                                          |
+//! |-------|------------------------------------------------|
+//! | 6     | magic, `CRABC\0`                               |
+//! | 2     | format version                                 |
+//! | 8     | source modification time, nanoseconds          |
+//! | 8     | FNV-1a hash of the source                      |
+//! | 4     | payload length                                 |
+//! | 4     | CRC-32 of the payload                          |
+//!
+//! All integers are little-endian.
+
+use crate::bytecode::{Capture, CompiledModule, Constant, Export, FunctionProto, Op};
+use crate::ir::{BinaryOp, FormatAlign, FormatSpec, UnaryOp};
+use crate::utils::{CrabbyError, fnv1a};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+pub const MAGIC: &[u8; 6] = b"CRABC\0";
+
+/// Bumped whenever the encoding or the meaning of an instruction changes
+pub const FORMAT_VERSION: u16 = 2;
+
+const HEADER_LEN: usize = 32;
+const NO_INDEX: u32 = u32::MAX;
+
+/// Identifies the source a `.crabc` file was compiled from, so caches can
+/// tell when they are stale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SourceStamp {
+    pub mtime: u64,
+    pub hash: u64,
+}
+
+impl SourceStamp {
+    /// Stamps `source`, read from `path`. A file whose modification time is
+    /// unavailable gets an mtime of 0 and is recognised by its hash alone.
+    pub fn new(path: &Path, source: &[u8]) -> Self {
+        Self {
+            mtime: modified(path),
+            hash: fnv1a(source),
+        }
+    }
+}
+
+/// The modification time of `path` in nanoseconds since the Unix epoch
+pub fn modified(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// The directory compiled imports are cached in: `CRABBY_CACHE_DIR` when it
+/// is set, where an empty value turns the cache off, and otherwise the
+/// platform's per-user cache directory
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("CRABBY_CACHE_DIR") {
+        return (!dir.is_empty()).then(|| PathBuf::from(dir));
+    }
+    let env_dir = |name: &str| std::env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    if cfg!(windows) {
+        env_dir("LOCALAPPDATA").map(|dir| dir.join("crabby").join("cache"))
+    } else {
+        env_dir("XDG_CACHE_HOME")
+            .or_else(|| env_dir("HOME").map(|home| home.join(".cache")))
+            .map(|dir| dir.join("crabby"))
+    }
+}
+
+/// Where the compiled form of the source at `path` is cached. The name
+/// keeps the file stem for readability and hashes the full path, so
+/// modules with the same name in different directories don't collide.
+pub fn cache_path(path: &Path) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let key = fnv1a(path.as_os_str().as_encoded_bytes());
+    cache_dir().map(|dir| dir.join(format!("{}-{:016x}.crabc", stem, key)))
+}
+
+pub fn encode(module: &CompiledModule, stamp: SourceStamp) -> Vec<u8> {
+    let mut payload = Writer::default();
+    payload.u32(module.exports.len() as u32);
+    for export in &module.exports {
+        payload.string(&export.name);
+        payload.u8(export.public as u8);
+    }
+    payload.proto(&module.proto);
+    let payload = payload.bytes;
+
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&stamp.mtime.to_le_bytes());
+    bytes.extend_from_slice(&stamp.hash.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Reads just the header, to check a cache without decoding the whole module
+pub fn read_stamp(bytes: &[u8]) -> Result<SourceStamp, CrabbyError> {
+    let mut header = Reader::new(bytes);
+    if header.take(MAGIC.len())? != MAGIC {
+        return Err(error("Not a Crabby bytecode file"));
+    }
+    let version = header.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(CrabbyError::BytecodeError(format!(
+            "Unsupported bytecode version {} (expected {})",
+            version, FORMAT_VERSION
+        )));
+    }
+    Ok(SourceStamp {
+        mtime: header.u64()?,
+        hash: header.u64()?,
+    })
+}
+
+pub fn decode(bytes: &[u8]) -> Result<(SourceStamp, CompiledModule), CrabbyError> {
+    let stamp = read_stamp(bytes)?;
+
+    let mut header = Reader::new(&bytes[HEADER_LEN - 8..]);
+    let len = header.u32()? as usize;
+    let checksum = header.u32()?;
+    let payload = &bytes[HEADER_LEN..];
+    if payload.len() != len || crc32fast::hash(payload) != checksum {
+        return Err(error("Bytecode checksum mismatch; the file is corrupt"));
+    }
+
+    let mut reader = Reader::new(payload);
+    let mut exports = Vec::new();
+    for _ in 0..reader.u32()? {
+        exports.push(Export {
+            name: reader.string()?,
+            public: reader.u8()? != 0,
+        });
+    }
+    let proto = reader.proto()?;
+    // The top level runs without a closure around it to capture from
+    if !proto.captures.is_empty() {
+        return Err(error("Malformed bytecode"));
+    }
+    if reader.pos != payload.len() {
+        return Err(error("Trailing data after bytecode"));
+    }
+
+    Ok((stamp, CompiledModule { proto, exports }))
+}
+
+fn error(message: &str) -> CrabbyError {
+    CrabbyError::BytecodeError(message.to_string())
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn optional(&mut self, value: Option<u32>) {
+        self.u32(value.unwrap_or(NO_INDEX));
+    }
+
+    fn proto(&mut self, proto: &FunctionProto) {
+        self.string(&proto.name);
+        self.u32(proto.arity);
+        self.u32(proto.slots);
+        self.u32(proto.cells);
+
+        self.u32(proto.param_cells.len() as u32);
+        for &(slot, cell) in &proto.param_cells {
+            self.u32(slot);
+            self.u32(cell);
+        }
+
+        self.u32(proto.captures.len() as u32);
+        for capture in &proto.captures {
+            match *capture {
+                Capture::Cell(index) => {
+                    self.u8(0);
+                    self.u32(index);
+                }
+                Capture::Upvalue(index) => {
+                    self.u8(1);
+                    self.u32(index);
+                }
+            }
+        }
+
+        self.u32(proto.constants.len() as u32);
+        for constant in &proto.constants {
+            match constant {
+                Constant::Integer(n) => {
+                    self.u8(0);
+                    self.u64(*n as u64);
+                }
+                Constant::Float(f) => {
+                    self.u8(1);
+                    self.u64(f.to_bits());
+                }
+                Constant::String(s) => {
+                    self.u8(2);
+                    self.string(s);
+                }
+                Constant::Function(proto) => {
+                    self.u8(3);
+                    self.proto(proto);
+                }
+            }
+        }
+
+        self.u32(proto.specs.len() as u32);
+        for spec in &proto.specs {
+            self.spec(spec);
+        }
+
+        self.u32(proto.code.len() as u32);
+        for op in &proto.code {
+            self.op(*op);
+        }
+    }
+
+    fn spec(&mut self, spec: &FormatSpec) {
+        self.u32(spec.fill as u32);
+        self.u8(match spec.align {
+            None => 0,
+            Some(FormatAlign::Left) => 1,
+            Some(FormatAlign::Right) => 2,
+            Some(FormatAlign::Center) => 3,
+        });
+        self.u8(spec.sign as u8);
+        self.u8(spec.zero_pad as u8);
+        self.optional(spec.width.map(|width| width as u32));
+        self.optional(spec.precision.map(|precision| precision as u32));
+        self.u32(spec.kind.map_or(0, |kind| kind as u32));
+    }
+
+    fn op(&mut self, op: Op) {
+        match op {
+            Op::Constant(index) => self.op1(0, index),
+            Op::PushNone => self.u8(1),
+            Op::Pop => self.u8(2),
+            Op::LoadLocal(slot) => self.op1(3, slot),
+            Op::StoreLocal(slot) => self.op1(4, slot),
+            Op::LoadCell(cell) => self.op1(5, cell),
+            Op::StoreCell(cell) => self.op1(6, cell),
+            Op::LoadUpvalue(index) => self.op1(7, index),
+            Op::LoadGlobal(name) => self.op1(8, name),
+            Op::StoreGlobal(name) => self.op1(9, name),
+            Op::Unary(op) => {
+                self.u8(10);
+                self.u8(unary_code(op));
+            }
+            Op::Binary(op) => {
+                self.u8(11);
+                self.u8(binary_code(op));
+            }
+            Op::BinaryLocalConst { op, slot, constant } => {
+                self.u8(12);
+                self.u8(binary_code(op));
+                self.u32(slot);
+                self.u32(constant);
+            }
+            Op::Truthy => self.u8(13),
+            Op::Jump(target) => self.op1(14, target),
+            Op::JumpIfFalse(target) => self.op1(15, target),
+            Op::JumpIfTrue(target) => self.op1(16, target),
+            Op::JumpIfNotNone(target) => self.op1(17, target),
+            Op::ExpectInteger(message) => self.op1(18, message),
+            Op::ForIter { counter, exit } => {
+                self.op1(19, counter);
+                self.u32(exit);
+            }
+            Op::Call(argc) => self.op1(20, argc),
+            Op::CallGlobal { name, argc } => {
+                self.op1(21, name);
+                self.u32(argc);
+            }
+            Op::Return => self.u8(22),
+            Op::Closure(index) => self.op1(23, index),
+            Op::Import { name, source } => {
+                self.op1(24, name);
+                self.u32(source);
+            }
+            Op::Format(spec) => {
+                self.u8(25);
+                self.optional(spec);
+            }
+            Op::Concat(count) => self.op1(26, count),
+            Op::MakeList(count) => self.op1(27, count),
+            Op::Index => self.u8(28),
+            Op::Member(name) => self.op1(29, name),
+            Op::MakeDict(count) => self.op1(30, count),
+            Op::MakeKeywords(count) => self.op1(31, count),
+        }
+    }
+
+    fn op1(&mut self, code: u8, operand: u32) {
+        self.u8(code);
+        self.u32(operand);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CrabbyError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| error("Unexpected end of bytecode"))?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CrabbyError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, CrabbyError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, CrabbyError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, CrabbyError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CrabbyError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, CrabbyError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| error("Invalid UTF-8 in bytecode"))
+    }
+
+    fn optional(&mut self) -> Result<Option<u32>, CrabbyError> {
+        Ok(Some(self.u32()?).filter(|&value| value != NO_INDEX))
+    }
+
+    fn char(&mut self) -> Result<char, CrabbyError> {
+        char::from_u32(self.u32()?).ok_or_else(|| error("Invalid character in bytecode"))
+    }
+
+    fn proto(&mut self) -> Result<Rc<FunctionProto>, CrabbyError> {
+        let mut proto = FunctionProto {
+            name: self.string()?,
+            arity: self.u32()?,
+            slots: self.u32()?,
+            cells: self.u32()?,
+            ..FunctionProto::default()
+        };
+
+        for _ in 0..self.u32()? {
+            proto.param_cells.push((self.u32()?, self.u32()?));
+        }
+
+        for _ in 0..self.u32()? {
+            proto.captures.push(match self.u8()? {
+                0 => Capture::Cell(self.u32()?),
+                1 => Capture::Upvalue(self.u32()?),
+                _ => return Err(error("Invalid capture in bytecode")),
+            });
+        }
+
+        for _ in 0..self.u32()? {
+            proto.constants.push(match self.u8()? {
+                0 => Constant::Integer(self.u64()? as i64),
+                1 => Constant::Float(f64::from_bits(self.u64()?)),
+                2 => Constant::String(Rc::from(self.string()?)),
+                3 => Constant::Function(self.proto()?),
+                _ => return Err(error("Invalid constant in bytecode")),
+            });
+        }
+
+        for _ in 0..self.u32()? {
+            let spec = self.spec()?;
+            proto.specs.push(spec);
+        }
+
+        for _ in 0..self.u32()? {
+            let op = self.op()?;
+            proto.code.push(op);
+        }
+
+        validate(&proto)?;
+        Ok(Rc::new(proto))
+    }
+
+    fn spec(&mut self) -> Result<FormatSpec, CrabbyError> {
+        Ok(FormatSpec {
+            fill: self.char()?,
+            align: match self.u8()? {
+                0 => None,
+                1 => Some(FormatAlign::Left),
+                2 => Some(FormatAlign::Right),
+                3 => Some(FormatAlign::Center),
+                _ => return Err(error("Invalid format alignment in bytecode")),
+            },
+            sign: self.u8()? != 0,
+            zero_pad: self.u8()? != 0,
+            width: self.optional()?.map(|width| width as usize),
+            precision: self.optional()?.map(|precision| precision as usize),
+            kind: match self.u32()? {
+                0 => None,
+                _ => {
+                    self.pos -= 4;
+                    Some(self.char()?)
+                }
+            },
+        })
+    }
+
+    fn op(&mut self) -> Result<Op, CrabbyError> {
+        Ok(match self.u8()? {
+            0 => Op::Constant(self.u32()?),
+            1 => Op::PushNone,
+            2 => Op::Pop,
+            3 => Op::LoadLocal(self.u32()?),
+            4 => Op::StoreLocal(self.u32()?),
+            5 => Op::LoadCell(self.u32()?),
+            6 => Op::StoreCell(self.u32()?),
+            7 => Op::LoadUpvalue(self.u32()?),
+            8 => Op::LoadGlobal(self.u32()?),
+            9 => Op::StoreGlobal(self.u32()?),
+            10 => Op::Unary(unary_op(self.u8()?)?),
+            11 => Op::Binary(binary_op(self.u8()?)?),
+            12 => Op::BinaryLocalConst {
+                op: binary_op(self.u8()?)?,
+                slot: self.u32()?,
+                constant: self.u32()?,
+            },
+            13 => Op::Truthy,
+            14 => Op::Jump(self.u32()?),
+            15 => Op::JumpIfFalse(self.u32()?),
+            16 => Op::JumpIfTrue(self.u32()?),
+            17 => Op::JumpIfNotNone(self.u32()?),
+            18 => Op::ExpectInteger(self.u32()?),
+            19 => Op::ForIter {
+                counter: self.u32()?,
+                exit: self.u32()?,
+            },
+            20 => Op::Call(self.u32()?),
+            21 => Op::CallGlobal {
+                name: self.u32()?,
+                argc: self.u32()?,
+            },
+            22 => Op::Return,
+            23 => Op::Closure(self.u32()?),
+            24 => Op::Import {
+                name: self.u32()?,
+                source: self.u32()?,
+            },
+            25 => Op::Format(self.optional()?),
+            26 => Op::Concat(self.u32()?),
+            27 => Op::MakeList(self.u32()?),
+            28 => Op::Index,
+            29 => Op::Member(self.u32()?),
+            30 => Op::MakeDict(self.u32()?),
+            31 => Op::MakeKeywords(self.u32()?),
+            _ => return Err(error("Invalid instruction in bytecode")),
+        })
+    }
+}
+
+const UNARY_OPS: [UnaryOp; 2] = [UnaryOp::Neg, UnaryOp::Not];
+
+const BINARY_OPS: [BinaryOp; 15] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Eq,
+    BinaryOp::Ne,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Le,
+    BinaryOp::Ge,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Dot,
+    BinaryOp::FloorDiv,
+    BinaryOp::Mod,
+];
+
+fn unary_code(op: UnaryOp) -> u8 {
+    UNARY_OPS.iter().position(|&o| o == op).unwrap_or(0) as u8
+}
+
+fn unary_op(code: u8) -> Result<UnaryOp, CrabbyError> {
+    UNARY_OPS
+        .get(code as usize)
+        .copied()
+        .ok_or_else(|| error("Invalid unary operator in bytecode"))
+}
+
+fn binary_code(op: BinaryOp) -> u8 {
+    BINARY_OPS.iter().position(|&o| o == op).unwrap_or(0) as u8
+}
+
+fn binary_op(code: u8) -> Result<BinaryOp, CrabbyError> {
+    BINARY_OPS
+        .get(code as usize)
+        .copied()
+        .ok_or_else(|| error("Invalid binary operator in bytecode"))
+}
+
+/// Checks that every index an instruction carries is in range, that every
+/// instruction finds the operands it pops on the stack, and that closures
+/// capture cells and upvalues their enclosing function has, so a file that
+/// decodes cleanly cannot make the VM index out of bounds
+fn validate(proto: &FunctionProto) -> Result<(), CrabbyError> {
+    let constants = proto.constants.len() as u32;
+    let is_string = |index: u32| {
+        matches!(
+            proto.constants.get(index as usize),
+            Some(Constant::String(_))
+        )
+    };
+    let code_len = proto.code.len() as u32;
+
+    let valid = |op: &Op| match *op {
+        Op::Constant(index) => {
+            index < constants && !matches!(proto.constants[index as usize], Constant::Function(_))
+        }
+        Op::LoadLocal(slot) | Op::StoreLocal(slot) => slot < proto.slots,
+        Op::LoadCell(cell) | Op::StoreCell(cell) => cell < proto.cells,
+        Op::LoadUpvalue(index) => index < proto.captures.len() as u32,
+        Op::LoadGlobal(name) | Op::StoreGlobal(name) | Op::ExpectInteger(name) => is_string(name),
+        Op::CallGlobal { name, .. } | Op::Member(name) => is_string(name),
+        Op::BinaryLocalConst { slot, constant, .. } => slot < proto.slots && constant < constants,
+        Op::Jump(target)
+        | Op::JumpIfFalse(target)
+        | Op::JumpIfTrue(target)
+        | Op::JumpIfNotNone(target) => target < code_len,
+        // The loop limit lives in the slot after the counter
+        Op::ForIter { counter, exit } => {
+            counter.checked_add(1).is_some_and(|limit| limit < proto.slots) && exit < code_len
+        }
+        Op::Closure(index) => matches!(
+            proto.constants.get(index as usize),
+            Some(Constant::Function(_))
+        ),
+        Op::Import { name, source } => is_string(name) && (source == NO_INDEX || is_string(source)),
+        Op::Format(spec) => spec.is_none_or(|spec| spec < proto.specs.len() as u32),
+        Op::PushNone
+        | Op::Pop
+        | Op::Unary(_)
+        | Op::Binary(_)
+        | Op::Truthy
+        | Op::Call(_)
+        | Op::Return
+        | Op::Concat(_)
+        | Op::MakeList(_)
+        | Op::MakeDict(_)
+        | Op::MakeKeywords(_)
+        | Op::Index => true,
+    };
+
+    let in_range = proto.code.iter().all(valid)
+        && proto
+            .param_cells
+            .iter()
+            .all(|&(slot, cell)| slot < proto.slots && cell < proto.cells)
+        && matches!(proto.code.last(), Some(Op::Return | Op::Jump(_)))
+        && proto.constants.iter().all(|constant| match constant {
+            Constant::Function(inner) => inner.captures.iter().all(|capture| match *capture {
+                Capture::Cell(cell) => cell < proto.cells,
+                Capture::Upvalue(index) => index < proto.captures.len() as u32,
+            }),
+            _ => true,
+        });
+    if in_range && balanced(proto) {
+        Ok(())
+    } else {
+        Err(error("Malformed bytecode"))
+    }
+}
+
+/// Whether every path through the code pops only values it pushed, reaches
+/// each instruction with the same stack depth and ends in a `Return`
+fn balanced(proto: &FunctionProto) -> bool {
+    let mut depths = vec![None; proto.code.len()];
+    let mut pending = vec![(0usize, 0u32)];
+
+    while let Some((ip, depth)) = pending.pop() {
+        let Some(&op) = proto.code.get(ip) else {
+            return false;
+        };
+        match depths[ip] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => return false,
+            None => depths[ip] = Some(depth),
+        }
+
+        let (pops, pushes) = match op {
+            Op::Constant(_)
+            | Op::PushNone
+            | Op::LoadLocal(_)
+            | Op::LoadCell(_)
+            | Op::LoadUpvalue(_)
+            | Op::LoadGlobal(_)
+            | Op::BinaryLocalConst { .. }
+            | Op::Closure(_)
+            | Op::Import { .. } => (0, 1),
+            Op::Pop
+            | Op::StoreLocal(_)
+            | Op::StoreCell(_)
+            | Op::StoreGlobal(_)
+            | Op::JumpIfFalse(_)
+            | Op::JumpIfTrue(_)
+            | Op::Return => (1, 0),
+            Op::Unary(_)
+            | Op::Truthy
+            | Op::JumpIfNotNone(_)
+            | Op::ExpectInteger(_)
+            | Op::Format(_)
+            | Op::Member(_) => (1, 1),
+            Op::Binary(_) | Op::Index => (2, 1),
+            Op::Jump(_) | Op::ForIter { .. } => (0, 0),
+            Op::Call(argc) => (argc.saturating_add(1), 1),
+            Op::CallGlobal { argc, .. } | Op::Concat(argc) | Op::MakeList(argc) => (argc, 1),
+            Op::MakeDict(count) | Op::MakeKeywords(count) => (count.saturating_mul(2), 1),
+        };
+        let Some(after) = depth.checked_sub(pops).and_then(|d| d.checked_add(pushes)) else {
+            return false;
+        };
+
+        match op {
+            Op::Jump(target) => pending.push((target as usize, after)),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) | Op::JumpIfNotNone(target) => {
+                pending.push((target as usize, after));
+                pending.push((ip + 1, after));
+            }
+            Op::ForIter { exit, .. } => {
+                let Some(element) = after.checked_add(1) else {
+                    return false;
+                };
+                pending.push((exit as usize, after));
+                pending.push((ip + 1, element));
+            }
+            Op::Return => {}
+            _ => pending.push((ip + 1, after)),
+        }
+    }
+    true
+}
@@ -1,502 +1,302 @@
+//! The front-end: lowers a parsed `Program` into checked IR for the runtime.
+//! Name resolution problems and misplaced `return`s are reported here, before
+//! anything runs.
+
+use crate::ir::{self, Expr, Stmt};
+use crate::lexer::tokenize;
+use crate::parser::ast::*;
+use crate::parser::parser::parse;
+use crate::runtime::ModuleLoader;
 use crate::utils::CrabbyError;
-use std::collections::HashMap;
-use std::path::{Path, PathBuf};
-use crate::fs;
-pub use crate::parser::ast::*;
-pub use crate::parser::parser::*;
-use crate::lexer::*;
+use std::collections::HashSet;
+use std::rc::Rc;
 
 pub struct Compiler {
-    variables: HashMap<String, Value>,
-    functions: HashMap<String, Function>,
-    module: Module,
-    current_file: Option<PathBuf>,
-}
-
-#[derive(Clone)]
-enum Value {
-    Integer(i64),
-    Float(f64),
-    String(String),
-    Lambda(Function),
-}
-
-impl Value {
-    fn to_string(&self) -> String {
-        match self {
-            Value::Integer(n) => n.to_string(),
-            Value::Float(f) => f.to_string(),
-            Value::String(s) => s.clone(),
-            Value::Lambda(_) => "<lambda>".to_string(),
-        }
-    }
-}
-
-#[derive(Clone)]
-struct Function {
-    params: Vec<String>,
-    body: Box<Statement>,
-}
-
-#[derive(Clone)]
-struct Module {
-    public_items: HashMap<String, Value>,
-    private_items: HashMap<String, Value>,
+    /// Names defined before this program runs: builtins and earlier REPL input
+    globals: HashSet<String>,
+    /// Names bound in each enclosing scope, innermost last
+    scopes: Vec<HashSet<String>>,
+    function_depth: usize,
 }
 
 impl Compiler {
-    pub fn new(file_path: Option<PathBuf>) -> Self {
-        let mut compiler = Self {
-            variables: HashMap::new(),
-            functions: HashMap::new(),
-            module: Module {
-                public_items: HashMap::new(),
-                private_items: HashMap::new(),
-            },
-            current_file: file_path,
-        };
-
-        // Add built-in print function
-        compiler.functions.insert("print".to_string(), Function {
-            params: vec!["value".to_string()],
-            body: Box::new(Statement::Expression(Expression::Variable("value".to_string()))),
-        });
-
-        compiler
-    }
-
-    fn new_module() -> Module {
-        Module {
-            public_items: HashMap::new(),
-            private_items: HashMap::new(),
-        }
-    }
-
-    fn compile_function_def(&mut self, name: &str, params: &[String], body: &Statement) -> Result<(), CrabbyError> {
-        let is_public = name.starts_with("pub ");
-        let func_name = if is_public {
-            name.trim_start_matches("pub ").to_string()
-        } else {
-            name.to_string()
-        };
-
-        let function = Function {
-            params: params.to_vec(),
-            body: Box::new(body.clone()),
-        };
-
-        if is_public {
-            self.module.public_items.insert(func_name, Value::Lambda(function));
-        } else {
-            self.module.private_items.insert(func_name, Value::Lambda(function));
-        }
-
-        Ok(())
-    }
-
-    fn compile_let_statement(&mut self, name: &str, value: &Expression) -> Result<(), CrabbyError> {
-        let is_public = name.starts_with("pub ");
-        let var_name = if is_public {
-            name.trim_start_matches("pub ").to_string()
-        } else {
-            name.to_string()
-        };
-
-        let compiled_value = self.compile_expression(value)?;
-
-        if is_public {
-            self.module.public_items.insert(var_name, compiled_value);
-        } else {
-            self.module.private_items.insert(var_name, compiled_value);
+    pub fn new<I: IntoIterator<Item = String>>(globals: I) -> Self {
+        Self {
+            globals: globals.into_iter().collect(),
+            scopes: Vec::new(),
+            function_depth: 0,
         }
-
-        Ok(())
     }
 
-    fn import_item(&mut self, module: &Module, item_name: &str) -> Result<(), CrabbyError> {
-        if let Some(value) = module.public_items.get(item_name) {
-            self.variables.insert(item_name.to_string(), value.clone());
-            Ok(())
-        } else if module.private_items.contains_key(item_name) {
-            Err(CrabbyError::CompileError(format!(
-                "Cannot import private item '{}' from module",
-                item_name
-            )))
-        } else {
-            Err(CrabbyError::CompileError(format!(
-                "Item '{}' not found in module",
-                item_name
-            )))
-        }
-    }
-
-    fn handle_lambda_call(&mut self, lambda: Function, arguments: &[Expression]) -> Result<Value, CrabbyError> {
-        if arguments.len() != lambda.params.len() {
-            return Err(CrabbyError::CompileError(format!(
-                "Lambda expects {} arguments, got {}",
-                lambda.params.len(),
-                arguments.len()
-            )));
-        }
+    pub fn compile(&mut self, program: &Program) -> Result<ir::Module, CrabbyError> {
+        // Top-level names are visible everywhere, so functions can call
+        // functions that are defined further down
+        let mut names = HashSet::new();
+        collect_bindings(&program.statements, &mut names);
 
-        for (param, arg) in lambda.params.iter().zip(arguments) {
-            let arg_value = self.compile_expression(arg)?;
-            self.variables.insert(param.clone(), arg_value);
-        }
+        self.scopes.push(names);
+        let body = self.compile_statements(&program.statements);
+        self.scopes.pop();
 
-        match self.compile_statement(&lambda.body)? {
-            Some(value) => Ok(value),
-            None => Ok(Value::Integer(0)),
-        }
+        Ok(ir::Module { body: body? })
     }
 
-    fn handle_print(&mut self, args: &[Expression]) -> Result<Value, CrabbyError> {
-        if args.len() != 1 {
-            return Err(CrabbyError::CompileError("print takes exactly one argument".to_string()));
+    fn compile_statements(&mut self, statements: &[Statement]) -> Result<Vec<Stmt>, CrabbyError> {
+        let mut result = Vec::with_capacity(statements.len());
+        for statement in statements {
+            self.compile_statement(statement, &mut result)?;
         }
-
-        let value = self.compile_expression(&args[0])?;
-        println!("{}", value.to_string());
-        Ok(Value::Integer(0))
+        Ok(result)
     }
 
-    pub fn compile(&mut self, program: &Program) -> Result<(), CrabbyError> {
-        for statement in &program.statements {
-            self.compile_statement(statement)?;
+    /// Lowers a block body; blocks do not introduce scopes, so they are flattened
+    fn compile_body(&mut self, body: &Statement) -> Result<Vec<Stmt>, CrabbyError> {
+        match body {
+            Statement::Block(statements) => self.compile_statements(statements),
+            other => self.compile_statements(std::slice::from_ref(other)),
         }
-        Ok(())
     }
 
-    fn resolve_path(&self, current_file: &Path, import_path: &str) -> PathBuf {
-        if let Some(current_dir) = current_file.parent() {
-            if import_path.starts_with("./") {
-                // Handle explicit relative path
-                current_dir.join(&import_path[2..])
-            } else if import_path.starts_with("../") {
-                // Handle parent directory reference
-                current_dir.join(import_path)
-            } else {
-                // Handle implicit relative path
-                current_dir.join(import_path)
+    fn compile_statement(&mut self, statement: &Statement, out: &mut Vec<Stmt>) -> Result<(), CrabbyError> {
+        let lowered = match statement {
+            Statement::FunctionDef { name, params, body, public, doc } => {
+                let decl = self.compile_function(name, params, body, *public, doc.clone())?;
+                Stmt::Function(decl)
             }
-        } else {
-            // Fallback to current directory if no parent
-            PathBuf::from(import_path)
-        }
-    }
-
-    fn load_module(&mut self, current_file: &Path, name: &str, source: &str) -> Result<(), CrabbyError> {
-        let resolved_path = self.resolve_path(current_file, source);
-
-        println!("Trying to load: {}", resolved_path.display());
-        
-        // Try to read the source file
-        let source_code = fs::read_to_string(&resolved_path).map_err(|e| {
-            CrabbyError::CompileError(format!(
-                "Failed to read module '{}': {} (resolved path: {})", 
-                source, 
-                e,
-                resolved_path.display()
-            ))
-        })?;
-
-        // Tokenizes and parses the imported file
-        let tokens = tokenize(&source_code)?;
-        let ast = parse(tokens)?;
-
-        // Creates a new compiler instance for the module
-        let mut module_compiler = Compiler::new(Some(resolved_path));
-        module_compiler.compile(&ast)?;
-    
-        // Only exposes public functions
-        for (name, value) in module_compiler.module.public_items {
-            self.variables.insert(name, value);
-        }
+            Statement::Let { name, value, public, .. } => Stmt::Let {
+                name: name.clone(),
+                value: self.compile_expression(value)?,
+                public: *public,
+            },
+            Statement::Return(expr) => {
+                if self.function_depth == 0 {
+                    return Err(CrabbyError::CompileError(
+                        "'return' outside of a function".to_string(),
+                    ));
+                }
+                Stmt::Return(self.compile_expression(expr)?)
+            }
+            Statement::If { condition, then_branch, else_branch } => Stmt::If {
+                condition: self.compile_expression(condition)?,
+                then_branch: self.compile_body(then_branch)?,
+                else_branch: match else_branch {
+                    Some(else_branch) => self.compile_body(else_branch)?,
+                    None => Vec::new(),
+                },
+            },
+            Statement::While { condition, body } => Stmt::While {
+                condition: self.compile_expression(condition)?,
+                body: self.compile_body(body)?,
+            },
+            Statement::Loop { count, body } => Stmt::Loop {
+                count: self.compile_expression(count)?,
+                body: self.compile_body(body)?,
+            },
+            Statement::ForIn { variable, iterator, body } => Stmt::ForIn {
+                variable: variable.clone(),
+                iterable: self.compile_expression(iterator)?,
+                body: self.compile_body(body)?,
+            },
+            Statement::Import { name, source } => Stmt::Import {
+                name: name.clone(),
+                source: source.clone(),
+            },
+            Statement::Block(statements) => {
+                for statement in statements {
+                    self.compile_statement(statement, out)?;
+                }
+                return Ok(());
+            }
+            Statement::Expression(expr) => Stmt::Expr(self.compile_expression(expr)?),
+        };
 
+        out.push(lowered);
         Ok(())
     }
 
-    fn compile_statement(&mut self, statement: &Statement) -> Result<Option<Value>, CrabbyError> {
-        match statement {
-            Statement::FunctionDef { name, params, body } => {
-                let is_public = name.starts_with("pub ");
-                let func_name = if is_public {
-                    name.trim_start_matches("pub ").to_string()
+    fn compile_function(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &Statement,
+        public: bool,
+        doc: Option<String>,
+    ) -> Result<Rc<ir::FunctionDecl>, CrabbyError> {
+        let mut names = HashSet::new();
+        for param in params {
+            if !names.insert(param.clone()) {
+                let owner = if name.is_empty() {
+                    "lambda".to_string()
                 } else {
-                    name.to_string()
-                };
-
-                let function = Function {
-                    params: params.clone(),
-                    body: body.clone(),
+                    format!("function '{}'", name)
                 };
+                return Err(CrabbyError::CompileError(format!(
+                    "Duplicate parameter '{}' in {}",
+                    param, owner
+                )));
+            }
+        }
+        collect_bindings(std::slice::from_ref(body), &mut names);
 
-                if is_public {
-                    self.module.public_items.insert(func_name.clone(), Value::Lambda(function.clone()));
-                } else {
-                    self.module.private_items.insert(func_name.clone(), Value::Lambda(function.clone()));
-                }
+        self.scopes.push(names);
+        self.function_depth += 1;
+        let body = self.compile_body(body);
+        self.function_depth -= 1;
+        self.scopes.pop();
 
-                self.functions.insert(func_name, function);
+        Ok(Rc::new(ir::FunctionDecl {
+            name: name.to_string(),
+            params: params.to_vec(),
+            body: body?,
+            public,
+            doc,
+        }))
+    }
 
-                Ok(None)
+    fn compile_expression(&mut self, expression: &Expression) -> Result<Expr, CrabbyError> {
+        Ok(match expression {
+            Expression::Integer(n) => Expr::Integer(*n),
+            Expression::Float(f) => Expr::Float(*f),
+            Expression::String(s) => Expr::String(s.clone()),
+            Expression::Variable(name) => {
+                self.check_defined(name, "variable")?;
+                Expr::Variable(name.clone())
             }
-            Statement::Let { name, value } => {
-                let is_public = name.starts_with("pub ");
-                let var_name = if is_public {
-                    name.trim_start_matches("pub ").to_string()
-                } else {
-                    name.to_string()
-                };
-
-                let compiled_value = self.compile_expression(value)?;
-
-                // Store in both places - module for exports and variables for local use
-                if is_public {
-                    self.module.public_items.insert(var_name.clone(), compiled_value.clone());
-                } else {
-                    self.module.private_items.insert(var_name.clone(), compiled_value.clone());
+            Expression::Range(count) => Expr::Range(Box::new(self.compile_expression(count)?)),
+            Expression::Unary { operator, operand } => Expr::Unary {
+                op: *operator,
+                operand: Box::new(self.compile_expression(operand)?),
+            },
+            Expression::Binary { left, operator, right } => Expr::Binary {
+                left: Box::new(self.compile_expression(left)?),
+                op: *operator,
+                right: Box::new(self.compile_expression(right)?),
+            },
+            Expression::Call { function, arguments } => {
+                self.check_defined(function, "function")?;
+                // Only builtins take keyword arguments, and a name the
+                // program binds is never a builtin
+                if matches!(arguments.last(), Some(Expression::Keywords(_)))
+                    && self.scopes.iter().any(|scope| scope.contains(function))
+                {
+                    return Err(keywords_not_taken(function));
+                }
+                Expr::Call {
+                    callee: function.clone(),
+                    args: arguments
+                        .iter()
+                        .map(|arg| self.compile_expression(arg))
+                        .collect::<Result<_, _>>()?,
                 }
-
-                // Always add to variables for local use
-                self.variables.insert(var_name, compiled_value);
-
-                Ok(None)
             }
-            Statement::Return(expr) => {
-                let value = self.compile_expression(expr)?;
-                Ok(Some(value))
+            Expression::CallValue { callee, arguments }
+                if matches!(**callee, Expression::Lambda { .. })
+                    && matches!(arguments.last(), Some(Expression::Keywords(_))) =>
+            {
+                return Err(keywords_not_taken("Lambda"));
             }
-            Statement::Loop { count, body } => {
-                let count_value = self.compile_expression(count)?;
-                if let Value::Integer(n) = count_value {
-                    for _ in 0..n {
-                        self.compile_statement(body)?;
-                    }
-                    Ok(None)
-                } else {
-                    Err(CrabbyError::CompileError("Loop count must be an integer".to_string()))
-                }
+            Expression::CallValue { callee, arguments } => Expr::CallValue {
+                callee: Box::new(self.compile_expression(callee)?),
+                args: arguments
+                    .iter()
+                    .map(|arg| self.compile_expression(arg))
+                    .collect::<Result<_, _>>()?,
+            },
+            Expression::List(elements) => Expr::List(
+                elements
+                    .iter()
+                    .map(|element| self.compile_expression(element))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expression::Dict(entries) => Expr::Dict(
+                entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.compile_expression(key)?, self.compile_expression(value)?)))
+                    .collect::<Result<_, CrabbyError>>()?,
+            ),
+            Expression::Keywords(arguments) => Expr::Keywords(
+                arguments
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.compile_expression(value)?)))
+                    .collect::<Result<_, CrabbyError>>()?,
+            ),
+            Expression::Index { object, index } => Expr::Index {
+                object: Box::new(self.compile_expression(object)?),
+                index: Box::new(self.compile_expression(index)?),
+            },
+            Expression::Member { object, name } => Expr::Member {
+                object: Box::new(self.compile_expression(object)?),
+                name: name.clone(),
+            },
+            Expression::Lambda { params, body } => {
+                Expr::Lambda(self.compile_function("", params, body, false, None)?)
             }
-            Statement::ForIn { variable, iterator, body } => {
-                let iter_value = self.compile_expression(iterator)?;
-                if let Value::Integer(n) = iter_value {
-                    for i in 0..n {
-                        self.variables.insert(variable.clone(), Value::Integer(i));
-                        self.compile_statement(body)?;
-                    }
-                    Ok(None)
-                } else {
-                    Err(CrabbyError::CompileError("Iterator must be a range".to_string()))
+            Expression::FormatString(parts) => {
+                let mut lowered = Vec::with_capacity(parts.len());
+                for part in parts {
+                    lowered.push(match part {
+                        FormatPart::Literal(text) => ir::FormatPart::Literal(text.clone()),
+                        FormatPart::Expression { expr, spec } => ir::FormatPart::Value {
+                            expr: self.compile_expression(expr)?,
+                            spec: spec.clone(),
+                        },
+                    });
                 }
+                Expr::Format(lowered)
             }
-            Statement::Import { name, source } => {
-                if let Some(source_path) = source {
-                    // Create a new compiler instance for the imported module
-                    let mut module_compiler = Compiler::new(Some(PathBuf::from(source_path)));
+        })
+    }
 
-                    // Load and compile the module
-                    let path = Path::new(source_path);
-                    let source_code = fs::read_to_string(path).map_err(|e| {
-                        CrabbyError::CompileError(format!("Failed to read module '{}': {}", source_path, e))
-                    })?;
+    fn check_defined(&self, name: &str, kind: &str) -> Result<(), CrabbyError> {
+        if self.globals.contains(name) || self.scopes.iter().any(|scope| scope.contains(name)) {
+            Ok(())
+        } else {
+            Err(CrabbyError::CompileError(format!("Undefined {}: {}", kind, name)))
+        }
+    }
+}
 
-                    let tokens = tokenize(&source_code)?;
-                    let ast = parse(tokens)?;
-                    module_compiler.compile(&ast)?;
+fn keywords_not_taken(name: &str) -> CrabbyError {
+    CrabbyError::CompileError(format!("{} does not take keyword arguments", name))
+}
 
-                    // Try to import the requested item
-                    if let Some(value) = module_compiler.module.public_items.get(name) {
-                        self.variables.insert(name.clone(), value.clone());
-                        Ok(None)
-                    } else if module_compiler.module.private_items.contains_key(name) {
-                        Err(CrabbyError::CompileError(format!(
-                            "Cannot import private item '{}' from module",
-                            name
-                        )))
-                    } else {
-                        Err(CrabbyError::CompileError(format!(
-                            "Item '{}' not found in module",
-                            name
-                        )))
-                    }
-                } else {
-                    Err(CrabbyError::CompileError("Standard library imports not yet implemented".to_string()))
-                }
-            }
-            Statement::If { condition, then_branch, else_branch } => {
-                let condition_value = self.compile_expression(condition)?;
-                match condition_value {
-                    Value::Integer(0) => {
-                        if let Some(else_branch) = else_branch {
-                            self.compile_statement(else_branch)
-                        } else {
-                            Ok(None)
-                        }
-                    }
-                    _ => self.compile_statement(then_branch),
-                }
+/// Collects the names a sequence of statements binds in its scope, looking
+/// into nested blocks but not into function bodies
+fn collect_bindings(statements: &[Statement], names: &mut HashSet<String>) {
+    for statement in statements {
+        match statement {
+            Statement::FunctionDef { name, .. }
+            | Statement::Let { name, .. }
+            | Statement::Import { name, .. } => {
+                names.insert(name.clone());
             }
-            Statement::While { condition, body } => {
-                loop {
-                    let condition_value = self.compile_expression(condition)?;
-                    match condition_value {
-                        Value::Integer(0) => break,
-                        _ => {
-                            if let Some(Value::Integer(-1)) = self.compile_statement(body)? {
-                                break;
-                            }
-                        }
-                    }
-                }
-                Ok(None)
+            Statement::ForIn { variable, body, .. } => {
+                names.insert(variable.clone());
+                collect_bindings(std::slice::from_ref(body), names);
             }
-            Statement::Block(statements) => {
-                let mut last_value = None;
-                for stmt in statements {
-                    last_value = self.compile_statement(stmt)?;
+            Statement::If { then_branch, else_branch, .. } => {
+                collect_bindings(std::slice::from_ref(then_branch), names);
+                if let Some(else_branch) = else_branch {
+                    collect_bindings(std::slice::from_ref(else_branch), names);
                 }
-                Ok(last_value)
             }
-            Statement::Expression(expr) => {
-                let value = self.compile_expression(expr)?;
-                Ok(Some(value))
+            Statement::While { body, .. } | Statement::Loop { body, .. } => {
+                collect_bindings(std::slice::from_ref(body), names);
             }
+            Statement::Block(statements) => collect_bindings(statements, names),
+            Statement::Return(_) | Statement::Expression(_) => {}
         }
     }
+}
 
-    // fn compile_statement_original(&mut self, statement: &Statement) -> Result<Option<Value>, CrabbyError> {
-    //    unimplemented!("Original statement compilation")
-    // }
-
-    fn compile_expression(&mut self, expression: &Expression) -> Result<Value, CrabbyError> {
-        match expression {
-            Expression::Integer(n) => Ok(Value::Integer(*n)),
-            Expression::Float(f) => Ok(Value::Float(*f)),
-            Expression::String(s) => Ok(Value::String(s.clone())),
-            Expression::Variable(name) => {
-                self.variables.get(name).cloned().ok_or_else(|| {
-                    CrabbyError::CompileError(format!("Undefined variable: {}", name))
-                })
-            },
-            Expression::Range(count) => {
-                let count_value = self.compile_expression(count)?;
-                if let Value::Integer(n) = count_value {
-                    Ok(Value::Integer(n))
-                } else {
-                    Err(CrabbyError::CompileError("Range argument must be an integer".to_string()))
-                }
-            },
-            Expression::Call { function, arguments } => {
-                if function == "print" {
-                    return self.handle_print(arguments);
-                }
-
-                if let Some(Value::Lambda(lambda)) = self.variables.get(function) {
-                    return self.handle_lambda_call(lambda.clone(), arguments);
-                }
-
-                let func = self.functions.get(function).cloned().ok_or_else(|| {
-                    CrabbyError::CompileError(format!("Undefined function: {}", function))
-                })?;
-
-                if arguments.len() != func.params.len() {
-                    return Err(CrabbyError::CompileError(format!(
-                        "Function {} expects {} arguments, got {}",
-                        function,
-                        func.params.len(),
-                        arguments.len()
-                    )));
-                }
-
-                let mut new_compiler = Compiler::new(None);
-                for (param, arg) in func.params.iter().zip(arguments) {
-                    let arg_value = self.compile_expression(arg)?;
-                    new_compiler.variables.insert(param.clone(), arg_value);
-                }
-
-                match new_compiler.compile_statement(&func.body)? {
-                    Some(value) => Ok(value),
-                    None => Ok(Value::Integer(0)),
-                }
-            },
-            Expression::Lambda { params, body } => {
-                Ok(Value::Lambda(Function {
-                    params: params.clone(),
-                    body: body.clone(),
-                }))
-            },
-            Expression::Binary { left, operator, right } => {
-                let left_val = self.compile_expression(left)?;
-                let right_val = self.compile_expression(right)?;
-
-                match (left_val, operator, right_val) {
-                    // Integer operations
-                    (Value::Integer(l), BinaryOp::Add, Value::Integer(r)) => Ok(Value::Integer(l + r)),
-                    (Value::Integer(l), BinaryOp::Sub, Value::Integer(r)) => Ok(Value::Integer(l - r)),
-                    (Value::Integer(l), BinaryOp::Mul, Value::Integer(r)) => Ok(Value::Integer(l * r)),
-                    (Value::Integer(l), BinaryOp::Div, Value::Integer(r)) => {
-                        if r == 0 {
-                            return Err(CrabbyError::CompileError("Division by zero".to_string()));
-                        }
-                        Ok(Value::Integer(l / r))
-                    }
-
-                    // Float operations
-                    (Value::Float(l), BinaryOp::Add, Value::Float(r)) => Ok(Value::Float(l + r)),
-                    (Value::Float(l), BinaryOp::Sub, Value::Float(r)) => Ok(Value::Float(l - r)),
-                    (Value::Float(l), BinaryOp::Mul, Value::Float(r)) => Ok(Value::Float(l * r)),
-                    (Value::Float(l), BinaryOp::Div, Value::Float(r)) => {
-                        if r == 0.0 {
-                            return Err(CrabbyError::CompileError("Division by zero".to_string()));
-                        }
-                        Ok(Value::Float(l / r))
-                    }
-
-                    // Mixed Integer and Float operations
-                    (Value::Integer(l), op, Value::Float(r)) => {
-                        let l = l as f64;
-                        match op {
-                            BinaryOp::Add => Ok(Value::Float(l + r)),
-                            BinaryOp::Sub => Ok(Value::Float(l - r)),
-                            BinaryOp::Mul => Ok(Value::Float(l * r)),
-                            BinaryOp::Div => {
-                                if r == 0.0 {
-                                    return Err(CrabbyError::CompileError("Division by zero".to_string()));
-                                }
-                                Ok(Value::Float(l / r))
-                            }
-                            BinaryOp::Eq => Ok(Value::Integer(if (l - r).abs() < f64::EPSILON { 1 } else { 0 })),
-                            BinaryOp::Dot => Err(CrabbyError::CompileError("Cannot use dot operator with numbers".to_string())),
-                        }
-                    }
-
-                    (Value::Float(l), op, Value::Integer(r)) => {
-                        let r = r as f64;
-                        match op {
-                            BinaryOp::Add => Ok(Value::Float(l + r)),
-                            BinaryOp::Sub => Ok(Value::Float(l - r)),
-                            BinaryOp::Mul => Ok(Value::Float(l * r)),
-                            BinaryOp::Div => {
-                                if r == 0.0 {
-                                    return Err(CrabbyError::CompileError("Division by zero".to_string()));
-                                }
-                                Ok(Value::Float(l / r))
-                            }
-                            BinaryOp::Eq => Ok(Value::Integer(if (l - r).abs() < f64::EPSILON { 1 } else { 0 })),
-                            BinaryOp::Dot => Err(CrabbyError::CompileError("Cannot use dot operator with numbers".to_string())),
-                        }
-                    }
-
-                    // String operations
-                    (Value::String(l), BinaryOp::Add, Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
-                    (Value::String(l), BinaryOp::Dot, Value::String(r)) => Ok(Value::String(format!("{}.{}", l, r))),
-                    (Value::String(l), BinaryOp::Add, r) => Ok(Value::String(format!("{}{}", l, r.to_string()))),
-                    (l, BinaryOp::Add, Value::String(r)) => Ok(Value::String(format!("{}{}", l.to_string(), r))),
+/// Lexes, parses and checks `source`, given the names already defined
+pub fn compile_source(source: &str, globals: &[String]) -> Result<ir::Module, CrabbyError> {
+    let tokens = tokenize(source)?;
+    let program = parse(tokens)?;
+    Compiler::new(globals.iter().cloned()).compile(&program)
+}
 
-                    _ => Err(CrabbyError::CompileError("Invalid operation".to_string())),
-                }
-            }
-        }
-    }
+/// The loader the runtime uses to turn imported files into IR
+pub fn module_loader() -> ModuleLoader {
+    Box::new(compile_source)
 }
@@ -0,0 +1,328 @@
+//! `crabby fmt`: reprints a program with consistent indentation and spacing.
+//! It works on the token stream, so comments and line breaks stay where the
+//! author put them.
+
+use crate::lexer::{Token, TokenStream, shebang, tokenize};
+use crate::parser::parser::parse;
+use crate::utils::CrabbyError;
+
+const INDENT: &str = "    ";
+
+/// Formats a program, failing if it does not lex and parse
+pub fn format_source(source: &str) -> Result<String, CrabbyError> {
+    let tokens = tokenize(source)?;
+    let formatted = Formatter::new(source).format(&tokens);
+
+    // Formatting only moves whitespace, so the program must parse the same
+    let before = format!("{:?}", parse(tokens)?);
+    let after = tokenize(&formatted)
+        .and_then(parse)
+        .map(|program| format!("{:?}", program));
+    if after.ok().as_ref() != Some(&before) {
+        return Err(CrabbyError::CompileError(
+            "Formatting would change the meaning of the program".to_string(),
+        ));
+    }
+
+    Ok(formatted)
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    /// The indentation of the line that opened each unclosed bracket and
+    /// indented block; their contents go one level deeper
+    levels: Vec<usize>,
+    /// The indentation of the line being written
+    line_indent: usize,
+    /// The last two tokens written, to tell unary from binary operators
+    previous: [Option<&'a Token>; 2],
+    /// Whether a comment was written since the last token
+    after_comment: bool,
+    /// Whether each unclosed brace is a dict literal rather than a block
+    braces: Vec<bool>,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            out: String::new(),
+            levels: Vec::new(),
+            line_indent: 0,
+            previous: [None, None],
+            after_comment: false,
+            braces: Vec::new(),
+        }
+    }
+
+    fn format(mut self, tokens: &'a [TokenStream<'a>]) -> String {
+        let shebang = shebang(self.source);
+        self.out.push_str(shebang.trim_end());
+        let mut position = shebang.len();
+        let mut layout = Vec::new();
+
+        for token in tokens {
+            // Indent and Dedent are zero-width and apply to the next real token
+            if token.slice.is_empty() {
+                layout.push(&token.token);
+                continue;
+            }
+
+            let gap = &self.source[position..token.span.start];
+            let starts_line = gap.contains('\n');
+            let depth_before = self.inner();
+            self.apply_layout(&mut layout);
+            let depth_after = match &token.token {
+                Token::RParen | Token::RBrace | Token::RBracket if starts_line => {
+                    self.levels.last().copied().unwrap_or(0)
+                }
+                // A line continuing an unfinished expression, or a pipeline
+                // stage on a line of its own, hangs one level deeper
+                _ if starts_line
+                    && (self.previous[0].is_some_and(continues_line)
+                        || token.token == Token::Pipe) =>
+                {
+                    self.inner() + 1
+                }
+                _ => self.inner(),
+            };
+
+            self.write_gap(position, gap, depth_before, depth_after, token.span.column);
+            if starts_line && !self.out.is_empty() {
+                self.line_indent = depth_after;
+                self.out.push_str(&INDENT.repeat(depth_after));
+            } else if self.after_comment
+                || (self.previous[0].is_some() && self.spaced(&token.token))
+            {
+                self.out.push(' ');
+            }
+
+            self.out.push_str(token.slice.trim_end());
+            self.after_comment = false;
+            match token.token {
+                Token::LParen | Token::LBrace | Token::LBracket => {
+                    self.levels.push(self.line_indent)
+                }
+                Token::RParen | Token::RBrace | Token::RBracket => {
+                    self.levels.pop();
+                }
+                _ => {}
+            }
+            match token.token {
+                Token::LBrace => self.braces.push(self.opens_dict()),
+                Token::RBrace => {
+                    self.braces.pop();
+                }
+                _ => {}
+            }
+            self.previous = [Some(&token.token), self.previous[0]];
+            position = token.span.end;
+        }
+
+        let depth_before = self.inner();
+        self.apply_layout(&mut layout);
+        let depth_after = self.inner();
+        let rest = &self.source[position..];
+        self.write_gap(position, rest, depth_before, depth_after, 1);
+
+        let end = self.out.trim_end().len();
+        self.out.truncate(end);
+        self.out.push('\n');
+        self.out
+    }
+
+    /// The indentation of a line inside the innermost open level
+    fn inner(&self) -> usize {
+        self.levels.last().map_or(0, |outer| outer + 1)
+    }
+
+    fn apply_layout(&mut self, layout: &mut Vec<&Token>) {
+        for token in layout.drain(..) {
+            match token {
+                Token::Indent => self.levels.push(self.line_indent),
+                _ => {
+                    self.levels.pop();
+                }
+            }
+        }
+    }
+
+    /// Writes the comments and line breaks between two tokens. A comment on a
+    /// line of its own lines up with the next token, unless it was indented
+    /// further than that token, when it stays in the block it was written in.
+    fn write_gap(
+        &mut self,
+        offset: usize,
+        gap: &str,
+        before: usize,
+        after: usize,
+        next_column: usize,
+    ) {
+        let mut newlines = 0;
+        let mut rest = gap;
+
+        while let Some(start) = rest.find("//").into_iter().chain(rest.find("/*")).min() {
+            newlines += rest[..start].matches('\n').count();
+            let comment = &rest[start..comment_len(&rest[start..]) + start];
+            let at = offset + (gap.len() - rest.len()) + start;
+
+            if newlines == 0 && !self.out.is_empty() {
+                self.out.push(' ');
+            } else {
+                self.line_break(newlines);
+                let column = self.source[..at]
+                    .rsplit('\n')
+                    .next()
+                    .unwrap_or("")
+                    .chars()
+                    .count()
+                    + 1;
+                let depth = if column > next_column {
+                    before.max(after)
+                } else {
+                    after
+                };
+                self.out.push_str(&INDENT.repeat(depth));
+            }
+
+            self.out.push_str(comment.trim_end());
+            self.after_comment = true;
+            newlines = 0;
+            rest = &rest[start + comment.len()..];
+        }
+
+        newlines += rest.matches('\n').count();
+        if newlines > 0 {
+            self.line_break(newlines);
+            self.after_comment = false;
+        }
+    }
+
+    /// Ends the current line, keeping at most one blank line and none at the
+    /// start of the file
+    fn line_break(&mut self, newlines: usize) {
+        if self.out.is_empty() {
+            return;
+        }
+        self.out.push('\n');
+        if newlines > 1 {
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether a `{` written after the previous tokens starts a dict literal.
+    /// Blocks open after `else` or a `:`, except the `:` after a key inside
+    /// a dict, which a lambda's `):` is not.
+    fn opens_dict(&self) -> bool {
+        match self.previous {
+            [Some(Token::Else), _] => false,
+            [Some(Token::Colon), before] => {
+                self.in_dict() && !matches!(before, Some(Token::RParen))
+            }
+            _ => true,
+        }
+    }
+
+    fn in_dict(&self) -> bool {
+        self.braces.last().copied().unwrap_or(false)
+    }
+
+    /// Whether a space separates the previous token from `next` on a line
+    fn spaced(&self, next: &Token) -> bool {
+        let [Some(previous), before] = self.previous else {
+            return false;
+        };
+
+        match (previous, next) {
+            (Token::LParen | Token::LBracket | Token::Dot | Token::Decorator, _) => false,
+            (_, Token::RParen | Token::RBracket | Token::Comma | Token::Colon | Token::Dot) => {
+                false
+            }
+            (Token::LBrace, Token::RBrace) => false,
+            // A member name, which may be a keyword as in `re.match`
+            (_, Token::LParen | Token::LBracket) if matches!(before, Some(Token::Dot)) => false,
+            (Token::LBrace, _) | (_, Token::RBrace) if self.in_dict() => false,
+            (
+                Token::Identifier(_) | Token::RParen | Token::RBracket | Token::Lambda | Token::Range,
+                Token::LParen,
+            ) => false,
+            // Indexing, as opposed to a list literal
+            (
+                Token::Identifier(_) | Token::RParen | Token::RBracket | Token::String(_),
+                Token::LBracket,
+            ) => false,
+            (Token::Not, _) => false,
+            (Token::Minus, _) => before.is_some_and(ends_operand),
+            _ => true,
+        }
+    }
+}
+
+/// Whether a line ending in this token leaves an expression unfinished
+fn continues_line(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Equals
+            | Token::Plus
+            | Token::Minus
+            | Token::Star
+            | Token::Slash
+            | Token::TildeSlash
+            | Token::Percent
+            | Token::NotEquals
+            | Token::LessThan
+            | Token::GreaterThan
+            | Token::LessThanOrEqual
+            | Token::GreaterThanOrEqual
+            | Token::DoubleEquals
+            | Token::And
+            | Token::Or
+            | Token::Pipe
+            | Token::Not
+            | Token::Dot
+    )
+}
+
+/// Whether a token can end an operand, making a following `-` binary
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Integer(_)
+            | Token::Float(_)
+            | Token::String(_)
+            | Token::FString(_)
+            | Token::RParen
+            | Token::RBracket
+            | Token::RBrace
+    )
+}
+
+/// The length of the comment at the start of `text`; block comments nest
+fn comment_len(text: &str) -> usize {
+    if text.starts_with("//") {
+        return text.find('\n').unwrap_or(text.len());
+    }
+
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    text.len()
+}
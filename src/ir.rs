@@ -0,0 +1,154 @@
+//! The checked intermediate representation handed from the front-end to the
+//! runtime. It does not depend on the lexer or parser, so the runtime can be
+//! embedded on its own.
+
+use std::rc::Rc;
+
+/// A checked program: the top-level statements of one source file
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+pub struct FunctionDecl {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub public: bool,
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Let {
+        name: String,
+        value: Expr,
+        public: bool,
+    },
+    Function(Rc<FunctionDecl>),
+    Return(Expr),
+    If {
+        condition: Expr,
+        then_branch: Vec<Stmt>,
+        else_branch: Vec<Stmt>,
+    },
+    While {
+        condition: Expr,
+        body: Vec<Stmt>,
+    },
+    Loop {
+        count: Expr,
+        body: Vec<Stmt>,
+    },
+    ForIn {
+        variable: String,
+        iterable: Expr,
+        body: Vec<Stmt>,
+    },
+    Import {
+        name: String,
+        source: Option<String>,
+    },
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Variable(String),
+    Range(Box<Expr>),
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        op: BinaryOp,
+        right: Box<Expr>,
+    },
+    Call {
+        callee: String,
+        args: Vec<Expr>,
+    },
+    /// A call of a value computed by an expression
+    CallValue {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    List(Vec<Expr>),
+    /// A dict literal's `key: value` pairs
+    Dict(Vec<(Expr, Expr)>),
+    /// The keyword arguments that end a call's arguments
+    Keywords(Vec<(String, Expr)>),
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Member {
+        object: Box<Expr>,
+        name: String,
+    },
+    Lambda(Rc<FunctionDecl>),
+    Format(Vec<FormatPart>),
+}
+
+#[derive(Debug, Clone)]
+pub enum FormatPart {
+    Literal(String),
+    Value {
+        expr: Expr,
+        spec: Option<FormatSpec>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    /// Always divides to a float
+    Div,
+    /// Rounds the quotient down, to an integer when both operands are
+    FloorDiv,
+    /// Takes the sign of the divisor, so `a == (a ~/ b) * b + a % b`
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    /// `&&` and `||` short-circuit, so executors handle them before evaluating
+    /// the right operand
+    And,
+    Or,
+    Dot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+/// A parsed `{value:spec}` format spec: `[[fill]align][+][0][width][.precision][type]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<FormatAlign>,
+    pub sign: bool,
+    pub zero_pad: bool,
+    pub width: Option<usize>,
+    pub precision: Option<usize>,
+    pub kind: Option<char>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormatAlign {
+    Left,
+    Right,
+    Center,
+}
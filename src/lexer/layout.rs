@@ -0,0 +1,197 @@
+use crate::lexer::tokenizer::{Token, TokenStream};
+use crate::utils::{CrabbyError, Span};
+
+/// An open indentation block: its indentation, the indentation of the line
+/// that opened it, and the bracket depth it lives at
+struct Level {
+    indent: String,
+    base: String,
+    depth: usize,
+}
+
+/// An open bracket. A `{` is either a brace block or a dict literal.
+#[derive(PartialEq)]
+enum Bracket {
+    Paren,
+    Square,
+    Block,
+    Dict,
+}
+
+/// Inserts `Indent`/`Dedent` tokens for off-side-rule blocks.
+///
+/// A block is indentation based when the `:` that opens it ends a line and the
+/// next line does not start with `{`. Lines inside brackets, and lines that
+/// start with `|>`, never affect the layout, so brace blocks keep working
+/// exactly as before. Nor does the `:` after a dict key, even when the value
+/// is on the next line.
+///
+/// Layout is always on rather than opt-in. A block `:` at the end of a line
+/// followed by anything but `{` used to be a parse error, so no program that
+/// parsed before changes meaning.
+pub fn insert_layout_tokens<'a>(
+    source: &'a str,
+    tokens: Vec<TokenStream<'a>>,
+) -> Result<Vec<TokenStream<'a>>, CrabbyError> {
+    let mut result = Vec::with_capacity(tokens.len());
+    let mut levels: Vec<Level> = Vec::new();
+    // Open brackets, innermost last
+    let mut brackets: Vec<Bracket> = Vec::new();
+    let mut previous_end: Option<usize> = None;
+    let mut opens_block = false;
+
+    for token in tokens {
+        let starts_line = previous_end.is_some_and(|end| source[end..token.span.start].contains('\n'));
+        let depth = brackets.len();
+
+        if starts_line && opens_block && token.token != Token::LBrace {
+            let indent = line_indent(source, token.span.start);
+            let base = previous_end.map(|end| line_indent(source, end - 1)).unwrap_or_default();
+            check_mixed(indent, &token.span)?;
+
+            if !indent.starts_with(base) || indent.len() <= base.len() {
+                return Err(if uses_other_whitespace(indent, base) {
+                    inconsistent(&token.span)
+                } else {
+                    layout_error(&token.span, "Expected an indented block after ':'")
+                });
+            }
+
+            levels.push(Level {
+                indent: indent.to_string(),
+                base: base.to_string(),
+                depth,
+            });
+            result.push(synthetic(Token::Indent, &token.span));
+        } else if starts_line && token.token != Token::Pipe {
+            // A line starting with `|>` continues the pipeline above it
+            let indent = line_indent(source, token.span.start);
+            if levels.last().is_some_and(|level| level.depth == depth) {
+                check_mixed(indent, &token.span)?;
+            }
+
+            while let Some(level) = levels.last().filter(|level| level.depth == depth) {
+                if indent == level.indent {
+                    break;
+                }
+                if indent.starts_with(level.indent.as_str()) {
+                    return Err(layout_error(&token.span, "Unexpected indent"));
+                }
+                if !level.indent.starts_with(indent) {
+                    return Err(inconsistent(&token.span));
+                }
+
+                let base = level.base.clone();
+                levels.pop();
+                result.push(synthetic(Token::Dedent, &token.span));
+
+                let still_open = levels.last().is_some_and(|level| level.depth == depth);
+                if !still_open && indent != base && indent.starts_with(base.as_str()) {
+                    return Err(layout_error(
+                        &token.span,
+                        "Unindent does not match any outer indentation level",
+                    ));
+                }
+            }
+        }
+
+        match token.token {
+            Token::LParen => brackets.push(Bracket::Paren),
+            Token::LBracket => brackets.push(Bracket::Square),
+            Token::LBrace => {
+                let in_dict = brackets.last() == Some(&Bracket::Dict);
+                brackets.push(if opens_dict(&result, in_dict) { Bracket::Dict } else { Bracket::Block });
+            }
+            Token::RParen | Token::RBrace | Token::RBracket => {
+                // Closing a bracket ends every indentation block opened inside it
+                while levels.last().is_some_and(|level| level.depth >= depth) {
+                    levels.pop();
+                    result.push(synthetic(Token::Dedent, &token.span));
+                }
+                brackets.pop();
+            }
+            _ => {}
+        }
+
+        // Only a ':' outside of parentheses and square brackets can open a
+        // block. Directly inside a dict it ends a key, unless it follows the
+        // `)` of a lambda's parameters.
+        let after_key = brackets.last() == Some(&Bracket::Dict)
+            && levels.last().is_none_or(|level| level.depth != brackets.len())
+            && !matches!(result.last().map(|previous: &TokenStream| &previous.token), Some(Token::RParen));
+        opens_block = token.token == Token::Colon
+            && !after_key
+            && !brackets.iter().any(|bracket| matches!(bracket, Bracket::Paren | Bracket::Square));
+        previous_end = Some(token.span.end);
+        result.push(token);
+    }
+
+    if let Some(last) = result.last() {
+        let span = Span::new(last.span.end, last.span.end, last.span.line, last.span.column);
+        for _ in levels.drain(..) {
+            result.push(synthetic(Token::Dedent, &span));
+        }
+    }
+
+    Ok(result)
+}
+
+/// Whether a `{` after the tokens so far starts a dict literal, by the same
+/// rule the formatter uses: blocks open after `else` or a `:`, except the
+/// `:` after a key inside a dict
+fn opens_dict(previous: &[TokenStream], in_dict: bool) -> bool {
+    let mut previous = previous.iter().rev().map(|stream| &stream.token);
+    match (previous.next(), previous.next()) {
+        (Some(Token::Else), _) => false,
+        (Some(Token::Colon), before) => in_dict && !matches!(before, Some(Token::RParen)),
+        _ => true,
+    }
+}
+
+/// Leading whitespace of the line containing byte `pos`
+fn line_indent(source: &str, pos: usize) -> &str {
+    let start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let line = &source[start..];
+    let end = line.find(|c: char| c != ' ' && c != '\t').unwrap_or(line.len());
+    &line[..end]
+}
+
+fn uses_other_whitespace(indent: &str, base: &str) -> bool {
+    let kind = |s: &str| s.chars().next();
+    !base.is_empty() && !indent.is_empty() && kind(indent) != kind(base)
+}
+
+fn check_mixed(indent: &str, span: &Span) -> Result<(), CrabbyError> {
+    if indent.contains(' ') && indent.contains('\t') {
+        return Err(CrabbyError::LexerError {
+            line: span.line,
+            column: 1,
+            message: "Indentation mixes tabs and spaces".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn inconsistent(span: &Span) -> CrabbyError {
+    CrabbyError::LexerError {
+        line: span.line,
+        column: 1,
+        message: "Inconsistent use of tabs and spaces in indentation".to_string(),
+    }
+}
+
+fn layout_error(span: &Span, message: &str) -> CrabbyError {
+    CrabbyError::LexerError {
+        line: span.line,
+        column: span.column,
+        message: message.to_string(),
+    }
+}
+
+fn synthetic<'a>(token: Token, at: &Span) -> TokenStream<'a> {
+    TokenStream {
+        token,
+        span: Span::new(at.start, at.start, at.line, at.column),
+        slice: "",
+    }
+}
@@ -1,3 +1,4 @@
+mod layout;
 mod tokenizer;
 
-pub use tokenizer::{Token, TokenStream, tokenize};
+pub use tokenizer::{LexError, Token, TokenStream, shebang, tokenize};
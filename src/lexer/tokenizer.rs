@@ -1,7 +1,22 @@
-use logos::Logos;
+use logos::{FilterResult, Lexer, Logos};
+use unicode_normalization::UnicodeNormalization;
+use crate::lexer::layout::insert_layout_tokens;
 use crate::utils::{CrabbyError, Span};
 
+/// Why the lexer rejected a piece of input
+#[derive(Debug, Default, PartialEq, Clone)]
+pub enum LexError {
+    #[default]
+    InvalidCharacter,
+    IntegerOverflow,
+    FloatOverflow,
+    MissingDigits,
+    MisplacedUnderscore,
+    UnterminatedComment,
+}
+
 #[derive(Logos, Debug, PartialEq, Clone)]
+#[logos(error = LexError)]
 pub enum Token {
     // Keywords
     #[token("def")]
@@ -40,16 +55,27 @@ pub enum Token {
     Public,
 
     // Literals
-    #[regex(r"-?[0-9]+\.[0-9]+", |lex| lex.slice().parse::<f64>().ok())]
+    // A leading '-' is a unary operator, so literals are always non-negative.
+    // Integers keep their full magnitude so the parser can fold `-9223372036854775808`
+    #[regex(r"[0-9][0-9_]*\.[0-9][0-9_]*([eE][+-]?[0-9][0-9_]*)?", lex_float)]
+    #[regex(r"[0-9][0-9_]*[eE][+-]?[0-9][0-9_]*", lex_float)]
     Float(f64),
 
-    #[regex(r"-?[0-9]+", |lex| lex.slice().parse::<i64>().ok())]
-    Integer(i64),
+    #[regex(r"[0-9][0-9_]*", lex_integer)]
+    #[regex(r"0[xX][0-9a-fA-F_]+", lex_integer)]
+    #[regex(r"0[oO][0-7_]+", lex_integer)]
+    #[regex(r"0[bB][01_]+", lex_integer)]
+    Integer(u64),
 
     #[regex(r#""[^"]*""#, |lex| Some(lex.slice().trim_matches('"').to_string()))]
     String(String),
 
-    #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| Some(lex.slice().to_string()))]
+    // The body of an f-string is kept raw; the parser splits out the embedded expressions
+    #[regex(r#"f"[^"]*""#, |lex| Some(lex.slice()[2..lex.slice().len() - 1].to_string()))]
+    FString(String),
+
+    // Identifiers follow UAX #31 and are NFC-normalized, so visually equal names match
+    #[regex(r"[\p{XID_Start}_]\p{XID_Continue}*", |lex| lex.slice().nfc().collect::<String>())]
     Identifier(String),
 
     // Operators and delimiters
@@ -61,6 +87,11 @@ pub enum Token {
     Star,
     #[token("/")]
     Slash,
+    /// Floor division; `//` already starts a comment
+    #[token("~/")]
+    TildeSlash,
+    #[token("%")]
+    Percent,
     #[token("=")]
     Equals,
     #[token("$")]
@@ -79,6 +110,9 @@ pub enum Token {
     And,
     #[token("||")]
     Or,
+    /// `value |> f(args)` passes `value` as the first argument of `f`
+    #[token("|>")]
+    Pipe,
     #[token("!")]
     Not,
     #[token("@")]
@@ -93,6 +127,10 @@ pub enum Token {
     LBrace,
     #[token("}")]
     RBrace,
+    #[token("[")]
+    LBracket,
+    #[token("]")]
+    RBracket,
     #[token(":")]
     Colon,
     #[token(",")]
@@ -100,20 +138,129 @@ pub enum Token {
     #[token(".")]
     Dot,
 
+    // Produced by the layout pass for indentation-based blocks, never by logos
+    Indent,
+    Dedent,
+
+    // `///` documents the `def` or `let` that follows it
+    #[regex(r"///[^\r\n]*", lex_doc_comment, priority = 10)]
+    DocComment(String),
+
     #[regex(r"[ \t\r\n]+", logos::skip)]
     #[regex(r"//[^\r\n]*", logos::skip)]
+    #[token("/*", skip_block_comment)]
     Whitespace,
 }
 
+/// Underscores may only separate digits, so `1_000` is fine but `1_` and `1__0` are not
+fn separates_digits(digits: &str) -> bool {
+    !digits.ends_with('_') && !digits.contains("__")
+}
+
+fn lex_integer(lex: &mut Lexer<Token>) -> Result<u64, LexError> {
+    let literal = lex.slice();
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x" | "0X") => (&literal[2..], 16),
+        Some("0o" | "0O") => (&literal[2..], 8),
+        Some("0b" | "0B") => (&literal[2..], 2),
+        _ => (literal, 10),
+    };
+
+    let cleaned = digits.replace('_', "");
+    if cleaned.is_empty() {
+        return Err(LexError::MissingDigits);
+    }
+    if !separates_digits(digits) {
+        return Err(LexError::MisplacedUnderscore);
+    }
+
+    // The regexes only admit valid digits, so the only failure left is overflow
+    u64::from_str_radix(&cleaned, radix).map_err(|_| LexError::IntegerOverflow)
+}
+
+fn lex_float(lex: &mut Lexer<Token>) -> Result<f64, LexError> {
+    if !lex.slice().split(['.', 'e', 'E']).all(separates_digits) {
+        return Err(LexError::MisplacedUnderscore);
+    }
+
+    let value = lex
+        .slice()
+        .replace('_', "")
+        .parse::<f64>()
+        .map_err(|_| LexError::MissingDigits)?;
+
+    if value.is_infinite() {
+        return Err(LexError::FloatOverflow);
+    }
+    Ok(value)
+}
+
+fn lex_doc_comment(lex: &mut Lexer<Token>) -> String {
+    let text = &lex.slice()[3..];
+    text.strip_prefix(' ').unwrap_or(text).trim_end().to_string()
+}
+
+/// Skips a `/* ... */` comment, which may contain nested block comments
+fn skip_block_comment(lex: &mut Lexer<Token>) -> FilterResult<(), LexError> {
+    let rest = lex.remainder().as_bytes();
+    let mut depth = 1;
+    let mut i = 0;
+
+    while i < rest.len() {
+        match (rest[i], rest.get(i + 1)) {
+            (b'/', Some(b'*')) => {
+                depth += 1;
+                i += 2;
+            }
+            (b'*', Some(b'/')) => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    lex.bump(i);
+                    return FilterResult::Skip;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    lex.bump(rest.len());
+    FilterResult::Error(LexError::UnterminatedComment)
+}
+
+/// Moves a line/column position past `text`. Columns count characters, not bytes.
+fn advance_position(text: &str, line: &mut usize, column: &mut usize) {
+    for ch in text.chars() {
+        if ch == '\n' {
+            *line += 1;
+            *column = 1;
+        } else {
+            *column += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct TokenStream<'source> {
     pub token: Token,
     pub span: Span,
     pub slice: &'source str,
 }
 
-pub fn tokenize(source: &str) -> Result<Vec<TokenStream>, CrabbyError> {
+/// The `#!` line at the start of an executable script, without its line
+/// break, or an empty string when there is none
+pub fn shebang(source: &str) -> &str {
+    if !source.starts_with("#!") {
+        return "";
+    }
+    source.lines().next().unwrap_or_default()
+}
+
+pub fn tokenize(source: &str) -> Result<Vec<TokenStream<'_>>, CrabbyError> {
     let mut tokens = Vec::new();
     let mut lex = Token::lexer(source);
+    // The shebang is for the OS; the first token still starts on line 2
+    lex.bump(shebang(source).len());
     let mut line = 1;
     let mut column = 1;
 
@@ -124,14 +271,7 @@ pub fn tokenize(source: &str) -> Result<Vec<TokenStream>, CrabbyError> {
         let span_start = lex.span().start;
 
         // Update line and column for any skipped whitespace
-        for (_pos, ch) in source[last_valid_pos..span_start].chars().enumerate() {
-            if ch == '\n' {
-                line += 1;
-                column = 1;
-            } else {
-                column += 1;
-            }
-        }
+        advance_position(&source[last_valid_pos..span_start], &mut line, &mut column);
 
         match token_result {
             Ok(token) => {
@@ -153,20 +293,39 @@ pub fn tokenize(source: &str) -> Result<Vec<TokenStream>, CrabbyError> {
                     slice: lex.slice(),
                 });
 
-                // Update column for the token
-                column += lex.slice().len();
+                // Update line and column for the token itself
+                advance_position(lex.slice(), &mut line, &mut column);
                 last_valid_pos = lex.span().end;
             }
-            Err(_) => {
+            Err(error) => {
                 if last_valid_pos < source.len() {
                     let problem_char = source[span_start..].chars().next()
                         .map(|c| format!("'{}'", c))
                         .unwrap_or_else(|| "unknown".to_string());
 
+                    let message = match error {
+                        LexError::InvalidCharacter => {
+                            format!("Invalid character {} at position {}", problem_char, span_start)
+                        }
+                        LexError::IntegerOverflow => {
+                            format!("Integer literal '{}' does not fit in 64 bits", lex.slice())
+                        }
+                        LexError::FloatOverflow => {
+                            format!("Float literal '{}' is out of range", lex.slice())
+                        }
+                        LexError::MissingDigits => {
+                            format!("Number literal '{}' has no digits", lex.slice())
+                        }
+                        LexError::MisplacedUnderscore => {
+                            format!("Number literal '{}' has an underscore that does not separate digits", lex.slice())
+                        }
+                        LexError::UnterminatedComment => "Unterminated block comment".to_string(),
+                    };
+
                     return Err(CrabbyError::LexerError {
                         line,
                         column,
-                        message: format!("Invalid character {} at position {}", problem_char, span_start),
+                        message,
                     });
                 }
             }
@@ -181,5 +340,5 @@ pub fn tokenize(source: &str) -> Result<Vec<TokenStream>, CrabbyError> {
         });
     }
 
-    Ok(tokens)
+    insert_layout_tokens(source, tokens)
 }
@@ -0,0 +1,10 @@
+pub mod utils;
+pub mod lexer;
+pub mod parser;
+pub mod ir;
+pub mod bytecode;
+pub mod backend;
+pub mod compile;
+pub mod format;
+pub mod runtime;
+pub mod repl;
@@ -1,47 +1,602 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use crabby::backend;
+use crabby::bytecode::{self, SourceStamp};
+use crabby::compile;
+use crabby::format;
+use crabby::ir;
+use crabby::lexer::tokenize;
+use crabby::parser::parser::parse;
+use crabby::repl::Repl;
+use crabby::runtime::{Engine, Runtime};
+use crabby::utils::CrabbyError;
+use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
-use crate::compile::parse;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
 
-mod utils;
-mod lexer;
-mod parser;
-mod compile;
+/// Exit statuses shared by every command. Usage errors exit with 2, as clap
+/// reports them; runtime and I/O errors and failed checks exit with 1.
+const EXIT_LEXER_ERROR: u8 = 3;
+const EXIT_PARSER_ERROR: u8 = 4;
+const EXIT_COMPILE_ERROR: u8 = 5;
+
+/// The tree-walking interpreter recurses on the native stack, so commands run
+/// on a thread with room for the runtime's deepest allowed recursion
+const STACK_SIZE: usize = 1024 * 1024 * 1024;
 
 #[derive(Parser)]
 #[command(name = "crabby")]
 #[command(about = "Crabby programming language compiler")]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(
+    after_help = "Exit status: 0 on success, 1 for runtime errors and failures, 2 for usage errors, 3 for lexer errors, 4 for parser errors and 5 for compile errors."
+)]
 struct Cli {
-    #[arg(help = "Input .crab or .cb file")]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "CODE",
+        conflicts_with = "input",
+        help = "Run a one-line program"
+    )]
+    eval: Option<String>,
+
+    #[arg(help = "Input .crab, .cb or .crabc file, or - for stdin; starts a REPL when omitted")]
+    input: Option<PathBuf>,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        help = "Arguments passed to the script"
+    )]
+    args: Vec<String>,
+
+    #[command(flatten)]
+    options: RunOptions,
+}
+
+#[derive(Args)]
+struct RunOptions {
+    #[arg(
+        long,
+        help = "Run on the tree-walking interpreter instead of the bytecode VM"
+    )]
+    interpreter: bool,
+
+    #[arg(
+        long,
+        overrides_with = "no_jit",
+        help = "Compile hot functions to native code (default)"
+    )]
+    jit: bool,
+
+    #[arg(
+        long,
+        overrides_with = "jit",
+        help = "Run everything on the bytecode VM"
+    )]
+    no_jit: bool,
+
+    #[arg(long, help = "Print what the JIT compiled when the program ends")]
+    jit_stats: bool,
+
+    #[arg(long, help = "Run the input as Crabby source whatever its extension")]
+    force: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a script to a .crabc bytecode file
+    Build {
+        #[arg(help = "Input .crab or .cb file")]
+        input: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            help = "Output file, defaults to the input with a .crabc extension, a .wasm extension with --wasm, or no extension with --native"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Build a native executable through C and the system C compiler"
+        )]
+        native: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "native",
+            help = "Build a WebAssembly module exporting the script's public functions"
+        )]
+        wasm: bool,
+    },
+    /// Translate a script to C
+    EmitC {
+        #[arg(help = "Input .crab or .cb file")]
+        input: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            help = "Output file, defaults to the input with a .c extension. The runtime is written next to it."
+        )]
+        output: Option<PathBuf>,
+    },
+    /// Run a script or a compiled .crabc file
+    Run {
+        #[arg(help = "Input .crab, .cb or .crabc file, or - for stdin")]
+        input: PathBuf,
+
+        #[arg(
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            help = "Arguments passed to the script"
+        )]
+        args: Vec<String>,
+
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Lex, parse and check scripts without running them
+    Check {
+        #[arg(required = true, help = "Input .crab or .cb files")]
+        inputs: Vec<PathBuf>,
+    },
+    /// Reformat scripts in place
+    Fmt {
+        #[arg(required = true, help = "Input .crab or .cb files")]
+        inputs: Vec<PathBuf>,
+
+        #[arg(long, help = "List files that need formatting instead of changing them")]
+        check: bool,
+    },
+    /// Run the test_* functions in test scripts
+    Test {
+        #[arg(help = "Test files, or directories to search for test_*.crab and *_test.crab files [default: .]")]
+        paths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Start an interactive session
+    Repl,
+    /// Print the tokens of a script
+    Tokens {
+        #[arg(help = "Input .crab or .cb file")]
+        input: PathBuf,
+    },
+    /// Print the syntax tree of a script
+    Ast {
+        #[arg(help = "Input .crab or .cb file")]
+        input: PathBuf,
+    },
+}
+
+/// A command failure that has already been reported to the user, with the
+/// exit status to end with
+#[derive(Debug)]
+struct Reported(u8);
+
+impl fmt::Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed")
+    }
+}
+
+impl Error for Reported {}
+
+fn main() -> ExitCode {
+    match thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| dispatch(Cli::parse()))
+    {
+        Ok(command) => command.join().unwrap_or(ExitCode::FAILURE),
+        // Without the larger stack, deep recursion only overflows sooner
+        Err(_) => dispatch(Cli::parse()),
+    }
+}
+
+fn dispatch(cli: Cli) -> ExitCode {
+    let result = match cli.command {
+        Some(Command::Build {
+            input,
+            output,
+            native,
+            wasm,
+        }) => match (native, wasm) {
+            (true, _) => build_native(&input, output),
+            (_, true) => build_wasm(&input, output),
+            _ => build(&input, output),
+        },
+        Some(Command::EmitC { input, output }) => emit_c(&input, output),
+        Some(Command::Run {
+            input,
+            args,
+            options,
+        }) => run(&input, args, &options),
+        Some(Command::Check { inputs }) => check(&inputs),
+        Some(Command::Fmt { inputs, check }) => format_files(&inputs, check),
+        Some(Command::Test { paths, options }) => test(&paths, &options),
+        Some(Command::Repl) => repl(),
+        Some(Command::Tokens { input }) => dump_tokens(&input),
+        Some(Command::Ast { input }) => dump_ast(&input),
+        None => match (cli.eval, cli.input) {
+            (Some(code), _) => eval(&code, cli.args, &cli.options),
+            (None, Some(input)) => run(&input, cli.args, &cli.options),
+            (None, None) => repl(),
+        },
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report(error.as_ref());
+            ExitCode::from(exit_status(error.as_ref()))
+        }
+    }
+}
+
+fn report(error: &(dyn Error + 'static)) {
+    match error.downcast_ref::<CrabbyError>() {
+        Some(CrabbyError::Exit(_)) => {}
+        Some(error) => eprintln!("Error: {}", error),
+        None if error.is::<Reported>() => {}
+        None => eprintln!("Error: {}", error),
+    }
+}
+
+fn exit_status(error: &(dyn Error + 'static)) -> u8 {
+    match error.downcast_ref::<CrabbyError>() {
+        Some(CrabbyError::LexerError { .. }) => EXIT_LEXER_ERROR,
+        Some(CrabbyError::ParserError { .. }) => EXIT_PARSER_ERROR,
+        Some(CrabbyError::CompileError(_)) => EXIT_COMPILE_ERROR,
+        // `sys.exit` only accepts statuses that fit
+        Some(CrabbyError::Exit(code)) => u8::try_from(*code).unwrap_or(1),
+        Some(_) => 1,
+        None => error.downcast_ref::<Reported>().map_or(1, |reported| reported.0),
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+fn repl() -> Result<(), Box<dyn Error>> {
+    Repl::new().run()?;
+    Ok(())
+}
 
-    if !cli.input.exists() {
+/// Checks the input exists and has an extension Crabby understands, and
+/// returns its absolute path
+fn input_path(input: &Path, allowed: &[&str]) -> Result<PathBuf, Box<dyn Error>> {
+    if !input.exists() {
         return Err("Input file does not exist".into());
     }
 
-    let ext = cli.input.extension().unwrap_or_default();
-    if ext != "crab" && ext != "cb" {
-        return Err("Input file must have .crab or .cb extension".into());
+    let ext = input.extension().unwrap_or_default();
+    if !allowed.iter().any(|allowed| ext == *allowed) {
+        return Err(format!("Input file must have .{} extension", allowed.join(" or .")).into());
+    }
+
+    Ok(input.canonicalize()?)
+}
+
+/// Like `input_path` for a script to run, which may also have no Crabby
+/// extension when it starts with a `#!` line or `--force` is given
+fn script_path(input: &Path, force: bool) -> Result<PathBuf, Box<dyn Error>> {
+    match input_path(input, &["crab", "cb", "crabc"]) {
+        Err(_) if input.exists() && (force || has_shebang(input)) => Ok(input.canonicalize()?),
+        Err(error) if input.exists() => {
+            Err(format!("{}, unless it starts with #! or --force is given", error).into())
+        }
+        result => result,
+    }
+}
+
+fn has_shebang(path: &Path) -> bool {
+    let mut start = [0; 2];
+    fs::File::open(path).is_ok_and(|mut file| file.read_exact(&mut start).is_ok())
+        && &start == b"#!"
+}
+
+fn build(input: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let absolute_path = input_path(input, &["crab", "cb"])?;
+    let source = fs::read_to_string(&absolute_path)?;
+
+    let module = compile::compile_source(&source, &Runtime::new().global_names())?;
+    let compiled = bytecode::compile_module(&module);
+    let stamp = SourceStamp::new(&absolute_path, source.as_bytes());
+
+    let output = output.unwrap_or_else(|| input.with_extension("crabc"));
+    fs::write(&output, bytecode::encode(&compiled, stamp))?;
+    Ok(())
+}
+
+/// Lexes, parses and checks a source file into IR
+fn load(input: &Path) -> Result<ir::Module, Box<dyn Error>> {
+    let absolute_path = input_path(input, &["crab", "cb"])?;
+    let source = fs::read_to_string(&absolute_path)?;
+    Ok(compile::compile_source(
+        &source,
+        &Runtime::new().global_names(),
+    )?)
+}
+
+fn build_native(input: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let module = load(input)?;
+    let output = output.unwrap_or_else(|| input.with_extension(""));
+    backend::c::build_native(&module, &output)?;
+    Ok(())
+}
+
+fn build_wasm(input: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let module = load(input)?;
+    let output = output.unwrap_or_else(|| input.with_extension("wasm"));
+    fs::write(&output, backend::wasm::emit_wasm(&module)?)?;
+    Ok(())
+}
+
+fn emit_c(input: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let module = load(input)?;
+    let program = backend::c::emit_c(&module)?;
+
+    let output = output.unwrap_or_else(|| input.with_extension("c"));
+    let dir = output.parent().unwrap_or(Path::new("."));
+    fs::write(&output, program)?;
+    fs::write(dir.join("crabby_runtime.h"), backend::c::RUNTIME_HEADER)?;
+    fs::write(dir.join("crabby_runtime.c"), backend::c::RUNTIME_SOURCE)?;
+    Ok(())
+}
+
+/// A runtime set up to run scripts the way the command line asked for
+fn new_runtime(options: &RunOptions) -> Runtime {
+    let mut runtime = Runtime::new();
+    runtime.set_module_loader(compile::module_loader());
+    if options.interpreter {
+        runtime.set_engine(Engine::Interpreter);
+    }
+    if options.no_jit {
+        runtime.set_jit(false);
+    }
+    runtime
+}
+
+fn run(input: &Path, args: Vec<String>, options: &RunOptions) -> Result<(), Box<dyn Error>> {
+    if input == Path::new("-") {
+        let mut code = String::new();
+        io::stdin().read_to_string(&mut code)?;
+        return eval(&code, args, options);
+    }
+    let absolute_path = script_path(input, options.force)?;
+
+    let mut runtime = new_runtime(options);
+    runtime.set_args(args);
+
+    let result = execute(&mut runtime, absolute_path, options.interpreter);
+    if options.jit_stats {
+        print_jit_stats(&runtime);
+    }
+    result
+}
+
+/// Runs `crabby -e` code or a script read from stdin, resolving imports
+/// against the working directory
+fn eval(code: &str, args: Vec<String>, options: &RunOptions) -> Result<(), Box<dyn Error>> {
+    let mut runtime = new_runtime(options);
+    runtime.set_args(args);
+
+    let result = compile::compile_source(code, &runtime.global_names())
+        .and_then(|module| runtime.run(&module));
+    if options.jit_stats {
+        print_jit_stats(&runtime);
+    }
+    result?;
+    Ok(())
+}
+
+fn execute(
+    runtime: &mut Runtime,
+    absolute_path: PathBuf,
+    interpreter: bool,
+) -> Result<(), Box<dyn Error>> {
+    if absolute_path.extension().is_some_and(|ext| ext == "crabc") {
+        if interpreter {
+            return Err("Compiled .crabc files can only run on the bytecode VM".into());
+        }
+
+        // Already compiled, so there is nothing to lex, parse or check
+        let (_, module) = bytecode::decode(&fs::read(&absolute_path)?)?;
+        runtime.set_current_file(absolute_path);
+        runtime.run_compiled(&module)?;
+        return Ok(());
     }
 
-    // Get the absolute path of the input file
-    let absolute_path = cli.input.canonicalize()?;
     let source = fs::read_to_string(&absolute_path)?;
-    // Lexical analysis
-    let tokens = lexer::tokenize(&source)?;
 
-    // Parsing
-    let ast = parse(tokens)?;
+    // Lex, parse and check the program into IR
+    let module = compile::compile_source(&source, &runtime.global_names())?;
+
+    // Run it, resolving imports relative to the input file
+    runtime.set_current_file(absolute_path);
+    runtime.run(&module)?;
+
+    Ok(())
+}
+
+fn print_jit_stats(runtime: &Runtime) {
+    let stats = runtime.jit_stats();
+    let compiled = stats.iter().filter(|f| f.rejected.is_none()).count();
+    eprintln!(
+        "JIT: {} hot function(s), {} compiled to native code",
+        stats.len(),
+        compiled
+    );
+    for function in stats {
+        match function.rejected {
+            None => eprintln!(
+                "  {:<20} compiled, {} native call(s), {} bailout(s)",
+                function.name, function.native_calls, function.bailouts
+            ),
+            Some(reason) => eprintln!("  {:<20} not compiled: {}", function.name, reason),
+        }
+    }
+}
+
+/// Checks every file, even after one fails, and ends with the exit status
+/// of the first failure
+fn check(inputs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let mut status = None;
+    for input in inputs {
+        if let Err(error) = load(input) {
+            eprintln!("Error: {}: {}", input.display(), error);
+            status.get_or_insert(exit_status(error.as_ref()));
+        }
+    }
+
+    match status {
+        Some(status) => Err(Reported(status).into()),
+        None => Ok(()),
+    }
+}
+
+fn format_files(inputs: &[PathBuf], check: bool) -> Result<(), Box<dyn Error>> {
+    let mut unformatted = 0;
+    for input in inputs {
+        let absolute_path = input_path(input, &["crab", "cb"])?;
+        let source = fs::read_to_string(&absolute_path)?;
+        let formatted = format::format_source(&source)?;
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{}", input.display());
+            unformatted += 1;
+        } else {
+            fs::write(&absolute_path, formatted)?;
+        }
+    }
+
+    if unformatted > 0 {
+        eprintln!("{} file(s) need formatting", unformatted);
+        return Err(Reported(1).into());
+    }
+    Ok(())
+}
+
+/// Runs each test file, then calls every top-level `test_*` function that
+/// takes no arguments. A test passes when it returns without an error.
+fn test(paths: &[PathBuf], options: &RunOptions) -> Result<(), Box<dyn Error>> {
+    let mut files = Vec::new();
+    if paths.is_empty() {
+        find_tests(Path::new("."), &mut files)?;
+    }
+    for path in paths {
+        if path.is_dir() {
+            find_tests(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
 
-    // Create compiler with the current file path
-    let mut compiler = compile::Compiler::new(Some(absolute_path));
+    let (mut passed, mut failed) = (0, 0);
+    for file in &files {
+        // A file that does not load counts as a failure, and the rest still run
+        let mut runtime = new_runtime(options);
+        let loaded = load(file).and_then(|module| {
+            runtime.set_current_file(input_path(file, &["crab", "cb"])?);
+            runtime.run(&module)?;
+            Ok(module)
+        });
+        let module = match loaded {
+            Ok(module) => module,
+            Err(error) => {
+                println!("test {} ... FAILED\n    {}", file.display(), error);
+                failed += 1;
+                continue;
+            }
+        };
 
-    // Compilation
-    compiler.compile(&ast)?;
+        for name in test_names(&module) {
+            let result = match runtime.global(&name) {
+                Some(test) => runtime.call(&test, Vec::new()),
+                None => continue,
+            };
+            match result {
+                Ok(_) => {
+                    println!("test {}::{} ... ok", file.display(), name);
+                    passed += 1;
+                }
+                Err(error) => {
+                    println!("test {}::{} ... FAILED\n    {}", file.display(), name, error);
+                    failed += 1;
+                }
+            }
+        }
+    }
 
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    println!("\ntest result: {}. {} passed; {} failed", status, passed, failed);
+    if failed > 0 {
+        return Err(Reported(1).into());
+    }
+    Ok(())
+}
+
+/// Collects test_*.crab and *_test.crab files under a directory, skipping
+/// hidden directories and build output
+fn find_tests(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') && name != "target" {
+                find_tests(&path, files)?;
+            }
+            continue;
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let is_test = stem.starts_with("test_") || stem.ends_with("_test");
+        if is_test && path.extension().is_some_and(|ext| ext == "crab" || ext == "cb") {
+            files.push(path.strip_prefix(".").unwrap_or(&path).to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+fn test_names(module: &ir::Module) -> Vec<String> {
+    module
+        .body
+        .iter()
+        .filter_map(|statement| match statement {
+            ir::Stmt::Function(decl) if decl.name.starts_with("test_") && decl.params.is_empty() => {
+                Some(decl.name.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn dump_tokens(input: &Path) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(input_path(input, &["crab", "cb"])?)?;
+    for token in tokenize(&source)? {
+        println!(
+            "{}:{}\t{:?}\t{}",
+            token.span.line, token.span.column, token.token, token.slice
+        );
+    }
+    Ok(())
+}
+
+fn dump_ast(input: &Path) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(input_path(input, &["crab", "cb"])?)?;
+    for statement in parse(tokenize(&source)?)?.statements {
+        println!("{:#?}", statement);
+    }
     Ok(())
 }
@@ -1,13 +1,14 @@
-#[derive(Debug, Clone)]
+// Operators and format specs are shared with the IR unchanged
+pub use crate::ir::{BinaryOp, FormatAlign, FormatSpec, UnaryOp};
+
+#[derive(Debug, Clone, Default)]
 pub struct Program {
     pub statements: Vec<Statement>,
 }
 
 impl Program {
     pub fn new() -> Self {
-        Self {
-            statements: Vec::new(),
-        }
+        Self::default()
     }
 }
 
@@ -17,10 +18,14 @@ pub enum Statement {
         name: String,
         params: Vec<String>,
         body: Box<Statement>,
+        public: bool,
+        doc: Option<String>,
     },
     Let {
         name: String,
         value: Box<Expression>,
+        public: bool,
+        doc: Option<String>,
     },
     Return(Box<Expression>),
     If {
@@ -56,6 +61,10 @@ pub enum Expression {
     String(String),
     Variable(String),
     Range(Box<Expression>),
+    Unary {
+        operator: UnaryOp,
+        operand: Box<Expression>,
+    },
     Binary {
         left: Box<Expression>,
         operator: BinaryOp,
@@ -65,18 +74,36 @@ pub enum Expression {
         function: String,
         arguments: Vec<Expression>,
     },
+    /// A call of any other expression, such as `make()()` or `sys.args()`
+    CallValue {
+        callee: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
+    List(Vec<Expression>),
+    Dict(Vec<(Expression, Expression)>),
+    /// The `name = value` arguments of a call, which the parser puts after
+    /// its other arguments
+    Keywords(Vec<(String, Expression)>),
+    Index {
+        object: Box<Expression>,
+        index: Box<Expression>,
+    },
+    Member {
+        object: Box<Expression>,
+        name: String,
+    },
     Lambda {
         params: Vec<String>,
         body: Box<Statement>,
     },
+    FormatString(Vec<FormatPart>),
 }
 
 #[derive(Debug, Clone)]
-pub enum BinaryOp {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Eq,
-    Dot,
+pub enum FormatPart {
+    Literal(String),
+    Expression {
+        expr: Box<Expression>,
+        spec: Option<FormatSpec>,
+    },
 }
@@ -0,0 +1,238 @@
+use crate::lexer::{tokenize, TokenStream};
+use crate::parser::ast::{Expression, FormatAlign, FormatPart, FormatSpec};
+use crate::parser::parser::parse_expression;
+use crate::utils::{CrabbyError, Span};
+
+// Length of the `f"` prefix in front of the f-string body
+const PREFIX_LEN: usize = 2;
+
+/// Splits the body of an `f"..."` literal into text and embedded expressions.
+/// Positions in errors refer to the exact spot inside the literal.
+pub fn parse_format_string(body: &str, token: &TokenStream) -> Result<Expression, CrabbyError> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = body.char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '{' if matches!(chars.peek(), Some((_, '{'))) => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if matches!(chars.peek(), Some((_, '}'))) => {
+                chars.next();
+                text.push('}');
+            }
+            '}' => {
+                return Err(error_at(token, i, "Single '}' in f-string, use '}}' for a literal brace"));
+            }
+            '{' => {
+                if !text.is_empty() {
+                    parts.push(FormatPart::Literal(std::mem::take(&mut text)));
+                }
+
+                let (expr_end, close) = find_placeholder_end(body, i + 1)
+                    .ok_or_else(|| error_at(token, i, "Unclosed '{' in f-string"))?;
+
+                let source = &body[i + 1..expr_end];
+                if source.trim().is_empty() {
+                    return Err(error_at(token, i, "Empty expression in f-string"));
+                }
+                let expr = parse_embedded(source, token, i + 1)?;
+
+                let spec = if expr_end < close {
+                    let spec_start = expr_end + 1;
+                    let spec = parse_format_spec(&body[spec_start..close])
+                        .map_err(|message| error_at(token, spec_start, &message))?;
+                    Some(spec)
+                } else {
+                    None
+                };
+
+                parts.push(FormatPart::Expression {
+                    expr: Box::new(expr),
+                    spec,
+                });
+
+                // Skip past the closing '}'
+                while matches!(chars.peek(), Some((j, _)) if *j <= close) {
+                    chars.next();
+                }
+            }
+            _ => text.push(ch),
+        }
+    }
+
+    if !text.is_empty() {
+        parts.push(FormatPart::Literal(text));
+    }
+
+    Ok(Expression::FormatString(parts))
+}
+
+/// Parses a format spec of the form `[[fill]align][+][0][width][.precision][type]`
+pub fn parse_format_spec(spec: &str) -> Result<FormatSpec, String> {
+    let chars: Vec<char> = spec.chars().collect();
+    let mut result = FormatSpec {
+        fill: ' ',
+        align: None,
+        sign: false,
+        zero_pad: false,
+        width: None,
+        precision: None,
+        kind: None,
+    };
+    let mut i = 0;
+
+    if let Some(align) = chars.get(1).and_then(|c| align_from(*c)) {
+        result.fill = chars[0];
+        result.align = Some(align);
+        i = 2;
+    } else if let Some(align) = chars.first().and_then(|c| align_from(*c)) {
+        result.align = Some(align);
+        i = 1;
+    }
+
+    if chars.get(i) == Some(&'+') {
+        result.sign = true;
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'0') {
+        result.zero_pad = true;
+        i += 1;
+    }
+
+    let (width, next) = read_number(&chars, i);
+    result.width = width;
+    i = next;
+
+    if chars.get(i) == Some(&'.') {
+        let (precision, next) = read_number(&chars, i + 1);
+        if precision.is_none() {
+            return Err(format!("Expected precision after '.' in format spec '{}'", spec));
+        }
+        result.precision = precision;
+        i = next;
+    }
+
+    if let Some(kind) = chars.get(i).filter(|c| "dfexXobs%".contains(**c)) {
+        result.kind = Some(*kind);
+        i += 1;
+    }
+
+    if i != chars.len() {
+        return Err(format!("Invalid format spec '{}'", spec));
+    }
+
+    Ok(result)
+}
+
+fn align_from(ch: char) -> Option<FormatAlign> {
+    match ch {
+        '<' => Some(FormatAlign::Left),
+        '>' => Some(FormatAlign::Right),
+        '^' => Some(FormatAlign::Center),
+        _ => None,
+    }
+}
+
+fn read_number(chars: &[char], start: usize) -> (Option<usize>, usize) {
+    let mut end = start;
+    while chars.get(end).is_some_and(|c| c.is_ascii_digit()) {
+        end += 1;
+    }
+
+    if end == start {
+        return (None, start);
+    }
+
+    let digits: String = chars[start..end].iter().collect();
+    (digits.parse().ok(), end)
+}
+
+/// Finds where the expression of a placeholder ends (at a top-level ':' or '}')
+/// and where its closing '}' is
+fn find_placeholder_end(body: &str, start: usize) -> Option<(usize, usize)> {
+    let mut depth = 0usize;
+    let mut expr_end = None;
+
+    for (offset, ch) in body[start..].char_indices() {
+        let i = start + offset;
+        match ch {
+            '(' | '[' | '{' if expr_end.is_none() => depth += 1,
+            ')' | ']' if expr_end.is_none() => depth = depth.saturating_sub(1),
+            '}' if depth > 0 && expr_end.is_none() => depth -= 1,
+            ':' if depth == 0 && expr_end.is_none() => expr_end = Some(i),
+            '}' => return Some((expr_end.unwrap_or(i), i)),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn parse_embedded(source: &str, token: &TokenStream, offset: usize) -> Result<Expression, CrabbyError> {
+    let base = position_at(token, offset);
+    let base_byte = token.span.start + PREFIX_LEN + offset;
+
+    let tokens = tokenize(source).map_err(|e| relocate_error(e, base))?;
+    let tokens = tokens
+        .into_iter()
+        .map(|t| {
+            let (line, column) = relocate(t.span.line, t.span.column, base);
+            TokenStream {
+                token: t.token,
+                span: Span::new(base_byte + t.span.start, base_byte + t.span.end, line, column),
+                slice: t.slice,
+            }
+        })
+        .collect();
+
+    // The embedded tokens already carry absolute positions
+    parse_expression(tokens)
+}
+
+/// Line and column of a byte offset into the f-string body
+fn position_at(token: &TokenStream, offset: usize) -> (usize, usize) {
+    let (mut line, mut column) = (token.span.line, token.span.column);
+    let end = (PREFIX_LEN + offset).min(token.slice.len());
+
+    for ch in token.slice[..end].chars() {
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+
+    (line, column)
+}
+
+fn relocate(line: usize, column: usize, base: (usize, usize)) -> (usize, usize) {
+    if line == 1 {
+        (base.0, base.1 + column - 1)
+    } else {
+        (base.0 + line - 1, column)
+    }
+}
+
+fn relocate_error(error: CrabbyError, base: (usize, usize)) -> CrabbyError {
+    match error {
+        CrabbyError::LexerError { line, column, message } => {
+            let (line, column) = relocate(line, column, base);
+            CrabbyError::LexerError { line, column, message }
+        }
+        other => other,
+    }
+}
+
+fn error_at(token: &TokenStream, offset: usize, message: &str) -> CrabbyError {
+    let (line, column) = position_at(token, offset);
+    CrabbyError::ParserError {
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
@@ -1,2 +1,4 @@
 pub mod ast;
+mod fstring;
+#[allow(clippy::module_inception)]
 pub mod parser;
@@ -1,5 +1,6 @@
 use crate::lexer::{Token, TokenStream};
 use crate::parser::ast::*;
+use crate::parser::fstring::parse_format_string;
 use crate::utils::CrabbyError;
 
 pub struct Parser<'a> {
@@ -24,12 +25,15 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_statement(&mut self) -> Result<Statement, CrabbyError> {
+        if matches!(self.peek().token, Token::DocComment(_)) {
+            return self.parse_documented_statement();
+        }
+
         match &self.peek().token {
             Token::Loop => self.parse_loop_statement(),
             Token::For => self.parse_for_statement(),
             Token::Import => self.parse_import_statement(),
-            Token::Def => self.parse_function_definition(),
-            Token::Let => self.parse_let_statement(),
+            Token::Public | Token::Def | Token::Let => self.parse_declaration(None),
             Token::Return => {
                 self.advance(); // consume 'return'
                 let expr = self.parse_expression()?;
@@ -44,7 +48,35 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_function_definition(&mut self) -> Result<Statement, CrabbyError> {
+    fn parse_documented_statement(&mut self) -> Result<Statement, CrabbyError> {
+        let mut lines = Vec::new();
+        while let Token::DocComment(line) = &self.peek().token {
+            if self.is_at_end() {
+                break;
+            }
+            lines.push(line.clone());
+            self.advance();
+        }
+        // `parse` drops doc comments that no declaration follows
+        self.parse_declaration(Some(lines.join("\n")))
+    }
+
+    /// Parses a `def` or `let`, optionally preceded by `pub` to export it
+    fn parse_declaration(&mut self, doc: Option<String>) -> Result<Statement, CrabbyError> {
+        let public = matches!(self.peek().token, Token::Public);
+        if public {
+            self.advance(); // consume 'pub'
+        }
+
+        match self.peek().token {
+            Token::Def => self.parse_function_definition(doc, public),
+            Token::Let => self.parse_let_statement(doc, public),
+            _ if public => Err(self.error("Expected 'def' or 'let' after 'pub'")),
+            _ => Err(self.error("Expected 'def' or 'let' after doc comment")),
+        }
+    }
+
+    fn parse_function_definition(&mut self, doc: Option<String>, public: bool) -> Result<Statement, CrabbyError> {
         self.advance(); // consume 'def'
 
         let name = if let Token::Identifier(name) = &self.peek().token {
@@ -81,6 +113,8 @@ impl<'a> Parser<'a> {
             name,
             params,
             body: Box::new(body),
+            public,
+            doc,
         })
     }
 
@@ -88,10 +122,6 @@ impl<'a> Parser<'a> {
     //    // ...
     // }
 
-    fn parse_macro_statement() {
-        // ...
-    }
-
     fn parse_if_statement(&mut self) -> Result<Statement, CrabbyError> {
         self.advance(); // consume 'if'
         let condition = self.parse_expression()?;
@@ -101,6 +131,10 @@ impl<'a> Parser<'a> {
 
         let else_branch = if matches!(self.peek().token, Token::Else) {
             self.advance(); // consume 'else'
+            // The ':' is optional before a brace block but needed to open an indented one
+            if matches!(self.peek().token, Token::Colon) {
+                self.advance();
+            }
             Some(Box::new(self.parse_block()?))
         } else {
             None
@@ -126,7 +160,121 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_expression(&mut self) -> Result<Expression, CrabbyError> {
-        self.parse_addition()
+        self.parse_pipe()
+    }
+
+    /// `value |> f(args)` becomes `f(value, args)` and `value |> f` becomes
+    /// `f(value)`, binding more loosely than any other operator
+    fn parse_pipe(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_or()?;
+
+        while matches!(self.peek().token, Token::Pipe) {
+            self.advance();
+            expr = match self.parse_or()? {
+                Expression::Call { function, mut arguments } => {
+                    arguments.insert(0, expr);
+                    Expression::Call { function, arguments }
+                }
+                Expression::CallValue { callee, mut arguments } => {
+                    arguments.insert(0, expr);
+                    Expression::CallValue { callee, arguments }
+                }
+                Expression::Variable(function) => Expression::Call {
+                    function,
+                    arguments: vec![expr],
+                },
+                callee @ (Expression::Member { .. } | Expression::Index { .. } | Expression::Lambda { .. }) => {
+                    Expression::CallValue {
+                        callee: Box::new(callee),
+                        arguments: vec![expr],
+                    }
+                }
+                _ => return Err(self.error("Expected a function or a call after '|>'")),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_and()?;
+
+        while matches!(self.peek().token, Token::Or) {
+            self.advance();
+            let right = self.parse_and()?;
+            expr = Expression::Binary {
+                left: Box::new(expr),
+                operator: BinaryOp::Or,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_equality()?;
+
+        while matches!(self.peek().token, Token::And) {
+            self.advance();
+            let right = self.parse_equality()?;
+            expr = Expression::Binary {
+                left: Box::new(expr),
+                operator: BinaryOp::And,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_equality(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_comparison()?;
+
+        while matches!(self.peek().token, Token::DoubleEquals | Token::NotEquals) {
+            let operator = match self.peek().token {
+                Token::DoubleEquals => BinaryOp::Eq,
+                Token::NotEquals => BinaryOp::Ne,
+                _ => unreachable!(),
+            };
+            self.advance();
+
+            let right = self.parse_comparison()?;
+            expr = Expression::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_addition()?;
+
+        while matches!(
+            self.peek().token,
+            Token::LessThan | Token::GreaterThan | Token::LessThanOrEqual | Token::GreaterThanOrEqual
+        ) {
+            let operator = match self.peek().token {
+                Token::LessThan => BinaryOp::Lt,
+                Token::GreaterThan => BinaryOp::Gt,
+                Token::LessThanOrEqual => BinaryOp::Le,
+                Token::GreaterThanOrEqual => BinaryOp::Ge,
+                _ => unreachable!(),
+            };
+            self.advance();
+
+            let right = self.parse_addition()?;
+            expr = Expression::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
     }
 
     fn parse_addition(&mut self) -> Result<Expression, CrabbyError> {
@@ -152,17 +300,22 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_multiplication(&mut self) -> Result<Expression, CrabbyError> {
-        let mut expr = self.parse_primary()?;
+        let mut expr = self.parse_unary()?;
 
-        while matches!(self.peek().token, Token::Star | Token::Slash) {
+        while matches!(
+            self.peek().token,
+            Token::Star | Token::Slash | Token::TildeSlash | Token::Percent
+        ) {
             let operator = match self.peek().token {
                 Token::Star => BinaryOp::Mul,
                 Token::Slash => BinaryOp::Div,
+                Token::TildeSlash => BinaryOp::FloorDiv,
+                Token::Percent => BinaryOp::Mod,
                 _ => unreachable!(),
             };
             self.advance();
 
-            let right = self.parse_primary()?;
+            let right = self.parse_unary()?;
             expr = Expression::Binary {
                 left: Box::new(expr),
                 operator,
@@ -173,10 +326,100 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    fn parse_unary(&mut self) -> Result<Expression, CrabbyError> {
+        let operator = match self.peek().token {
+            Token::Minus => UnaryOp::Neg,
+            Token::Not => UnaryOp::Not,
+            _ => return self.parse_postfix(),
+        };
+        self.advance();
+
+        // Fold `-` into a bare literal so that i64::MIN can be written
+        if let (UnaryOp::Neg, Token::Integer(magnitude)) = (&operator, &self.peek().token)
+            && !self.literal_has_postfix()
+        {
+            let value = 0i64.checked_sub_unsigned(*magnitude).ok_or_else(|| self.integer_overflow())?;
+            self.advance();
+            return Ok(Expression::Integer(value));
+        }
+
+        let operand = self.parse_unary()?;
+        Ok(Expression::Unary {
+            operator,
+            operand: Box::new(operand),
+        })
+    }
+
+    /// Parses member access, indexing and calls following a primary
+    /// expression. A `(` or `[` on a new line starts a new statement instead.
+    fn parse_postfix(&mut self) -> Result<Expression, CrabbyError> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            match self.peek().token {
+                Token::Dot => {
+                    self.advance(); // consume '.'
+                    // Keywords are fine as member names, as in `re.match`
+                    let name = match &self.peek().token {
+                        Token::Identifier(name) => name.clone(),
+                        _ if is_keyword(self.peek().slice) => self.peek().slice.to_string(),
+                        _ => return Err(self.error("Expected member name after '.'")),
+                    };
+                    self.advance();
+                    expr = Expression::Member {
+                        object: Box::new(expr),
+                        name,
+                    };
+                }
+                Token::LParen if self.continues_line() => {
+                    let arguments = self.parse_arguments()?;
+                    expr = Expression::CallValue {
+                        callee: Box::new(expr),
+                        arguments,
+                    };
+                }
+                Token::LBracket if self.continues_line() => {
+                    self.advance(); // consume '['
+                    let index = self.parse_expression()?;
+                    self.consume(&Token::RBracket, "Expected ']' after index")?;
+                    expr = Expression::Index {
+                        object: Box::new(expr),
+                        index: Box::new(index),
+                    };
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    /// Whether the literal at the current position is followed by member
+    /// access, indexing or a call, which bind tighter than a leading `-`
+    fn literal_has_postfix(&self) -> bool {
+        let Some(next) = self.tokens.get(self.current + 1) else {
+            return false;
+        };
+        match next.token {
+            Token::Dot => true,
+            Token::LParen | Token::LBracket => next.span.line == self.peek().span.line,
+            _ => false,
+        }
+    }
+
+    fn integer_overflow(&self) -> CrabbyError {
+        self.error(&format!("Integer literal '{}' does not fit in 64 bits", self.peek().slice))
+    }
+
+    /// Whether the next token is on the same line as the previous one
+    fn continues_line(&self) -> bool {
+        !self.is_at_end()
+            && self.current > 0
+            && self.tokens[self.current - 1].span.line == self.peek().span.line
+    }
+
     fn parse_primary(&mut self) -> Result<Expression, CrabbyError> {
         match &self.peek().token {
             Token::Integer(n) => {
-                let n = *n;
+                let n = i64::try_from(*n).map_err(|_| self.integer_overflow())?;
                 self.advance();
                 Ok(Expression::Integer(n))
             }
@@ -190,6 +433,11 @@ impl<'a> Parser<'a> {
                 self.advance();
                 Ok(Expression::String(s))
             }
+            Token::FString(body) => {
+                let expr = parse_format_string(body, self.peek())?;
+                self.advance();
+                Ok(expr)
+            }
             Token::Identifier(name) => {
                 let name = name.clone();
                 self.advance();
@@ -242,11 +490,43 @@ impl<'a> Parser<'a> {
                 self.consume(&Token::RParen, "Expected ')' after expression")?;
                 Ok(expr)
             }
+            Token::LBracket => {
+                self.advance(); // consume '['
+
+                let mut elements = Vec::new();
+                while !matches!(self.peek().token, Token::RBracket) {
+                    elements.push(self.parse_expression()?);
+                    if !matches!(self.peek().token, Token::Comma) {
+                        break;
+                    }
+                    self.advance(); // consume ','
+                }
+
+                self.consume(&Token::RBracket, "Expected ']' after list elements")?;
+                Ok(Expression::List(elements))
+            }
+            Token::LBrace => {
+                self.advance(); // consume '{'
+
+                let mut entries = Vec::new();
+                while !matches!(self.peek().token, Token::RBrace) {
+                    let key = self.parse_expression()?;
+                    self.consume(&Token::Colon, "Expected ':' after dict key")?;
+                    entries.push((key, self.parse_expression()?));
+                    if !matches!(self.peek().token, Token::Comma) {
+                        break;
+                    }
+                    self.advance(); // consume ','
+                }
+
+                self.consume(&Token::RBrace, "Expected '}' after dict entries")?;
+                Ok(Expression::Dict(entries))
+            }
             _ => Err(self.error("Expected expression")),
         }
     }
 
-    fn parse_let_statement(&mut self) -> Result<Statement, CrabbyError> {
+    fn parse_let_statement(&mut self, doc: Option<String>, public: bool) -> Result<Statement, CrabbyError> {
         self.advance(); // consume 'let'
 
         let name = if let Token::Identifier(name) = &self.peek().token {
@@ -262,6 +542,8 @@ impl<'a> Parser<'a> {
         Ok(Statement::Let {
             name,
             value: Box::new(value),
+            public,
+            doc,
         })
     }
 
@@ -315,26 +597,47 @@ impl<'a> Parser<'a> {
 
         let source = if matches!(self.peek().token, Token::From) {
             self.advance(); // consume 'from'
-            if let Token::String(path) = &self.peek().token {
-                Some(path.clone())
-            } else {
-                return Err(self.error("Expected string literal after 'from'"));
-            }
+            // A path to a file, or the name of a library module
+            let (Token::String(path) | Token::Identifier(path)) = &self.peek().token else {
+                return Err(self.error("Expected a path or module name after 'from'"));
+            };
+            let path = path.clone();
+            self.advance();
+            Some(path)
         } else {
             None
         };
-        self.advance();
 
         Ok(Statement::Import { name, source })
     }
 
     fn parse_function_call(&mut self, name: String) -> Result<Expression, CrabbyError> {
+        let arguments = self.parse_arguments()?;
+        Ok(Expression::Call {
+            function: name,
+            arguments,
+        })
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, CrabbyError> {
         self.advance(); // consume '('
 
         let mut arguments = Vec::new();
+        let mut keywords = Vec::new();
         if !matches!(self.peek().token, Token::RParen) {
             loop {
-                arguments.push(self.parse_expression()?);
+                if let Some(name) = self.keyword_name() {
+                    self.advance(); // consume name
+                    self.advance(); // consume '='
+                    if keywords.iter().any(|(seen, _)| *seen == name) {
+                        return Err(self.error(&format!("Keyword argument '{}' is repeated", name)));
+                    }
+                    keywords.push((name, self.parse_expression()?));
+                } else if keywords.is_empty() {
+                    arguments.push(self.parse_expression()?);
+                } else {
+                    return Err(self.error("Positional arguments must come before keyword arguments"));
+                }
                 if !matches!(self.peek().token, Token::Comma) {
                     break;
                 }
@@ -343,15 +646,27 @@ impl<'a> Parser<'a> {
         }
 
         self.consume(&Token::RParen, "Expected ')' after arguments")?;
+        if !keywords.is_empty() {
+            arguments.push(Expression::Keywords(keywords));
+        }
+        Ok(arguments)
+    }
 
-        Ok(Expression::Call {
-            function: name,
-            arguments,
-        })
+    /// The name of a `name = value` keyword argument starting here
+    fn keyword_name(&self) -> Option<String> {
+        let Token::Identifier(name) = &self.peek().token else {
+            return None;
+        };
+        let next = self.tokens.get(self.current + 1)?;
+        matches!(next.token, Token::Equals).then(|| name.to_string())
     }
 
     fn parse_block(&mut self) -> Result<Statement, CrabbyError> {
-        self.consume(&Token::LBrace, "Expected '{' at start of block")?;
+        if matches!(self.peek().token, Token::Indent) {
+            return self.parse_indented_block();
+        }
+
+        self.consume(&Token::LBrace, "Expected '{' or an indented block at start of block")?;
 
         let mut statements = Vec::new();
         while !matches!(self.peek().token, Token::RBrace) && !self.is_at_end() {
@@ -362,6 +677,18 @@ impl<'a> Parser<'a> {
         Ok(Statement::Block(statements))
     }
 
+    fn parse_indented_block(&mut self) -> Result<Statement, CrabbyError> {
+        self.advance(); // consume INDENT
+
+        let mut statements = Vec::new();
+        while !matches!(self.peek().token, Token::Dedent) && !self.is_at_end() {
+            statements.push(self.parse_statement()?);
+        }
+
+        self.consume(&Token::Dedent, "Expected end of indented block")?;
+        Ok(Statement::Block(statements))
+    }
+
     fn peek(&self) -> &TokenStream<'a> {
         if self.is_at_end() {
             &self.tokens[self.tokens.len() - 1]
@@ -404,7 +731,44 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Whether a token's text is a keyword, which are all lowercase words
+fn is_keyword(slice: &str) -> bool {
+    !slice.is_empty() && slice.bytes().all(|b| b.is_ascii_lowercase())
+}
+
 pub fn parse(tokens: Vec<TokenStream>) -> Result<Program, CrabbyError> {
+    let tokens = drop_dangling_doc_comments(tokens);
     let mut parser = Parser::new(&tokens);
     parser.parse()
 }
+
+/// Keeps only the `///` comments that document a declaration. Any other
+/// `///` is an ordinary comment and never reaches the parser.
+fn drop_dangling_doc_comments(tokens: Vec<TokenStream>) -> Vec<TokenStream> {
+    let mut documents = vec![false; tokens.len()];
+    let mut next_is_declaration = false;
+    for (i, stream) in tokens.iter().enumerate().rev() {
+        match stream.token {
+            Token::DocComment(_) => documents[i] = next_is_declaration,
+            _ => next_is_declaration = matches!(stream.token, Token::Public | Token::Def | Token::Let),
+        }
+    }
+
+    tokens
+        .into_iter()
+        .zip(documents)
+        .filter(|(stream, documents)| *documents || !matches!(stream.token, Token::DocComment(_)))
+        .map(|(stream, _)| stream)
+        .collect()
+}
+
+/// Parses a single expression that must use up every token, such as the
+/// expressions embedded in an f-string
+pub fn parse_expression(tokens: Vec<TokenStream>) -> Result<Expression, CrabbyError> {
+    let mut parser = Parser::new(&tokens);
+    let expr = parser.parse_expression()?;
+    if !parser.is_at_end() {
+        return Err(parser.error("Unexpected token after expression"));
+    }
+    Ok(expr)
+}
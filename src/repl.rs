@@ -0,0 +1,200 @@
+use crate::compile::{compile_source, module_loader};
+use crate::lexer::tokenize;
+use crate::parser::parser::parse;
+use crate::runtime::{Runtime, Value};
+use crate::utils::CrabbyError;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs;
+use std::path::PathBuf;
+
+const PROMPT: &str = "crabby> ";
+const CONTINUATION_PROMPT: &str = "   ...> ";
+const HISTORY_FILE: &str = ".crabby_history";
+
+const HELP: &str = "\
+Commands:
+  :help            Show this message
+  :load <file>     Run a file in the current session
+  :reset           Forget every definition made so far
+  :ast <code>      Show the syntax tree of <code>
+  :tokens <code>   Show the tokens of <code>
+  :quit            Leave the REPL (Ctrl-D works too)";
+
+/// An interactive session that keeps one `Runtime` alive, so definitions
+/// carry over from one input to the next
+pub struct Repl {
+    runtime: Runtime,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            runtime: new_runtime(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), ReadlineError> {
+        let mut editor = DefaultEditor::new()?;
+        let history = history_path();
+        if let Some(path) = &history {
+            // A missing history file just means this is the first session
+            let _ = editor.load_history(path);
+        }
+
+        println!(
+            "Crabby {} REPL. Type :help for commands, :quit to exit.",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let mut buffer = String::new();
+        loop {
+            let prompt = if buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+            match editor.readline(prompt) {
+                Ok(line) => {
+                    if buffer.is_empty() {
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        if line.trim_start().starts_with(':') {
+                            editor.add_history_entry(line.as_str())?;
+                            if !self.meta_command(line.trim()) {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
+
+                    buffer.push_str(&line);
+                    buffer.push('\n');
+                    if needs_more_input(&buffer, &line) {
+                        continue;
+                    }
+
+                    editor.add_history_entry(buffer.trim_end())?;
+                    let source = std::mem::take(&mut buffer);
+                    self.eval(&source);
+                }
+                // Ctrl-C throws away a half-typed block but keeps the session
+                Err(ReadlineError::Interrupted) => buffer.clear(),
+                Err(ReadlineError::Eof) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(path) = &history {
+            let _ = editor.save_history(path);
+        }
+        Ok(())
+    }
+
+    /// Runs one complete input and echoes the value of a trailing expression
+    pub fn eval(&mut self, source: &str) {
+        let result = compile_source(source, &self.runtime.global_names())
+            .and_then(|module| self.runtime.run(&module));
+
+        match result {
+            Ok(None | Some(Value::None)) => {}
+            Ok(Some(value)) => println!("{}", value.repr()),
+            Err(CrabbyError::Exit(code)) => std::process::exit(code),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+
+    /// Handles a `:command`, returning `false` when the session should end
+    fn meta_command(&mut self, line: &str) -> bool {
+        let (command, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+
+        match command {
+            ":quit" | ":q" | ":exit" => return false,
+            ":help" | ":h" => println!("{}", HELP),
+            ":reset" => {
+                self.runtime = new_runtime();
+                println!("Session reset");
+            }
+            ":load" if !argument.is_empty() => match fs::read_to_string(argument) {
+                Ok(source) => self.eval(&source),
+                Err(e) => eprintln!("Error: cannot read '{}': {}", argument, e),
+            },
+            ":ast" if !argument.is_empty() => match tokenize(argument).and_then(parse) {
+                Ok(program) => {
+                    for statement in &program.statements {
+                        println!("{:#?}", statement);
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            ":tokens" if !argument.is_empty() => match tokenize(argument) {
+                Ok(tokens) => {
+                    for token in &tokens {
+                        println!("{}:{}\t{:?}", token.span.line, token.span.column, token.token);
+                    }
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            ":load" | ":ast" | ":tokens" => eprintln!("Error: {} needs an argument", command),
+            _ => eprintln!("Error: unknown command '{}', try :help", command),
+        }
+
+        true
+    }
+}
+
+/// Whether the input so far is an unfinished block: an unclosed bracket, or an
+/// indented block that has not yet been ended with an empty line
+pub fn needs_more_input(buffer: &str, last_line: &str) -> bool {
+    if bracket_depth(buffer) > 0 {
+        return true;
+    }
+
+    let opens_indented_block = buffer.lines().any(|line| line.trim_end().ends_with(':'));
+    opens_indented_block && !last_line.trim().is_empty()
+}
+
+fn bracket_depth(source: &str) -> i64 {
+    let mut depth = 0;
+    let mut chars = source.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' => {
+                for ch in chars.by_ref() {
+                    if ch == '"' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for ch in chars.by_ref() {
+                    if ch == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth
+}
+
+fn new_runtime() -> Runtime {
+    let mut runtime = Runtime::new();
+    runtime.set_module_loader(module_loader());
+    runtime
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
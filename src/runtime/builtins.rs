@@ -0,0 +1,354 @@
+use crate::ir::BinaryOp;
+use crate::runtime::stdlib::{arg_range, integer_arg, read_line};
+use crate::runtime::value::Value;
+use crate::runtime::{Runtime, ops};
+use crate::utils::CrabbyError;
+use indexmap::IndexMap;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// Registers the builtins every program can use without importing anything
+pub fn register(runtime: &mut Runtime) {
+    runtime.register_native_with_keywords("print", None, &["sep", "end"], print);
+    runtime.register_native("input", None, input);
+
+    runtime.register_native("type", Some(1), type_of);
+    runtime.register_native("repr", Some(1), repr);
+    runtime.register_native("str", Some(1), str);
+    runtime.register_native("int", None, int);
+    runtime.register_native("float", Some(1), float);
+    runtime.register_native("list", Some(1), list);
+    runtime.register_native("bytes", Some(1), bytes);
+    runtime.register_native("assert", None, assert);
+    runtime.register_native("try", None, try_call);
+
+    runtime.register_native("len", Some(1), len);
+    runtime.register_native_with_keywords("sorted", Some(1), &["key", "reverse"], sorted);
+    runtime.register_native("reversed", Some(1), reversed);
+    runtime.register_native("enumerate", None, enumerate);
+    runtime.register_native("zip", None, zip);
+    runtime.register_native("sum", None, sum);
+    runtime.register_native("any", Some(1), any);
+    runtime.register_native("all", Some(1), all);
+}
+
+fn error(message: String) -> CrabbyError {
+    CrabbyError::RuntimeError(message)
+}
+
+/// Everything a `for` loop over `value` would visit, collected into a list
+fn elements(runtime: &mut Runtime, value: &Value) -> Result<Vec<Value>, CrabbyError> {
+    Ok(match value {
+        Value::Integer(n) => (0..*n).map(Value::Integer).collect(),
+        Value::List(elements) => elements.to_vec(),
+        Value::Dict(entries) => entries.keys().cloned().map(Value::String).collect(),
+        Value::Bytes(bytes) => bytes.iter().map(|&byte| Value::Integer(byte as i64)).collect(),
+        Value::Iterator(iterator) => {
+            let mut elements = Vec::new();
+            while let Some(element) = iterator.next(runtime)? {
+                elements.push(element);
+            }
+            elements
+        }
+        other => return Err(ops::not_iterable(other)),
+    })
+}
+
+/// `print(values..., sep = " ", end = "\n")` writes the values separated by
+/// `sep` and followed by `end`
+fn print(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let mut sep = " ".to_string();
+    let mut end = "\n".to_string();
+    for (name, value) in runtime.take_keywords() {
+        let Value::String(text) = value else {
+            return Err(error(format!(
+                "print() expects {} to be a string, got {}",
+                name,
+                value.type_name()
+            )));
+        };
+        match name.as_str() {
+            "sep" => sep = text,
+            _ => end = text,
+        }
+    }
+
+    let mut line = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            line.push_str(&sep);
+        }
+        line.push_str(&arg.to_string());
+    }
+    line.push_str(&end);
+    print!("{}", line);
+    Ok(Value::None)
+}
+
+/// `input(prompt = none)` reads a line from stdin, or none at its end
+fn input(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("input", &args, 0, 1)?;
+    read_line(args.first())
+}
+
+/// The UTF-8 encoding of a string, or bytes from a list of integers 0 to 255
+fn bytes(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let bytes = match &args[0] {
+        Value::Bytes(bytes) => return Ok(Value::Bytes(bytes.clone())),
+        Value::String(s) => s.as_bytes().to_vec(),
+        Value::List(elements) => elements
+            .iter()
+            .map(|element| match element {
+                Value::Integer(n) => u8::try_from(*n).map_err(|_| {
+                    CrabbyError::RuntimeError(format!("Byte value {} is out of range 0 to 255", n))
+                }),
+                other => Err(CrabbyError::RuntimeError(format!(
+                    "bytes() expects a list of integers, got a {} in it",
+                    other.type_name()
+                ))),
+            })
+            .collect::<Result<_, _>>()?,
+        other => {
+            return Err(CrabbyError::RuntimeError(format!(
+                "bytes() expects a string or a list of integers, got {}",
+                other.type_name()
+            )))
+        }
+    };
+    Ok(Value::Bytes(Rc::new(bytes)))
+}
+
+/// The name of a value's type, such as `"integer"` or `"list"`
+fn type_of(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    Ok(Value::String(args[0].type_name().to_string()))
+}
+
+/// A value as source would write it, with strings quoted
+fn repr(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    Ok(Value::String(args[0].repr()))
+}
+
+/// A value as `print` would write it
+fn str(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    Ok(Value::String(args[0].to_string()))
+}
+
+/// `int(value, base = 10)` truncates a float towards zero or reads a string
+/// of digits in the given base
+fn int(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("int", &args, 1, 2)?;
+    let cannot = |value: &Value| error(format!("int() cannot convert {} to an integer", value.repr()));
+    match (&args[0], args.get(1)) {
+        (Value::Integer(n), None) => Ok(Value::Integer(*n)),
+        // Casting saturates, so check the range first
+        (Value::Float(x), None) if x.is_finite() && x.abs() < i64::MAX as f64 => {
+            Ok(Value::Integer(x.trunc() as i64))
+        }
+        (Value::Float(_), None) => Err(cannot(&args[0])),
+        (Value::String(text), base) => {
+            let base = match base {
+                Some(base) => integer_arg("int", base)?,
+                None => 10,
+            };
+            let radix = u32::try_from(base)
+                .ok()
+                .filter(|radix| (2..=36).contains(radix))
+                .ok_or_else(|| error(format!("int() expects a base from 2 to 36, got {}", base)))?;
+            i64::from_str_radix(text.trim(), radix)
+                .map(Value::Integer)
+                .map_err(|_| cannot(&args[0]))
+        }
+        (other, Some(_)) => Err(error(format!(
+            "int() takes a base only for a string, got {}",
+            other.type_name()
+        ))),
+        (other, None) => Err(error(format!(
+            "int() cannot convert a value of type {} to an integer",
+            other.type_name()
+        ))),
+    }
+}
+
+/// An integer or a string of digits as a float
+fn float(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    match &args[0] {
+        Value::Integer(n) => Ok(Value::Float(*n as f64)),
+        Value::Float(x) => Ok(Value::Float(*x)),
+        Value::String(text) => text.trim().parse().map(Value::Float).map_err(|_| {
+            error(format!("float() cannot convert {} to a float", args[0].repr()))
+        }),
+        other => Err(error(format!(
+            "float() cannot convert a value of type {} to a float",
+            other.type_name()
+        ))),
+    }
+}
+
+/// The elements of anything a `for` loop accepts, such as an iterator
+fn list(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    Ok(Value::List(Rc::new(elements(runtime, &args[0])?)))
+}
+
+/// `assert(condition, message = none)` fails when the condition is false
+fn assert(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("assert", &args, 1, 2)?;
+    if args[0].is_truthy() {
+        return Ok(Value::None);
+    }
+    Err(error(match args.get(1) {
+        Some(message) => format!("Assertion failed: {}", message),
+        None => "Assertion failed".to_string(),
+    }))
+}
+
+/// `try(f, args...)` calls `f` with `args` and turns a runtime error into a
+/// result instead of letting it end the program. The result is a dict whose
+/// `value` is what `f` returned, or else whose `error` is the message and
+/// `kind` says what went wrong: "not_found", "timeout" and the like from the
+/// modules that give one, "runtime" for anything else. `sys.exit` is not
+/// caught.
+fn try_call(runtime: &mut Runtime, mut args: Vec<Value>) -> Result<Value, CrabbyError> {
+    if args.is_empty() {
+        return Err(error("try() expects a function to call".to_string()));
+    }
+    let function = args.remove(0);
+    let (value, failure) = match runtime.call(&function, args) {
+        Ok(value) => (value, None),
+        Err(CrabbyError::RuntimeError(message)) => (Value::None, Some(("runtime", message))),
+        Err(CrabbyError::Failure { kind, message }) => (Value::None, Some((kind, message))),
+        Err(other) => return Err(other),
+    };
+    let (error, kind) = match failure {
+        Some((kind, message)) => (Value::String(message), Value::String(kind.to_string())),
+        None => (Value::None, Value::None),
+    };
+    let result = IndexMap::from([
+        ("value".to_string(), value),
+        ("error".to_string(), error),
+        ("kind".to_string(), kind),
+    ]);
+    Ok(Value::Dict(Rc::new(result)))
+}
+
+/// The number of characters in a string, elements in a list, entries in a
+/// dict or bytes in bytes
+fn len(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let len = match &args[0] {
+        Value::String(s) => s.chars().count(),
+        Value::List(elements) => elements.len(),
+        Value::Dict(entries) => entries.len(),
+        Value::Bytes(bytes) => bytes.len(),
+        other => {
+            return Err(error(format!(
+                "len() expects a string, list, dict or bytes, got {}",
+                other.type_name()
+            )));
+        }
+    };
+    Ok(Value::Integer(len as i64))
+}
+
+/// Orders two values with `<`, failing for values it cannot compare
+fn compare(left: &Value, right: &Value) -> Result<Ordering, CrabbyError> {
+    let less = |l: &Value, r: &Value| {
+        ops::binary(BinaryOp::Lt, l.clone(), r.clone()).map(|less| less.is_truthy())
+    };
+    if less(left, right)? {
+        Ok(Ordering::Less)
+    } else if less(right, left)? {
+        Ok(Ordering::Greater)
+    } else {
+        Ok(Ordering::Equal)
+    }
+}
+
+/// `sorted(values, key = none, reverse = 0)` returns a new list in ascending
+/// order, or descending with `reverse`. Elements that compare equal keep
+/// their order, and `key` maps each element to what it is sorted by.
+fn sorted(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let keywords = runtime.take_keywords();
+    let reverse = keywords.get("reverse").is_some_and(Value::is_truthy);
+    let mut keyed = Vec::new();
+    for element in elements(runtime, &args[0])? {
+        let key = match keywords.get("key") {
+            Some(key) => runtime.call(key, vec![element.clone()])?,
+            None => element.clone(),
+        };
+        keyed.push((key, element));
+    }
+
+    // sort_by cannot stop early, so remember the first failure
+    let mut failure = None;
+    keyed.sort_by(|(left, _), (right, _)| {
+        if failure.is_some() {
+            return Ordering::Equal;
+        }
+        let (left, right) = if reverse { (right, left) } else { (left, right) };
+        compare(left, right).unwrap_or_else(|e| {
+            failure = Some(e);
+            Ordering::Equal
+        })
+    });
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
+    Ok(Value::List(Rc::new(keyed.into_iter().map(|(_, element)| element).collect())))
+}
+
+/// A new list of the elements in the opposite order
+fn reversed(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let mut elements = elements(runtime, &args[0])?;
+    elements.reverse();
+    Ok(Value::List(Rc::new(elements)))
+}
+
+/// `enumerate(values, start = 0)` pairs each element with its position, as
+/// `[index, element]` lists
+fn enumerate(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("enumerate", &args, 1, 2)?;
+    let start = match args.get(1) {
+        Some(start) => integer_arg("enumerate", start)?,
+        None => 0,
+    };
+    let pairs = elements(runtime, &args[0])?
+        .into_iter()
+        .zip(start..)
+        .map(|(element, i)| Value::List(Rc::new(vec![Value::Integer(i), element])))
+        .collect();
+    Ok(Value::List(Rc::new(pairs)))
+}
+
+/// Lists of the elements at each position of every argument, stopping at
+/// the end of the shortest
+fn zip(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let columns = args
+        .iter()
+        .map(|arg| elements(runtime, arg))
+        .collect::<Result<Vec<_>, _>>()?;
+    let rows = columns.iter().map(Vec::len).min().unwrap_or(0);
+    let rows = (0..rows)
+        .map(|i| Value::List(Rc::new(columns.iter().map(|column| column[i].clone()).collect())))
+        .collect();
+    Ok(Value::List(Rc::new(rows)))
+}
+
+/// `sum(values, start = 0)` adds the elements to `start` with `+`
+fn sum(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("sum", &args, 1, 2)?;
+    let start = args.get(1).cloned().unwrap_or(Value::Integer(0));
+    elements(runtime, &args[0])?
+        .into_iter()
+        .try_fold(start, |total, element| ops::binary(BinaryOp::Add, total, element))
+}
+
+/// Whether any element is true
+fn any(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let found = elements(runtime, &args[0])?.iter().any(Value::is_truthy);
+    Ok(Value::Integer(found as i64))
+}
+
+/// Whether every element is true
+fn all(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let found = elements(runtime, &args[0])?.iter().all(Value::is_truthy);
+    Ok(Value::Integer(found as i64))
+}
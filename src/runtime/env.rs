@@ -0,0 +1,70 @@
+use crate::runtime::value::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+thread_local! {
+    /// Bumped whenever a global binding is defined, so cached global lookups
+    /// can tell when they may be stale
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The current binding generation; see `invalidate`
+pub fn generation() -> u64 {
+    GENERATION.with(Cell::get)
+}
+
+/// Marks every cached lookup as stale
+pub fn invalidate() {
+    GENERATION.with(|generation| generation.set(generation.get() + 1));
+}
+
+/// A scope of variables. Each function call gets a child of the environment the
+/// function was defined in, which is what makes closures see their surroundings.
+/// A function stored in the scope it closes over keeps that scope alive; such
+/// cycles live until the runtime is dropped at exit.
+#[derive(Clone, Default)]
+pub struct Env(Rc<RefCell<Scope>>);
+
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Value>,
+    parent: Option<Env>,
+}
+
+impl Env {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn child(&self) -> Self {
+        Env(Rc::new(RefCell::new(Scope {
+            vars: HashMap::new(),
+            parent: Some(self.clone()),
+        })))
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let scope = self.0.borrow();
+        match scope.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => scope.parent.as_ref().and_then(|parent| parent.get(name)),
+        }
+    }
+
+    /// Binds `name` in this scope, shadowing any outer binding. Only a global
+    /// scope, one without a parent, can change what a cached global lookup
+    /// finds, so binding a parameter or local leaves the caches alone.
+    pub fn define(&self, name: &str, value: Value) {
+        let mut scope = self.0.borrow_mut();
+        scope.vars.insert(name.to_string(), value);
+        if scope.parent.is_none() {
+            invalidate();
+        }
+    }
+
+    /// Names bound directly in this scope
+    pub fn names(&self) -> Vec<String> {
+        self.0.borrow().vars.keys().cloned().collect()
+    }
+}
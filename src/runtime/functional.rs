@@ -0,0 +1,274 @@
+//! The functional builtins. `map`, `filter`, `take`, `drop` and `flat_map`
+//! take a list, range or iterator and return an iterator that does its work
+//! one element at a time as a `for` loop asks for it, so they can be chained
+//! over long sequences. `reduce`, `fold` and `group_by` consume
+//! what they are given. The sequence comes first, which suits `|>`:
+//! `xs |> filter(even) |> map(square)`.
+
+use crate::runtime::stdlib::arg_range;
+use crate::runtime::value::{IteratorValue, Producer, Value};
+use crate::runtime::{Runtime, ops};
+use crate::utils::CrabbyError;
+use indexmap::IndexMap;
+use std::rc::Rc;
+
+/// Registers the functional builtins
+pub fn register(runtime: &mut Runtime) {
+    runtime.register_native("map", Some(2), map);
+    runtime.register_native("filter", Some(2), filter);
+    runtime.register_native("take", Some(2), take);
+    runtime.register_native("drop", Some(2), drop);
+    runtime.register_native("flat_map", Some(2), flat_map);
+    runtime.register_native("reduce", None, reduce);
+    runtime.register_native("fold", Some(3), fold);
+    runtime.register_native("group_by", Some(2), group_by);
+}
+
+fn error(message: String) -> CrabbyError {
+    CrabbyError::RuntimeError(message)
+}
+
+/// An iterator over anything a `for` loop accepts. An iterator is shared,
+/// not copied, so consuming either consumes both.
+fn iterate(value: &Value) -> Result<Rc<IteratorValue>, CrabbyError> {
+    match value {
+        Value::Iterator(iterator) => Ok(Rc::clone(iterator)),
+        Value::Integer(_) | Value::List(_) | Value::Dict(_) | Value::Bytes(_) => {
+            Ok(Rc::new(IteratorValue::new(Elements {
+                sequence: value.clone(),
+                index: 0,
+            })))
+        }
+        other => Err(ops::not_iterable(other)),
+    }
+}
+
+fn lazy(producer: impl Producer + 'static) -> Result<Value, CrabbyError> {
+    Ok(Value::Iterator(Rc::new(IteratorValue::new(producer))))
+}
+
+fn count_arg(function: &str, value: &Value) -> Result<usize, CrabbyError> {
+    match value {
+        Value::Integer(n) => usize::try_from(*n).map_err(|_| {
+            error(format!(
+                "{}() expects a count of 0 or more, got {}",
+                function, n
+            ))
+        }),
+        other => Err(error(format!(
+            "{}() expects an integer count, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+/// The elements of a range, list, dict or bytes, in the order `for` visits them
+struct Elements {
+    sequence: Value,
+    index: usize,
+}
+
+impl Producer for Elements {
+    fn next(&mut self, _: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        let element = match &self.sequence {
+            Value::Integer(n) => {
+                ((self.index as i64) < *n).then(|| Value::Integer(self.index as i64))
+            }
+            Value::List(elements) => elements.get(self.index).cloned(),
+            Value::Dict(entries) => entries
+                .get_index(self.index)
+                .map(|(key, _)| Value::String(key.clone())),
+            Value::Bytes(bytes) => bytes
+                .get(self.index)
+                .map(|&byte| Value::Integer(byte as i64)),
+            _ => None,
+        };
+        self.index += 1;
+        Ok(element)
+    }
+}
+
+/// `map(values, f)`: `f` of each element
+fn map(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    lazy(Map {
+        source: iterate(&args[0])?,
+        function: args[1].clone(),
+    })
+}
+
+struct Map {
+    source: Rc<IteratorValue>,
+    function: Value,
+}
+
+impl Producer for Map {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        match self.source.next(runtime)? {
+            Some(element) => runtime.call(&self.function, vec![element]).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// `filter(values, f)`: the elements for which `f` is true
+fn filter(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    lazy(Filter {
+        source: iterate(&args[0])?,
+        function: args[1].clone(),
+    })
+}
+
+struct Filter {
+    source: Rc<IteratorValue>,
+    function: Value,
+}
+
+impl Producer for Filter {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        while let Some(element) = self.source.next(runtime)? {
+            if runtime
+                .call(&self.function, vec![element.clone()])?
+                .is_truthy()
+            {
+                return Ok(Some(element));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// `take(values, n)`: the first `n` elements, without asking for any more
+fn take(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    lazy(Take {
+        source: iterate(&args[0])?,
+        remaining: count_arg("take", &args[1])?,
+    })
+}
+
+struct Take {
+    source: Rc<IteratorValue>,
+    remaining: usize,
+}
+
+impl Producer for Take {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        self.source.next(runtime)
+    }
+}
+
+/// `drop(values, n)`: everything after the first `n` elements
+fn drop(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    lazy(Skip {
+        source: iterate(&args[0])?,
+        skip: count_arg("drop", &args[1])?,
+    })
+}
+
+struct Skip {
+    source: Rc<IteratorValue>,
+    /// Elements still to skip before the first one is produced
+    skip: usize,
+}
+
+impl Producer for Skip {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        while self.skip > 0 {
+            self.skip -= 1;
+            if self.source.next(runtime)?.is_none() {
+                self.skip = 0;
+                return Ok(None);
+            }
+        }
+        self.source.next(runtime)
+    }
+}
+
+/// `flat_map(values, f)`: the elements of each `f(element)` in turn
+fn flat_map(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    lazy(FlatMap {
+        source: iterate(&args[0])?,
+        function: args[1].clone(),
+        inner: None,
+    })
+}
+
+struct FlatMap {
+    source: Rc<IteratorValue>,
+    function: Value,
+    /// The elements of the latest `f(element)`
+    inner: Option<Rc<IteratorValue>>,
+}
+
+impl Producer for FlatMap {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        loop {
+            if let Some(inner) = &self.inner
+                && let Some(element) = inner.next(runtime)?
+            {
+                return Ok(Some(element));
+            }
+            let Some(element) = self.source.next(runtime)? else {
+                self.inner = None;
+                return Ok(None);
+            };
+            let elements = runtime.call(&self.function, vec![element])?;
+            self.inner = Some(iterate(&elements)?);
+        }
+    }
+}
+
+/// `reduce(values, f, initial)` combines the elements with `f(total,
+/// element)`, starting from `initial` or else the first element
+fn reduce(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("reduce", &args, 2, 3)?;
+    let source = iterate(&args[0])?;
+    let initial = match args.get(2) {
+        Some(initial) => initial.clone(),
+        None => source.next(runtime)?.ok_or_else(|| {
+            error("reduce() of an empty sequence needs an initial value".to_string())
+        })?,
+    };
+    combine(runtime, &source, initial, &args[1])
+}
+
+/// `fold(values, initial, f)` combines the elements with `f(total,
+/// element)`, starting from `initial`
+fn fold(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let source = iterate(&args[0])?;
+    combine(runtime, &source, args[1].clone(), &args[2])
+}
+
+fn combine(
+    runtime: &mut Runtime,
+    source: &IteratorValue,
+    initial: Value,
+    function: &Value,
+) -> Result<Value, CrabbyError> {
+    let mut total = initial;
+    while let Some(element) = source.next(runtime)? {
+        total = runtime.call(function, vec![total, element])?;
+    }
+    Ok(total)
+}
+
+/// `group_by(values, f)`: a dict from each key `f` returns, written as `str`
+/// would, to a list of the elements with that key. Keys and the elements
+/// under them keep the order they were first seen in.
+fn group_by(runtime: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let source = iterate(&args[0])?;
+    let mut groups: IndexMap<String, Vec<Value>> = IndexMap::new();
+    while let Some(element) = source.next(runtime)? {
+        let key = runtime.call(&args[1], vec![element.clone()])?;
+        groups.entry(key.to_string()).or_default().push(element);
+    }
+    let groups = groups
+        .into_iter()
+        .map(|(key, elements)| (key, Value::List(Rc::new(elements))))
+        .collect();
+    Ok(Value::Dict(Rc::new(groups)))
+}
@@ -0,0 +1,209 @@
+use crate::ir::{BinaryOp, Expr, FormatPart, Stmt};
+use crate::runtime::env::Env;
+use crate::runtime::ops;
+use crate::runtime::value::{format_value, Closure, Value};
+use crate::runtime::Runtime;
+use crate::utils::CrabbyError;
+use std::rc::Rc;
+
+/// How a statement finished: normally, carrying the value of a trailing
+/// expression, or through `return`
+pub enum Flow {
+    Normal(Option<Value>),
+    Return(Value),
+}
+
+/// Tree-walking execution of checked IR
+pub struct Interpreter<'rt> {
+    runtime: &'rt mut Runtime,
+}
+
+impl<'rt> Interpreter<'rt> {
+    pub fn new(runtime: &'rt mut Runtime) -> Self {
+        Self { runtime }
+    }
+
+    pub fn exec_block(&mut self, statements: &[Stmt], env: &Env) -> Result<Flow, CrabbyError> {
+        let mut last = None;
+        for statement in statements {
+            match self.exec(statement, env)? {
+                Flow::Normal(value) => last = value,
+                Flow::Return(value) => return Ok(Flow::Return(value)),
+            }
+        }
+        Ok(Flow::Normal(last))
+    }
+
+    fn exec(&mut self, statement: &Stmt, env: &Env) -> Result<Flow, CrabbyError> {
+        match statement {
+            Stmt::Let { name, value, .. } => {
+                let value = self.eval(value, env)?;
+                env.define(name, value);
+                Ok(Flow::Normal(None))
+            }
+            Stmt::Function(decl) => {
+                let closure = Closure {
+                    decl: Rc::clone(decl),
+                    env: env.clone(),
+                };
+                env.define(&decl.name, Value::Function(Rc::new(closure)));
+                Ok(Flow::Normal(None))
+            }
+            Stmt::Return(expr) => Ok(Flow::Return(self.eval(expr, env)?)),
+            Stmt::If { condition, then_branch, else_branch } => {
+                if self.eval(condition, env)?.is_truthy() {
+                    self.exec_block(then_branch, env)
+                } else {
+                    self.exec_block(else_branch, env)
+                }
+            }
+            Stmt::While { condition, body } => {
+                while self.eval(condition, env)?.is_truthy() {
+                    if let Flow::Return(value) = self.exec_block(body, env)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                Ok(Flow::Normal(None))
+            }
+            Stmt::Loop { count, body } => {
+                let Value::Integer(n) = self.eval(count, env)? else {
+                    return Err(CrabbyError::RuntimeError("Loop count must be an integer".to_string()));
+                };
+                for _ in 0..n {
+                    if let Flow::Return(value) = self.exec_block(body, env)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                Ok(Flow::Normal(None))
+            }
+            Stmt::ForIn { variable, iterable, body } => {
+                let elements: Box<dyn Iterator<Item = Value>> = match self.eval(iterable, env)? {
+                    Value::Iterator(iterator) => {
+                        while let Some(element) = iterator.next(self.runtime)? {
+                            env.define(variable, element);
+                            if let Flow::Return(value) = self.exec_block(body, env)? {
+                                return Ok(Flow::Return(value));
+                            }
+                        }
+                        return Ok(Flow::Normal(None));
+                    }
+                    Value::Integer(n) => Box::new((0..n).map(Value::Integer)),
+                    Value::List(elements) => Box::new((0..elements.len()).map(move |i| elements[i].clone())),
+                    Value::Dict(entries) => {
+                        Box::new((0..entries.len()).filter_map(move |i| entries.get_index(i).map(|(key, _)| Value::String(key.clone()))))
+                    }
+                    Value::Bytes(bytes) => Box::new((0..bytes.len()).map(move |i| Value::Integer(bytes[i] as i64))),
+                    other => return Err(ops::not_iterable(&other)),
+                };
+                for element in elements {
+                    env.define(variable, element);
+                    if let Flow::Return(value) = self.exec_block(body, env)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                Ok(Flow::Normal(None))
+            }
+            Stmt::Import { name, source } => {
+                env.define(name, self.runtime.import(name, source.as_deref())?);
+                Ok(Flow::Normal(None))
+            }
+            Stmt::Expr(expr) => Ok(Flow::Normal(Some(self.eval(expr, env)?))),
+        }
+    }
+
+    pub fn eval(&mut self, expression: &Expr, env: &Env) -> Result<Value, CrabbyError> {
+        match expression {
+            Expr::Integer(n) => Ok(Value::Integer(*n)),
+            Expr::Float(f) => Ok(Value::Float(*f)),
+            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::Variable(name) => self.runtime.lookup(env, name),
+            Expr::Range(count) => match self.eval(count, env)? {
+                Value::Integer(n) => Ok(Value::Integer(n)),
+                _ => Err(CrabbyError::RuntimeError("Range argument must be an integer".to_string())),
+            },
+            Expr::Unary { op, operand } => {
+                let value = self.eval(operand, env)?;
+                ops::unary(*op, value)
+            }
+            Expr::Binary { left, op: op @ (BinaryOp::And | BinaryOp::Or), right } => {
+                let left = self.eval(left, env)?.is_truthy();
+                let value = match op {
+                    BinaryOp::And => left && self.eval(right, env)?.is_truthy(),
+                    _ => left || self.eval(right, env)?.is_truthy(),
+                };
+                Ok(Value::Integer(value as i64))
+            }
+            Expr::Binary { left, op, right } => {
+                let left = self.eval(left, env)?;
+                let right = self.eval(right, env)?;
+                ops::binary(*op, left, right)
+            }
+            Expr::Call { callee, args } => {
+                let function = self.runtime.lookup(env, callee)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime.call(&function, args)
+            }
+            Expr::CallValue { callee, args } => {
+                let function = self.eval(callee, env)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval(arg, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.runtime.call(&function, args)
+            }
+            Expr::List(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.eval(element, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::List(Rc::new(elements)))
+            }
+            Expr::Dict(entries) => {
+                let pairs = entries
+                    .iter()
+                    .map(|(key, value)| Ok((self.eval(key, env)?, self.eval(value, env)?)))
+                    .collect::<Result<Vec<_>, CrabbyError>>()?;
+                ops::make_dict(pairs)
+            }
+            Expr::Keywords(arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.eval(value, env)?)))
+                    .collect::<Result<_, CrabbyError>>()?;
+                Ok(Value::Keywords(Rc::new(arguments)))
+            }
+            Expr::Index { object, index } => {
+                let object = self.eval(object, env)?;
+                let index = self.eval(index, env)?;
+                ops::index(object, index)
+            }
+            Expr::Member { object, name } => {
+                let object = self.eval(object, env)?;
+                ops::member(object, name)
+            }
+            Expr::Lambda(decl) => Ok(Value::Function(Rc::new(Closure {
+                decl: Rc::clone(decl),
+                env: env.clone(),
+            }))),
+            Expr::Format(parts) => {
+                let mut result = String::new();
+                for part in parts {
+                    match part {
+                        FormatPart::Literal(text) => result.push_str(text),
+                        FormatPart::Value { expr, spec } => {
+                            let value = self.eval(expr, env)?;
+                            match spec {
+                                Some(spec) => result.push_str(&format_value(&value, spec)?),
+                                None => result.push_str(&value.to_string()),
+                            }
+                        }
+                    }
+                }
+                Ok(Value::String(result))
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Tiered compilation. Every compiled closure counts its calls and loop
+//! back-edges; once it is hot, its bytecode is compiled to native code when
+//! the function only does integer arithmetic on its own locals. Native code
+//! has no side effects, so whenever it meets something it cannot handle
+//! (a non-integer, an overflow, a `none` result) it bails out and the call
+//! simply runs again on the VM.
+
+#[cfg(all(target_arch = "x86_64", unix))]
+mod x86_64;
+
+use crate::bytecode::FunctionProto;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Calls plus back-edges after which a function is compiled
+const HOT_THRESHOLD: u32 = 1000;
+
+/// Functions that bail out this often go back to the VM for good
+const MAX_BAILOUTS: u64 = 16;
+
+/// Native calls nested deeper than this bail out, so deep recursion hits the
+/// VM's recursion limit instead of overflowing the native stack
+const MAX_NATIVE_DEPTH: u64 = 2000;
+
+/// Shared between native code and the VM for one native call
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct JitContext {
+    /// Set by native code that could not finish the call
+    pub bailout: u64,
+    pub depth: u64,
+}
+
+/// A function's entry in the JIT, shared by every closure over it
+pub(crate) struct JitFunction {
+    name: String,
+    code: Result<NativeCode, String>,
+    native_calls: Cell<u64>,
+    bailouts: Cell<u64>,
+}
+
+impl JitFunction {
+    /// The native code while it is still worth running
+    pub fn code(&self) -> Option<&NativeCode> {
+        self.code
+            .as_ref()
+            .ok()
+            .filter(|_| self.bailouts.get() < MAX_BAILOUTS)
+    }
+
+    /// Runs the native code with integer arguments, or returns `None` when
+    /// the call has to be run on the VM instead
+    pub fn call(&self, code: &NativeCode, args: &[i64]) -> Option<i64> {
+        let mut context = JitContext::default();
+        let result = code.call(args, &mut context);
+        if context.bailout != 0 {
+            self.bailouts.set(self.bailouts.get() + 1);
+            return None;
+        }
+        self.native_calls.set(self.native_calls.get() + 1);
+        Some(result)
+    }
+}
+
+/// Native code for one function
+pub(crate) struct NativeCode {
+    #[cfg(all(target_arch = "x86_64", unix))]
+    inner: x86_64::Code,
+    /// The global name the function calls itself through, which must still
+    /// refer to the same closure when native code is entered
+    pub self_call: Option<u32>,
+}
+
+impl NativeCode {
+    #[cfg(all(target_arch = "x86_64", unix))]
+    fn call(&self, args: &[i64], context: &mut JitContext) -> i64 {
+        self.inner.call(args, context)
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", unix)))]
+    fn call(&self, _args: &[i64], context: &mut JitContext) -> i64 {
+        context.bailout = 1;
+        0
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", unix))]
+fn compile(proto: &FunctionProto) -> Result<NativeCode, String> {
+    let (inner, self_call) = x86_64::compile(proto, MAX_NATIVE_DEPTH)?;
+    Ok(NativeCode { inner, self_call })
+}
+
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+fn compile(_proto: &FunctionProto) -> Result<NativeCode, String> {
+    Err("the JIT only targets x86-64".to_string())
+}
+
+/// Per-closure hotness, and the compiled function once it got hot
+#[derive(Default)]
+pub(crate) struct JitSlot {
+    hotness: Cell<u32>,
+    function: RefCell<Option<Rc<JitFunction>>>,
+}
+
+impl JitSlot {
+    /// Counts a call or back-edge, returning true when the closure has just
+    /// become hot
+    pub fn tick(&self) -> bool {
+        let hotness = self.hotness.get().saturating_add(1);
+        self.hotness.set(hotness);
+        hotness == HOT_THRESHOLD
+    }
+
+    pub fn function(&self) -> Option<Rc<JitFunction>> {
+        self.function.borrow().clone()
+    }
+
+    pub fn set_function(&self, function: Rc<JitFunction>) {
+        *self.function.borrow_mut() = Some(function);
+    }
+}
+
+/// What the JIT did with one function
+#[derive(Debug, Clone, PartialEq)]
+pub struct JitStats {
+    pub name: String,
+    /// Why the function stayed on the VM, when it was not compiled
+    pub rejected: Option<String>,
+    pub native_calls: u64,
+    pub bailouts: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Jit {
+    enabled: bool,
+    /// Keyed by prototype address; the prototype is kept alive alongside so
+    /// the address cannot be reused
+    functions: HashMap<*const FunctionProto, (Rc<FunctionProto>, Rc<JitFunction>)>,
+    order: Vec<Rc<JitFunction>>,
+}
+
+impl Jit {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            ..Self::default()
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// The JIT entry for `proto`, compiling it the first time it is asked for
+    pub fn function(&mut self, proto: &Rc<FunctionProto>) -> Rc<JitFunction> {
+        if let Some((_, function)) = self.functions.get(&Rc::as_ptr(proto)) {
+            return Rc::clone(function);
+        }
+
+        let name = if proto.name.is_empty() {
+            "<lambda>".to_string()
+        } else {
+            proto.name.clone()
+        };
+        let function = Rc::new(JitFunction {
+            name,
+            code: compile(proto),
+            native_calls: Cell::new(0),
+            bailouts: Cell::new(0),
+        });
+        self.functions
+            .insert(Rc::as_ptr(proto), (Rc::clone(proto), Rc::clone(&function)));
+        self.order.push(Rc::clone(&function));
+        function
+    }
+
+    /// Every function that got hot, in the order they did
+    pub fn stats(&self) -> Vec<JitStats> {
+        self.order
+            .iter()
+            .map(|function| JitStats {
+                name: function.name.clone(),
+                rejected: match &function.code {
+                    Ok(_) if function.bailouts.get() >= MAX_BAILOUTS => {
+                        Some("bailed out too often".to_string())
+                    }
+                    Ok(_) => None,
+                    Err(reason) => Some(reason.clone()),
+                },
+                native_calls: function.native_calls.get(),
+                bailouts: function.bailouts.get(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,462 @@
+//! Native code generation for x86-64 (System V). Compiled functions keep
+//! every slot and operand stack entry in their machine stack frame, as
+//! untagged 64-bit integers:
+//!
+//! ```text
+//! [rbp - 8]                  saved rbx, which holds the `JitContext`
+//! [rbp - 8 - frame + 8 * k]  slot `k`, then operand stack entry `k - slots`
+//! ```
+
+use crate::bytecode::{Constant, FunctionProto, Op};
+use crate::ir::{BinaryOp, UnaryOp};
+use crate::runtime::jit::JitContext;
+
+type Entry = unsafe extern "sysv64" fn(*const i64, *mut JitContext) -> i64;
+
+/// Frames with more slots and operand stack entries than this stay on the VM
+const MAX_FRAME_ENTRIES: usize = 64;
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+
+/// Executable memory holding one compiled function
+pub struct Code {
+    memory: *mut libc::c_void,
+    len: usize,
+}
+
+impl Code {
+    fn new(bytes: &[u8]) -> Result<Self, String> {
+        let len = bytes.len().max(1);
+        // SAFETY: a fresh anonymous private mapping; the result is checked
+        // before use
+        let memory = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if memory == libc::MAP_FAILED {
+            return Err("could not allocate executable memory".to_string());
+        }
+
+        let code = Self { memory, len };
+        // SAFETY: the mapping is `len` writable bytes and `bytes` fits in it.
+        // It is made executable only once it is no longer writable.
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), memory.cast::<u8>(), bytes.len());
+            if libc::mprotect(memory, len, libc::PROT_READ | libc::PROT_EXEC) != 0 {
+                return Err("could not make code executable".to_string());
+            }
+        }
+        Ok(code)
+    }
+
+    pub fn call(&self, args: &[i64], context: &mut JitContext) -> i64 {
+        // SAFETY: the memory holds a function generated by `compile` with the
+        // `Entry` signature. It reads exactly `arity` arguments, which the VM
+        // checked before calling, and touches nothing outside its own frame
+        // and `context`.
+        unsafe {
+            let entry = std::mem::transmute::<*mut libc::c_void, Entry>(self.memory);
+            entry(args.as_ptr(), context)
+        }
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly the mapping created in `new`
+        unsafe {
+            libc::munmap(self.memory, self.len);
+        }
+    }
+}
+
+/// Compiles `proto`, returning the code and the global name it recurses
+/// through, or why it cannot be compiled
+pub fn compile(proto: &FunctionProto, max_depth: u64) -> Result<(Code, Option<u32>), String> {
+    let self_call = check(proto)?;
+    let depths = stack_depths(proto)?;
+    let max_stack = depths.iter().flatten().copied().max().unwrap_or(0) as usize;
+
+    let entries = proto.slots as usize + max_stack + 1;
+    if entries > MAX_FRAME_ENTRIES {
+        return Err("too many locals".to_string());
+    }
+    // Odd, so the frame plus the saved rbx keeps the stack 16-byte aligned
+    let entries = entries | 1;
+
+    let mut assembler = Assembler {
+        code: Vec::new(),
+        frame: entries as i32 * 8,
+        slots: proto.slots,
+        fixups: Vec::new(),
+    };
+    let code = assembler.function(proto, &depths, max_depth)?;
+    Ok((Code::new(&code)?, self_call))
+}
+
+/// Rejects functions using anything but integer locals, arithmetic and calls
+/// to themselves
+fn check(proto: &FunctionProto) -> Result<Option<u32>, String> {
+    let integer = |index: u32| match proto.constants.get(index as usize) {
+        Some(Constant::Integer(_)) => Ok(()),
+        Some(Constant::Float(_)) => Err("uses floats"),
+        Some(Constant::String(_)) => Err("uses strings"),
+        _ => Err("uses unsupported constants"),
+    };
+
+    let mut self_call = None;
+    for op in &proto.code {
+        let result = match *op {
+            Op::Constant(index) => integer(index),
+            Op::BinaryLocalConst { op, constant, .. } => {
+                integer(constant).and(if op == BinaryOp::Dot {
+                    Err("uses strings")
+                } else {
+                    Ok(())
+                })
+            }
+            Op::Binary(BinaryOp::Dot) | Op::Format(_) | Op::Concat(_) => Err("uses strings"),
+            Op::LoadCell(_) | Op::StoreCell(_) | Op::LoadUpvalue(_) | Op::Closure(_) => {
+                Err("uses closures")
+            }
+            Op::LoadGlobal(_) | Op::StoreGlobal(_) | Op::JumpIfNotNone(_) => Err("uses globals"),
+            Op::Import { .. } => Err("imports modules"),
+            Op::Call(_) => Err("calls other functions"),
+            Op::MakeList(_) | Op::Index => Err("uses lists"),
+            Op::MakeDict(_) => Err("uses dicts"),
+            Op::MakeKeywords(_) => Err("passes keyword arguments"),
+            Op::Member(_) => Err("uses modules"),
+            Op::CallGlobal { name, argc } => {
+                let recursive = !proto.name.is_empty()
+                    && argc == proto.arity
+                    && matches!(&proto.constants[name as usize], Constant::String(s) if **s == *proto.name);
+                if recursive {
+                    self_call = Some(name);
+                    Ok(())
+                } else {
+                    Err("calls other functions")
+                }
+            }
+            _ => Ok(()),
+        };
+        result.map_err(str::to_string)?;
+    }
+    Ok(self_call)
+}
+
+/// The operand stack depth before each instruction, or `None` for
+/// unreachable ones. Every path into an instruction must agree.
+fn stack_depths(proto: &FunctionProto) -> Result<Vec<Option<u32>>, String> {
+    let mut depths = vec![None; proto.code.len()];
+    let mut pending = vec![(0usize, 0u32)];
+    let malformed = || "has an unexpected stack shape".to_string();
+
+    while let Some((ip, depth)) = pending.pop() {
+        let Some(op) = proto.code.get(ip) else {
+            return Err(malformed());
+        };
+        match depths[ip] {
+            Some(seen) if seen == depth => continue,
+            Some(_) => return Err(malformed()),
+            None => depths[ip] = Some(depth),
+        }
+
+        let (pops, pushes) = match *op {
+            Op::Constant(_) | Op::PushNone | Op::LoadLocal(_) | Op::BinaryLocalConst { .. } => {
+                (0, 1)
+            }
+            Op::Pop | Op::StoreLocal(_) | Op::JumpIfFalse(_) | Op::JumpIfTrue(_) | Op::Return => {
+                (1, 0)
+            }
+            Op::Unary(_) | Op::Truthy | Op::ExpectInteger(_) => (1, 1),
+            Op::Binary(_) => (2, 1),
+            Op::CallGlobal { argc, .. } => (argc, 1),
+            Op::Jump(_) | Op::ForIter { .. } => (0, 0),
+            _ => return Err(malformed()),
+        };
+        let after = depth.checked_sub(pops).ok_or_else(malformed)? + pushes;
+
+        match *op {
+            Op::Jump(target) => pending.push((target as usize, after)),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                pending.push((target as usize, after));
+                pending.push((ip + 1, after));
+            }
+            Op::ForIter { exit, .. } => {
+                pending.push((exit as usize, after));
+                pending.push((ip + 1, after + 1));
+            }
+            Op::Return => {}
+            _ => pending.push((ip + 1, after)),
+        }
+    }
+    Ok(depths)
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Instruction(u32),
+    Exit,
+    Bailout,
+}
+
+struct Assembler {
+    code: Vec<u8>,
+    frame: i32,
+    slots: u32,
+    /// rel32 fields to patch, by their offset in `code`
+    fixups: Vec<(usize, Target)>,
+}
+
+impl Assembler {
+    fn function(
+        &mut self,
+        proto: &FunctionProto,
+        depths: &[Option<u32>],
+        max_depth: u64,
+    ) -> Result<Vec<u8>, String> {
+        // push rbp; mov rbp, rsp; push rbx; sub rsp, frame; mov rbx, rsi
+        self.emit(&[0x55, 0x48, 0x89, 0xE5, 0x53, 0x48, 0x81, 0xEC]);
+        self.emit(&self.frame.to_le_bytes());
+        self.emit(&[0x48, 0x89, 0xF3]);
+
+        // Count the nesting depth, bailing out past the limit
+        self.emit(&[0x48, 0x8B, 0x43, 0x08, 0x48, 0x3D]); // mov rax, [rbx + 8]; cmp rax, imm32
+        self.emit(&(max_depth as i32).to_le_bytes());
+        self.jump(&[0x0F, 0x8D], Target::Bailout); // jge
+        self.emit(&[0x48, 0xFF, 0xC0, 0x48, 0x89, 0x43, 0x08]); // inc rax; mov [rbx + 8], rax
+
+        // Copy the arguments from [rdi] into their slots
+        for i in 0..proto.arity {
+            self.emit(&[0x48, 0x8B, 0x87]); // mov rax, [rdi + disp32]
+            self.emit(&(i as i32 * 8).to_le_bytes());
+            self.store(i, RAX);
+        }
+
+        let mut offsets = Vec::with_capacity(proto.code.len());
+        for (op, depth) in proto.code.iter().zip(depths) {
+            offsets.push(self.code.len());
+            if let Some(depth) = *depth {
+                self.instruction(proto, *op, depth)?;
+            }
+        }
+
+        // Normal return, with the result in rax: decrement the depth
+        let exit = self.code.len();
+        self.emit(&[0x48, 0xFF, 0x4B, 0x08]); // dec qword [rbx + 8]
+        self.epilogue();
+
+        // Bail out: flag it and return; the VM runs the call instead
+        let bailout = self.code.len();
+        self.emit(&[0x48, 0xC7, 0x03, 0x01, 0x00, 0x00, 0x00]); // mov qword [rbx], 1
+        self.emit(&[0x31, 0xC0]); // xor eax, eax
+        self.epilogue();
+
+        for &(at, target) in &self.fixups {
+            let destination = match target {
+                Target::Instruction(ip) => offsets[ip as usize],
+                Target::Exit => exit,
+                Target::Bailout => bailout,
+            };
+            let rel = destination as i64 - (at as i64 + 4);
+            self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        Ok(std::mem::take(&mut self.code))
+    }
+
+    fn instruction(&mut self, proto: &FunctionProto, op: Op, depth: u32) -> Result<(), String> {
+        let top = self.slots + depth;
+        match op {
+            Op::Constant(index) => {
+                self.load_constant(proto, index, RAX);
+                self.store(top, RAX);
+            }
+            Op::PushNone => self.jump(&[0xE9], Target::Bailout),
+            Op::Pop | Op::ExpectInteger(_) => {}
+            Op::LoadLocal(slot) => {
+                self.load(RAX, slot);
+                self.store(top, RAX);
+            }
+            Op::StoreLocal(slot) => {
+                self.load(RAX, top - 1);
+                self.store(slot, RAX);
+            }
+            Op::Unary(op) => {
+                self.load(RAX, top - 1);
+                match op {
+                    UnaryOp::Neg => {
+                        self.emit(&[0x48, 0xF7, 0xD8]); // neg rax
+                        self.jump(&[0x0F, 0x80], Target::Bailout); // jo
+                    }
+                    UnaryOp::Not => self.truthiness(0x94),
+                }
+                self.store(top - 1, RAX);
+            }
+            Op::Binary(op) => {
+                self.load(RAX, top - 2);
+                self.load(RCX, top - 1);
+                self.binary(op)?;
+                self.store(top - 2, RAX);
+            }
+            Op::BinaryLocalConst { op, slot, constant } => {
+                self.load(RAX, slot);
+                self.load_constant(proto, constant, RCX);
+                self.binary(op)?;
+                self.store(top, RAX);
+            }
+            Op::Truthy => {
+                self.load(RAX, top - 1);
+                self.truthiness(0x95);
+                self.store(top - 1, RAX);
+            }
+            Op::Jump(target) => self.jump(&[0xE9], Target::Instruction(target)),
+            Op::JumpIfFalse(target) | Op::JumpIfTrue(target) => {
+                self.load(RAX, top - 1);
+                self.emit(&[0x48, 0x85, 0xC0]); // test rax, rax
+                let jcc = if matches!(op, Op::JumpIfFalse(_)) {
+                    0x84
+                } else {
+                    0x85
+                };
+                self.jump(&[0x0F, jcc], Target::Instruction(target));
+            }
+            Op::ForIter { counter, exit } => {
+                self.load(RAX, counter);
+                self.load(RCX, counter + 1);
+                self.emit(&[0x48, 0x39, 0xC8]); // cmp rax, rcx
+                self.jump(&[0x0F, 0x8D], Target::Instruction(exit)); // jge
+                self.store(top, RAX);
+                self.emit(&[0x48, 0xFF, 0xC0]); // inc rax
+                self.store(counter, RAX);
+            }
+            Op::CallGlobal { argc, .. } => {
+                let args = top - argc;
+                self.emit(&[0x48, 0x8D, 0xBD]); // lea rdi, [rbp + disp32]
+                self.emit(&self.displacement(args).to_le_bytes());
+                self.emit(&[0x48, 0x89, 0xDE]); // mov rsi, rbx
+                self.emit(&[0xE8]); // call rel32, back to the entry point
+                let rel = -(self.code.len() as i32 + 4);
+                self.emit(&rel.to_le_bytes());
+                self.emit(&[0x48, 0x83, 0x3B, 0x00]); // cmp qword [rbx], 0
+                self.jump(&[0x0F, 0x85], Target::Bailout); // jne
+                self.store(args, RAX);
+            }
+            Op::Return => {
+                self.load(RAX, top - 1);
+                self.jump(&[0xE9], Target::Exit);
+            }
+            _ => return Err("uses unsupported instructions".to_string()),
+        }
+        Ok(())
+    }
+
+    /// `rax = rax <op> rcx`, bailing out where the VM would fail or overflow
+    fn binary(&mut self, op: BinaryOp) -> Result<(), String> {
+        let setcc = match op {
+            BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => {
+                self.emit(match op {
+                    BinaryOp::Add => &[0x48, 0x01, 0xC8], // add rax, rcx
+                    BinaryOp::Sub => &[0x48, 0x29, 0xC8], // sub rax, rcx
+                    _ => &[0x48, 0x0F, 0xAF, 0xC1],       // imul rax, rcx
+                });
+                self.jump(&[0x0F, 0x80], Target::Bailout); // jo
+                return Ok(());
+            }
+            BinaryOp::Div => return Err("divides to a float".to_string()),
+            BinaryOp::FloorDiv | BinaryOp::Mod => {
+                self.emit(&[0x48, 0x85, 0xC9]); // test rcx, rcx
+                self.jump(&[0x0F, 0x84], Target::Bailout); // jz
+                // i64::MIN / -1 overflows
+                self.emit(&[0x48, 0x83, 0xF9, 0xFF, 0x75, 0x13]); // cmp rcx, -1; jne +19
+                self.emit(&[0x48, 0xBA]); // mov rdx, imm64
+                self.emit(&i64::MIN.to_le_bytes());
+                self.emit(&[0x48, 0x39, 0xD0]); // cmp rax, rdx
+                self.jump(&[0x0F, 0x84], Target::Bailout); // je
+                self.emit(&[0x48, 0x99, 0x48, 0xF7, 0xF9]); // cqo; idiv rcx
+                // idiv truncates; a remainder whose sign differs from the
+                // divisor's moves the result one step towards -infinity
+                if op == BinaryOp::FloorDiv {
+                    self.emit(&[0x48, 0x85, 0xD2, 0x74, 0x08]); // test rdx, rdx; jz +8
+                    self.emit(&[0x48, 0x31, 0xCA, 0x79, 0x03]); // xor rdx, rcx; jns +3
+                    self.emit(&[0x48, 0xFF, 0xC8]); // dec rax
+                } else {
+                    self.emit(&[0x48, 0x89, 0xD0]); // mov rax, rdx
+                    self.emit(&[0x48, 0x85, 0xC0, 0x74, 0x08]); // test rax, rax; jz +8
+                    self.emit(&[0x48, 0x31, 0xCA, 0x79, 0x03]); // xor rdx, rcx; jns +3
+                    self.emit(&[0x48, 0x01, 0xC8]); // add rax, rcx
+                }
+                return Ok(());
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                // Both operands reduced to their truthiness, then combined
+                self.emit(&[0x48, 0x85, 0xC0, 0x0F, 0x95, 0xC0]); // test rax, rax; setne al
+                self.emit(&[0x48, 0x85, 0xC9, 0x0F, 0x95, 0xC1]); // test rcx, rcx; setne cl
+                let combine = if op == BinaryOp::And { 0x20 } else { 0x08 };
+                self.emit(&[combine, 0xC8, 0x0F, 0xB6, 0xC0]); // and/or al, cl; movzx eax, al
+                return Ok(());
+            }
+            BinaryOp::Eq => 0x94,
+            BinaryOp::Ne => 0x95,
+            BinaryOp::Lt => 0x9C,
+            BinaryOp::Gt => 0x9F,
+            BinaryOp::Le => 0x9E,
+            BinaryOp::Ge => 0x9D,
+            BinaryOp::Dot => return Err("uses strings".to_string()),
+        };
+        self.emit(&[0x48, 0x39, 0xC8]); // cmp rax, rcx
+        self.emit(&[0x0F, setcc, 0xC0, 0x0F, 0xB6, 0xC0]); // setcc al; movzx eax, al
+        Ok(())
+    }
+
+    /// `rax = (rax != 0) <setcc> 0` as 1 or 0
+    fn truthiness(&mut self, setcc: u8) {
+        self.emit(&[0x48, 0x85, 0xC0, 0x0F, setcc, 0xC0, 0x0F, 0xB6, 0xC0]);
+    }
+
+    fn load_constant(&mut self, proto: &FunctionProto, index: u32, register: u8) {
+        let value = match proto.constants[index as usize] {
+            Constant::Integer(n) => n,
+            _ => 0,
+        };
+        self.emit(&[0x48, 0xB8 + register]); // mov reg, imm64
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn displacement(&self, entry: u32) -> i32 {
+        -(8 + self.frame) + entry as i32 * 8
+    }
+
+    /// `mov reg, [rbp + entry]`
+    fn load(&mut self, register: u8, entry: u32) {
+        self.emit(&[0x48, 0x8B, 0x85 | (register << 3)]);
+        self.emit(&self.displacement(entry).to_le_bytes());
+    }
+
+    /// `mov [rbp + entry], reg`
+    fn store(&mut self, entry: u32, register: u8) {
+        self.emit(&[0x48, 0x89, 0x85 | (register << 3)]);
+        self.emit(&self.displacement(entry).to_le_bytes());
+    }
+
+    /// Emits a jump opcode followed by a rel32 to patch
+    fn jump(&mut self, opcode: &[u8], target: Target) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), target));
+        self.emit(&[0; 4]);
+    }
+
+    fn epilogue(&mut self) {
+        self.emit(&[0x48, 0x8B, 0x5D, 0xF8, 0xC9, 0xC3]); // mov rbx, [rbp - 8]; leave; ret
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+}
@@ -0,0 +1,547 @@
+//! The runtime owns everything a running program needs: values, environments,
+//! builtins and loaded modules. It executes checked IR and never touches the
+//! lexer or parser.
+
+mod builtins;
+mod env;
+mod functional;
+mod interpreter;
+mod jit;
+mod module;
+mod ops;
+mod stdlib;
+mod value;
+mod vm;
+
+pub use env::Env;
+pub use interpreter::{Flow, Interpreter};
+pub use jit::JitStats;
+pub use module::{ModuleExports, ModuleLoader, ModuleRegistry};
+pub use value::{
+    format_value, Closure, CompiledClosure, IteratorValue, ModuleValue, NativeFn, NativeFunction,
+    Producer, Value,
+};
+pub use vm::Vm;
+
+use crate::bytecode::{self, CompiledModule, Export, SourceStamp};
+use crate::ir;
+use crate::utils::CrabbyError;
+use indexmap::IndexMap;
+use jit::Jit;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Calls that recurse through Rust, into the interpreter or a fresh VM,
+/// nested deeper than this fail instead of overflowing the native stack
+const MAX_DEPTH: usize = 10_000;
+
+/// Which executor runs programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    /// Compile to bytecode and run it on the stack VM
+    #[default]
+    Vm,
+    /// Walk the IR directly, kept for differential testing against the VM
+    Interpreter,
+}
+
+pub struct Runtime {
+    engine: Engine,
+    jit: Jit,
+    globals: Env,
+    builtins: HashMap<String, Value>,
+    /// Modules implemented in Rust, which `import name` binds without a file
+    native_modules: HashMap<String, Value>,
+    modules: ModuleRegistry,
+    loader: Option<ModuleLoader>,
+    current_file: Option<PathBuf>,
+    args: Vec<String>,
+    /// How many `call`s into the interpreter or a fresh VM are running
+    depth: usize,
+    /// The keyword arguments of the builtin being called; see `take_keywords`
+    keywords: IndexMap<String, Value>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        let mut runtime = Self {
+            engine: Engine::default(),
+            jit: Jit::new(cfg!(all(target_arch = "x86_64", unix))),
+            globals: Env::new(),
+            builtins: HashMap::new(),
+            native_modules: HashMap::new(),
+            modules: ModuleRegistry::default(),
+            loader: None,
+            current_file: None,
+            args: Vec::new(),
+            depth: 0,
+            keywords: IndexMap::new(),
+        };
+        builtins::register(&mut runtime);
+        functional::register(&mut runtime);
+        stdlib::register(&mut runtime);
+        runtime
+    }
+
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    /// Turns compiling hot functions to native code on or off. It is on by
+    /// default where the JIT has a backend.
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.jit.set_enabled(enabled);
+    }
+
+    /// What the JIT made of each function that got hot
+    pub fn jit_stats(&self) -> Vec<JitStats> {
+        self.jit.stats()
+    }
+
+    pub fn set_module_loader(&mut self, loader: ModuleLoader) {
+        self.loader = Some(loader);
+    }
+
+    /// The file being run, which relative imports are resolved against
+    pub fn set_current_file(&mut self, path: PathBuf) {
+        self.current_file = Some(path);
+    }
+
+    /// The command-line arguments passed to the script
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    /// The keyword arguments passed to the builtin now running, which only
+    /// ever reach it this way and never as one of its arguments
+    pub fn take_keywords(&mut self) -> IndexMap<String, Value> {
+        std::mem::take(&mut self.keywords)
+    }
+
+    pub fn register_native(&mut self, name: &str, arity: Option<usize>, function: NativeFn) {
+        self.register_native_with_keywords(name, arity, &[], function);
+    }
+
+    /// Registers a builtin that also takes the named keyword arguments, which
+    /// it gets from `take_keywords`
+    pub fn register_native_with_keywords(
+        &mut self,
+        name: &str,
+        arity: Option<usize>,
+        keywords: &'static [&'static str],
+        function: NativeFn,
+    ) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            keywords,
+            function,
+        };
+        self.builtins.insert(name.to_string(), Value::Native(Rc::new(native)));
+        env::invalidate();
+    }
+
+    /// Registers a module of native functions that `import name` binds. A
+    /// leading `_` marks the native half of a library module, like `_math`
+    /// for `math`, and its functions are named after that module.
+    pub fn register_module(&mut self, name: &str, functions: &[(&str, Option<usize>, NativeFn)]) {
+        let members = functions
+            .iter()
+            .map(|&(member, arity, function)| {
+                let native = NativeFunction {
+                    name: format!("{}.{}", name.trim_start_matches('_'), member),
+                    arity,
+                    keywords: &[],
+                    function,
+                };
+                (member.to_string(), Value::Native(Rc::new(native)))
+            })
+            .collect();
+        let module = ModuleValue {
+            name: name.to_string(),
+            members,
+        };
+        self.native_modules.insert(name.to_string(), Value::Module(Rc::new(module)));
+    }
+
+    pub fn builtin_names(&self) -> Vec<String> {
+        self.builtins.keys().cloned().collect()
+    }
+
+    /// Every name a new program may refer to: builtins plus anything an
+    /// earlier `run` defined at the top level
+    pub fn global_names(&self) -> Vec<String> {
+        let mut names = self.builtin_names();
+        names.extend(self.globals.names());
+        names
+    }
+
+    /// A value the program defined at the top level
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.globals.get(name)
+    }
+
+    /// Runs a module in the global scope and returns the value of its final
+    /// statement when that is an expression
+    pub fn run(&mut self, module: &ir::Module) -> Result<Option<Value>, CrabbyError> {
+        let globals = self.globals.clone();
+        self.execute(module, &globals)
+    }
+
+    /// Runs an already compiled module in the global scope. Bytecode always
+    /// runs on the VM, whichever engine is selected.
+    pub fn run_compiled(&mut self, module: &CompiledModule) -> Result<Option<Value>, CrabbyError> {
+        let globals = self.globals.clone();
+        self.execute_compiled(module, &globals)
+    }
+
+    fn execute(&mut self, module: &ir::Module, env: &Env) -> Result<Option<Value>, CrabbyError> {
+        match self.engine {
+            Engine::Vm => self.execute_compiled(&bytecode::compile_module(module), env),
+            Engine::Interpreter => match Interpreter::new(self).exec_block(&module.body, env)? {
+                Flow::Normal(value) => Ok(value),
+                Flow::Return(value) => Ok(Some(value)),
+            },
+        }
+    }
+
+    fn execute_compiled(
+        &mut self,
+        module: &CompiledModule,
+        env: &Env,
+    ) -> Result<Option<Value>, CrabbyError> {
+        match Vm::new(self).run(Rc::clone(&module.proto), env.clone())? {
+            Value::None => Ok(None),
+            value => Ok(Some(value)),
+        }
+    }
+
+    pub fn lookup(&self, env: &Env, name: &str) -> Result<Value, CrabbyError> {
+        env.get(name)
+            .or_else(|| self.builtins.get(name).cloned())
+            .ok_or_else(|| CrabbyError::RuntimeError(format!("Undefined variable: {}", name)))
+    }
+
+    pub fn call(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, CrabbyError> {
+        match callee {
+            Value::Native(native) => {
+                let mut args = args;
+                let keywords = match args.last() {
+                    Some(Value::Keywords(_)) => args.pop(),
+                    _ => None,
+                };
+                if let Some(arity) = native.arity {
+                    check_arity(&native.name, arity, args.len())?;
+                }
+                let keywords = match keywords {
+                    Some(Value::Keywords(keywords)) => Rc::unwrap_or_clone(keywords),
+                    _ => IndexMap::new(),
+                };
+                if let Some(name) = keywords.keys().find(|name| !native.keywords.contains(&name.as_str())) {
+                    return Err(CrabbyError::RuntimeError(format!(
+                        "{}() got an unexpected keyword argument '{}'",
+                        native.name, name
+                    )));
+                }
+                // A builtin that calls back into Crabby must not see its
+                // callee's keywords, nor leave its own for the next builtin
+                let outer = std::mem::replace(&mut self.keywords, keywords);
+                let result = (native.function)(self, args);
+                self.keywords = outer;
+                result
+            }
+            Value::Function(closure) => self.nested(|runtime| runtime.call_function(closure, args)),
+            Value::Compiled(closure) => {
+                self.nested(|runtime| Vm::new(runtime).call(Rc::clone(closure), args))
+            }
+            other => Err(CrabbyError::RuntimeError(format!(
+                "Value of type {} is not callable",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn call_function(&mut self, closure: &Closure, args: Vec<Value>) -> Result<Value, CrabbyError> {
+        let decl = &closure.decl;
+        reject_keywords(&decl.name, &args)?;
+        check_arity(&decl.name, decl.params.len(), args.len())?;
+
+        let env = closure.env.child();
+        for (param, arg) in decl.params.iter().zip(args) {
+            env.define(param, arg);
+        }
+
+        match Interpreter::new(self).exec_block(&decl.body, &env)? {
+            Flow::Return(value) | Flow::Normal(Some(value)) => Ok(value),
+            Flow::Normal(None) => Ok(Value::None),
+        }
+    }
+
+    /// Runs `call` one level deeper, failing once calls nest past `MAX_DEPTH`
+    fn nested(
+        &mut self,
+        call: impl FnOnce(&mut Self) -> Result<Value, CrabbyError>,
+    ) -> Result<Value, CrabbyError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(CrabbyError::RuntimeError(
+                "Maximum recursion depth exceeded".to_string(),
+            ));
+        }
+        self.depth += 1;
+        let result = call(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Handles `import name` and `import name from source`, returning the
+    /// value to bind to `name`. A source with an extension or a `/` is a file
+    /// relative to the importing one; a bare name like `math` is a library.
+    pub fn import(&mut self, name: &str, source: Option<&str>) -> Result<Value, CrabbyError> {
+        match source {
+            None => self.library(name),
+            Some(source) if is_library_name(source) => {
+                let module = self.library(source)?;
+                ops::member(module, name)
+            }
+            Some(source) => {
+                let path = self.resolve_path(source);
+                let exports = self.load_module(&path)?;
+                exports.get(name)
+            }
+        }
+    }
+
+    /// Finds a library module: `name.crab` in a `CRABBY_PATH` directory, then
+    /// the standard library bundled into the binary, then a native module
+    fn library(&mut self, name: &str) -> Result<Value, CrabbyError> {
+        let exports = if let Some(path) = search_path(name) {
+            self.load_module(&path)?
+        } else if let Some(source) = stdlib::source(name) {
+            let path = PathBuf::from(format!("<stdlib>/{}.crab", name));
+            self.load(&path, Some(source))?
+        } else if let Some(module) = self.native_modules.get(name) {
+            return Ok(module.clone());
+        } else {
+            return Err(CrabbyError::RuntimeError(format!(
+                "No module named '{}' in the standard library or CRABBY_PATH",
+                name
+            )));
+        };
+
+        let module = ModuleValue {
+            name: name.to_string(),
+            members: exports.public.clone(),
+        };
+        Ok(Value::Module(Rc::new(module)))
+    }
+
+    fn resolve_path(&self, import_path: &str) -> PathBuf {
+        match self.current_file.as_deref().and_then(Path::parent) {
+            Some(current_dir) => match import_path.strip_prefix("./") {
+                Some(relative) => current_dir.join(relative),
+                None => current_dir.join(import_path),
+            },
+            // Fallback to current directory if there is no current file
+            None => PathBuf::from(import_path),
+        }
+    }
+
+    fn load_module(&mut self, path: &Path) -> Result<Rc<ModuleExports>, CrabbyError> {
+        let path = path.canonicalize().map_err(|e| {
+            CrabbyError::RuntimeError(format!("Failed to read module '{}': {}", path.display(), e))
+        })?;
+
+        self.load(&path, None)
+    }
+
+    /// Runs a module once, from its file or from `embedded` source
+    fn load(
+        &mut self,
+        path: &Path,
+        embedded: Option<&'static str>,
+    ) -> Result<Rc<ModuleExports>, CrabbyError> {
+        if let Some(exports) = self.modules.get(path) {
+            return Ok(exports);
+        }
+
+        self.modules.begin(path)?;
+        let result = self.execute_module(path, embedded);
+        let exports = result.as_ref().ok().cloned();
+        self.modules.finish(path, exports);
+        result
+    }
+
+    fn execute_module(
+        &mut self,
+        path: &Path,
+        embedded: Option<&'static str>,
+    ) -> Result<Rc<ModuleExports>, CrabbyError> {
+        let bytecode_file = path.extension().is_some_and(|ext| ext == "crabc");
+        if bytecode_file && self.engine == Engine::Interpreter {
+            return Err(CrabbyError::RuntimeError(format!(
+                "Cannot import bytecode module '{}' on the interpreter",
+                path.display()
+            )));
+        }
+
+        // Modules run in their own global scope, relative to their own file
+        let env = Env::new();
+        let previous_file = self.current_file.replace(path.to_path_buf());
+        let result = match self.engine {
+            Engine::Vm => self.compiled_module(path, bytecode_file, embedded).and_then(|module| {
+                self.execute_compiled(&module, &env)?;
+                Ok(module.exports)
+            }),
+            Engine::Interpreter => self.loader_module(path, embedded).and_then(|module| {
+                self.execute(&module, &env)?;
+                Ok(bytecode::module_exports(&module))
+            }),
+        };
+        self.current_file = previous_file;
+
+        let mut exports = ModuleExports::default();
+        for Export { name, public } in result? {
+            match env.get(&name) {
+                Some(value) if public => {
+                    exports.public.insert(name, value);
+                }
+                _ => {
+                    exports.private.insert(name);
+                }
+            }
+        }
+
+        Ok(Rc::new(exports))
+    }
+
+    fn loader_module(
+        &self,
+        path: &Path,
+        embedded: Option<&str>,
+    ) -> Result<ir::Module, CrabbyError> {
+        let loader = self.loader.as_ref().ok_or_else(|| {
+            CrabbyError::RuntimeError("No module loader is configured for imports".to_string())
+        })?;
+        match embedded {
+            Some(source) => loader(source, &self.builtin_names()),
+            None => {
+                let source = fs::read_to_string(path).map_err(|e| read_error(path, e))?;
+                loader(&source, &self.builtin_names())
+            }
+        }
+    }
+
+    /// Compiles the module at `path`, reusing its `.crabc` file in the cache
+    /// directory when that was built from the same source, and refreshing it
+    /// otherwise. Embedded modules, and every module when the cache is turned
+    /// off, are compiled every time.
+    fn compiled_module(
+        &self,
+        path: &Path,
+        bytecode_file: bool,
+        embedded: Option<&str>,
+    ) -> Result<CompiledModule, CrabbyError> {
+        if embedded.is_some() {
+            return Ok(bytecode::compile_module(&self.loader_module(path, embedded)?));
+        }
+        let read_error = |e: std::io::Error| read_error(path, e);
+
+        if bytecode_file {
+            let bytes = fs::read(path).map_err(read_error)?;
+            return bytecode::decode(&bytes).map(|(_, module)| module);
+        }
+
+        let Some(cache_path) = bytecode::cache_path(path) else {
+            return Ok(bytecode::compile_module(&self.loader_module(path, None)?));
+        };
+        let cached = fs::read(&cache_path).ok();
+        let cached_stamp = cached.as_deref().and_then(|bytes| bytecode::read_stamp(bytes).ok());
+
+        let mtime = bytecode::modified(path);
+        let mut stamp = None;
+        if let (Some(bytes), Some(cached_stamp)) = (&cached, cached_stamp) {
+            let fresh = if mtime != 0 && cached_stamp.mtime == mtime {
+                true
+            } else {
+                let source = fs::read(path).map_err(read_error)?;
+                let current = SourceStamp::new(path, &source);
+                stamp = Some(current);
+                current.hash == cached_stamp.hash
+            };
+            if fresh && let Ok((_, module)) = bytecode::decode(bytes) {
+                // Same source under a new mtime: restamp so the next run skips hashing
+                if let Some(stamp) = stamp {
+                    let _ = fs::write(&cache_path, bytecode::encode(&module, stamp));
+                }
+                return Ok(module);
+            }
+        }
+
+        let stamp = match stamp {
+            Some(stamp) => stamp,
+            None => SourceStamp::new(path, &fs::read(path).map_err(read_error)?),
+        };
+        let module = bytecode::compile_module(&self.loader_module(path, None)?);
+
+        // The cache only speeds up later runs, so failing to write it is fine
+        if let Some(dir) = cache_path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = fs::write(&cache_path, bytecode::encode(&module, stamp));
+        Ok(module)
+    }
+}
+
+fn read_error(path: &Path, error: std::io::Error) -> CrabbyError {
+    CrabbyError::RuntimeError(format!("Failed to read module '{}': {}", path.display(), error))
+}
+
+/// Whether an import source names a library module rather than a file
+fn is_library_name(source: &str) -> bool {
+    Path::new(source).extension().is_none() && !source.contains(['/', '\\'])
+}
+
+/// The first `name.crab` in the directories listed in `CRABBY_PATH`
+fn search_path(name: &str) -> Option<PathBuf> {
+    let dirs = std::env::var_os("CRABBY_PATH")?;
+    std::env::split_paths(&dirs)
+        .map(|dir| dir.join(format!("{}.crab", name)))
+        .find(|path| path.is_file())
+}
+
+/// Only builtins take keyword arguments so far
+pub(crate) fn reject_keywords(name: &str, args: &[Value]) -> Result<(), CrabbyError> {
+    if !matches!(args.last(), Some(Value::Keywords(_))) {
+        return Ok(());
+    }
+
+    let name = if name.is_empty() { "Lambda" } else { name };
+    Err(CrabbyError::RuntimeError(format!(
+        "{} does not take keyword arguments",
+        name
+    )))
+}
+
+pub(crate) fn check_arity(name: &str, expected: usize, got: usize) -> Result<(), CrabbyError> {
+    if expected == got {
+        return Ok(());
+    }
+
+    let name = if name.is_empty() { "Lambda" } else { name };
+    Err(CrabbyError::RuntimeError(format!(
+        "{} expects {} arguments, got {}",
+        name, expected, got
+    )))
+}
@@ -0,0 +1,66 @@
+use crate::ir;
+use crate::runtime::value::Value;
+use crate::utils::CrabbyError;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Turns a module's source into checked IR. The runtime has no parser of its
+/// own; the front-end supplies one, together with the names of the builtins.
+pub type ModuleLoader = Box<dyn Fn(&str, &[String]) -> Result<ir::Module, CrabbyError>>;
+
+/// What a module makes available to importers
+#[derive(Default)]
+pub struct ModuleExports {
+    pub public: HashMap<String, Value>,
+    pub private: HashSet<String>,
+}
+
+impl ModuleExports {
+    pub fn get(&self, name: &str) -> Result<Value, CrabbyError> {
+        if let Some(value) = self.public.get(name) {
+            Ok(value.clone())
+        } else if self.private.contains(name) {
+            Err(CrabbyError::RuntimeError(format!(
+                "Cannot import private item '{}' from module",
+                name
+            )))
+        } else {
+            Err(CrabbyError::RuntimeError(format!(
+                "Item '{}' not found in module",
+                name
+            )))
+        }
+    }
+}
+
+/// Modules that have been loaded, so each file runs at most once per runtime
+#[derive(Default)]
+pub struct ModuleRegistry {
+    loaded: HashMap<PathBuf, Rc<ModuleExports>>,
+    loading: Vec<PathBuf>,
+}
+
+impl ModuleRegistry {
+    pub fn get(&self, path: &Path) -> Option<Rc<ModuleExports>> {
+        self.loaded.get(path).cloned()
+    }
+
+    pub fn begin(&mut self, path: &Path) -> Result<(), CrabbyError> {
+        if self.loading.iter().any(|p| p == path) {
+            return Err(CrabbyError::RuntimeError(format!(
+                "Circular import of '{}'",
+                path.display()
+            )));
+        }
+        self.loading.push(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn finish(&mut self, path: &Path, exports: Option<Rc<ModuleExports>>) {
+        self.loading.retain(|p| p != path);
+        if let Some(exports) = exports {
+            self.loaded.insert(path.to_path_buf(), exports);
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::ir::{BinaryOp, UnaryOp};
+use crate::runtime::value::Value;
+use crate::utils::CrabbyError;
+use chrono::{DateTime, Datelike, FixedOffset, TimeDelta, Timelike};
+use indexmap::IndexMap;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+fn error(message: &str) -> CrabbyError {
+    CrabbyError::RuntimeError(message.to_string())
+}
+
+fn overflow() -> CrabbyError {
+    error("Integer overflow")
+}
+
+pub fn unary(op: UnaryOp, value: Value) -> Result<Value, CrabbyError> {
+    match (op, value) {
+        (UnaryOp::Neg, Value::Integer(n)) => n.checked_neg().map(Value::Integer).ok_or_else(overflow),
+        (UnaryOp::Neg, Value::Float(f)) => Ok(Value::Float(-f)),
+        (UnaryOp::Neg, Value::Duration(d)) => Ok(Value::Duration(-d)),
+        (UnaryOp::Neg, _) => Err(error("Cannot negate a non-numeric value")),
+        (UnaryOp::Not, value) => Ok(Value::Integer(if value.is_truthy() { 0 } else { 1 })),
+    }
+}
+
+fn boolean(b: bool) -> Value {
+    Value::Integer(b as i64)
+}
+
+pub fn binary(op: BinaryOp, left: Value, right: Value) -> Result<Value, CrabbyError> {
+    match (left, op, right) {
+        // Executors short-circuit these; evaluated eagerly they only combine truthiness
+        (l, BinaryOp::And, r) => Ok(boolean(l.is_truthy() && r.is_truthy())),
+        (l, BinaryOp::Or, r) => Ok(boolean(l.is_truthy() || r.is_truthy())),
+
+        // Integer operations
+        (Value::Integer(l), BinaryOp::Add, Value::Integer(r)) => l.checked_add(r).map(Value::Integer).ok_or_else(overflow),
+        (Value::Integer(l), BinaryOp::Sub, Value::Integer(r)) => l.checked_sub(r).map(Value::Integer).ok_or_else(overflow),
+        (Value::Integer(l), BinaryOp::Mul, Value::Integer(r)) => l.checked_mul(r).map(Value::Integer).ok_or_else(overflow),
+        (Value::Integer(l), BinaryOp::FloorDiv, Value::Integer(r)) => {
+            if r == 0 {
+                return Err(error("Division by zero"));
+            }
+            floor_div(l, r).map(Value::Integer).ok_or_else(overflow)
+        }
+        (Value::Integer(l), BinaryOp::Mod, Value::Integer(r)) => {
+            if r == 0 {
+                return Err(error("Modulo by zero"));
+            }
+            Ok(Value::Integer(floor_mod(l, r)))
+        }
+        (Value::Integer(l), BinaryOp::Eq, Value::Integer(r)) => Ok(boolean(l == r)),
+        (Value::Integer(l), BinaryOp::Ne, Value::Integer(r)) => Ok(boolean(l != r)),
+        (Value::Integer(l), BinaryOp::Lt, Value::Integer(r)) => Ok(boolean(l < r)),
+        (Value::Integer(l), BinaryOp::Gt, Value::Integer(r)) => Ok(boolean(l > r)),
+        (Value::Integer(l), BinaryOp::Le, Value::Integer(r)) => Ok(boolean(l <= r)),
+        (Value::Integer(l), BinaryOp::Ge, Value::Integer(r)) => Ok(boolean(l >= r)),
+
+        // Float operations, with integers widened when mixed in or divided
+        (Value::Integer(l), BinaryOp::Div, Value::Integer(r)) => float_binary(BinaryOp::Div, l as f64, r as f64),
+        (Value::Integer(l), op, Value::Float(r)) => float_binary(op, l as f64, r),
+        (Value::Float(l), op, Value::Integer(r)) => float_binary(op, l, r as f64),
+        (Value::Float(l), op, Value::Float(r)) => float_binary(op, l, r),
+
+        // List operations
+        (Value::List(l), BinaryOp::Add, Value::List(r)) => {
+            Ok(Value::List(Rc::new(l.iter().chain(r.iter()).cloned().collect())))
+        }
+        (Value::List(l), BinaryOp::Eq, Value::List(r)) => Ok(boolean(lists_equal(&l, &r))),
+        (Value::List(l), BinaryOp::Ne, Value::List(r)) => Ok(boolean(!lists_equal(&l, &r))),
+
+        // Dict operations
+        (Value::Dict(l), BinaryOp::Eq, Value::Dict(r)) => Ok(boolean(dicts_equal(&l, &r))),
+        (Value::Dict(l), BinaryOp::Ne, Value::Dict(r)) => Ok(boolean(!dicts_equal(&l, &r))),
+
+        // Regexes are equal when their patterns are
+        (Value::Regex(l), BinaryOp::Eq, Value::Regex(r)) => Ok(boolean(l.as_str() == r.as_str())),
+        (Value::Regex(l), BinaryOp::Ne, Value::Regex(r)) => Ok(boolean(l.as_str() != r.as_str())),
+
+        // Bytes operations
+        (Value::Bytes(l), BinaryOp::Add, Value::Bytes(r)) => Ok(Value::Bytes(Rc::new([&l[..], &r[..]].concat()))),
+        (Value::Bytes(l), BinaryOp::Eq, Value::Bytes(r)) => Ok(boolean(l == r)),
+        (Value::Bytes(l), BinaryOp::Ne, Value::Bytes(r)) => Ok(boolean(l != r)),
+
+        // Time operations
+        (Value::DateTime(l), BinaryOp::Add, Value::Duration(r)) | (Value::Duration(r), BinaryOp::Add, Value::DateTime(l)) => {
+            l.checked_add_signed(r).map(Value::DateTime).ok_or_else(date_out_of_range)
+        }
+        (Value::DateTime(l), BinaryOp::Sub, Value::Duration(r)) => {
+            l.checked_sub_signed(r).map(Value::DateTime).ok_or_else(date_out_of_range)
+        }
+        (Value::DateTime(l), BinaryOp::Sub, Value::DateTime(r)) => Ok(Value::Duration(l.signed_duration_since(r))),
+        (Value::Instant(l), BinaryOp::Sub, Value::Instant(r)) => Ok(Value::Duration(instant_difference(l, r))),
+        (Value::Duration(l), BinaryOp::Add, Value::Duration(r)) => l.checked_add(&r).map(Value::Duration).ok_or_else(duration_overflow),
+        (Value::Duration(l), BinaryOp::Sub, Value::Duration(r)) => l.checked_sub(&r).map(Value::Duration).ok_or_else(duration_overflow),
+        (Value::Duration(d), BinaryOp::Mul, Value::Integer(n)) | (Value::Integer(n), BinaryOp::Mul, Value::Duration(d)) => {
+            scale_duration(d, n as f64)
+        }
+        (Value::Duration(d), BinaryOp::Mul, Value::Float(x)) | (Value::Float(x), BinaryOp::Mul, Value::Duration(d)) => scale_duration(d, x),
+        (Value::Duration(d), BinaryOp::Div, r @ (Value::Integer(_) | Value::Float(_))) => {
+            let divisor = match r {
+                Value::Integer(n) => n as f64,
+                Value::Float(x) => x,
+                _ => unreachable!(),
+            };
+            if divisor == 0.0 {
+                return Err(error("Division by zero"));
+            }
+            scale_duration(d, 1.0 / divisor)
+        }
+        (Value::Duration(l), BinaryOp::Div, Value::Duration(r)) => {
+            if r.is_zero() {
+                return Err(error("Division by zero"));
+            }
+            Ok(Value::Float(l.as_seconds_f64() / r.as_seconds_f64()))
+        }
+        (Value::DateTime(l), op, Value::DateTime(r)) if is_comparison(op) => Ok(compare(op, l.cmp(&r))),
+        (Value::Duration(l), op, Value::Duration(r)) if is_comparison(op) => Ok(compare(op, l.cmp(&r))),
+        (Value::Instant(l), op, Value::Instant(r)) if is_comparison(op) => Ok(compare(op, l.cmp(&r))),
+
+        // String operations
+        (Value::String(l), BinaryOp::Add, Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
+        (Value::String(l), BinaryOp::Dot, Value::String(r)) => Ok(Value::String(format!("{}.{}", l, r))),
+        (Value::String(l), BinaryOp::Eq, Value::String(r)) => Ok(boolean(l == r)),
+        (Value::String(l), BinaryOp::Ne, Value::String(r)) => Ok(boolean(l != r)),
+        (Value::String(l), BinaryOp::Lt, Value::String(r)) => Ok(boolean(l < r)),
+        (Value::String(l), BinaryOp::Gt, Value::String(r)) => Ok(boolean(l > r)),
+        (Value::String(l), BinaryOp::Le, Value::String(r)) => Ok(boolean(l <= r)),
+        (Value::String(l), BinaryOp::Ge, Value::String(r)) => Ok(boolean(l >= r)),
+        (Value::String(l), BinaryOp::Add, r) => Ok(Value::String(format!("{}{}", l, r))),
+        (l, BinaryOp::Add, Value::String(r)) => Ok(Value::String(format!("{}{}", l, r))),
+
+        // Values of different types are never equal
+        (Value::None, BinaryOp::Eq, Value::None) => Ok(boolean(true)),
+        (Value::None, BinaryOp::Ne, Value::None) => Ok(boolean(false)),
+        (_, BinaryOp::Eq, _) => Ok(boolean(false)),
+        (_, BinaryOp::Ne, _) => Ok(boolean(true)),
+
+        (l, _, r) => Err(CrabbyError::RuntimeError(format!(
+            "Invalid operation between {} and {}",
+            l.type_name(),
+            r.type_name()
+        ))),
+    }
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge
+    )
+}
+
+/// The result of a comparison operator given how its operands order
+fn compare(op: BinaryOp, ordering: Ordering) -> Value {
+    boolean(match op {
+        BinaryOp::Eq => ordering.is_eq(),
+        BinaryOp::Ne => ordering.is_ne(),
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Gt => ordering.is_gt(),
+        BinaryOp::Le => ordering.is_le(),
+        _ => ordering.is_ge(),
+    })
+}
+
+fn date_out_of_range() -> CrabbyError {
+    error("Date is out of range")
+}
+
+fn duration_overflow() -> CrabbyError {
+    error("Duration overflow")
+}
+
+/// A duration of a fractional number of seconds, `None` when it cannot be
+/// represented
+pub fn duration_from_seconds(seconds: f64) -> Option<TimeDelta> {
+    // TimeDelta counts whole milliseconds up to i64::MAX
+    if !seconds.is_finite() || seconds.abs() >= i64::MAX as f64 / 1000.0 {
+        return None;
+    }
+    let whole = TimeDelta::try_seconds(seconds.trunc() as i64)?;
+    whole.checked_add(&TimeDelta::nanoseconds((seconds.fract() * 1e9).round() as i64))
+}
+
+fn scale_duration(duration: TimeDelta, factor: f64) -> Result<Value, CrabbyError> {
+    duration_from_seconds(duration.as_seconds_f64() * factor)
+        .map(Value::Duration)
+        .ok_or_else(duration_overflow)
+}
+
+/// `later - earlier` between two monotonic clock readings, negative when
+/// `later` is in fact earlier
+fn instant_difference(later: std::time::Instant, earlier: std::time::Instant) -> TimeDelta {
+    let (difference, sign) = match later.checked_duration_since(earlier) {
+        Some(difference) => (difference, 1),
+        None => (earlier.duration_since(later), -1),
+    };
+    TimeDelta::from_std(difference).unwrap_or(TimeDelta::MAX) * sign
+}
+
+/// The error for `for` over something that is not a range, list, dict or bytes
+pub fn not_iterable(value: &Value) -> CrabbyError {
+    CrabbyError::RuntimeError(format!("Cannot iterate over a value of type {}", value.type_name()))
+}
+
+fn lists_equal(left: &[Value], right: &[Value]) -> bool {
+    left.len() == right.len()
+        && left.iter().zip(right).all(|(l, r)| {
+            binary(BinaryOp::Eq, l.clone(), r.clone()).is_ok_and(|equal| equal.is_truthy())
+        })
+}
+
+/// Dicts are equal when they hold equal values under the same keys, in any
+/// order
+fn dicts_equal(left: &IndexMap<String, Value>, right: &IndexMap<String, Value>) -> bool {
+    left.len() == right.len()
+        && left.iter().all(|(key, l)| {
+            right.get(key).is_some_and(|r| {
+                binary(BinaryOp::Eq, l.clone(), r.clone()).is_ok_and(|equal| equal.is_truthy())
+            })
+        })
+}
+
+/// Builds a dict from `key, value` pairs, where a repeated key keeps its first
+/// position and its last value
+pub fn make_dict(pairs: impl IntoIterator<Item = (Value, Value)>) -> Result<Value, CrabbyError> {
+    let mut entries = IndexMap::new();
+    for (key, value) in pairs {
+        let Value::String(key) = key else {
+            return Err(CrabbyError::RuntimeError(format!(
+                "Dict keys must be strings, not {}",
+                key.type_name()
+            )));
+        };
+        entries.insert(key, value);
+    }
+    Ok(Value::Dict(Rc::new(entries)))
+}
+
+/// `object[index]` on a list, string or bytes, where negative indices count
+/// from the end, or `dict[key]`
+pub fn index(object: Value, index: Value) -> Result<Value, CrabbyError> {
+    if let Value::Dict(entries) = &object {
+        let Value::String(key) = &index else {
+            return Err(CrabbyError::RuntimeError(format!(
+                "Dict keys must be strings, not {}",
+                index.type_name()
+            )));
+        };
+        return entries
+            .get(key)
+            .cloned()
+            .ok_or_else(|| CrabbyError::RuntimeError(format!("Key {:?} is not in the dict", key)));
+    }
+    let Value::Integer(i) = index else {
+        return Err(CrabbyError::RuntimeError(format!(
+            "Index must be an integer, not {}",
+            index.type_name()
+        )));
+    };
+    let position = |len: usize| {
+        let position = if i < 0 { i + len as i64 } else { i };
+        usize::try_from(position).ok().filter(|&position| position < len).ok_or_else(|| {
+            CrabbyError::RuntimeError(format!(
+                "Index {} is out of range for a {} of length {}",
+                i,
+                object.type_name(),
+                len
+            ))
+        })
+    };
+
+    match &object {
+        Value::List(elements) => Ok(elements[position(elements.len())?].clone()),
+        Value::Bytes(bytes) => Ok(Value::Integer(bytes[position(bytes.len())?] as i64)),
+        Value::String(s) => {
+            let chars = s.chars().count();
+            let position = position(chars)?;
+            Ok(Value::String(s.chars().nth(position).map(String::from).unwrap_or_default()))
+        }
+        other => Err(CrabbyError::RuntimeError(format!(
+            "Cannot index a value of type {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// `object.name`, which reads a member of a module, a field of a datetime
+/// or duration, or the pattern of a regex
+pub fn member(object: Value, name: &str) -> Result<Value, CrabbyError> {
+    let field = match &object {
+        Value::Module(module) => {
+            return module.members.get(name).cloned().ok_or_else(|| {
+                CrabbyError::RuntimeError(format!("Module '{}' has no member '{}'", module.name, name))
+            });
+        }
+        Value::DateTime(datetime) => datetime_field(datetime, name),
+        Value::Duration(duration) => duration_field(duration, name),
+        Value::Regex(regex) if name == "pattern" => Some(Value::String(regex.as_str().to_string())),
+        _ => None,
+    };
+    field.ok_or_else(|| {
+        CrabbyError::RuntimeError(format!(
+            "Value of type {} has no member '{}'",
+            object.type_name(),
+            name
+        ))
+    })
+}
+
+fn datetime_field(datetime: &DateTime<FixedOffset>, name: &str) -> Option<Value> {
+    let integer = |n: u32| Some(Value::Integer(n as i64));
+    match name {
+        "year" => Some(Value::Integer(datetime.year() as i64)),
+        "month" => integer(datetime.month()),
+        "day" => integer(datetime.day()),
+        "hour" => integer(datetime.hour()),
+        "minute" => integer(datetime.minute()),
+        "second" => integer(datetime.second()),
+        "nanosecond" => integer(datetime.nanosecond()),
+        // 1 for Monday through 7 for Sunday, as in ISO 8601
+        "weekday" => integer(datetime.weekday().number_from_monday()),
+        "yearday" => integer(datetime.ordinal()),
+        // Seconds east of UTC
+        "offset" => Some(Value::Integer(datetime.offset().local_minus_utc() as i64)),
+        "timestamp" => Some(Value::Float(
+            datetime.timestamp() as f64 + datetime.timestamp_subsec_nanos() as f64 / 1e9,
+        )),
+        _ => None,
+    }
+}
+
+fn duration_field(duration: &TimeDelta, name: &str) -> Option<Value> {
+    match name {
+        "seconds" => Some(Value::Float(duration.as_seconds_f64())),
+        "milliseconds" => Some(Value::Integer(duration.num_milliseconds())),
+        _ => None,
+    }
+}
+
+/// Integer division rounding towards negative infinity, `None` on overflow
+pub fn floor_div(l: i64, r: i64) -> Option<i64> {
+    let quotient = l.checked_div(r)?;
+    if l % r != 0 && (l < 0) != (r < 0) {
+        Some(quotient - 1)
+    } else {
+        Some(quotient)
+    }
+}
+
+/// The remainder of `floor_div`, which has the sign of the divisor
+pub fn floor_mod(l: i64, r: i64) -> i64 {
+    let remainder = l.wrapping_rem(r);
+    if remainder != 0 && (remainder < 0) != (r < 0) {
+        remainder + r
+    } else {
+        remainder
+    }
+}
+
+fn float_binary(op: BinaryOp, l: f64, r: f64) -> Result<Value, CrabbyError> {
+    match op {
+        BinaryOp::Add => Ok(Value::Float(l + r)),
+        BinaryOp::Sub => Ok(Value::Float(l - r)),
+        BinaryOp::Mul => Ok(Value::Float(l * r)),
+        BinaryOp::Div => {
+            if r == 0.0 {
+                return Err(error("Division by zero"));
+            }
+            Ok(Value::Float(l / r))
+        }
+        BinaryOp::FloorDiv => {
+            if r == 0.0 {
+                return Err(error("Division by zero"));
+            }
+            Ok(Value::Float((l / r).floor()))
+        }
+        BinaryOp::Mod => {
+            if r == 0.0 {
+                return Err(error("Modulo by zero"));
+            }
+            Ok(Value::Float(l - r * (l / r).floor()))
+        }
+        // Exact, as in IEEE 754: a fixed tolerance would call tiny values
+        // equal and make no difference at all to large ones
+        BinaryOp::Eq => Ok(boolean(l == r)),
+        BinaryOp::Ne => Ok(boolean(l != r)),
+        BinaryOp::Lt => Ok(boolean(l < r)),
+        BinaryOp::Gt => Ok(boolean(l > r)),
+        BinaryOp::Le => Ok(boolean(l <= r)),
+        BinaryOp::Ge => Ok(boolean(l >= r)),
+        BinaryOp::And => Ok(boolean(l != 0.0 && r != 0.0)),
+        BinaryOp::Or => Ok(boolean(l != 0.0 || r != 0.0)),
+        BinaryOp::Dot => Err(error("Cannot use dot operator with numbers")),
+    }
+}
@@ -0,0 +1,164 @@
+//! `_fs`: the native half of `libs/fs.crab`
+
+use super::{arg_range, string_arg};
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime};
+use crate::utils::CrabbyError;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("read", Some(1), read),
+    ("read_bytes", Some(1), read_bytes),
+    ("write", Some(2), write),
+    ("append", Some(2), append),
+    ("list", None, list),
+    ("exists", Some(1), exists),
+    ("is_dir", Some(1), is_dir),
+    ("mkdir", Some(1), mkdir),
+    ("remove", Some(1), remove),
+    ("rename", Some(2), rename),
+    ("glob", Some(1), glob),
+];
+
+/// The error for an operation on `path` that the operating system refused,
+/// with a kind saying why
+fn io_error(function: &str, path: &str, error: io::Error) -> CrabbyError {
+    let kind = match error.kind() {
+        io::ErrorKind::NotFound => "not_found",
+        io::ErrorKind::PermissionDenied => "permission_denied",
+        io::ErrorKind::AlreadyExists => "already_exists",
+        _ => "io",
+    };
+    CrabbyError::Failure {
+        kind,
+        message: format!("fs.{}() failed for '{}': {}", function, path, error),
+    }
+}
+
+/// Text or bytes to write; strings are written as UTF-8
+fn data_arg<'a>(function: &str, value: &'a Value) -> Result<&'a [u8], CrabbyError> {
+    match value {
+        Value::String(s) => Ok(s.as_bytes()),
+        Value::Bytes(bytes) => Ok(bytes),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "fs.{}() expects a string or bytes to write, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+fn read(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.read", &args[0])?;
+    fs::read_to_string(path)
+        .map(Value::String)
+        .map_err(|e| io_error("read", path, e))
+}
+
+fn read_bytes(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.read_bytes", &args[0])?;
+    fs::read(path)
+        .map(|bytes| Value::Bytes(Rc::new(bytes)))
+        .map_err(|e| io_error("read_bytes", path, e))
+}
+
+/// Replaces the file's contents, creating it if needed
+fn write(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.write", &args[0])?;
+    let data = data_arg("write", &args[1])?;
+    fs::write(path, data).map_err(|e| io_error("write", path, e))?;
+    Ok(Value::None)
+}
+
+/// Adds to the end of the file, creating it if needed
+fn append(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.append", &args[0])?;
+    let data = data_arg("append", &args[1])?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| io_error("append", path, e))?;
+    Ok(Value::None)
+}
+
+/// The names in a directory, the current one by default, sorted
+fn list(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("fs.list", &args, 0, 1)?;
+    let path = match args.first() {
+        Some(path) => string_arg("fs.list", path)?,
+        None => ".",
+    };
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|e| io_error("list", path, e))?;
+    names.sort();
+    Ok(Value::List(Rc::new(
+        names.into_iter().map(Value::String).collect(),
+    )))
+}
+
+fn exists(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.exists", &args[0])?;
+    Ok(Value::Integer(Path::new(path).exists() as i64))
+}
+
+fn is_dir(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.is_dir", &args[0])?;
+    Ok(Value::Integer(Path::new(path).is_dir() as i64))
+}
+
+/// Creates a directory along with any missing parents
+fn mkdir(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.mkdir", &args[0])?;
+    fs::create_dir_all(path).map_err(|e| io_error("mkdir", path, e))?;
+    Ok(Value::None)
+}
+
+/// Deletes a file or an empty directory
+fn remove(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("fs.remove", &args[0])?;
+    let result = if Path::new(path).is_dir() {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    };
+    result.map_err(|e| io_error("remove", path, e))?;
+    Ok(Value::None)
+}
+
+fn rename(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let from = string_arg("fs.rename", &args[0])?;
+    let to = string_arg("fs.rename", &args[1])?;
+    fs::rename(from, to).map_err(|e| io_error("rename", from, e))?;
+    Ok(Value::None)
+}
+
+/// The paths matching a pattern such as `src/**/*.crab`, sorted
+fn glob(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let pattern = string_arg("fs.glob", &args[0])?;
+    let paths = glob::glob(pattern).map_err(|e| {
+        CrabbyError::RuntimeError(format!(
+            "fs.glob() cannot use the pattern '{}': {}",
+            pattern, e
+        ))
+    })?;
+    let paths = paths
+        .map(|path| {
+            path.map(|path| Value::String(path.to_string_lossy().into_owned()))
+                .map_err(|e| {
+                    let path = e.path().to_string_lossy().into_owned();
+                    io_error("glob", &path, e.into())
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::List(Rc::new(paths)))
+}
@@ -0,0 +1,88 @@
+//! `_hash`: the native half of `libs/hash.crab`
+
+use super::{arg_range, string_arg};
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime};
+use crate::utils::{self, CrabbyError};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::rc::Rc;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("sha256", None, sha256),
+    ("sha1", None, sha1),
+    ("md5", None, md5),
+    ("crc32", None, crc32),
+    ("fnv1a", None, fnv1a),
+    ("blake3", None, blake3),
+];
+
+fn sha256(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("sha256", &args, |data| Sha256::digest(data).to_vec())
+}
+
+fn sha1(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("sha1", &args, |data| Sha1::digest(data).to_vec())
+}
+
+fn md5(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("md5", &args, |data| Md5::digest(data).to_vec())
+}
+
+/// The CRC-32 checksum used by zip and PNG, big-endian
+fn crc32(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("crc32", &args, |data| {
+        crc32fast::hash(data).to_be_bytes().to_vec()
+    })
+}
+
+/// The 64-bit FNV-1a hash, big-endian
+fn fnv1a(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("fnv1a", &args, |data| {
+        utils::fnv1a(data).to_be_bytes().to_vec()
+    })
+}
+
+fn blake3(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    digest("blake3", &args, |data| {
+        blake3::hash(data).as_bytes().to_vec()
+    })
+}
+
+/// Hashes a string's UTF-8 encoding or bytes, returning the digest as a
+/// lowercase hex string, or as bytes when the second argument is `"bytes"`
+fn digest(
+    function: &str,
+    args: &[Value],
+    hash: impl Fn(&[u8]) -> Vec<u8>,
+) -> Result<Value, CrabbyError> {
+    let name = format!("hash.{}", function);
+    arg_range(&name, args, 1, 2)?;
+    let digest = match &args[0] {
+        Value::String(s) => hash(s.as_bytes()),
+        Value::Bytes(bytes) => hash(bytes),
+        other => {
+            return Err(CrabbyError::RuntimeError(format!(
+                "{}() expects a string or bytes, got {}",
+                name,
+                other.type_name()
+            )));
+        }
+    };
+
+    let format = match args.get(1) {
+        Some(format) => string_arg(&name, format)?,
+        None => "hex",
+    };
+    match format {
+        "hex" => Ok(Value::String(
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        )),
+        "bytes" => Ok(Value::Bytes(Rc::new(digest))),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "{}() returns \"hex\" or \"bytes\", not \"{}\"",
+            name, other
+        ))),
+    }
+}
@@ -0,0 +1,428 @@
+//! `_json`: the native half of `libs/json.crab`
+
+use super::{arg_range, string_arg};
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime};
+use crate::utils::CrabbyError;
+use indexmap::IndexMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] =
+    &[("parse", Some(1), parse), ("stringify", None, stringify)];
+
+/// How deeply arrays and objects may nest before parsing gives up, which
+/// keeps hostile input from overflowing the stack
+const MAX_DEPTH: usize = 512;
+
+fn parse(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let text = string_arg("json.parse", &args[0])?;
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.document().map_err(|message| {
+        let (line, column) = parser.location();
+        CrabbyError::RuntimeError(format!(
+            "json.parse() failed at line {}, column {}: {}",
+            line, column, message
+        ))
+    })?;
+    Ok(value)
+}
+
+/// Reads a JSON document, leaving `pos` where an error was found
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// The 1-based line and column of `pos`, counting columns in characters
+    fn location(&self) -> (usize, usize) {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    /// A description of what is at `pos`, for error messages
+    fn found(&self) -> String {
+        match self.text[self.pos..].chars().next() {
+            Some(c) => format!("{:?}", c),
+            None => "the end of the text".to_string(),
+        }
+    }
+
+    fn expect(&mut self, byte: u8, context: &str) -> Result<(), String> {
+        if self.peek() == Some(byte) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!(
+                "expected '{}' {}, found {}",
+                byte as char,
+                context,
+                self.found()
+            ))
+        }
+    }
+
+    fn document(&mut self) -> Result<Value, String> {
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(value),
+            Some(_) => Err(format!("unexpected {} after the value", self.found())),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(format!("nesting is deeper than {} levels", MAX_DEPTH));
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Value::Integer(1)),
+            Some(b'f') => self.literal("false", Value::Integer(0)),
+            Some(b'n') => self.literal("null", Value::None),
+            _ => Err(format!("expected a value, found {}", self.found())),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(format!("expected a value, found {}", self.found()))
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1; // consume '{'
+        let mut entries = IndexMap::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Value::Dict(Rc::new(entries)));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(format!("expected a string key, found {}", self.found()));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':', "after an object key")?;
+            let value = self.value(depth + 1)?;
+            entries.insert(key, value);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Dict(Rc::new(entries)));
+                }
+                _ => {
+                    return Err(format!(
+                        "expected ',' or '}}' after an object entry, found {}",
+                        self.found()
+                    ));
+                }
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1; // consume '['
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Value::List(Rc::new(elements)));
+        }
+
+        loop {
+            elements.push(self.value(depth + 1)?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::List(Rc::new(elements)));
+                }
+                _ => {
+                    return Err(format!(
+                        "expected ',' or ']' after an array element, found {}",
+                        self.found()
+                    ));
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1; // consume '"'
+        let mut result = String::new();
+        loop {
+            let Some(c) = self.text[self.pos..].chars().next() else {
+                return Err("unterminated string".to_string());
+            };
+            match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok(result);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    result.push(self.escape()?);
+                }
+                c if (c as u32) < 0x20 => {
+                    return Err(format!("control character {:?} in a string", c));
+                }
+                c => {
+                    self.pos += c.len_utf8();
+                    result.push(c);
+                }
+            }
+        }
+    }
+
+    /// The character of an escape sequence, after its backslash
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.peek() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                self.pos += 1;
+                let unit = self.hex4()?;
+                let code = if (0xD800..0xDC00).contains(&unit) {
+                    // A high surrogate must be followed by an escaped low one
+                    if !self.text[self.pos..].starts_with("\\u") {
+                        return Err("unpaired surrogate in a \\u escape".to_string());
+                    }
+                    self.pos += 2;
+                    let low = self.hex4()?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err("unpaired surrogate in a \\u escape".to_string());
+                    }
+                    0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    unit
+                };
+                return char::from_u32(code)
+                    .ok_or_else(|| "unpaired surrogate in a \\u escape".to_string());
+            }
+            _ => {
+                return Err(match self.text[self.pos..].chars().next() {
+                    Some(c) => format!("invalid escape '\\{}'", c),
+                    None => "unterminated string".to_string(),
+                });
+            }
+        };
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).unwrap_or_default();
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err("expected four hex digits after '\\u'".to_string());
+        }
+        self.pos += 4;
+        u32::from_str_radix(digits, 16).map_err(|e| e.to_string())
+    }
+
+    /// An integer when the number has no fraction or exponent and fits,
+    /// otherwise a float. One too large even for a float is an error rather
+    /// than an infinity, which JSON cannot write back.
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let digits = |parser: &mut Self| {
+            let from = parser.pos;
+            while matches!(parser.peek(), Some(b'0'..=b'9')) {
+                parser.pos += 1;
+            }
+            parser.pos > from
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err(format!("expected a digit, found {}", self.found()));
+        }
+        let mut integral = true;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            integral = false;
+            if !digits(self) {
+                return Err(format!(
+                    "expected a digit after '.', found {}",
+                    self.found()
+                ));
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            integral = false;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(format!(
+                    "expected a digit in the exponent, found {}",
+                    self.found()
+                ));
+            }
+        }
+
+        let number = &self.text[start..self.pos];
+        if integral && let Ok(n) = number.parse() {
+            return Ok(Value::Integer(n));
+        }
+        let value: f64 = number.parse().map_err(|e| format!("{}", e))?;
+        if !value.is_finite() {
+            self.pos = start;
+            return Err(format!("the number {} is too large", number));
+        }
+        Ok(Value::Float(value))
+    }
+}
+
+/// `stringify(value, indent = none)`: compact JSON, or one entry per line
+/// indented by a number of spaces or by a given string
+fn stringify(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("json.stringify", &args, 1, 2)?;
+    let indent = match args.get(1) {
+        None | Some(Value::None) => None,
+        Some(Value::Integer(n)) => Some(" ".repeat((*n).clamp(0, 10) as usize)),
+        Some(Value::String(s)) => Some(s.clone()),
+        Some(other) => {
+            return Err(CrabbyError::RuntimeError(format!(
+                "json.stringify() expects an indent that is a number of spaces or a string, got {}",
+                other.type_name()
+            )));
+        }
+    };
+
+    let mut out = String::new();
+    write_json(&mut out, &args[0], indent.as_deref(), 0).map_err(CrabbyError::RuntimeError)?;
+    Ok(Value::String(out))
+}
+
+fn write_json(
+    out: &mut String,
+    value: &Value,
+    indent: Option<&str>,
+    depth: usize,
+) -> Result<(), String> {
+    match value {
+        Value::None => out.push_str("null"),
+        Value::Integer(n) => out.push_str(&n.to_string()),
+        Value::Float(x) if x.is_finite() => out.push_str(&x.to_string()),
+        Value::Float(x) => return Err(format!("json.stringify() cannot encode {}", x)),
+        Value::String(s) => write_string(out, s),
+        Value::List(elements) => {
+            write_container(
+                out,
+                ('[', ']'),
+                elements.iter(),
+                indent,
+                depth,
+                |out, element| write_json(out, element, indent, depth + 1),
+            )?;
+        }
+        Value::Dict(entries) => {
+            write_container(
+                out,
+                ('{', '}'),
+                entries.iter(),
+                indent,
+                depth,
+                |out, (key, value)| {
+                    write_string(out, key);
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    write_json(out, value, indent, depth + 1)
+                },
+            )?;
+        }
+        other => {
+            return Err(format!(
+                "json.stringify() cannot encode a value of type {}",
+                other.type_name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the items of an array or object between `brackets`, one per line
+/// when indenting
+fn write_container<T>(
+    out: &mut String,
+    (open, close): (char, char),
+    items: impl ExactSizeIterator<Item = T>,
+    indent: Option<&str>,
+    depth: usize,
+    mut write_item: impl FnMut(&mut String, T) -> Result<(), String>,
+) -> Result<(), String> {
+    out.push(open);
+    let empty = items.len() == 0;
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if let Some(indent) = indent {
+            out.push('\n');
+            out.push_str(&indent.repeat(depth + 1));
+        }
+        write_item(out, item)?;
+    }
+    if let Some(indent) = indent.filter(|_| !empty) {
+        out.push('\n');
+        out.push_str(&indent.repeat(depth));
+    }
+    out.push(close);
+    Ok(())
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
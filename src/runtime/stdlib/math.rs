@@ -0,0 +1,246 @@
+//! `_math`: the native half of `libs/math.crab`
+
+use super::{arg_range, integer_arg};
+use crate::ir::BinaryOp;
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime, ops};
+use crate::utils::CrabbyError;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("sqrt", Some(1), sqrt),
+    ("pow", Some(2), pow),
+    ("floor", Some(1), floor),
+    ("ceil", Some(1), ceil),
+    ("round", None, round),
+    ("sin", Some(1), sin),
+    ("cos", Some(1), cos),
+    ("tan", Some(1), tan),
+    ("asin", Some(1), asin),
+    ("acos", Some(1), acos),
+    ("atan", Some(1), atan),
+    ("atan2", Some(2), atan2),
+    ("log", None, log),
+    ("log2", Some(1), log2),
+    ("log10", Some(1), log10),
+    ("exp", Some(1), exp),
+    ("abs", Some(1), abs),
+    ("min", None, min),
+    ("max", None, max),
+    ("gcd", Some(2), gcd),
+];
+
+fn number_arg(function: &str, value: &Value) -> Result<f64, CrabbyError> {
+    match value {
+        Value::Integer(n) => Ok(*n as f64),
+        Value::Float(f) => Ok(*f),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "math.{}() expects a number, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+fn domain_error(function: &str, x: f64) -> CrabbyError {
+    CrabbyError::RuntimeError(format!(
+        "math.{}() is undefined for {}",
+        function,
+        Value::Float(x)
+    ))
+}
+
+/// Applies `f` to a float argument that must lie in `domain`
+fn float_fn(
+    function: &str,
+    args: &[Value],
+    domain: impl Fn(f64) -> bool,
+    f: impl Fn(f64) -> f64,
+) -> Result<Value, CrabbyError> {
+    let x = number_arg(function, &args[0])?;
+    if !domain(x) {
+        return Err(domain_error(function, x));
+    }
+    Ok(Value::Float(f(x)))
+}
+
+/// Converts a rounded float to an integer, failing for infinities, NaN and
+/// values outside the integer range
+fn to_integer(function: &str, x: f64) -> Result<Value, CrabbyError> {
+    if x.is_finite() && x >= i64::MIN as f64 && x < i64::MAX as f64 {
+        Ok(Value::Integer(x as i64))
+    } else {
+        Err(CrabbyError::RuntimeError(format!(
+            "math.{}() result does not fit in an integer",
+            function
+        )))
+    }
+}
+
+fn sqrt(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("sqrt", &args, |x| x >= 0.0, f64::sqrt)
+}
+
+/// Integer powers stay integers while the exponent is not negative
+fn pow(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    if let (Value::Integer(base), Value::Integer(exponent)) = (&args[0], &args[1])
+        && *exponent >= 0
+    {
+        return u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent))
+            .map(Value::Integer)
+            .ok_or_else(|| CrabbyError::RuntimeError("Integer overflow".to_string()));
+    }
+
+    let base = number_arg("pow", &args[0])?;
+    let exponent = number_arg("pow", &args[1])?;
+    let result = base.powf(exponent);
+    if result.is_nan() && !base.is_nan() && !exponent.is_nan() {
+        return Err(domain_error("pow", base));
+    }
+    Ok(Value::Float(result))
+}
+
+fn floor(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    match &args[0] {
+        Value::Integer(n) => Ok(Value::Integer(*n)),
+        other => to_integer("floor", number_arg("floor", other)?.floor()),
+    }
+}
+
+fn ceil(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    match &args[0] {
+        Value::Integer(n) => Ok(Value::Integer(*n)),
+        other => to_integer("ceil", number_arg("ceil", other)?.ceil()),
+    }
+}
+
+/// `round(x)` rounds half away from zero to an integer; `round(x, digits)`
+/// keeps a float with that many decimal places
+fn round(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("math.round", &args, 1, 2)?;
+    let x = number_arg("round", &args[0])?;
+    let Some(digits) = args.get(1) else {
+        return match &args[0] {
+            Value::Integer(n) => Ok(Value::Integer(*n)),
+            _ => to_integer("round", x.round()),
+        };
+    };
+
+    let digits = integer_arg("math.round", digits)?;
+    let scale = 10f64.powi(digits.clamp(-308, 308) as i32);
+    Ok(Value::Float((x * scale).round() / scale))
+}
+
+fn sin(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("sin", &args, f64::is_finite, f64::sin)
+}
+
+fn cos(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("cos", &args, f64::is_finite, f64::cos)
+}
+
+fn tan(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("tan", &args, f64::is_finite, f64::tan)
+}
+
+fn asin(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("asin", &args, |x| (-1.0..=1.0).contains(&x), f64::asin)
+}
+
+fn acos(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("acos", &args, |x| (-1.0..=1.0).contains(&x), f64::acos)
+}
+
+fn atan(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("atan", &args, |_| true, f64::atan)
+}
+
+fn atan2(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let y = number_arg("atan2", &args[0])?;
+    let x = number_arg("atan2", &args[1])?;
+    Ok(Value::Float(y.atan2(x)))
+}
+
+/// The natural logarithm, or the logarithm in a given base
+fn log(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("math.log", &args, 1, 2)?;
+    let x = number_arg("log", &args[0])?;
+    if x <= 0.0 {
+        return Err(domain_error("log", x));
+    }
+    let Some(base) = args.get(1) else {
+        return Ok(Value::Float(x.ln()));
+    };
+
+    let base = number_arg("log", base)?;
+    if base <= 0.0 || base == 1.0 {
+        return Err(CrabbyError::RuntimeError(format!(
+            "math.log() cannot use base {}",
+            Value::Float(base)
+        )));
+    }
+    Ok(Value::Float(x.log(base)))
+}
+
+fn log2(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("log2", &args, |x| x > 0.0, f64::log2)
+}
+
+fn log10(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("log10", &args, |x| x > 0.0, f64::log10)
+}
+
+fn exp(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    float_fn("exp", &args, |_| true, f64::exp)
+}
+
+fn abs(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    match &args[0] {
+        Value::Integer(n) => n
+            .checked_abs()
+            .map(Value::Integer)
+            .ok_or_else(|| CrabbyError::RuntimeError("Integer overflow".to_string())),
+        other => Ok(Value::Float(number_arg("abs", other)?.abs())),
+    }
+}
+
+fn min(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    extreme("min", args, BinaryOp::Lt)
+}
+
+fn max(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    extreme("max", args, BinaryOp::Gt)
+}
+
+/// The first of the arguments, or of the elements of a single list argument,
+/// that no other beats under `op`
+fn extreme(function: &str, args: Vec<Value>, op: BinaryOp) -> Result<Value, CrabbyError> {
+    let values = match args.as_slice() {
+        [Value::List(values)] => values.to_vec(),
+        _ => args,
+    };
+
+    let mut values = values.into_iter();
+    let mut best = values.next().ok_or_else(|| {
+        CrabbyError::RuntimeError(format!("math.{}() needs at least one value", function))
+    })?;
+    for value in values {
+        if ops::binary(op, value.clone(), best.clone())?.is_truthy() {
+            best = value;
+        }
+    }
+    Ok(best)
+}
+
+/// The greatest common divisor, which is never negative
+fn gcd(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let mut a = integer_arg("math.gcd", &args[0])?.unsigned_abs();
+    let mut b = integer_arg("math.gcd", &args[1])?.unsigned_abs();
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    i64::try_from(a)
+        .map(Value::Integer)
+        .map_err(|_| CrabbyError::RuntimeError("Integer overflow".to_string()))
+}
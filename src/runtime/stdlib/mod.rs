@@ -0,0 +1,84 @@
+//! Modules implemented in Rust, bound by `import name`
+
+mod fs;
+mod hash;
+mod json;
+mod math;
+mod path;
+mod process;
+mod re;
+mod sys;
+mod time;
+
+pub(super) use sys::read_line;
+
+use crate::runtime::Runtime;
+use crate::runtime::value::Value;
+use crate::utils::CrabbyError;
+
+/// The library modules written in Crabby, bundled into the binary
+const SOURCES: &[(&str, &str)] = &[
+    ("fs", include_str!("../../../libs/fs.crab")),
+    ("hash", include_str!("../../../libs/hash.crab")),
+    ("json", include_str!("../../../libs/json.crab")),
+    ("math", include_str!("../../../libs/math.crab")),
+    ("path", include_str!("../../../libs/path.crab")),
+    ("time", include_str!("../../../libs/time.crab")),
+];
+
+pub fn register(runtime: &mut Runtime) {
+    runtime.register_module("sys", sys::FUNCTIONS);
+    runtime.register_module("_fs", fs::FUNCTIONS);
+    runtime.register_module("_hash", hash::FUNCTIONS);
+    runtime.register_module("_json", json::FUNCTIONS);
+    runtime.register_module("_math", math::FUNCTIONS);
+    runtime.register_module("_path", path::FUNCTIONS);
+    runtime.register_module("process", process::FUNCTIONS);
+    runtime.register_module("re", re::FUNCTIONS);
+    runtime.register_module("_time", time::FUNCTIONS);
+}
+
+/// The bundled source of a library module
+pub fn source(name: &str) -> Option<&'static str> {
+    SOURCES
+        .iter()
+        .find(|(module, _)| *module == name)
+        .map(|(_, source)| *source)
+}
+
+pub(super) fn string_arg<'a>(function: &str, value: &'a Value) -> Result<&'a str, CrabbyError> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "{}() expects a string, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+pub(super) fn integer_arg(function: &str, value: &Value) -> Result<i64, CrabbyError> {
+    match value {
+        Value::Integer(n) => Ok(*n),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "{}() expects an integer, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+/// Checks the argument count of a native that takes between `min` and `max`
+pub(super) fn arg_range(function: &str, args: &[Value], min: usize, max: usize) -> Result<(), CrabbyError> {
+    if (min..=max).contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(CrabbyError::RuntimeError(format!(
+            "{} expects {} to {} arguments, got {}",
+            function,
+            min,
+            max,
+            args.len()
+        )))
+    }
+}
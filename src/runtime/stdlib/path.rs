@@ -0,0 +1,53 @@
+//! `_path`: the native half of `libs/path.crab`
+
+use super::string_arg;
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime};
+use crate::utils::CrabbyError;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("join", None, join),
+    ("dirname", Some(1), dirname),
+    ("basename", Some(1), basename),
+    ("extension", Some(1), extension),
+];
+
+fn text(part: Option<&OsStr>) -> Value {
+    Value::String(
+        part.map(|part| part.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    )
+}
+
+/// Joins parts with the platform's separator; an absolute part starts over
+fn join(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    if args.is_empty() {
+        return Err(CrabbyError::RuntimeError(
+            "path.join() needs at least one part".to_string(),
+        ));
+    }
+    let mut path = PathBuf::new();
+    for part in &args {
+        path.push(string_arg("path.join", part)?);
+    }
+    Ok(text(Some(path.as_os_str())))
+}
+
+/// Everything before the last component, or `""` for a bare name
+fn dirname(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("path.dirname", &args[0])?;
+    Ok(text(Path::new(path).parent().map(Path::as_os_str)))
+}
+
+fn basename(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("path.basename", &args[0])?;
+    Ok(text(Path::new(path).file_name()))
+}
+
+/// The extension without its dot, or `""` when there is none
+fn extension(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let path = string_arg("path.extension", &args[0])?;
+    Ok(text(Path::new(path).extension()))
+}
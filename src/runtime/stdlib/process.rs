@@ -0,0 +1,440 @@
+//! `import process`: running other programs. `process.run` waits for a
+//! command and returns what it printed, while `process.lines` hands out its
+//! output a line at a time as it is produced. Failures have a kind that
+//! `try` reports: "not_found" when the command does not exist, "timeout",
+//! "exit_status" for a non-zero exit under `check`, and "io" otherwise.
+
+use super::{arg_range, string_arg};
+use crate::runtime::value::{IteratorValue, Producer, Value};
+use crate::runtime::{NativeFn, Runtime, ops};
+use crate::utils::CrabbyError;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] =
+    &[("run", None, run), ("lines", None, lines)];
+
+/// How often `run` checks whether a command with a timeout has finished
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+fn error(message: String) -> CrabbyError {
+    CrabbyError::RuntimeError(message)
+}
+
+fn failure(kind: &'static str, message: String) -> CrabbyError {
+    CrabbyError::Failure { kind, message }
+}
+
+/// The optional settings in the dict passed after the arguments
+#[derive(Default)]
+struct Options {
+    cwd: Option<String>,
+    env: Vec<(String, String)>,
+    stdin: Option<Vec<u8>>,
+    timeout: Option<Duration>,
+    /// Whether a non-zero exit status is an error
+    check: bool,
+}
+
+/// A command line from `(cmd, args = [], options = {})`
+struct Invocation {
+    function: &'static str,
+    program: String,
+    command: Command,
+    options: Options,
+}
+
+impl Invocation {
+    fn new(function: &'static str, args: &[Value]) -> Result<Self, CrabbyError> {
+        let name = format!("process.{}", function);
+        arg_range(&name, args, 1, 3)?;
+        let program = string_arg(&name, &args[0])?.to_string();
+        let mut command = Command::new(&program);
+
+        match args.get(1) {
+            None | Some(Value::None) => {}
+            Some(Value::List(arguments)) => {
+                for argument in arguments.iter() {
+                    match argument {
+                        Value::String(s) => command.arg(s),
+                        Value::Integer(_) | Value::Float(_) => command.arg(argument.to_string()),
+                        other => {
+                            return Err(error(format!(
+                                "{}() arguments must be strings, got {}",
+                                name,
+                                other.type_name()
+                            )));
+                        }
+                    };
+                }
+            }
+            Some(other) => {
+                return Err(error(format!(
+                    "{}() expects a list of arguments, got {}",
+                    name,
+                    other.type_name()
+                )));
+            }
+        }
+
+        let options = match args.get(2) {
+            None | Some(Value::None) => Options::default(),
+            Some(value) => parse_options(&name, value)?,
+        };
+        if let Some(cwd) = &options.cwd {
+            if !Path::new(cwd).is_dir() {
+                return Err(error(format!(
+                    "{}() cannot use '{}' as the working directory, it is not a directory",
+                    name, cwd
+                )));
+            }
+            command.current_dir(cwd);
+        }
+        command.envs(options.env.iter().map(|(key, value)| (key, value)));
+        // A command that can time out gets its own process group, so anything
+        // it starts is killed along with it. Others stay in ours, where they
+        // can still read from the terminal and see its Ctrl-C.
+        #[cfg(unix)]
+        if options.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+
+        Ok(Self {
+            function,
+            program,
+            command,
+            options,
+        })
+    }
+
+    /// Starts the command, writing any `stdin` option to it from a thread
+    fn spawn(&mut self) -> Result<Child, CrabbyError> {
+        let stdin = if self.options.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        };
+        let mut child = self
+            .command
+            .stdin(stdin)
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => failure(
+                    "not_found",
+                    format!(
+                        "process.{}() cannot find the command '{}'",
+                        self.function, self.program
+                    ),
+                ),
+                _ => failure(
+                    "io",
+                    format!(
+                        "process.{}() cannot start '{}': {}",
+                        self.function, self.program, e
+                    ),
+                ),
+            })?;
+
+        if let (Some(input), Some(mut pipe)) = (self.options.stdin.take(), child.stdin.take()) {
+            // A command that exits without reading its input closes the pipe
+            // early, which is not an error
+            thread::spawn(move || pipe.write_all(&input));
+        }
+        Ok(child)
+    }
+
+    fn timed_out(&self) -> CrabbyError {
+        failure(
+            "timeout",
+            format!(
+                "process.{}(): '{}' timed out after {} ms",
+                self.function,
+                self.program,
+                self.options.timeout.unwrap_or_default().as_millis()
+            ),
+        )
+    }
+
+    /// Stops the command and waits for it to go. One with a timeout is
+    /// killed along with the rest of its process group.
+    fn kill(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if self.options.timeout.is_some() {
+            // SAFETY: kill() only sends a signal; the group is the one the
+            // child leads, created in `new`, and its id is not reused while
+            // the child is unreaped or anything else in the group still runs
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    /// Fails for a non-zero exit when the `check` option is set
+    fn check(&self, status: ExitStatus) -> Result<(), CrabbyError> {
+        if !self.options.check || status.success() {
+            return Ok(());
+        }
+        Err(failure(
+            "exit_status",
+            match status.code() {
+                Some(code) => format!(
+                    "process.{}(): '{}' exited with status {}",
+                    self.function, self.program, code
+                ),
+                None => format!(
+                    "process.{}(): '{}' was killed by a signal",
+                    self.function, self.program
+                ),
+            },
+        ))
+    }
+}
+
+fn parse_options(function: &str, value: &Value) -> Result<Options, CrabbyError> {
+    let Value::Dict(entries) = value else {
+        return Err(error(format!(
+            "{}() expects a dict of options, got {}",
+            function,
+            value.type_name()
+        )));
+    };
+
+    let mut options = Options::default();
+    for (key, value) in entries.iter() {
+        let invalid = |expected: &str| {
+            error(format!(
+                "{}() expects the '{}' option to be {}, got {}",
+                function,
+                key,
+                expected,
+                value.type_name()
+            ))
+        };
+        match (key.as_str(), value) {
+            ("cwd", Value::String(cwd)) => options.cwd = Some(cwd.clone()),
+            ("cwd", _) => return Err(invalid("a string")),
+            ("env", Value::Dict(variables)) => {
+                for (name, value) in variables.iter() {
+                    let Value::String(value) = value else {
+                        return Err(invalid("a dict of strings"));
+                    };
+                    options.env.push((name.clone(), value.clone()));
+                }
+            }
+            ("env", _) => return Err(invalid("a dict of strings")),
+            ("stdin", Value::String(input)) => options.stdin = Some(input.clone().into_bytes()),
+            ("stdin", Value::Bytes(input)) => options.stdin = Some(input.to_vec()),
+            ("stdin", _) => return Err(invalid("a string or bytes")),
+            ("timeout", Value::Duration(timeout)) => {
+                options.timeout = Some(timeout.to_std().map_err(|_| invalid("positive"))?);
+            }
+            ("timeout", Value::Integer(_) | Value::Float(_)) => {
+                let milliseconds = match value {
+                    Value::Integer(n) => *n as f64,
+                    Value::Float(x) => *x,
+                    _ => unreachable!(),
+                };
+                let timeout = ops::duration_from_seconds(milliseconds / 1000.0)
+                    .and_then(|timeout| timeout.to_std().ok())
+                    .ok_or_else(|| invalid("a positive number of milliseconds"))?;
+                options.timeout = Some(timeout);
+            }
+            ("timeout", _) => return Err(invalid("a number of milliseconds or a duration")),
+            ("check", value) => options.check = value.is_truthy(),
+            _ => {
+                return Err(error(format!(
+                    "{}() has no option '{}'; the options are cwd, env, stdin, timeout and check",
+                    function, key
+                )));
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Reads a pipe to the end on its own thread, so a command filling one pipe
+/// cannot block while we wait on the other
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+/// `run(cmd, args = [], options = {})` waits for the command and returns a
+/// dict of its `stdout`, `stderr` and exit `code`, which is none when it was
+/// killed by a signal
+fn run(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let mut invocation = Invocation::new("run", &args)?;
+    invocation
+        .command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = invocation.spawn()?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = invocation
+        .options
+        .timeout
+        .map(|timeout| Instant::now() + timeout);
+    let status = match deadline {
+        None => child.wait(),
+        Some(deadline) => loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break Ok(status),
+                Ok(None) if Instant::now() >= deadline => {
+                    invocation.kill(&mut child);
+                    return Err(invocation.timed_out());
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => break Err(e),
+            }
+        },
+    }
+    .map_err(|e| {
+        failure(
+            "io",
+            format!(
+                "process.run() lost track of '{}': {}",
+                invocation.program, e
+            ),
+        )
+    })?;
+
+    // Something the command started can outlive it and keep the pipes open,
+    // so the output must also arrive by the deadline. Past it the readers are
+    // left behind, to finish once the killed group lets go of the pipes.
+    if let Some(deadline) = deadline {
+        while !(stdout.is_finished() && stderr.is_finished()) {
+            if Instant::now() >= deadline {
+                invocation.kill(&mut child);
+                return Err(invocation.timed_out());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+    invocation.check(status)?;
+
+    let text = |output: thread::JoinHandle<Vec<u8>>| {
+        Value::String(String::from_utf8_lossy(&output.join().unwrap_or_default()).into_owned())
+    };
+    ops::make_dict([
+        (Value::String("stdout".to_string()), text(stdout)),
+        (Value::String("stderr".to_string()), text(stderr)),
+        (
+            Value::String("code".to_string()),
+            status
+                .code()
+                .map_or(Value::None, |code| Value::Integer(code as i64)),
+        ),
+    ])
+}
+
+/// `lines(cmd, args = [], options = {})` starts the command and returns an
+/// iterator over the lines of its stdout as they arrive. Its stderr goes
+/// straight to ours.
+fn lines(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let mut invocation = Invocation::new("lines", &args)?;
+    invocation.command.stdout(Stdio::piped());
+    let mut child = invocation.spawn()?;
+
+    // A reader thread lets the iterator wait for a line with a timeout
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let deadline = invocation
+        .options
+        .timeout
+        .map(|timeout| Instant::now() + timeout);
+    Ok(Value::Iterator(Rc::new(IteratorValue::new(Lines {
+        invocation,
+        child: Some(child),
+        receiver,
+        deadline,
+    }))))
+}
+
+struct Lines {
+    invocation: Invocation,
+    /// The running command, until its output ends
+    child: Option<Child>,
+    receiver: Receiver<io::Result<String>>,
+    deadline: Option<Instant>,
+}
+
+impl Producer for Lines {
+    fn next(&mut self, _: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        let Some(child) = &mut self.child else {
+            return Ok(None);
+        };
+        let received = match self.deadline {
+            Some(deadline) => self
+                .receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => self
+                .receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(Ok(line)) => Ok(Some(Value::String(line))),
+            Ok(Err(e)) => Err(failure(
+                "io",
+                format!(
+                    "process.lines() cannot read the output of '{}': {}",
+                    self.invocation.program, e
+                ),
+            )),
+            Err(RecvTimeoutError::Disconnected) => {
+                let status = child.wait();
+                self.child = None;
+                let status = status.map_err(|e| {
+                    failure(
+                        "io",
+                        format!(
+                            "process.lines() lost track of '{}': {}",
+                            self.invocation.program, e
+                        ),
+                    )
+                })?;
+                self.invocation.check(status)?;
+                Ok(None)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.invocation.kill(child);
+                self.child = None;
+                Err(self.invocation.timed_out())
+            }
+        }
+    }
+}
+
+impl Drop for Lines {
+    /// A loop that stops early leaves nobody to read the rest of the output
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            self.invocation.kill(child);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! `import re`: regular expressions, in the syntax of Rust's regex crate.
+//! Every function takes a pattern string or a regex from `re.compile`;
+//! pattern strings are compiled once and cached. A match is a dict with the
+//! matched `text`, its `start` and `end` offsets, the capture `groups` in
+//! order and the `named` groups.
+
+use super::{arg_range, integer_arg, string_arg};
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime, ops};
+use crate::utils::CrabbyError;
+use regex::{Captures, Regex};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("compile", Some(1), compile),
+    ("match", Some(2), match_start),
+    ("search", Some(2), search),
+    ("find_all", Some(2), find_all),
+    ("replace", None, replace),
+    ("split", Some(2), split),
+];
+
+/// How many compiled patterns to keep; past this the cache starts over
+const CACHE_SIZE: usize = 256;
+
+thread_local! {
+    /// Patterns compiled from strings, so a loop calling `re.search(pattern,
+    /// line)` compiles `pattern` once
+    static CACHE: RefCell<HashMap<String, Rc<Regex>>> = RefCell::new(HashMap::new());
+}
+
+/// A regex argument, either compiled already or a pattern string to compile
+fn regex_arg(function: &str, value: &Value) -> Result<Rc<Regex>, CrabbyError> {
+    match value {
+        Value::Regex(regex) => Ok(Rc::clone(regex)),
+        Value::String(pattern) => cached(function, pattern),
+        other => Err(CrabbyError::RuntimeError(format!(
+            "re.{}() expects a pattern string or regex, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+fn cached(function: &str, pattern: &str) -> Result<Rc<Regex>, CrabbyError> {
+    if let Some(regex) = CACHE.with_borrow(|cache| cache.get(pattern).cloned()) {
+        return Ok(regex);
+    }
+    let regex = Rc::new(Regex::new(pattern).map_err(|e| {
+        CrabbyError::RuntimeError(format!("re.{}() got an invalid pattern: {}", function, e))
+    })?);
+    CACHE.with_borrow_mut(|cache| {
+        if cache.len() >= CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), Rc::clone(&regex));
+    });
+    Ok(regex)
+}
+
+/// Character offset of byte offset `at`, since Crabby indexes strings by
+/// character
+fn char_offset(text: &str, at: usize) -> i64 {
+    text[..at].chars().count() as i64
+}
+
+/// A match as a dict: the matched `text`, its `start` and `end` character
+/// offsets, the numbered capture `groups` (none for a group that did not
+/// take part) and the `named` groups
+fn match_dict(regex: &Regex, text: &str, captures: &Captures) -> Result<Value, CrabbyError> {
+    let whole = captures.get_match();
+    let group =
+        |m: Option<regex::Match>| m.map_or(Value::None, |m| Value::String(m.as_str().to_string()));
+    let groups = captures.iter().skip(1).map(group).collect();
+    let named = regex
+        .capture_names()
+        .flatten()
+        .map(|name| (Value::String(name.to_string()), group(captures.name(name))));
+
+    ops::make_dict([
+        (
+            Value::String("text".to_string()),
+            Value::String(whole.as_str().to_string()),
+        ),
+        (
+            Value::String("start".to_string()),
+            Value::Integer(char_offset(text, whole.start())),
+        ),
+        (
+            Value::String("end".to_string()),
+            Value::Integer(char_offset(text, whole.end())),
+        ),
+        (
+            Value::String("groups".to_string()),
+            Value::List(Rc::new(groups)),
+        ),
+        (Value::String("named".to_string()), ops::make_dict(named)?),
+    ])
+}
+
+fn compile(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    regex_arg("compile", &args[0]).map(Value::Regex)
+}
+
+/// The match starting at the beginning of the text, or none
+fn match_start(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let regex = regex_arg("match", &args[0])?;
+    let text = string_arg("re.match", &args[1])?;
+    // Matches are leftmost, so one starting at 0 is found if there is any
+    match regex.captures(text) {
+        Some(captures) if captures.get_match().start() == 0 => match_dict(&regex, text, &captures),
+        _ => Ok(Value::None),
+    }
+}
+
+/// The first match anywhere in the text, or none
+fn search(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let regex = regex_arg("search", &args[0])?;
+    let text = string_arg("re.search", &args[1])?;
+    match regex.captures(text) {
+        Some(captures) => match_dict(&regex, text, &captures),
+        None => Ok(Value::None),
+    }
+}
+
+/// The text of every non-overlapping match
+fn find_all(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let regex = regex_arg("find_all", &args[0])?;
+    let text = string_arg("re.find_all", &args[1])?;
+    let matches = regex
+        .find_iter(text)
+        .map(|m| Value::String(m.as_str().to_string()))
+        .collect();
+    Ok(Value::List(Rc::new(matches)))
+}
+
+/// `replace(pattern, text, replacement, count = 0)`, where the replacement
+/// refers to groups as `$1` or `${name}` and a count of 0 replaces every match
+fn replace(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("re.replace", &args, 3, 4)?;
+    let regex = regex_arg("replace", &args[0])?;
+    let text = string_arg("re.replace", &args[1])?;
+    let replacement = string_arg("re.replace", &args[2])?;
+    let count = match args.get(3) {
+        Some(count) => integer_arg("re.replace", count)?,
+        None => 0,
+    };
+    let count = usize::try_from(count).map_err(|_| {
+        CrabbyError::RuntimeError(format!("re.replace() cannot replace {} matches", count))
+    })?;
+    Ok(Value::String(
+        regex.replacen(text, count, replacement).into_owned(),
+    ))
+}
+
+/// The pieces of the text between matches
+fn split(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let regex = regex_arg("split", &args[0])?;
+    let text = string_arg("re.split", &args[1])?;
+    let pieces = regex
+        .split(text)
+        .map(|piece| Value::String(piece.to_string()))
+        .collect();
+    Ok(Value::List(Rc::new(pieces)))
+}
@@ -0,0 +1,80 @@
+//! `import sys`: the script's arguments, environment, exit status and stdin
+
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use super::{arg_range, integer_arg, string_arg};
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime};
+use crate::utils::CrabbyError;
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("args", Some(0), args),
+    ("env", Some(1), env),
+    ("exit", None, exit),
+    ("input", None, input),
+    ("read_lines", Some(0), read_lines),
+];
+
+/// The arguments given after the script, as a list of strings
+fn args(runtime: &mut Runtime, _: Vec<Value>) -> Result<Value, CrabbyError> {
+    let args = runtime.args().iter().cloned().map(Value::String).collect();
+    Ok(Value::List(Rc::new(args)))
+}
+
+/// An environment variable, or none when it is unset
+fn env(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let name = string_arg("sys.env", &args[0])?;
+    Ok(std::env::var(name).map_or(Value::None, Value::String))
+}
+
+/// Ends the program with a status from 0 to 255, 0 when none is given.
+/// Anything wider would be cut down to its low byte by the shell.
+fn exit(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("sys.exit", &args, 0, 1)?;
+    let code = match args.first() {
+        Some(code) => integer_arg("sys.exit", code)?,
+        None => 0,
+    };
+    let code = u8::try_from(code).map_err(|_| {
+        CrabbyError::RuntimeError(format!("Exit status {} is out of range 0 to 255", code))
+    })?;
+    Err(CrabbyError::Exit(code as i32))
+}
+
+fn input(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("sys.input", &args, 0, 1)?;
+    read_line(args.first())
+}
+
+/// Reads a line from stdin after printing an optional prompt. Returns none
+/// at the end of input.
+pub fn read_line(prompt: Option<&Value>) -> Result<Value, CrabbyError> {
+    if let Some(prompt) = prompt {
+        print!("{}", prompt);
+        io::stdout().flush().map_err(io_error)?;
+    }
+
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line).map_err(io_error)? == 0 {
+        return Ok(Value::None);
+    }
+    let end = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(end);
+    Ok(Value::String(line))
+}
+
+/// The rest of stdin, as a list of lines
+fn read_lines(_: &mut Runtime, _: Vec<Value>) -> Result<Value, CrabbyError> {
+    let lines = io::stdin()
+        .lock()
+        .lines()
+        .map(|line| line.map(Value::String))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    Ok(Value::List(Rc::new(lines)))
+}
+
+fn io_error(error: io::Error) -> CrabbyError {
+    CrabbyError::RuntimeError(format!("Input failed: {}", error))
+}
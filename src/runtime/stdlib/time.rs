@@ -0,0 +1,207 @@
+//! `_time`: the native half of `libs/time.crab`
+
+use super::{arg_range, integer_arg, string_arg};
+use crate::ir::BinaryOp;
+use crate::runtime::value::Value;
+use crate::runtime::{NativeFn, Runtime, ops};
+use crate::utils::CrabbyError;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Utc};
+use std::fmt::Write;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+pub const FUNCTIONS: &[(&str, Option<usize>, NativeFn)] = &[
+    ("now", Some(0), now),
+    ("utc", None, utc),
+    ("local", None, local),
+    ("date", None, date),
+    ("instant", Some(0), instant),
+    ("elapsed", Some(1), elapsed),
+    ("sleep", Some(1), sleep),
+    ("format", Some(2), format),
+    ("parse", Some(2), parse),
+    ("seconds", Some(1), seconds),
+    ("milliseconds", Some(1), milliseconds),
+    ("minutes", Some(1), minutes),
+    ("hours", Some(1), hours),
+    ("days", Some(1), days),
+];
+
+fn error(message: String) -> CrabbyError {
+    CrabbyError::RuntimeError(message)
+}
+
+fn number_arg(function: &str, value: &Value) -> Result<f64, CrabbyError> {
+    match value {
+        Value::Integer(n) => Ok(*n as f64),
+        Value::Float(f) => Ok(*f),
+        other => Err(error(format!(
+            "time.{}() expects a number, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+fn datetime_arg(function: &str, value: &Value) -> Result<DateTime<FixedOffset>, CrabbyError> {
+    match value {
+        Value::DateTime(datetime) => Ok(*datetime),
+        other => Err(error(format!(
+            "time.{}() expects a datetime, got {}",
+            function,
+            other.type_name()
+        ))),
+    }
+}
+
+/// The optional Unix timestamp argument of `utc` and `local`, defaulting to now
+fn timestamp_arg(function: &str, args: &[Value]) -> Result<DateTime<Utc>, CrabbyError> {
+    arg_range(&format!("time.{}", function), args, 0, 1)?;
+    let Some(timestamp) = args.first() else {
+        return Ok(Utc::now());
+    };
+    let seconds = number_arg(function, timestamp)?;
+    ops::duration_from_seconds(seconds)
+        .and_then(|since_epoch| DateTime::UNIX_EPOCH.checked_add_signed(since_epoch))
+        .ok_or_else(|| {
+            error(format!(
+                "time.{}() cannot represent timestamp {}",
+                function, timestamp
+            ))
+        })
+}
+
+/// Seconds since the Unix epoch, with a fractional part
+fn now(_: &mut Runtime, _: Vec<Value>) -> Result<Value, CrabbyError> {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| error("The system clock is set before 1970".to_string()))?;
+    Ok(Value::Float(since_epoch.as_secs_f64()))
+}
+
+fn utc(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let datetime = timestamp_arg("utc", &args)?;
+    Ok(Value::DateTime(datetime.fixed_offset()))
+}
+
+fn local(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let datetime = timestamp_arg("local", &args)?;
+    Ok(Value::DateTime(
+        datetime.with_timezone(&Local).fixed_offset(),
+    ))
+}
+
+/// `date(year, month, day, hour = 0, minute = 0, second = 0)` in UTC
+fn date(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    arg_range("time.date", &args, 3, 6)?;
+    let mut parts = [0; 6];
+    for (part, arg) in parts.iter_mut().zip(&args) {
+        *part = integer_arg("time.date", arg)?;
+    }
+    let [year, month, day, hour, minute, second] = parts;
+    let field = |n: i64| u32::try_from(n).ok();
+    let datetime = i32::try_from(year)
+        .ok()
+        .zip(field(month).zip(field(day)))
+        .and_then(|(year, (month, day))| NaiveDate::from_ymd_opt(year, month, day))
+        .zip(field(hour).zip(field(minute)).zip(field(second)))
+        .and_then(|(date, ((hour, minute), second))| date.and_hms_opt(hour, minute, second));
+    match datetime {
+        Some(datetime) => Ok(Value::DateTime(datetime.and_utc().fixed_offset())),
+        None => Err(error(format!(
+            "time.date() got an invalid date {}-{}-{} {}:{}:{}",
+            year, month, day, hour, minute, second
+        ))),
+    }
+}
+
+fn instant(_: &mut Runtime, _: Vec<Value>) -> Result<Value, CrabbyError> {
+    Ok(Value::Instant(Instant::now()))
+}
+
+/// The time that has passed since an `instant()`, as a duration
+fn elapsed(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    match &args[0] {
+        Value::Instant(start) => ops::binary(
+            BinaryOp::Sub,
+            Value::Instant(Instant::now()),
+            Value::Instant(*start),
+        ),
+        other => Err(error(format!(
+            "time.elapsed() expects an instant, got {}",
+            other.type_name()
+        ))),
+    }
+}
+
+/// Pauses for a number of milliseconds or for a duration
+fn sleep(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let time = match &args[0] {
+        Value::Duration(duration) => *duration,
+        other => ops::duration_from_seconds(number_arg("sleep", other)? / 1000.0)
+            .ok_or_else(|| error(format!("time.sleep() cannot sleep for {} ms", other)))?,
+    };
+    let time = time
+        .to_std()
+        .map_err(|_| error("time.sleep() cannot sleep for a negative time".to_string()))?;
+    std::thread::sleep(time);
+    Ok(Value::None)
+}
+
+/// Renders a datetime with strftime-style specifiers such as `%Y-%m-%d`
+fn format(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let datetime = datetime_arg("format", &args[0])?;
+    let pattern = string_arg("time.format", &args[1])?;
+    let mut text = String::new();
+    write!(text, "{}", datetime.format(pattern))
+        .map_err(|_| error(format!("time.format() cannot use the format {:?}", pattern)))?;
+    Ok(Value::String(text))
+}
+
+/// Reads a datetime written in the given format. A format without an offset
+/// (`%z`) reads the time as UTC, and one without a time reads midnight.
+fn parse(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    let text = string_arg("time.parse", &args[0])?;
+    let pattern = string_arg("time.parse", &args[1])?;
+    let datetime = DateTime::parse_from_str(text, pattern).or_else(|with_offset| {
+        NaiveDateTime::parse_from_str(text, pattern)
+            .or_else(|_| {
+                NaiveDate::parse_from_str(text, pattern)
+                    .map(|date| date.and_time(Default::default()))
+            })
+            .map(|naive| naive.and_utc().fixed_offset())
+            .map_err(|_| with_offset)
+    });
+    datetime.map(Value::DateTime).map_err(|reason| {
+        error(format!(
+            "time.parse() cannot read {:?} as {:?}: {}",
+            text, pattern, reason
+        ))
+    })
+}
+
+fn duration(function: &str, value: &Value, unit: f64) -> Result<Value, CrabbyError> {
+    let amount = number_arg(function, value)?;
+    ops::duration_from_seconds(amount * unit)
+        .map(Value::Duration)
+        .ok_or_else(|| error(format!("time.{}() cannot represent {}", function, value)))
+}
+
+fn seconds(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    duration("seconds", &args[0], 1.0)
+}
+
+fn milliseconds(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    duration("milliseconds", &args[0], 0.001)
+}
+
+fn minutes(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    duration("minutes", &args[0], 60.0)
+}
+
+fn hours(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    duration("hours", &args[0], 3600.0)
+}
+
+fn days(_: &mut Runtime, args: Vec<Value>) -> Result<Value, CrabbyError> {
+    duration("days", &args[0], 86400.0)
+}
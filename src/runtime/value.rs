@@ -0,0 +1,322 @@
+use crate::bytecode::FunctionProto;
+use crate::ir::{FormatAlign, FormatSpec, FunctionDecl};
+use crate::runtime::env::Env;
+use crate::runtime::jit::JitSlot;
+use crate::runtime::Runtime;
+use crate::utils::CrabbyError;
+use chrono::{DateTime, FixedOffset, SecondsFormat, TimeDelta};
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+pub type NativeFn = fn(&mut Runtime, Vec<Value>) -> Result<Value, CrabbyError>;
+
+/// A builtin implemented in Rust
+pub struct NativeFunction {
+    pub name: String,
+    /// `None` accepts any number of arguments
+    pub arity: Option<usize>,
+    /// The keyword arguments it takes, which don't count towards its arity
+    pub keywords: &'static [&'static str],
+    pub function: NativeFn,
+}
+
+/// A user-defined function or lambda together with the environment it closes over
+pub struct Closure {
+    pub decl: Rc<FunctionDecl>,
+    pub env: Env,
+}
+
+/// A function compiled to bytecode, with the cells it captured from the
+/// functions around it and the module globals it was defined in
+pub struct CompiledClosure {
+    pub proto: Rc<FunctionProto>,
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+    pub globals: Env,
+    /// Globals this closure has looked up, by name constant, tagged with the
+    /// binding generation they were found in
+    pub(crate) global_cache: RefCell<Vec<Option<(u64, Value)>>>,
+    pub(crate) jit: JitSlot,
+}
+
+impl CompiledClosure {
+    pub fn new(proto: Rc<FunctionProto>, upvalues: Vec<Rc<RefCell<Value>>>, globals: Env) -> Self {
+        Self {
+            proto,
+            upvalues,
+            globals,
+            global_cache: RefCell::new(Vec::new()),
+            jit: JitSlot::default(),
+        }
+    }
+}
+
+/// The state behind a lazy sequence, which hands out its values one at a
+/// time as a `for` loop asks for them
+pub trait Producer {
+    fn next(&mut self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError>;
+}
+
+/// A lazy sequence of values. Iterating it consumes it, so a second loop over
+/// the same iterator carries on where the first stopped.
+pub struct IteratorValue {
+    state: RefCell<Box<dyn Producer>>,
+}
+
+impl IteratorValue {
+    pub fn new(producer: impl Producer + 'static) -> Self {
+        Self {
+            state: RefCell::new(Box::new(producer)),
+        }
+    }
+
+    /// The next value, or `None` once the sequence is used up
+    pub fn next(&self, runtime: &mut Runtime) -> Result<Option<Value>, CrabbyError> {
+        let mut state = self.state.try_borrow_mut().map_err(|_| {
+            CrabbyError::RuntimeError("An iterator cannot be advanced while it is producing a value".to_string())
+        })?;
+        state.next(runtime)
+    }
+}
+
+/// A module bound by `import`, holding the values it makes public
+pub struct ModuleValue {
+    pub name: String,
+    pub members: HashMap<String, Value>,
+}
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    None,
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Function(Rc<Closure>),
+    Compiled(Rc<CompiledClosure>),
+    Native(Rc<NativeFunction>),
+    List(Rc<Vec<Value>>),
+    /// String keys mapped to values, kept in insertion order
+    Dict(Rc<IndexMap<String, Value>>),
+    Bytes(Rc<Vec<u8>>),
+    Iterator(Rc<IteratorValue>),
+    Module(Rc<ModuleValue>),
+    /// A compiled regular expression from the `re` module
+    Regex(Rc<regex::Regex>),
+    /// A reading of the monotonic clock, only useful for measuring elapsed time
+    Instant(std::time::Instant),
+    DateTime(DateTime<FixedOffset>),
+    Duration(TimeDelta),
+    /// The `name = value` arguments of a call, which only exist between
+    /// evaluating the call's arguments and making the call. `Runtime::call`
+    /// hands them to a builtin through `take_keywords` and rejects them for
+    /// anything else, so no function ever receives, stores or returns one.
+    Keywords(Rc<IndexMap<String, Value>>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::None => "none",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Compiled(_) | Value::Native(_) => "function",
+            Value::List(_) => "list",
+            Value::Dict(_) => "dict",
+            Value::Bytes(_) => "bytes",
+            Value::Iterator(_) => "iterator",
+            Value::Module(_) => "module",
+            Value::Regex(_) => "regex",
+            Value::Instant(_) => "instant",
+            Value::DateTime(_) => "datetime",
+            Value::Duration(_) => "duration",
+            Value::Keywords(_) => "keywords",
+        }
+    }
+
+    /// Conditions treat `0` and `none` as false and everything else as true
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::None | Value::Integer(0))
+    }
+
+    /// The source-like rendering the REPL echoes, with strings quoted
+    pub fn repr(&self) -> String {
+        match self {
+            Value::String(s) => format!("{:?}", s),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::None => write!(f, "none"),
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(closure) if closure.decl.name.is_empty() => write!(f, "<lambda>"),
+            Value::Function(closure) => write!(f, "<function {}>", closure.decl.name),
+            Value::Compiled(closure) if closure.proto.name.is_empty() => write!(f, "<lambda>"),
+            Value::Compiled(closure) => write!(f, "<function {}>", closure.proto.name),
+            Value::Native(native) => write!(f, "<builtin {}>", native.name),
+            Value::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element.repr())?;
+                }
+                write!(f, "]")
+            }
+            Value::Dict(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {}", key, value.repr())?;
+                }
+                write!(f, "}}")
+            }
+            // Printable ASCII as itself, anything else escaped
+            Value::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for &byte in bytes.iter() {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+                        b' '..=b'~' => write!(f, "{}", byte as char)?,
+                        b'\n' => write!(f, "\\n")?,
+                        b'\t' => write!(f, "\\t")?,
+                        b'\r' => write!(f, "\\r")?,
+                        _ => write!(f, "\\x{:02x}", byte)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::Module(module) => write!(f, "<module {}>", module.name),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Regex(regex) => write!(f, "<regex {:?}>", regex.as_str()),
+            Value::Instant(_) => write!(f, "<instant>"),
+            Value::DateTime(datetime) => {
+                write!(f, "{}", datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            Value::Duration(duration) => write_duration(f, *duration),
+            Value::Keywords(_) => write!(f, "<keywords>"),
+        }
+    }
+}
+
+/// Writes a duration the way Go does, such as `1h30m`, `2m0.5s` or `-0.25s`
+fn write_duration(f: &mut fmt::Formatter<'_>, duration: TimeDelta) -> fmt::Result {
+    if duration < TimeDelta::zero() {
+        write!(f, "-")?;
+    }
+    let duration = duration.abs();
+    let seconds = duration.num_seconds();
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    if hours > 0 {
+        write!(f, "{}h", hours)?;
+    }
+    if hours > 0 || minutes > 0 {
+        write!(f, "{}m", minutes)?;
+        if seconds % 60 == 0 && duration.subsec_nanos() == 0 {
+            return Ok(());
+        }
+    }
+    write!(f, "{}", seconds % 60)?;
+    if duration.subsec_nanos() > 0 {
+        let fraction = format!("{:09}", duration.subsec_nanos());
+        write!(f, ".{}", fraction.trim_end_matches('0'))?;
+    }
+    write!(f, "s")
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.repr())
+    }
+}
+
+/// Renders a value according to an f-string format spec such as `.2` or `>5`
+pub fn format_value(value: &Value, spec: &FormatSpec) -> Result<String, CrabbyError> {
+    let invalid = |kind: char| {
+        CrabbyError::RuntimeError(format!(
+            "Format type '{}' is not valid for a value of type {}",
+            kind,
+            value.type_name()
+        ))
+    };
+
+    let (body, numeric) = match (value, spec.kind) {
+        (Value::Integer(n), None | Some('d')) => match spec.precision {
+            Some(p) => (format!("{:.*}", p, *n as f64), true),
+            None => (n.to_string(), true),
+        },
+        (Value::Integer(n), Some('x')) => (format!("{:x}", n), true),
+        (Value::Integer(n), Some('X')) => (format!("{:X}", n), true),
+        (Value::Integer(n), Some('o')) => (format!("{:o}", n), true),
+        (Value::Integer(n), Some('b')) => (format!("{:b}", n), true),
+        (Value::Float(x), None) => match spec.precision {
+            Some(p) => (format!("{:.*}", p, x), true),
+            None => (x.to_string(), true),
+        },
+        (Value::Integer(_) | Value::Float(_), Some(kind @ ('f' | 'e' | '%'))) => {
+            let x = match value {
+                Value::Integer(n) => *n as f64,
+                Value::Float(x) => *x,
+                _ => unreachable!(),
+            };
+            let precision = spec.precision.unwrap_or(6);
+            let body = match kind {
+                'f' => format!("{:.*}", precision, x),
+                'e' => format!("{:.*e}", precision, x),
+                _ => format!("{:.*}%", precision, x * 100.0),
+            };
+            (body, true)
+        }
+        (Value::Integer(_) | Value::Float(_), Some(kind)) => return Err(invalid(kind)),
+        (Value::String(s), None | Some('s')) => match spec.precision {
+            Some(p) => (s.chars().take(p).collect(), false),
+            None => (s.clone(), false),
+        },
+        (_, None | Some('s')) => (value.to_string(), false),
+        (_, Some(kind)) => return Err(invalid(kind)),
+    };
+
+    let body = if spec.sign && numeric && !body.starts_with('-') {
+        format!("+{}", body)
+    } else {
+        body
+    };
+
+    let Some(width) = spec.width else {
+        return Ok(body);
+    };
+    let len = body.chars().count();
+    if len >= width {
+        return Ok(body);
+    }
+    let padding = width - len;
+
+    if spec.zero_pad && spec.align.is_none() && numeric {
+        let sign_len = if body.starts_with(['+', '-']) { 1 } else { 0 };
+        let (sign, digits) = body.split_at(sign_len);
+        return Ok(format!("{}{}{}", sign, "0".repeat(padding), digits));
+    }
+
+    let fill = |n: usize| spec.fill.to_string().repeat(n);
+    let align = spec.align.unwrap_or(if numeric { FormatAlign::Right } else { FormatAlign::Left });
+    Ok(match align {
+        FormatAlign::Left => format!("{}{}", body, fill(padding)),
+        FormatAlign::Right => format!("{}{}", fill(padding), body),
+        FormatAlign::Center => {
+            let left = padding / 2;
+            format!("{}{}{}", fill(left), body, fill(padding - left))
+        }
+    })
+}
@@ -0,0 +1,634 @@
+use crate::bytecode::{Capture, Constant, FunctionProto, Op};
+use crate::ir::BinaryOp;
+use crate::runtime::env::{self, Env};
+use crate::runtime::ops;
+use crate::runtime::value::{CompiledClosure, Value, format_value};
+use crate::runtime::{Runtime, check_arity, reject_keywords};
+use crate::utils::CrabbyError;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Calls nested deeper than this fail instead of exhausting memory
+const MAX_FRAMES: usize = 100_000;
+
+/// One active call: the closure being run, its next instruction, and where its
+/// slots start on the value stack
+struct Frame {
+    closure: Rc<CompiledClosure>,
+    ip: usize,
+    base: usize,
+    /// The stack height the caller gets back, with the result on top. It is
+    /// below `base` when the callee was a value under the arguments.
+    top: usize,
+    /// Where its captured locals start on `cells`
+    cells: usize,
+}
+
+/// A stack machine running bytecode. Calls between compiled functions push a
+/// frame instead of recursing; anything else goes through `Runtime::call`.
+pub struct Vm<'rt> {
+    runtime: &'rt mut Runtime,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// The cells of every active frame, each frame's after its caller's
+    cells: Vec<Rc<RefCell<Value>>>,
+}
+
+impl<'rt> Vm<'rt> {
+    pub fn new(runtime: &'rt mut Runtime) -> Self {
+        Self {
+            runtime,
+            stack: Vec::with_capacity(256),
+            frames: Vec::new(),
+            cells: Vec::new(),
+        }
+    }
+
+    /// Runs the top level of a module with `globals` as its global scope
+    pub fn run(&mut self, proto: Rc<FunctionProto>, globals: Env) -> Result<Value, CrabbyError> {
+        let closure = Rc::new(CompiledClosure::new(proto, Vec::new(), globals));
+        self.call(closure, Vec::new())
+    }
+
+    pub fn call(
+        &mut self,
+        closure: Rc<CompiledClosure>,
+        args: Vec<Value>,
+    ) -> Result<Value, CrabbyError> {
+        reject_keywords(&closure.proto.name, &args)?;
+        check_arity(
+            &closure.proto.name,
+            closure.proto.arity as usize,
+            args.len(),
+        )?;
+        let base = self.stack.len();
+        self.stack.extend(args);
+        self.enter(closure, base, base);
+        self.execute()
+    }
+
+    /// Pushes the frame for a call whose arguments start at `base`
+    #[inline(always)]
+    fn enter(&mut self, closure: Rc<CompiledClosure>, base: usize, top: usize) {
+        let proto = &closure.proto;
+        let slots = base + proto.slots as usize;
+        if self.stack.len() != slots {
+            self.stack.resize(slots, Value::None);
+        }
+
+        let cells = self.cells.len();
+        for _ in 0..proto.cells {
+            self.cells.push(Rc::new(RefCell::new(Value::None)));
+        }
+        for &(slot, cell) in &proto.param_cells {
+            *self.cells[cells + cell as usize].borrow_mut() =
+                self.stack[base + slot as usize].clone();
+        }
+
+        // Built in place from plain words, as a frame built first and copied
+        // in stalls on the copy the same way a pushed value does (see
+        // `push_integer`)
+        self.frames.extend(std::iter::once_with(|| Frame {
+            closure,
+            ip: 0,
+            base,
+            top,
+            cells,
+        }));
+    }
+
+    /// Pops the innermost frame and its cells, returning the stack height its
+    /// caller gets back
+    #[inline(always)]
+    fn leave(&mut self) -> usize {
+        let Some(frame) = self.frames.pop() else {
+            return self.stack.len();
+        };
+        if self.cells.len() > frame.cells {
+            self.cells.truncate(frame.cells);
+        }
+        frame.top
+    }
+
+    /// Looks up a global, reusing the closure's cached value while no binding
+    /// has been defined since
+    #[inline(always)]
+    fn load_global(&self, closure: &CompiledClosure, name: u32) -> Result<Value, CrabbyError> {
+        let generation = env::generation();
+        if let Some(Some((cached_at, value))) = closure.global_cache.borrow().get(name as usize)
+            && *cached_at == generation
+        {
+            return Ok(match value {
+                // Most lookups are for a function to call, which needs only
+                // its count bumped rather than the general clone
+                Value::Compiled(closure) => Value::Compiled(Rc::clone(closure)),
+                value => value.clone(),
+            });
+        }
+
+        let value = self
+            .runtime
+            .lookup(&closure.globals, constant_str(&closure.proto, name))?;
+        let mut cache = closure.global_cache.borrow_mut();
+        if cache.len() <= name as usize {
+            cache.resize(closure.proto.constants.len(), None);
+        }
+        cache[name as usize] = Some((generation, value.clone()));
+        Ok(value)
+    }
+
+    /// Calls `callee` with the top `argc` values as arguments, cutting the
+    /// stack back to `top` when it returns, and has the calling frame resume
+    /// at `ip`. A compiled function gets a new frame on `frames` to run next;
+    /// anything else runs to completion and leaves its result on the stack.
+    #[inline(always)]
+    fn call_value(
+        &mut self,
+        callee: Value,
+        argc: usize,
+        top: usize,
+        ip: usize,
+    ) -> Result<(), CrabbyError> {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.ip = ip;
+        }
+        let args_start = self.stack.len() - argc;
+        match callee {
+            Value::Compiled(closure) => {
+                let proto = &closure.proto;
+                if proto.arity as usize != argc
+                    || matches!(self.stack[args_start..].last(), Some(Value::Keywords(_)))
+                {
+                    reject_keywords(&proto.name, &self.stack[args_start..])?;
+                    check_arity(&proto.name, proto.arity as usize, argc)?;
+                }
+                if self.runtime.jit.enabled()
+                    && let Some(result) = self.call_native(&closure, args_start)
+                {
+                    self.stack.truncate(top);
+                    push_integer(&mut self.stack, result);
+                    return Ok(());
+                }
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(CrabbyError::RuntimeError(
+                        "Maximum recursion depth exceeded".to_string(),
+                    ));
+                }
+                self.enter(closure, args_start, top);
+                Ok(())
+            }
+            other => {
+                let args = self.stack.split_off(args_start);
+                self.stack.truncate(top);
+                let result = self.runtime.call(&other, args)?;
+                self.stack.push(result);
+                Ok(())
+            }
+        }
+    }
+
+    /// Runs a hot closure's native code when it has some and the arguments
+    /// at `args_start` are all integers. `None` means run it on the VM.
+    fn call_native(&mut self, closure: &Rc<CompiledClosure>, args_start: usize) -> Option<i64> {
+        let function = match closure.jit.function() {
+            Some(function) => function,
+            None => {
+                if closure.jit.tick() {
+                    closure.jit.set_function(self.runtime.jit.function(&closure.proto));
+                }
+                return None;
+            }
+        };
+        let code = function.code()?;
+
+        let args = self.stack[args_start..]
+            .iter()
+            .map(|arg| match arg {
+                Value::Integer(n) => Some(*n),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        // Native recursion assumes the global name still means this closure
+        if let Some(name) = code.self_call {
+            match self.load_global(closure, name) {
+                Ok(Value::Compiled(current)) if Rc::ptr_eq(&current, closure) => {}
+                _ => return None,
+            }
+        }
+
+        function.call(code, &args)
+    }
+
+    /// Runs the innermost frame, and everything it calls, until it returns
+    fn execute(&mut self) -> Result<Value, CrabbyError> {
+        let depth = self.frames.len().saturating_sub(1);
+        loop {
+            // Each pass runs the innermost frame until it calls or returns. The
+            // frame stays on `frames`, with only its `ip` written back on a call.
+            let Some(frame) = self.frames.last() else {
+                return Ok(Value::None);
+            };
+            let proto = &*frame.closure.proto;
+            let mut ip = frame.ip;
+
+            loop {
+                let op = proto.code[ip];
+                ip += 1;
+
+                match op {
+                    Op::Constant(index) => {
+                        self.stack.push(constant_value(&proto.constants[index as usize]));
+                    }
+                    Op::PushNone => self.stack.push(Value::None),
+                    Op::Pop => discard(&mut self.stack),
+                    Op::LoadLocal(slot) => {
+                        let value = &self.stack[frame.base + slot as usize];
+                        if let Value::Integer(n) = *value {
+                            // `return n` hands the number straight to the caller
+                            if let Some(Op::Return) = proto.code.get(ip)
+                                && self.frames.len() > depth + 1
+                            {
+                                let top = self.leave();
+                                return_integer(&mut self.stack, top, n);
+                                break;
+                            }
+                            push_integer(&mut self.stack, n);
+                            continue;
+                        }
+                        let value = value.clone();
+                        self.stack.push(value);
+                    }
+                    Op::StoreLocal(slot) => {
+                        let slot = frame.base + slot as usize;
+                        if let Some(&Value::Integer(n)) = self.stack.last() {
+                            discard(&mut self.stack);
+                            set_integer(&mut self.stack[slot], n);
+                            continue;
+                        }
+                        let value = pop(&mut self.stack);
+                        self.stack[slot] = value;
+                    }
+                    Op::LoadCell(cell) => {
+                        let value = self.cells[frame.cells + cell as usize].borrow().clone();
+                        self.stack.push(value);
+                    }
+                    Op::StoreCell(cell) => {
+                        let value = pop(&mut self.stack);
+                        *self.cells[frame.cells + cell as usize].borrow_mut() = value;
+                    }
+                    Op::LoadUpvalue(index) => {
+                        let value = frame.closure.upvalues[index as usize].borrow().clone();
+                        self.stack.push(value);
+                    }
+                    Op::LoadGlobal(name) => {
+                        let value = self.load_global(&frame.closure, name)?;
+                        self.stack.push(value);
+                    }
+                    Op::StoreGlobal(name) => {
+                        let value = pop(&mut self.stack);
+                        frame
+                            .closure
+                            .globals
+                            .define(constant_str(proto, name), value);
+                    }
+                    Op::Unary(op) => {
+                        let value = pop(&mut self.stack);
+                        self.stack.push(ops::unary(op, value)?);
+                    }
+                    Op::Binary(op) => {
+                        if let [.., Value::Integer(l), Value::Integer(r)] = &mut self.stack[..]
+                            && let Some(value) = integer_binary(op, *l, *r)
+                        {
+                            // As is one like `return a + b`
+                            if let Some(Op::Return) = proto.code.get(ip)
+                                && self.frames.len() > depth + 1
+                            {
+                                let top = self.leave();
+                                return_integer(&mut self.stack, top, value);
+                                break;
+                            }
+                            *l = value;
+                            discard(&mut self.stack);
+                            continue;
+                        }
+
+                        let right = pop(&mut self.stack);
+                        let left = pop(&mut self.stack);
+                        self.stack.push(ops::binary(op, left, right)?);
+                    }
+                    Op::BinaryLocalConst { op, slot, constant } => {
+                        let left = &self.stack[frame.base + slot as usize];
+                        let right = &proto.constants[constant as usize];
+                        if let (Value::Integer(l), Constant::Integer(r)) = (left, right)
+                            && let Some(value) = integer_binary(op, *l, *r)
+                        {
+                            // A test like `n < 2` that only feeds a branch is
+                            // branched on here, without pushing it
+                            match proto.code.get(ip) {
+                                Some(&Op::JumpIfFalse(target)) => {
+                                    ip = if value == 0 { target as usize } else { ip + 1 };
+                                }
+                                // And an argument like `n - 1` goes straight
+                                // into the call it is for
+                                Some(&Op::CallGlobal { name, argc }) => {
+                                    push_integer(&mut self.stack, value);
+                                    let callee = self.load_global(&frame.closure, name)?;
+                                    let top = self.stack.len() - argc as usize;
+                                    self.call_value(callee, argc as usize, top, ip + 1)?;
+                                    break;
+                                }
+                                _ => push_integer(&mut self.stack, value),
+                            }
+                            continue;
+                        }
+
+                        let value = ops::binary(op, left.clone(), constant_value(right))?;
+                        self.stack.push(value);
+                    }
+                    Op::Truthy => {
+                        let value = pop(&mut self.stack);
+                        push_integer(&mut self.stack, value.is_truthy() as i64);
+                    }
+                    Op::Jump(target) => {
+                        // Loop back-edges count towards compiling the function
+                        if (target as usize) < ip
+                            && self.runtime.jit.enabled()
+                            && frame.closure.jit.tick()
+                        {
+                            let function = self.runtime.jit.function(&frame.closure.proto);
+                            frame.closure.jit.set_function(function);
+                        }
+                        ip = target as usize;
+                    }
+                    Op::JumpIfFalse(target) => {
+                        if !pop_truthy(&mut self.stack) {
+                            ip = target as usize;
+                        }
+                    }
+                    Op::JumpIfTrue(target) => {
+                        if pop_truthy(&mut self.stack) {
+                            ip = target as usize;
+                        }
+                    }
+                    Op::JumpIfNotNone(target) => {
+                        if !matches!(self.stack.last(), Some(Value::None)) {
+                            ip = target as usize;
+                        }
+                    }
+                    Op::ExpectInteger(message) => {
+                        if !matches!(self.stack.last(), Some(Value::Integer(_))) {
+                            let message = constant_str(proto, message);
+                            return Err(CrabbyError::RuntimeError(message.to_string()));
+                        }
+                    }
+                    Op::ForIter { counter, exit } => {
+                        let slot = frame.base + counter as usize;
+                        match (&self.stack[slot], &self.stack[slot + 1]) {
+                            (Value::Integer(i), Value::Integer(limit)) if i < limit => {
+                                let i = *i;
+                                self.stack[slot] = Value::Integer(i + 1);
+                                self.stack.push(Value::Integer(i));
+                            }
+                            (Value::Integer(i), Value::List(elements))
+                                if (*i as usize) < elements.len() =>
+                            {
+                                let (i, element) = (*i, elements[*i as usize].clone());
+                                self.stack[slot] = Value::Integer(i + 1);
+                                self.stack.push(element);
+                            }
+                            (Value::Integer(i), Value::Dict(entries))
+                                if (*i as usize) < entries.len() =>
+                            {
+                                let i = *i;
+                                let key = entries.get_index(i as usize).map(|(key, _)| key.clone());
+                                self.stack[slot] = Value::Integer(i + 1);
+                                self.stack.push(Value::String(key.unwrap_or_default()));
+                            }
+                            (_, Value::Iterator(iterator)) => {
+                                let iterator = Rc::clone(iterator);
+                                match iterator.next(self.runtime)? {
+                                    Some(element) => self.stack.push(element),
+                                    None => ip = exit as usize,
+                                }
+                            }
+                            (Value::Integer(i), Value::Bytes(bytes))
+                                if (*i as usize) < bytes.len() =>
+                            {
+                                let (i, byte) = (*i, bytes[*i as usize]);
+                                self.stack[slot] = Value::Integer(i + 1);
+                                self.stack.push(Value::Integer(byte as i64));
+                            }
+                            (_, Value::Integer(_) | Value::List(_) | Value::Dict(_) | Value::Bytes(_)) => {
+                                ip = exit as usize
+                            }
+                            (_, other) => return Err(ops::not_iterable(other)),
+                        }
+                    }
+                    Op::Call(argc) => {
+                        // The callee's slot is reused for the result, so the
+                        // arguments need not move down over it
+                        let top = self.stack.len() - argc as usize - 1;
+                        let callee = std::mem::take(&mut self.stack[top]);
+                        self.call_value(callee, argc as usize, top, ip)?;
+                        break;
+                    }
+                    Op::CallGlobal { name, argc } => {
+                        let callee = self.load_global(&frame.closure, name)?;
+                        let top = self.stack.len() - argc as usize;
+                        self.call_value(callee, argc as usize, top, ip)?;
+                        break;
+                    }
+                    Op::Return => {
+                        let top = self.leave();
+                        if self.frames.len() == depth {
+                            let result = pop(&mut self.stack);
+                            truncate(&mut self.stack, top);
+                            return Ok(result);
+                        }
+
+                        if let Some(&Value::Integer(n)) = self.stack.last() {
+                            return_integer(&mut self.stack, top, n);
+                        } else {
+                            let result = pop(&mut self.stack);
+                            truncate(&mut self.stack, top);
+                            self.stack.push(result);
+                        }
+                        break;
+                    }
+                    Op::Closure(index) => {
+                        let Constant::Function(proto) = &proto.constants[index as usize] else {
+                            return Err(CrabbyError::RuntimeError(
+                                "Malformed bytecode".to_string(),
+                            ));
+                        };
+                        let upvalues = proto
+                            .captures
+                            .iter()
+                            .map(|capture| match *capture {
+                                Capture::Cell(cell) => {
+                                    Rc::clone(&self.cells[frame.cells + cell as usize])
+                                }
+                                Capture::Upvalue(index) => {
+                                    Rc::clone(&frame.closure.upvalues[index as usize])
+                                }
+                            })
+                            .collect();
+                        let closure = CompiledClosure::new(
+                            Rc::clone(proto),
+                            upvalues,
+                            frame.closure.globals.clone(),
+                        );
+                        self.stack.push(Value::Compiled(Rc::new(closure)));
+                    }
+                    Op::Import { name, source } => {
+                        let source = (source != u32::MAX).then(|| constant_str(proto, source));
+                        let value = self.runtime.import(constant_str(proto, name), source)?;
+                        self.stack.push(value);
+                    }
+                    Op::Format(spec) => {
+                        let value = pop(&mut self.stack);
+                        let text = match spec {
+                            Some(spec) => format_value(&value, &proto.specs[spec as usize])?,
+                            None => value.to_string(),
+                        };
+                        self.stack.push(Value::String(text));
+                    }
+                    Op::MakeList(count) => {
+                        let elements = self.stack.split_off(self.stack.len() - count as usize);
+                        self.stack.push(Value::List(Rc::new(elements)));
+                    }
+                    Op::MakeDict(count) => {
+                        let flat = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                        let mut flat = flat.into_iter();
+                        let pairs = std::iter::from_fn(|| Some((flat.next()?, flat.next()?)));
+                        self.stack.push(ops::make_dict(pairs)?);
+                    }
+                    Op::MakeKeywords(count) => {
+                        let flat = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                        let mut flat = flat.into_iter();
+                        let arguments = std::iter::from_fn(|| match (flat.next()?, flat.next()?) {
+                            (Value::String(name), value) => Some((name, value)),
+                            _ => None,
+                        });
+                        self.stack.push(Value::Keywords(Rc::new(arguments.collect())));
+                    }
+                    Op::Index => {
+                        let index = pop(&mut self.stack);
+                        let object = pop(&mut self.stack);
+                        self.stack.push(ops::index(object, index)?);
+                    }
+                    Op::Member(name) => {
+                        let object = pop(&mut self.stack);
+                        self.stack.push(ops::member(object, constant_str(proto, name))?);
+                    }
+                    Op::Concat(count) => {
+                        let parts = self.stack.split_off(self.stack.len() - count as usize);
+                        let mut result = String::new();
+                        for part in parts {
+                            match part {
+                                Value::String(s) => result.push_str(&s),
+                                other => result.push_str(&other.to_string()),
+                            }
+                        }
+                        self.stack.push(Value::String(result));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().unwrap_or_default()
+}
+
+/// Drops the top value. Integers, which most of what the VM drops is, have
+/// nothing to free and skip the general drop.
+#[inline(always)]
+fn discard(stack: &mut Vec<Value>) {
+    // The tag is checked in place first, as moving the value out to look at
+    // it stalls the same way a push does (see `push_integer`)
+    if let Some(Value::Integer(_)) = stack.last() {
+        if let Some(value) = stack.pop() {
+            std::mem::forget(value);
+        }
+    } else {
+        stack.pop();
+    }
+}
+
+/// Cuts the stack back to `len`, a value at a time for `discard`
+#[inline(always)]
+fn truncate(stack: &mut Vec<Value>, len: usize) {
+    while stack.len() > len {
+        discard(stack);
+    }
+}
+
+/// Pops a condition, reading it in place rather than moving it out first
+fn pop_truthy(stack: &mut Vec<Value>) -> bool {
+    let truthy = stack.last().is_some_and(Value::is_truthy);
+    discard(stack);
+    truthy
+}
+
+/// Cuts a returning frame's values off the stack, leaving its integer result
+/// where the caller expects it. The number is written over the callee's
+/// first slot, saving a push.
+#[inline(always)]
+fn return_integer(stack: &mut Vec<Value>, top: usize, n: i64) {
+    truncate(stack, top + 1);
+    set_integer(&mut stack[top], n);
+}
+
+/// Stores an integer, writing just the number over one that is there already
+#[inline(always)]
+fn set_integer(slot: &mut Value, n: i64) {
+    match slot {
+        Value::Integer(old) => *old = n,
+        slot => *slot = Value::Integer(n),
+    }
+}
+
+/// Pushes an integer by writing it straight into the new slot, which the
+/// hottest instructions need: building the value first and copying it in
+/// costs a store-forwarding stall on every push
+#[inline(always)]
+fn push_integer(stack: &mut Vec<Value>, n: i64) {
+    stack.resize_with(stack.len() + 1, || Value::Integer(n));
+}
+
+/// The integer operations that cannot fail, bar overflow, so the VM can skip
+/// the general dispatch in `ops::binary`
+fn integer_binary(op: BinaryOp, l: i64, r: i64) -> Option<i64> {
+    let value = match op {
+        BinaryOp::Add => l.checked_add(r)?,
+        BinaryOp::Sub => l.checked_sub(r)?,
+        BinaryOp::Mul => l.checked_mul(r)?,
+        BinaryOp::Lt => (l < r) as i64,
+        BinaryOp::Gt => (l > r) as i64,
+        BinaryOp::Le => (l <= r) as i64,
+        BinaryOp::Ge => (l >= r) as i64,
+        BinaryOp::Eq => (l == r) as i64,
+        BinaryOp::Ne => (l != r) as i64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn constant_value(constant: &Constant) -> Value {
+    match constant {
+        Constant::Integer(n) => Value::Integer(*n),
+        Constant::Float(f) => Value::Float(*f),
+        Constant::String(s) => Value::String(s.to_string()),
+        Constant::Function(_) => Value::None,
+    }
+}
+
+fn constant_str(proto: &FunctionProto, index: u32) -> &str {
+    match &proto.constants[index as usize] {
+        Constant::String(s) => s,
+        _ => "",
+    }
+}
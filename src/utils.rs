@@ -17,6 +17,13 @@ impl Span {
             column,
         }
     }
+
+    /// The 1-based column of the span start in UTF-16 code units, the unit the
+    /// Language Server Protocol uses by default
+    pub fn utf16_column(&self, source: &str) -> usize {
+        let line_start = source[..self.start].rfind('\n').map_or(0, |i| i + 1);
+        source[line_start..self.start].encode_utf16().count() + 1
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -37,6 +44,21 @@ pub enum CrabbyError {
 
     #[error("Compilation error: {0}")]
     CompileError(String),
+
+    #[error("Runtime error: {0}")]
+    RuntimeError(String),
+
+    /// A runtime error with a kind, like "not_found", that a script which
+    /// catches it with `try` can check without matching the message
+    #[error("Runtime error: {message}")]
+    Failure { kind: &'static str, message: String },
+
+    #[error("Bytecode error: {0}")]
+    BytecodeError(String),
+
+    /// Raised by `sys.exit` to end the program with a status
+    #[error("Program exited with status {0}")]
+    Exit(i32),
 }
 
 impl fmt::Display for Span {
@@ -48,3 +70,11 @@ impl fmt::Display for Span {
         )
     }
 }
+
+/// The 64-bit FNV-1a hash, which `.crabc` files use to recognise unchanged
+/// sources and the `hash` module offers to scripts
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
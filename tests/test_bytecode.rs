@@ -0,0 +1,215 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crabby::bytecode::{self, Capture, CompiledModule, Constant, FunctionProto, Op, SourceStamp};
+use crabby::compile::compile_source;
+use crabby::runtime::Runtime;
+use crabby::utils::CrabbyError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+const PROGRAM: &str = r#"
+def make_adder(k): {
+    return lambda(x): { x + k }
+}
+let add = make_adder(3)
+let name = "crab"
+print(f"{add(4):>3}|{1.5 * 2}|{name}")
+"#;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabby_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    dir
+}
+
+fn crabby(args: &[&Path]) -> String {
+    crabby_caching_in(args, &temp_dir("cache"))
+}
+
+fn crabby_caching_in(args: &[&Path], cache_dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(args)
+        .env("CRABBY_CACHE_DIR", cache_dir)
+        .output()
+        .expect("run crabby");
+    assert!(
+        output.status.success(),
+        "crabby failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn encoded_modules_decode_to_the_same_bytecode() {
+    let module = compile_source(PROGRAM, &Runtime::new().global_names()).unwrap();
+    let compiled = bytecode::compile_module(&module);
+    let stamp = SourceStamp { mtime: 42, hash: 7 };
+
+    let bytes = bytecode::encode(&compiled, stamp);
+    assert_eq!(bytecode::decode(&bytes).unwrap(), (stamp, compiled));
+
+    let mut corrupt = bytes.clone();
+    let last = corrupt.len() - 1;
+    corrupt[last] ^= 1;
+    let is_bytecode_error =
+        |bytes: &[u8]| matches!(bytecode::decode(bytes), Err(CrabbyError::BytecodeError(_)));
+    assert!(is_bytecode_error(&corrupt));
+    assert!(is_bytecode_error(&bytes[..bytes.len() - 3]));
+    assert!(is_bytecode_error(b"not bytecode at all, just some text"));
+
+    let mut future = bytes;
+    future[6] = 99;
+    assert!(is_bytecode_error(&future));
+}
+
+#[test]
+fn bytecode_that_would_misuse_the_stack_or_captures_is_rejected() {
+    let decode = |proto: FunctionProto| {
+        let module = CompiledModule {
+            proto: Rc::new(proto),
+            exports: Vec::new(),
+        };
+        bytecode::decode(&bytecode::encode(&module, SourceStamp { mtime: 0, hash: 0 }))
+    };
+    let is_bytecode_error = |proto: FunctionProto| {
+        matches!(decode(proto), Err(CrabbyError::BytecodeError(_)))
+    };
+
+    let underflows = [
+        vec![Op::MakeList(5), Op::Return],
+        vec![Op::PushNone, Op::Call(1), Op::Return],
+        vec![Op::Constant(0), Op::MakeDict(1), Op::Return],
+        vec![Op::Concat(2), Op::Return],
+        vec![Op::Pop, Op::PushNone, Op::Return],
+    ];
+    for code in underflows {
+        let proto = FunctionProto {
+            code: code.clone(),
+            constants: vec![Constant::Integer(1)],
+            ..FunctionProto::default()
+        };
+        assert!(is_bytecode_error(proto), "{:?} was accepted", code);
+    }
+
+    // Paths that meet with different stack depths, or run off the end
+    let merges = FunctionProto {
+        code: vec![Op::PushNone, Op::JumpIfFalse(3), Op::PushNone, Op::PushNone, Op::Return],
+        ..FunctionProto::default()
+    };
+    assert!(is_bytecode_error(merges));
+    let runs_off = FunctionProto {
+        code: vec![Op::PushNone, Op::JumpIfFalse(3), Op::Jump(0), Op::PushNone],
+        ..FunctionProto::default()
+    };
+    assert!(is_bytecode_error(runs_off));
+
+    // A loop counter at the last slot index must not overflow the check
+    let overflows = FunctionProto {
+        slots: u32::MAX,
+        code: vec![Op::ForIter { counter: u32::MAX, exit: 1 }, Op::PushNone, Op::Return],
+        ..FunctionProto::default()
+    };
+    assert!(is_bytecode_error(overflows));
+
+    // A closure capturing a cell its enclosing function does not have
+    let inner = FunctionProto {
+        captures: vec![Capture::Cell(3)],
+        code: vec![Op::LoadUpvalue(0), Op::Return],
+        ..FunctionProto::default()
+    };
+    let outer = FunctionProto {
+        cells: 1,
+        constants: vec![Constant::Function(Rc::new(inner))],
+        code: vec![Op::Closure(0), Op::Return],
+        ..FunctionProto::default()
+    };
+    assert!(is_bytecode_error(outer.clone()));
+    let mut fixed = outer;
+    let Constant::Function(inner) = &mut fixed.constants[0] else {
+        unreachable!()
+    };
+    Rc::make_mut(inner).captures = vec![Capture::Cell(0)];
+    assert!(decode(fixed).is_ok());
+}
+
+#[test]
+fn built_files_run_like_their_source() {
+    let dir = temp_dir("build");
+    let script = dir.join("main.crab");
+    fs::write(&script, PROGRAM).expect("write script");
+
+    let expected = crabby(&[&script]);
+    crabby(&[Path::new("build"), &script]);
+    let built = dir.join("main.crabc");
+    assert!(built.exists());
+
+    // The source is not needed once it is built
+    fs::remove_file(&script).expect("remove script");
+    assert_eq!(crabby(&[Path::new("run"), &built]), expected);
+    assert_eq!(crabby(&[&built]), expected);
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(expected, "  7|3|crab\n");
+}
+
+#[test]
+fn imports_reuse_cached_bytecode_until_the_source_changes() {
+    let dir = temp_dir("import_cache");
+    let cache_dir = dir.join("cache");
+    let lib = dir.join("lib.crab");
+    let script = dir.join("main.crab");
+    fs::write(&lib, "pub def value(): {\n    return 1\n}\n").expect("write module");
+    fs::write(
+        &script,
+        "import value from \"./lib.crab\"\nprint(value())\n",
+    )
+    .expect("write script");
+    let crabby = |args: &[&Path]| crabby_caching_in(args, &cache_dir);
+
+    assert_eq!(crabby(&[&script]), "1\n");
+    // The cache lives in its own directory, never next to the source
+    assert!(!dir.join("lib.crabc").exists());
+    let cache = fs::read_dir(&cache_dir).unwrap().next().unwrap().unwrap().path();
+    assert!(cache.file_name().unwrap().to_str().unwrap().starts_with("lib-"));
+    let (stamp, _) = bytecode::decode(&fs::read(&cache).expect("cache written")).unwrap();
+    assert_eq!(stamp, SourceStamp::new(&lib, &fs::read(&lib).unwrap()));
+
+    // A fresh cache is used as is, even though the source would now fail to parse
+    fs::write(&lib, "pub def value(: {").expect("break module");
+    let broken_stamp = bytecode::modified(&lib);
+    let module = compile_source("pub def value(): {\n    return 2\n}\n", &[]).unwrap();
+    let fake = SourceStamp {
+        mtime: broken_stamp,
+        hash: 0,
+    };
+    fs::write(
+        &cache,
+        bytecode::encode(&bytecode::compile_module(&module), fake),
+    )
+    .unwrap();
+    assert_eq!(crabby(&[&script]), "2\n");
+
+    // A changed source is recompiled and the cache refreshed
+    let edited = "pub def value(): {\n    return 3\n}\n";
+    fs::write(&lib, edited).expect("edit module");
+    assert_eq!(crabby(&[&script]), "3\n");
+    let (stamp, _) = bytecode::decode(&fs::read(&cache).unwrap()).unwrap();
+    assert_eq!(stamp.hash, SourceStamp::new(&lib, edited.as_bytes()).hash);
+
+    // An empty CRABBY_CACHE_DIR turns the cache off instead of falling back
+    fs::remove_dir_all(&cache_dir).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .arg(&script)
+        .env("CRABBY_CACHE_DIR", "")
+        .env("XDG_CACHE_HOME", &cache_dir)
+        .env("HOME", &cache_dir)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+    assert!(!cache_dir.exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
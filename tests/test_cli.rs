@@ -0,0 +1,238 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crabby::format::format_source;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn crabby(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(args)
+        .output()
+        .expect("run crabby")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+/// A fresh directory for one test's scripts
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabby_cli_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn exit_codes_tell_failures_apart() {
+    assert_eq!(crabby(&["-e", "print(1 + 2)"]).status.code(), Some(0));
+    assert_eq!(crabby(&["-e", "print(#)"]).status.code(), Some(3));
+    assert_eq!(crabby(&["-e", "print(1 +"]).status.code(), Some(4));
+    assert_eq!(crabby(&["-e", "print(missing)"]).status.code(), Some(5));
+    assert_eq!(crabby(&["-e", "print(1 / 0)"]).status.code(), Some(1));
+    assert_eq!(crabby(&["run"]).status.code(), Some(2));
+
+    let output = crabby(&["-e", "print(1 / 0)"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "Error: Runtime error: Division by zero\n"
+    );
+}
+
+#[test]
+fn scripts_see_their_arguments() {
+    let dir = temp_dir("args");
+    let script = dir.join("args.crab");
+    fs::write(&script, "import sys\nlet args = sys.args()\nprint(len(args))\nprint(args[0])\nprint(args[1])\n").unwrap();
+    let script = script.to_str().unwrap();
+
+    let output = crabby(&["run", script, "first", "--second"]);
+    assert_eq!(stdout(&output), "2\nfirst\n--second\n");
+    let output = crabby(&[script, "a", "b"]);
+    assert_eq!(stdout(&output), "2\na\nb\n");
+    assert_eq!(crabby(&[script, "only"]).status.code(), Some(1));
+}
+
+#[test]
+fn sys_module_reads_arguments_environment_and_stdin() {
+    let source = "import sys\nprint(sys.args())\nprint(sys.env(\"CRABBY_TEST_VAR\"))\n\
+                  print(sys.env(\"CRABBY_TEST_UNSET\"))\nlet name = sys.input(\"name? \")\n\
+                  print(f\"hi {name}\")\nprint(sys.read_lines())\nprint(sys.input())\n";
+    let script = temp_dir("sys").join("sys.crab");
+    fs::write(&script, source).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .arg(&script)
+        .args(["a", "b"])
+        .env("CRABBY_TEST_VAR", "set")
+        .env_remove("CRABBY_TEST_UNSET")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"crab\none\ntwo\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(
+        stdout(&output),
+        "[\"a\", \"b\"]\nset\nnone\nname? hi crab\n[\"one\", \"two\"]\nnone\n"
+    );
+}
+
+#[test]
+fn sys_exit_sets_the_exit_status() {
+    let output = crabby(&["-e", "import sys\nprint(1)\nsys.exit(42)\nprint(2)"]);
+    assert_eq!(output.status.code(), Some(42));
+    assert_eq!(stdout(&output), "1\n");
+    assert!(output.stderr.is_empty());
+
+    assert_eq!(crabby(&["-e", "import sys\nsys.exit()"]).status.code(), Some(0));
+    assert_eq!(crabby(&["--interpreter", "-e", "import sys\nsys.exit(3)"]).status.code(), Some(3));
+    assert_eq!(crabby(&["-e", "import sys\nsys.exit(\"no\")"]).status.code(), Some(1));
+
+    let output = crabby(&["-e", "import sys\nsys.exit(256)"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Exit status 256 is out of range 0 to 255")
+    );
+}
+
+#[test]
+fn scripts_run_through_a_shebang_or_from_stdin() {
+    let dir = temp_dir("shebang");
+    let script = dir.join("greet");
+    fs::write(
+        &script,
+        format!("#!{}\nimport sys\nprint(sys.args())\n", env!("CARGO_BIN_EXE_crabby")),
+    )
+    .unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let output = Command::new(&script).arg("hi").output().unwrap();
+        assert_eq!(stdout(&output), "[\"hi\"]\n");
+    }
+    assert_eq!(stdout(&crabby(&[script.to_str().unwrap(), "hi"])), "[\"hi\"]\n");
+
+    // Without a shebang an unknown extension needs --force
+    let plain = dir.join("plain.txt");
+    fs::write(&plain, "print(1)\n").unwrap();
+    let plain = plain.to_str().unwrap();
+    assert_eq!(crabby(&[plain]).status.code(), Some(1));
+    assert_eq!(stdout(&crabby(&["--force", plain])), "1\n");
+    assert_eq!(stdout(&crabby(&["run", "--force", plain])), "1\n");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(["-", "x"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"import sys\nprint(sys.args())\n").unwrap();
+    assert_eq!(stdout(&child.wait_with_output().unwrap()), "[\"x\"]\n");
+}
+
+#[test]
+fn check_does_not_run_the_script() {
+    let dir = temp_dir("check");
+    let good = dir.join("good.crab");
+    let bad = dir.join("bad.crab");
+    fs::write(&good, "print(\"ran\")\nprint(1 / 0)\n").unwrap();
+    fs::write(&bad, "def f(: {\n}\n").unwrap();
+
+    let output = crabby(&["check", good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(crabby(&["check", bad.to_str().unwrap()]).status.code(), Some(4));
+
+    // Every file is checked, and the first failure decides the exit status
+    let unknown = dir.join("unknown.crab");
+    fs::write(&unknown, "print(missing)\n").unwrap();
+    let output = crabby(&[
+        "check",
+        bad.to_str().unwrap(),
+        good.to_str().unwrap(),
+        unknown.to_str().unwrap(),
+    ]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr.contains("bad.crab: Parser error"), "{}", stderr);
+    assert!(stderr.contains("unknown.crab: Compilation error"), "{}", stderr);
+}
+
+#[test]
+fn test_runs_test_functions() {
+    let dir = temp_dir("test");
+    fs::write(
+        dir.join("test_math.crab"),
+        "def test_adds(): {\n    1 + 1\n}\ndef test_divides(): {\n    1 / 0\n}\ndef helper(x): {\n    x\n}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("helpers.crab"), "print(1 / 0)\n").unwrap();
+    fs::write(dir.join("broken_test.crab"), "def test_never(): {\n    1 +\n}\n").unwrap();
+    fs::write(dir.join("test_zlast.crab"), "def test_still_runs(): {\n    1\n}\n").unwrap();
+
+    let output = crabby(&["test", dir.to_str().unwrap()]);
+    let report = stdout(&output);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(report.contains("test_math.crab::test_adds ... ok"), "{}", report);
+    assert!(report.contains("test_math.crab::test_divides ... FAILED"), "{}", report);
+    assert!(report.contains("Division by zero"), "{}", report);
+    assert!(report.contains("broken_test.crab ... FAILED"), "{}", report);
+    assert!(report.contains("Parser error at line"), "{}", report);
+    assert!(report.contains("test_zlast.crab::test_still_runs ... ok"), "{}", report);
+    assert!(report.contains("test result: FAILED. 2 passed; 2 failed"), "{}", report);
+    assert!(!report.contains("helper"), "{}", report);
+}
+
+#[test]
+fn tokens_and_ast_dump_a_script() {
+    let dir = temp_dir("dump");
+    let script = dir.join("dump.crab");
+    fs::write(&script, "let x = 1\n").unwrap();
+    let script = script.to_str().unwrap();
+
+    assert_eq!(
+        stdout(&crabby(&["tokens", script])),
+        "1:1\tLet\tlet\n1:5\tIdentifier(\"x\")\tx\n1:7\tEquals\t=\n1:9\tInteger(1)\t1\n"
+    );
+    assert!(stdout(&crabby(&["ast", script])).starts_with("Let {"));
+}
+
+#[test]
+fn fmt_normalizes_spacing_and_indentation() {
+    let source = "#!/usr/bin/env crabby\n// header\n\n\n\ndef   add( a ,b ):{\n  return a+b   // sum\n}\n\
+                  def classify(n):\n  if n < 0 :\n      return -n\n  else:\n      return n*-1\n\
+                  let f = lambda (x) : { x+1 }\nlet y =\n1 +\n  2\nprint( add(1,2) )\n\
+                  let xs = [ 1,2 ,[3] ]\nprint(xs [0], xs[ -1 ])\n\
+                  let d = { \"a\" : {\"f\": lambda(v): {v} } }\nprint(re.match (\"a\", \"abc\"))\n\
+                  print(1,sep=\"\")\nlet ys = xs|>map(f)\n|> list";
+    let expected = "#!/usr/bin/env crabby\n// header\n\ndef add(a, b): {\n    return a + b // sum\n}\n\
+                    def classify(n):\n    if n < 0:\n        return -n\n    else:\n        return n * -1\n\
+                    let f = lambda(x): { x + 1 }\nlet y =\n    1 +\n    2\nprint(add(1, 2))\n\
+                    let xs = [1, 2, [3]]\nprint(xs[0], xs[-1])\n\
+                    let d = {\"a\": {\"f\": lambda(v): { v }}}\nprint(re.match(\"a\", \"abc\"))\n\
+                    print(1, sep = \"\")\nlet ys = xs |> map(f)\n    |> list\n";
+
+    let formatted = format_source(source).unwrap();
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn fmt_check_lists_unformatted_files() {
+    let dir = temp_dir("fmt");
+    let script = dir.join("messy.crab");
+    fs::write(&script, "print( 1+2 )\n").unwrap();
+    let script = script.to_str().unwrap();
+
+    let output = crabby(&["fmt", "--check", script]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), format!("{}\n", script));
+
+    assert_eq!(crabby(&["fmt", script]).status.code(), Some(0));
+    assert_eq!(fs::read_to_string(script).unwrap(), "print(1 + 2)\n");
+    assert_eq!(crabby(&["fmt", "--check", script]).status.code(), Some(0));
+}
@@ -0,0 +1,694 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn run_source(name: &str, source: &str) -> String {
+    run_source_with(name, source, &[])
+}
+
+fn run_source_with(name: &str, source: &str, flags: &[&str]) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!("crabby_{}_{}.crab", name, std::process::id()));
+    fs::write(&path, source).expect("write temp script");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(flags)
+        .arg(&path)
+        .output()
+        .expect("run crabby");
+    let _ = fs::remove_file(&path);
+
+    assert!(
+        output.status.success(),
+        "script failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn fstring_interpolation_and_format_specs() {
+    let output = run_source(
+        "fstring",
+        r#"
+let name = "Crab"
+let age = 41
+let pi = 3.14159
+print(f"hello {name}, you are {age + 1}")
+print(f"[{pi:.2}] [{age:>5}] [{name:*^8}] [{age:05}] [{age:x}] [{{braces}}]")
+"#,
+    );
+
+    assert_eq!(
+        output,
+        "hello Crab, you are 42\n[3.14] [   41] [**Crab**] [00041] [29] [{braces}]\n"
+    );
+}
+
+#[test]
+fn numeric_literals_and_unary_minus() {
+    let output = run_source(
+        "numbers",
+        r#"
+let x = 10
+print(x-1)
+print(-x * 2)
+print(0xff + 0o17 + 0b101)
+print(1_000_000)
+print(1.5e3)
+"#,
+    );
+
+    assert_eq!(output, "9\n-20\n275\n1000000\n1500\n");
+}
+
+#[test]
+fn indentation_and_brace_blocks_mix() {
+    let output = run_source(
+        "indent",
+        r#"
+def add(a, b):
+    let s = a + b
+    return s
+
+if add(1, 2):
+    print("indented")
+else:
+    print("nope")
+
+def braces(): {
+    loop 2:
+        print("mixed")
+}
+braces()
+"#,
+    );
+
+    assert_eq!(output, "indented\nmixed\nmixed\n");
+}
+
+#[test]
+fn repl_keeps_state_and_echoes_results() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start repl");
+
+    let input = "let x = 2\nx * 21\ndef sq(n): {\n    return n * n\n}\nsq(x)\n:reset\nx\n";
+    child
+        .stdin
+        .take()
+        .expect("repl stdin")
+        .write_all(input.as_bytes())
+        .expect("write to repl");
+    let output = child.wait_with_output().expect("wait for repl");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stdout.contains("42\n4\nSession reset\n"), "{}", stdout);
+    assert!(stderr.contains("Undefined variable: x"), "{}", stderr);
+}
+
+#[test]
+fn functions_see_globals_closures_and_themselves() {
+    let output = run_source(
+        "scoping",
+        r#"
+let base = 100
+def countdown(n): {
+    if n: {
+        print(n)
+        return countdown(n - 1)
+    }
+    return base
+}
+print(countdown(2))
+
+def adder(k): {
+    return lambda(x): { x + k }
+}
+let add5 = adder(5)
+print(add5(10))
+"#,
+    );
+
+    assert_eq!(output, "2\n1\n100\n15\n");
+}
+
+#[test]
+fn imports_resolve_relative_to_the_importing_file() {
+    let dir = std::env::temp_dir().join(format!("crabby_imports_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    fs::write(
+        dir.join("lib.crab"),
+        "pub def shout(s): {\n    return s + \"!\"\n}\ndef secret(): {\n    return 1\n}\n",
+    )
+    .expect("write module");
+    fs::write(dir.join("main.crab"), "import shout from \"./lib.crab\"\nprint(shout(\"hi\"))\n")
+        .expect("write script");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .arg(dir.join("main.crab"))
+        .output()
+        .expect("run crabby");
+    let _ = fs::remove_dir_all(&dir);
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hi!\n");
+}
+
+#[test]
+fn bare_imports_resolve_against_crabby_path_then_the_stdlib() {
+    let dir = std::env::temp_dir().join(format!("crabby_library_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    fs::write(
+        dir.join("greet.crab"),
+        "print(\"loading\")\npub let name = \"crab\"\npub def hello(): {\n    return \"hi \" + name\n}\ndef secret(): {\n    return 1\n}\n",
+    )
+    .expect("write module");
+    fs::write(
+        dir.join("main.crab"),
+        "import greet\nimport hello from greet\nimport math\nimport sys\n\
+         print(greet.hello())\nprint(hello())\nprint(greet.name)\nprint(math.pi)\nprint(sys)\n",
+    )
+    .expect("write script");
+
+    let run = |flags: &[&str], script: &str| {
+        Command::new(env!("CARGO_BIN_EXE_crabby"))
+            .args(flags)
+            .arg(dir.join(script))
+            .env("CRABBY_PATH", &dir)
+            .output()
+            .expect("run crabby")
+    };
+    for flags in [&[][..], &["--interpreter"]] {
+        let output = run(flags, "main.crab");
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "loading\nhi crab\nhi crab\ncrab\n3.141592653589793\n<module sys>\n"
+        );
+    }
+
+    fs::write(dir.join("bad.crab"), "import secret from greet\n").expect("write script");
+    fs::write(dir.join("missing.crab"), "import nowhere\n").expect("write script");
+    let bad = run(&[], "bad.crab");
+    let missing = run(&[], "missing.crab");
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(String::from_utf8_lossy(&bad.stderr).contains("Module 'greet' has no member 'secret'"));
+    assert!(String::from_utf8_lossy(&missing.stderr)
+        .contains("No module named 'nowhere' in the standard library or CRABBY_PATH"));
+}
+
+#[test]
+fn math_module_and_division_operators() {
+    let source = r#"
+import math
+import gcd from math
+print(10 / 4)
+print(-7 ~/ 2)
+print(-7 % 3)
+print(7.5 % 2)
+print(math.sqrt(16))
+print(math.pow(2, 10))
+print(math.pow(4, 0.5))
+print(math.floor(-2.5))
+print(math.ceil(2.1))
+print(math.round(2.5))
+print(math.round(math.pi, 3))
+print(math.sin(math.pi / 2))
+print(math.log(8, 2))
+print(math.exp(0))
+print(math.abs(-3))
+print(math.min(3, 1, 2))
+print(math.max([3, 9, 2]))
+print(gcd(-12, 18))
+print(math.lcm(4, 6))
+print(0.1 + 0.2 == 0.3, 1e-20 == 2e-20, 0.5 + 0.25 == 0.75)
+"#;
+    let expected = "2.5\n-4\n2\n1.5\n4\n1024\n2\n-3\n3\n3\n3.142\n1\n3\n1\n3\n1\n9\n6\n12\n0 0 1\n";
+    assert_eq!(run_source("math", source), expected);
+    assert_eq!(run_source_with("math", source, &["--interpreter"]), expected);
+
+    let errors = [
+        ("import math\nmath.sqrt(-1)", "math.sqrt() is undefined for -1"),
+        ("import math\nmath.floor(1e300)", "math.floor() result does not fit in an integer"),
+        ("import math\nmath.min()", "math.min() needs at least one value"),
+        ("import math\nmath.sqrt(\"x\")", "math.sqrt() expects a number, got string"),
+        ("print(1 % 0)", "Modulo by zero"),
+        ("print(1 ~/ 0)", "Division by zero"),
+    ];
+    for (source, message) in errors {
+        let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+            .args(["-e", source])
+            .output()
+            .expect("run crabby");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{:?}: {}", source, stderr);
+    }
+}
+
+#[test]
+fn front_end_rejects_unchecked_programs() {
+    use crabby::compile::compile_source;
+    use crabby::runtime::Runtime;
+    use crabby::utils::CrabbyError;
+
+    let globals = Runtime::new().global_names();
+    let message = |source: &str| match compile_source(source, &globals) {
+        Err(CrabbyError::CompileError(message)) => message,
+        other => panic!("expected a compile error, got {:?}", other.map(|_| ())),
+    };
+
+    assert_eq!(message("print(nope)"), "Undefined variable: nope");
+    assert_eq!(message("return 1"), "'return' outside of a function");
+    assert_eq!(message("def f(a, a): {\n    return a\n}"), "Duplicate parameter 'a' in function 'f'");
+    assert_eq!(
+        message("def f(x): {\n    return x\n}\ndef never(): {\n    f(x = 1)\n}"),
+        "f does not take keyword arguments"
+    );
+    assert_eq!(message("lambda(x): { x }(x = 1)"), "Lambda does not take keyword arguments");
+    assert!(compile_source("print(1, sep = \"\")", &globals).is_ok());
+
+    // Later definitions are visible inside function bodies
+    assert!(compile_source("def f(): {\n    return g()\n}\ndef g(): {\n    return 1\n}", &globals).is_ok());
+}
+
+#[test]
+fn bytecode_vm_matches_the_interpreter() {
+    let programs = [
+        (
+            "fib",
+            r#"
+def fib(n): {
+    if n < 2: {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+for i in range(12): {
+    print(fib(i))
+}
+"#,
+        ),
+        (
+            "closures",
+            r#"
+def counter(): {
+    let count = 0
+    def step(): {
+        let count = count + 1
+        return count
+    }
+    return lambda(): { step() * 10 }
+}
+let tick = counter()
+tick()
+print(tick())
+
+def outer(a): {
+    def middle(b): {
+        return lambda(c): { a + b + c }
+    }
+    return middle(2)
+}
+let add3 = outer(1)
+print(add3(3))
+
+def last(): {
+    let f = 0
+    for i in range(3): {
+        let f = lambda(): { i }
+    }
+    return f()
+}
+print(last())
+"#,
+        ),
+        (
+            "logic",
+            r#"
+let x = 5
+print(x > 3 && x <= 5)
+print(x == 4 || x != 5 || 0)
+print(1 < 2 == 1)
+print("a" < "b")
+def boom(): {
+    return 1 / 0
+}
+print(0 && boom())
+print(1 || boom())
+"#,
+        ),
+        (
+            "values",
+            r#"
+def pick(n): {
+    if n: {
+        f"yes {n:>3}"
+    } else {
+        "no"
+    }
+}
+print(pick(7))
+print(pick(0))
+let total = 0
+loop 4: {
+    let total = total + 2
+}
+let i = 0
+while i < 3: {
+    let i = i + 1
+}
+print(total * 10 + i)
+print(lambda(x): { x })
+print(pick)
+"#,
+        ),
+    ];
+
+    for (name, source) in programs {
+        let vm = run_source_with(name, source, &[]);
+        let interpreter = run_source_with(name, source, &["--interpreter"]);
+        assert_eq!(vm, interpreter, "engines disagree on {}", name);
+    }
+
+    assert_eq!(
+        run_source("fib_values", programs[0].1),
+        "0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\n89\n"
+    );
+    assert_eq!(run_source("closure_values", programs[1].1), "10\n6\n2\n");
+}
+
+#[test]
+fn deep_recursion_runs_and_runaway_recursion_fails_on_both_engines() {
+    let deep = r#"
+def depth(n): {
+    if n == 0: {
+        return 0
+    }
+    return depth(n - 1) + 1
+}
+print(depth(5000))
+"#;
+    assert_eq!(run_source("deep_vm", deep), "5000\n");
+    assert_eq!(run_source_with("deep_interpreter", deep, &["--interpreter"]), "5000\n");
+
+    let path = std::env::temp_dir().join(format!("crabby_runaway_{}.crab", std::process::id()));
+    fs::write(&path, "def forever(n): {\n    return forever(n + 1)\n}\nprint(forever(0))\n").unwrap();
+    for flags in [&[][..], &["--interpreter"], &["--no-jit"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+            .args(flags)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(1), "{:?}", flags);
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("Maximum recursion depth exceeded"),
+            "{:?}: {}",
+            flags,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn lists_index_and_iterate_on_both_engines() {
+    let source = r#"
+let xs = [1, 2, "three",]
+print(xs)
+print(xs[0] + xs[-2])
+print("crab"[1])
+for x in xs + [[4]]:
+    print(x)
+print([] == [] && xs != [1, 2])
+def pair(a, b): {
+    return [a, b]
+}
+print(pair(1, 2)[1])
+"#;
+    let expected = "[1, 2, \"three\"]\n3\nr\n1\n2\nthree\n[4]\n1\n2\n";
+    assert_eq!(run_source("lists", source), expected);
+    assert_eq!(run_source_with("lists", source, &["--interpreter"]), expected);
+
+    let errors = [
+        ("print([1][1])", "Index 1 is out of range for a list of length 1"),
+        ("print([1][\"a\"])", "Index must be an integer, not string"),
+        ("for x in \"ab\":\n    print(x)", "Cannot iterate over a value of type string"),
+        ("import sys\nprint(sys.nope)", "Module 'sys' has no member 'nope'"),
+    ];
+    for (source, message) in errors {
+        for flags in [&[][..], &["--interpreter"]] {
+            let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+                .args(flags)
+                .args(["-e", source])
+                .output()
+                .expect("run crabby");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(message), "{:?} with {:?}: {}", source, flags, stderr);
+        }
+    }
+}
+
+#[test]
+fn dicts_keep_insertion_order_on_both_engines() {
+    let source = r#"
+let crab = {"name": "crab", "legs": 10,}
+let nested = {
+    "shell": {"hard": 1},
+    "claws": [1, 2],
+}
+print(crab)
+print(crab["legs"] + nested["claws"][1])
+for key in nested:
+    print(key)
+print(crab == {"legs": 10, "name": "crab"} && crab != {} && {} == {})
+"#;
+    let expected = "{\"name\": \"crab\", \"legs\": 10}\n12\nshell\nclaws\n1\n";
+    assert_eq!(run_source("dicts", source), expected);
+    assert_eq!(run_source_with("dicts", source, &["--interpreter"]), expected);
+
+    let errors = [
+        ("print({\"a\": 1}[\"b\"])", "Key \"b\" is not in the dict"),
+        ("print({1: 2})", "Dict keys must be strings, not integer"),
+    ];
+    for (source, message) in errors {
+        for flags in [&[][..], &["--interpreter"]] {
+            let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+                .args(flags)
+                .args(["-e", source])
+                .output()
+                .expect("run crabby");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(message), "{:?} with {:?}: {}", source, flags, stderr);
+        }
+    }
+}
+
+#[test]
+fn builtins_print_convert_and_combine_values_on_both_engines() {
+    let source = r#"
+print("a", 1, [2], sep = ", ")
+print("no newline", end = "")
+print(" then one")
+print(len("héllo"), len([1, 2]), len({"k": 1}), len(bytes("abc")))
+print(type(1), type(1.5), type("s"), type([]), type({}), type(print))
+print(str(12) + "!", repr("quoted"), int(" -7 "), int("ff", 16), int(3.9), float("2.5"))
+print(sorted([3, 1, 2]), sorted(["b", "a", "c"], reverse = 1))
+print(sorted(["ccc", "a", "bb"], key = lambda(s): { return len(s) }))
+print(reversed([1, 2, 3]), reversed(3))
+print(enumerate(["a", "b"]), enumerate(["a"], 1))
+print(zip([1, 2, 3], ["x", "y"]))
+print(sum([1, 2, 3]), sum([0.5, 0.25]), sum(["a", "b"], ""))
+print(any([0, 0, 1]), any([]), all([1, 2]), all([1, 0]))
+assert(len([]) == 0, "empty")
+"#;
+    let expected = "a, 1, [2]\nno newline then one\n5 2 1 3\n\
+                    integer float string list dict function\n\
+                    12! \"quoted\" -7 255 3 2.5\n\
+                    [1, 2, 3] [\"c\", \"b\", \"a\"]\n[\"a\", \"bb\", \"ccc\"]\n\
+                    [3, 2, 1] [2, 1, 0]\n[[0, \"a\"], [1, \"b\"]] [[1, \"a\"]]\n\
+                    [[1, \"x\"], [2, \"y\"]]\n6 0.75 ab\n1 0 1 0\n";
+    assert_eq!(run_source("builtins", source), expected);
+    assert_eq!(run_source_with("builtins", source, &["--interpreter"]), expected);
+
+    let errors = [
+        ("assert(1 == 2, \"math broke\")", "Assertion failed: math broke"),
+        ("print(1, color = \"red\")", "print() got an unexpected keyword argument 'color'"),
+        ("print(1, sep = 2)", "print() expects sep to be a string, got integer"),
+        ("def f(x): { return x }\nf(x = 1)", "f does not take keyword arguments"),
+        ("int(\"abc\")", "int() cannot convert \"abc\" to an integer"),
+        ("len(5)", "len() expects a string, list, dict or bytes, got integer"),
+        ("sorted(5, key = 1)", "Value of type integer is not callable"),
+    ];
+    for (source, message) in errors {
+        for flags in [&[][..], &["--interpreter"]] {
+            let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+                .args(flags)
+                .args(["-e", source])
+                .output()
+                .expect("run crabby");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(message), "{:?} with {:?}: {}", source, flags, stderr);
+        }
+    }
+}
+
+#[test]
+fn pipelines_feed_lazy_iterators_on_both_engines() {
+    let source = r#"
+def even(n):
+    return n % 2 == 0
+
+def square(n):
+    return n * n
+
+let xs = [1, 2, 3, 4, 5, 6]
+print(xs |> filter(even) |> map(square) |> list)
+for n in range(10) |> drop(2) |> take(3):
+    print(n)
+
+def noisy(n): {
+    print(f"saw {n}")
+    return n
+}
+let seen = map(xs, noisy)
+print(type(seen))
+print(seen |> take(2) |> list)
+print(seen |> list)
+
+print(flat_map([1, 2, 3], lambda(n): { range(n) }) |> list)
+print(reduce(xs, lambda(a, b): { a * b }), reduce([], lambda(a, b): { a + b }, 0))
+print(fold(xs, "", lambda(text, n): { text + str(n) }))
+print(group_by(xs, lambda(n): { n % 3 }))
+
+def odd_squares(values):
+    return values
+        |> filter(lambda(n): { !even(n) })
+        |> map(square)
+        |> sum
+print(odd_squares(xs), xs |> sorted(reverse = 1) |> take(1) |> list)
+"#;
+    let expected = "[4, 16, 36]\n2\n3\n4\niterator\nsaw 1\nsaw 2\n[1, 2]\n\
+                    saw 3\nsaw 4\nsaw 5\nsaw 6\n[3, 4, 5, 6]\n[0, 0, 1, 0, 1, 2]\n720 0\n123456\n\
+                    {\"1\": [1, 4], \"2\": [2, 5], \"0\": [3, 6]}\n35 [6]\n";
+    assert_eq!(run_source("pipelines", source), expected);
+    assert_eq!(run_source_with("pipelines", source, &["--interpreter"]), expected);
+
+    let errors = [
+        ("print(1 |> 2)", "Expected a function or a call after '|>'"),
+        ("take([1], -1)", "take() expects a count of 0 or more, got -1"),
+        ("list(map(\"abc\", print))", "Cannot iterate over a value of type string"),
+        ("reduce([], print)", "reduce() of an empty sequence needs an initial value"),
+    ];
+    for (source, message) in errors {
+        for flags in [&[][..], &["--interpreter"]] {
+            let output = Command::new(env!("CARGO_BIN_EXE_crabby"))
+                .args(flags)
+                .args(["-e", source])
+                .output()
+                .expect("run crabby");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.contains(message), "{:?} with {:?}: {}", source, flags, stderr);
+        }
+    }
+}
+
+#[test]
+fn jit_compiles_hot_integer_functions_and_falls_back_otherwise() {
+    let source = r#"
+def fib(n): {
+    if n < 2: {
+        return n
+    }
+    return fib(n - 1) + fib(n - 2)
+}
+def half(n): {
+    if n > 0: {
+        return n ~/ 2
+    }
+}
+def shout(s): {
+    return s + "!"
+}
+let total = 0
+for i in range(1500): {
+    let total = total + fib(i ~/ 100) + half(i + 0.5)
+    let h = half(i - 10)
+    if h: {
+        let total = total + h
+    }
+    shout("hey")
+}
+print(total)
+print(half(-1))
+print(fib(25))
+print(half(9223372036854775807 - 1) + half(3))
+"#;
+    let path = std::env::temp_dir().join(format!("crabby_jit_{}.crab", std::process::id()));
+    fs::write(&path, source).expect("write temp script");
+    let run = |flag: &str| {
+        Command::new(env!("CARGO_BIN_EXE_crabby"))
+            .args([flag, "--jit-stats"])
+            .arg(&path)
+            .output()
+            .expect("run crabby")
+    };
+    let jit = run("--jit");
+    let vm = run("--no-jit");
+    let _ = fs::remove_file(&path);
+
+    let stdout = String::from_utf8_lossy(&jit.stdout);
+    assert_eq!(stdout, String::from_utf8_lossy(&vm.stdout));
+    assert_eq!(stdout, "1214630\nnone\n75025\n4611686018427387904\n");
+
+    let stats = String::from_utf8_lossy(&jit.stderr);
+    let line = |name: &str| {
+        stats
+            .lines()
+            .find(|line| line.trim_start().starts_with(name))
+            .unwrap_or_else(|| panic!("{} missing from JIT stats:\n{}", name, stats))
+            .to_string()
+    };
+    if cfg!(all(target_arch = "x86_64", unix)) {
+        assert!(line("fib").contains("compiled,"), "{}", stats);
+        assert!(line("half").contains("compiled,"), "{}", stats);
+        assert!(line("shout").contains("not compiled: uses strings"), "{}", stats);
+    }
+    assert!(String::from_utf8_lossy(&vm.stderr).contains("0 hot function(s)"));
+}
+
+/// Timing depends on the machine, so this only runs when asked for, against
+/// an optimized build: `cargo test --release -- --ignored`
+#[test]
+#[ignore]
+fn bytecode_vm_is_ten_times_faster_than_the_interpreter() {
+    let path = std::env::temp_dir().join(format!("crabby_bench_{}.crab", std::process::id()));
+    fs::write(
+        &path,
+        "def fib(n): {\n    if n < 2: {\n        return n\n    }\n    return fib(n - 1) + fib(n - 2)\n}\nprint(fib(32))\n",
+    )
+    .unwrap();
+    let time = |flag: &str| {
+        let start = std::time::Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_crabby")).arg(flag).arg(&path).output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2178309\n");
+        start.elapsed()
+    };
+    // Alternated, so a slow spell on the machine hits both sides alike
+    let (mut vm, mut interpreter) = (time("--no-jit"), time("--interpreter"));
+    for _ in 0..4 {
+        vm = vm.min(time("--no-jit"));
+        interpreter = interpreter.min(time("--interpreter"));
+    }
+    let _ = fs::remove_file(&path);
+
+    assert!(interpreter >= vm * 10, "the VM took {:?} and the interpreter {:?}", vm, interpreter);
+}
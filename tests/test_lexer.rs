@@ -0,0 +1,181 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crabby::lexer::{tokenize, Token};
+use crabby::utils::CrabbyError;
+
+fn tokens(source: &str) -> Vec<Token> {
+    tokenize(source)
+        .expect("source should lex")
+        .into_iter()
+        .map(|t| t.token)
+        .collect()
+}
+
+#[test]
+fn minus_is_always_an_operator() {
+    assert_eq!(
+        tokens("x-1"),
+        vec![Token::Identifier("x".to_string()), Token::Minus, Token::Integer(1)]
+    );
+    assert_eq!(tokens("-2.5"), vec![Token::Minus, Token::Float(2.5)]);
+}
+
+#[test]
+fn division_and_remainder_operators() {
+    assert_eq!(
+        tokens("a / b ~/ c % d // e"),
+        vec![
+            Token::Identifier("a".to_string()),
+            Token::Slash,
+            Token::Identifier("b".to_string()),
+            Token::TildeSlash,
+            Token::Identifier("c".to_string()),
+            Token::Percent,
+            Token::Identifier("d".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn numeric_literal_forms() {
+    assert_eq!(
+        tokens("0xff 0o17 0b101 1_000_000"),
+        vec![
+            Token::Integer(255),
+            Token::Integer(15),
+            Token::Integer(5),
+            Token::Integer(1_000_000),
+        ]
+    );
+    assert_eq!(
+        tokens("1.5e3 2e-2 3E+1 0.000_5"),
+        vec![
+            Token::Float(1500.0),
+            Token::Float(0.02),
+            Token::Float(30.0),
+            Token::Float(0.0005),
+        ]
+    );
+}
+
+#[test]
+fn overflowing_literals_are_reported() {
+    let err = tokenize("let y = 99999999999999999999").unwrap_err();
+    match err {
+        CrabbyError::LexerError { line: 1, column: 9, message } => {
+            assert!(message.contains("does not fit in 64 bits"), "{}", message);
+        }
+        other => panic!("unexpected error {:?}", other),
+    }
+
+    let err = tokenize("0x_").unwrap_err();
+    assert!(matches!(err, CrabbyError::LexerError { message, .. } if message.contains("no digits")));
+
+    assert!(tokenize("1e999").is_err());
+}
+
+#[test]
+fn underscores_must_separate_digits() {
+    assert_eq!(tokens("1_0 0xf_f 1_0.5_0"), vec![Token::Integer(10), Token::Integer(255), Token::Float(10.5)]);
+
+    for source in ["1_", "1__0", "0b1_", "1_.5", "1.5_", "1e5_"] {
+        let err = tokenize(source).unwrap_err();
+        assert!(
+            matches!(err, CrabbyError::LexerError { ref message, .. } if message.contains("does not separate digits")),
+            "{}: {:?}",
+            source,
+            err
+        );
+    }
+}
+
+#[test]
+fn a_leading_shebang_line_is_skipped() {
+    let stream = tokenize("#!/usr/bin/env crabby\nprint(1)").unwrap();
+    assert_eq!(stream[0].token, Token::Identifier("print".to_string()));
+    assert_eq!((stream[0].span.line, stream[0].span.column), (2, 1));
+
+    // Only on the first line
+    assert!(tokenize("print(1)\n#!/usr/bin/env crabby").is_err());
+}
+
+#[test]
+fn block_comments_nest() {
+    assert_eq!(
+        tokens("1 /* outer /* inner */ still outer */ 2"),
+        vec![Token::Integer(1), Token::Integer(2)]
+    );
+
+    let err = tokenize("let x = 1\n/* open /* nested */").unwrap_err();
+    assert!(
+        matches!(err, CrabbyError::LexerError { line: 2, column: 1, ref message } if message.contains("Unterminated")),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn doc_comments_are_tokens() {
+    assert_eq!(
+        tokens("/// Adds one\n// plain\ndef"),
+        vec![Token::DocComment("Adds one".to_string()), Token::Def]
+    );
+}
+
+#[test]
+fn indented_blocks_emit_layout_tokens() {
+    assert_eq!(
+        tokens("if x:\n    y\nz"),
+        vec![
+            Token::If,
+            Token::Identifier("x".to_string()),
+            Token::Colon,
+            Token::Indent,
+            Token::Identifier("y".to_string()),
+            Token::Dedent,
+            Token::Identifier("z".to_string()),
+        ]
+    );
+
+    // Brace blocks are untouched by indentation
+    assert!(!tokens("if x: {\n    y\n}").contains(&Token::Indent));
+
+    // Nor is a dict value on the line after its key
+    assert!(!tokens("let d = {\n    \"a\":\n        1\n}").contains(&Token::Indent));
+    assert!(!tokens("if x: {\n    {\"a\":\n        1}\n}").contains(&Token::Indent));
+    // A lambda inside a dict still opens a block
+    assert!(tokens("let d = {\"f\": lambda(v):\n    v\n}").contains(&Token::Indent));
+}
+
+#[test]
+fn mixed_tabs_and_spaces_are_rejected() {
+    let err = tokenize("if x:\n\t y").unwrap_err();
+    assert!(matches!(err, CrabbyError::LexerError { line: 2, ref message, .. } if message.contains("mixes tabs")));
+
+    let err = tokenize("if x:\n    y\n\tz").unwrap_err();
+    assert!(matches!(err, CrabbyError::LexerError { line: 3, ref message, .. } if message.contains("Inconsistent")));
+}
+
+#[test]
+fn unicode_identifiers_are_nfc_normalized() {
+    // "café" written with a precomposed é and with e + combining acute accent
+    let precomposed = tokens("caf\u{e9}");
+    let decomposed = tokens("cafe\u{301}");
+    assert_eq!(precomposed, decomposed);
+    assert_eq!(tokens("名前"), vec![Token::Identifier("名前".to_string())]);
+}
+
+#[test]
+fn columns_count_characters() {
+    let source = "let 名前 = \"é\" + x";
+    let stream = tokenize(source).unwrap();
+    let x = stream.last().unwrap();
+    assert_eq!((x.span.line, x.span.column), (1, 16));
+
+    // 𝕩 is one character but two UTF-16 code units
+    let source = "let 𝕩 = y";
+    let stream = tokenize(source).unwrap();
+    let y = stream.last().unwrap();
+    assert_eq!(y.span.column, 9);
+    assert_eq!(y.span.utf16_column(source), 10);
+}
@@ -0,0 +1,108 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn crabby(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(args)
+        .output()
+        .expect("run crabby")
+}
+
+fn has_c_compiler() -> bool {
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    Command::new(compiler).arg("--version").output().is_ok()
+}
+
+/// Every program in `tests/golden` must behave the same compiled to a native
+/// executable as on the interpreter: same output, same errors, same status
+#[test]
+fn native_builds_match_the_interpreter() {
+    if !has_c_compiler() {
+        eprintln!("skipping: no C compiler found");
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("crabby_golden_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+
+    let mut programs = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden"))
+        .expect("read golden programs")
+        .map(|entry| entry.expect("golden program").path())
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    for program in &programs {
+        let name = program.file_stem().unwrap().to_string_lossy();
+        let executable = dir.join(name.as_ref());
+
+        let build = crabby(&[
+            Path::new("build"),
+            Path::new("--native"),
+            program,
+            Path::new("-o"),
+            &executable,
+        ]);
+        assert!(
+            build.status.success(),
+            "building {} failed: {}",
+            name,
+            String::from_utf8_lossy(&build.stderr)
+        );
+
+        let native = Command::new(&executable)
+            .output()
+            .expect("run native build");
+        let interpreted = crabby(&[Path::new("--interpreter"), program]);
+        assert_eq!(
+            String::from_utf8_lossy(&native.stdout),
+            String::from_utf8_lossy(&interpreted.stdout),
+            "stdout differs for {}",
+            name
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&native.stderr),
+            String::from_utf8_lossy(&interpreted.stderr),
+            "stderr differs for {}",
+            name
+        );
+        assert_eq!(
+            native.status.code(),
+            interpreted.status.code(),
+            "status differs for {}",
+            name
+        );
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn emit_c_writes_the_program_and_its_runtime() {
+    let dir = std::env::temp_dir().join(format!("crabby_emit_c_{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("create temp dir");
+    let script = dir.join("hello.crab");
+    fs::write(&script, "print(\"hi\")\n").expect("write script");
+    let module = dir.join("lib.crab");
+    fs::write(&module, "import thing from \"./other.crab\"\n").expect("write script");
+
+    let emitted = crabby(&[Path::new("emit-c"), &script]);
+    assert!(
+        emitted.status.success(),
+        "{}",
+        String::from_utf8_lossy(&emitted.stderr)
+    );
+    let program = fs::read_to_string(dir.join("hello.c")).expect("program written");
+    assert!(program.contains("#include \"crabby_runtime.h\""));
+    assert!(dir.join("crabby_runtime.h").exists());
+    assert!(dir.join("crabby_runtime.c").exists());
+
+    let rejected = crabby(&[Path::new("emit-c"), &module]);
+    assert!(!rejected.status.success());
+    assert!(String::from_utf8_lossy(&rejected.stderr).contains("Imports are not supported"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,134 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crabby::lexer::tokenize;
+use crabby::parser::ast::*;
+use crabby::parser::parser::parse;
+use crabby::utils::CrabbyError;
+
+fn parse_source(source: &str) -> Result<Program, CrabbyError> {
+    parse(tokenize(source)?)
+}
+
+fn first_expression(source: &str) -> Expression {
+    let program = parse_source(source).expect("source should parse");
+    match program.statements.into_iter().next() {
+        Some(Statement::Expression(expr)) => expr,
+        other => panic!("expected an expression statement, got {:?}", other),
+    }
+}
+
+#[test]
+fn unary_minus_binds_tighter_than_binary_operators() {
+    let expr = first_expression("x-1");
+    assert!(matches!(expr, Expression::Binary { operator: BinaryOp::Sub, .. }));
+
+    match first_expression("-x * 2") {
+        Expression::Binary { left, operator: BinaryOp::Mul, .. } => {
+            assert!(matches!(*left, Expression::Unary { operator: UnaryOp::Neg, .. }));
+        }
+        other => panic!("expected a multiplication, got {:?}", other),
+    }
+}
+
+#[test]
+fn negative_literals_reach_the_full_integer_range() {
+    assert!(matches!(first_expression("-9223372036854775808"), Expression::Integer(i64::MIN)));
+    assert!(matches!(first_expression("-0x10"), Expression::Integer(-16)));
+
+    // Postfix operators bind tighter than `-`, so the literal stays an operand
+    assert!(matches!(first_expression("-1.abs()"), Expression::Unary { operator: UnaryOp::Neg, .. }));
+
+    for source in ["9223372036854775808", "-9223372036854775809"] {
+        let err = parse_source(source).unwrap_err();
+        assert!(
+            matches!(err, CrabbyError::ParserError { ref message, .. } if message.contains("does not fit in 64 bits")),
+            "{}: {:?}",
+            source,
+            err
+        );
+    }
+}
+
+#[test]
+fn comparisons_bind_looser_than_arithmetic_and_tighter_than_logic() {
+    match first_expression("a + 1 < b || c == d && e") {
+        Expression::Binary { left, operator: BinaryOp::Or, right } => {
+            match *left {
+                Expression::Binary { left, operator: BinaryOp::Lt, .. } => {
+                    assert!(matches!(*left, Expression::Binary { operator: BinaryOp::Add, .. }));
+                }
+                other => panic!("expected a comparison, got {:?}", other),
+            }
+            match *right {
+                Expression::Binary { left, operator: BinaryOp::And, .. } => {
+                    assert!(matches!(*left, Expression::Binary { operator: BinaryOp::Eq, .. }));
+                }
+                other => panic!("expected a conjunction, got {:?}", other),
+            }
+        }
+        other => panic!("expected a disjunction, got {:?}", other),
+    }
+}
+
+#[test]
+fn fstring_splits_text_and_expressions() {
+    let Expression::FormatString(parts) = first_expression(r#"f"hi {name}, {age + 1:>5}!""#) else {
+        panic!("expected an f-string");
+    };
+
+    assert_eq!(parts.len(), 5);
+    assert!(matches!(&parts[0], FormatPart::Literal(text) if text == "hi "));
+    assert!(matches!(&parts[1], FormatPart::Expression { spec: None, .. }));
+    match &parts[3] {
+        FormatPart::Expression { expr, spec: Some(spec) } => {
+            assert!(matches!(**expr, Expression::Binary { operator: BinaryOp::Add, .. }));
+            assert_eq!(spec.align, Some(FormatAlign::Right));
+            assert_eq!(spec.width, Some(5));
+        }
+        other => panic!("expected a formatted expression, got {:?}", other),
+    }
+    assert!(matches!(&parts[4], FormatPart::Literal(text) if text == "!"));
+}
+
+#[test]
+fn fstring_errors_point_inside_the_literal() {
+    let err = parse_source("let x = 1\nprint(f\"a {x + } b\")").unwrap_err();
+    assert!(matches!(err, CrabbyError::ParserError { line: 2, column: 14, .. }), "{:?}", err);
+
+    let err = parse_source(r#"print(f"value: {x:.q}")"#).unwrap_err();
+    assert!(matches!(err, CrabbyError::ParserError { line: 1, column: 19, .. }), "{:?}", err);
+
+    let err = parse_source(r#"print(f"oops {x")"#).unwrap_err();
+    assert!(matches!(err, CrabbyError::ParserError { line: 1, column: 14, .. }), "{:?}", err);
+}
+
+#[test]
+fn doc_comments_attach_to_definitions() {
+    let program = parse_source(
+        "/// Adds one.\n/// Second line.\ndef inc(x): {\n    return x + 1\n}\n/// The answer\nlet answer = 42",
+    )
+    .unwrap();
+
+    match &program.statements[0] {
+        Statement::FunctionDef { name, doc, .. } => {
+            assert_eq!(name, "inc");
+            assert_eq!(doc.as_deref(), Some("Adds one.\nSecond line."));
+        }
+        other => panic!("expected a function, got {:?}", other),
+    }
+    assert!(matches!(&program.statements[1], Statement::Let { doc: Some(doc), .. } if doc == "The answer"));
+
+}
+
+#[test]
+fn dangling_doc_comments_are_ordinary_comments() {
+    let program = parse_source("/// just a note\nprint(1)").unwrap();
+    assert!(matches!(&program.statements[..], [Statement::Expression(_)]));
+
+    let program = parse_source("def f(): {\n    1\n    /// trailing\n}\nprint([1,\n/// inside\n2])\n/// at the end").unwrap();
+    assert_eq!(program.statements.len(), 2);
+
+    // A doc comment still documents the declaration after it
+    let program = parse_source("/// note\nprint(1)\n/// The answer\nlet answer = 42").unwrap();
+    assert!(matches!(&program.statements[1], Statement::Let { doc: Some(doc), .. } if doc == "The answer"));
+}
@@ -0,0 +1,368 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// Runs a one-line program on the given engine flags
+fn crabby(flags: &[&str], source: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_crabby"))
+        .args(flags)
+        .args(["-e", source])
+        .output()
+        .expect("run crabby")
+}
+
+/// What a program prints, which must be the same on both engines
+fn run(source: &str) -> String {
+    let vm = crabby(&[], source);
+    assert!(
+        vm.status.success(),
+        "script failed: {}",
+        String::from_utf8_lossy(&vm.stderr)
+    );
+    let interpreter = crabby(&["--interpreter"], source);
+    assert_eq!(
+        vm.stdout, interpreter.stdout,
+        "engines disagree on {:?}",
+        source
+    );
+    String::from_utf8_lossy(&vm.stdout).into_owned()
+}
+
+/// A fresh, empty directory for one test's files
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crabby_stdlib_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn error(source: &str) -> String {
+    let output = crabby(&[], source);
+    assert!(!output.status.success(), "{:?} should fail", source);
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn hashes_match_published_test_vectors() {
+    let vectors = [
+        (
+            "sha256",
+            "",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        ),
+        (
+            "sha256",
+            "abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        ),
+        (
+            "sha256",
+            "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        ),
+        ("sha1", "", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+        ("sha1", "abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+        ("md5", "", "d41d8cd98f00b204e9800998ecf8427e"),
+        ("md5", "message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+        ("crc32", "123456789", "cbf43926"),
+        ("fnv1a", "", "cbf29ce484222325"),
+        ("fnv1a", "a", "af63dc4c8601ec8c"),
+        ("fnv1a", "foobar", "85944171f73967e8"),
+        (
+            "blake3",
+            "",
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+        ),
+        (
+            "blake3",
+            "abc",
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+        ),
+    ];
+
+    for (function, input, digest) in vectors {
+        let source = format!(
+            "import hash\nprint(hash.{0}({1:?}))\nprint(hash.{0}(bytes({1:?})))",
+            function, input
+        );
+        assert_eq!(
+            run(&source),
+            format!("{0}\n{0}\n", digest),
+            "{}({:?})",
+            function,
+            input
+        );
+    }
+}
+
+#[test]
+fn digests_come_back_as_hex_or_bytes() {
+    let source = "import crc32 from hash\nlet raw = crc32(\"123456789\", \"bytes\")\n\
+                  print(raw)\nprint(raw[0])\nprint(raw == bytes([203, 244, 57, 38]))";
+    assert_eq!(run(source), "b\"\\xcb\\xf49&\"\n203\n1\n");
+
+    assert!(
+        error("import hash\nhash.md5(1)")
+            .contains("hash.md5() expects a string or bytes, got integer")
+    );
+    assert!(
+        error("import hash\nhash.md5(\"\", \"b64\")")
+            .contains("returns \"hex\" or \"bytes\"")
+    );
+    assert!(error("print(bytes([256]))").contains("Byte value 256 is out of range 0 to 255"));
+}
+
+#[test]
+fn dates_decompose_format_and_parse() {
+    let source = "import time\nlet d = time.date(2024, 2, 29, 13, 45, 7)\nprint(d)\n\
+                  print([d.year, d.month, d.day, d.hour, d.weekday, d.yearday])\n\
+                  print(time.format(d, \"%A %d %B %Y, %H:%M\"))\n\
+                  print(time.parse(\"2024-03-01 10:00 +0200\", \"%Y-%m-%d %H:%M %z\"))\n\
+                  print(time.parse(\"1970-01-02\", \"%Y-%m-%d\").timestamp)\n\
+                  print(time.utc(0))";
+    assert_eq!(
+        run(source),
+        "2024-02-29T13:45:07Z\n[2024, 2, 29, 13, 4, 60]\nThursday 29 February 2024, 13:45\n\
+         2024-03-01T10:00:00+02:00\n86400\n1970-01-01T00:00:00Z\n"
+    );
+
+    assert!(error("import time\ntime.date(2023, 2, 29)").contains("invalid date 2023-2-29"));
+    assert!(error("import time\ntime.parse(\"soon\", \"%Y\")").contains("cannot read"));
+    assert!(error("import time\ntime.format(time.utc(), \"%Q\")").contains("cannot use the format"));
+}
+
+#[test]
+fn durations_combine_with_dates_and_numbers() {
+    let source = "import time\nlet d = time.date(2024, 2, 28)\n\
+                  print(d + time.days(1) + time.hours(12))\n\
+                  print(time.date(2024, 3, 1) - d)\n\
+                  print(time.minutes(90))\nprint(time.seconds(2.5) * 3)\n\
+                  print(-time.milliseconds(250))\n\
+                  print(time.hours(1) / time.minutes(15))\n\
+                  print(time.minutes(1) > time.seconds(59))\n\
+                  print(time.milliseconds(1500).seconds)";
+    assert_eq!(
+        run(source),
+        "2024-02-29T12:00:00Z\n48h0m\n1h30m\n7.5s\n-0.25s\n4\n1\n1.5\n"
+    );
+
+    let source = "import time\nlet start = time.instant()\ntime.sleep(20)\n\
+                  print(time.elapsed(start) >= time.milliseconds(20))\n\
+                  print(time.now() > 1700000000)";
+    assert_eq!(run(source), "1\n1\n");
+    assert!(error("import time\ntime.sleep(-5)").contains("negative time"));
+}
+
+#[test]
+fn files_are_written_listed_and_removed() {
+    let dir = temp_dir("fs");
+    let source = r#"import fs
+import path
+let dir = path.join(ROOT, "out", "nested")
+fs.mkdir(dir)
+let file = path.join(dir, "notes.txt")
+fs.write(file, "one")
+fs.append(file, "two")
+print(fs.read(file))
+fs.write(path.join(dir, "data.bin"), bytes([0, 1, 255]))
+print(fs.read_bytes(path.join(dir, "data.bin")))
+print(fs.list(dir))
+print(fs.glob(path.join(ROOT, "**", "*.txt")) == [file])
+fs.rename(file, path.join(dir, "moved.txt"))
+print(fs.exists(file))
+print(fs.is_dir(dir))
+fs.remove(path.join(dir, "moved.txt"))
+fs.remove(path.join(dir, "data.bin"))
+fs.remove(dir)
+print(fs.list(path.dirname(dir)))"#
+        .replace("ROOT", &format!("{:?}", dir.display().to_string()));
+    assert_eq!(
+        run(&source),
+        "onetwo\nb\"\\x00\\x01\\xff\"\n[\"data.bin\", \"notes.txt\"]\n1\n0\n1\n[]\n"
+    );
+
+    let missing = dir.join("missing.txt").display().to_string();
+    assert!(
+        error(&format!("import fs\nfs.read({:?})", missing))
+            .contains(&format!("fs.read() failed for '{}'", missing))
+    );
+    assert!(error("import fs\nfs.write(\"x\", 1)").contains("expects a string or bytes to write"));
+
+    let caught = format!(
+        "import fs\nlet r = try(fs.read, {:?})\nprint(r[\"value\"], r[\"kind\"])\n\
+         print(try(fs.remove, {:?})[\"kind\"])\nprint(try(lambda(x): {{ x + 1 }}, 1))\n\
+         print(try(lambda(): {{ 1 / 0 }})[\"kind\"])",
+        missing, missing
+    );
+    assert_eq!(
+        run(&caught),
+        "none not_found\nnot_found\n{\"value\": 2, \"error\": none, \"kind\": none}\nruntime\n"
+    );
+    assert!(error("try()").contains("try() expects a function to call"));
+}
+
+#[test]
+fn paths_are_split_and_joined() {
+    let source = "import path\nprint(path.join(\"src\", \"lib\", \"main.crab\"))\n\
+                  print(path.join(\"src\", \"/etc\"))\nprint(path.dirname(\"src/lib/main.crab\"))\n\
+                  print(path.basename(\"src/lib/main.crab\"))\nprint(path.extension(\"main.crab\"))\n\
+                  print(path.extension(\"Makefile\") == \"\")\nprint(path.dirname(\"main.crab\") == \"\")";
+    let expected = if cfg!(windows) {
+        "src\\lib\\main.crab\n/etc\nsrc/lib\nmain.crab\ncrab\n1\n1\n"
+    } else {
+        "src/lib/main.crab\n/etc\nsrc/lib\nmain.crab\ncrab\n1\n1\n"
+    };
+    assert_eq!(run(source), expected);
+}
+
+#[test]
+fn json_parses_into_dicts_and_lists_and_back() {
+    let dir = temp_dir("json");
+    let document = dir.join("crab.json");
+    fs::write(
+        &document,
+        "{\"name\": \"crab\", \"legs\": 10,\n \"tags\": [\"a\", true, null, 1.5, -2e3, \"\\u00e9\\n\"], \"shell\": {}}",
+    )
+    .unwrap();
+    let source = format!(
+        "import fs\nimport json\nlet crab = json.parse(fs.read({:?}))\nprint(crab[\"tags\"][1] + crab[\"legs\"])\n\
+         print(json.stringify(crab))\nprint(json.stringify(crab[\"tags\"], 2))\n\
+         print(json.parse(json.stringify(crab)) == crab)\nprint(json.parse(\"[]\") + json.parse(\" [7] \"))",
+        document.display().to_string()
+    );
+    assert_eq!(
+        run(&source),
+        "11\n{\"name\":\"crab\",\"legs\":10,\"tags\":[\"a\",1,null,1.5,-2000,\"é\\n\"],\"shell\":{}}\n\
+         [\n  \"a\",\n  1,\n  null,\n  1.5,\n  -2000,\n  \"é\\n\"\n]\n1\n[7]\n"
+    );
+}
+
+#[test]
+fn json_errors_point_at_the_problem() {
+    let dir = temp_dir("json_errors");
+    let document = dir.join("broken.json");
+    fs::write(&document, "{\"a\": 1,\n  \"b\" 2}").unwrap();
+    let source = format!(
+        "import fs\nimport json\njson.parse(fs.read({:?}))",
+        document.display().to_string()
+    );
+    assert!(error(&source).contains("line 2, column 7: expected ':' after an object key"));
+    assert!(error("import json\njson.parse(\"[1, 2\")").contains("line 1, column 6"));
+    assert!(error("import json\njson.parse(\"[01]\")").contains("expected ',' or ']'"));
+    for number in ["1e400", "-1e400", &"9".repeat(400)] {
+        let source = format!("import json\njson.parse(\"[0, {}]\")", number);
+        assert!(
+            error(&source).contains(&format!("line 1, column 5: the number {} is too large", number)),
+            "{}",
+            number
+        );
+    }
+    assert!(
+        error("import json\njson.stringify([1, lambda(x): { x }])")
+            .contains("json.stringify() cannot encode a value of type function")
+    );
+}
+
+#[test]
+fn regexes_search_capture_replace_and_split() {
+    let source = "import re\nlet date = re.compile(\"(?P<year>[0-9]{4})-(?P<month>[0-9]{2})(-([0-9]{2}))?\")\n\
+                  print(date)\nlet m = re.search(date, \"on 2024-02, or so\")\nprint(m)\n\
+                  print(m[\"named\"][\"year\"] + m[\"groups\"][1])\nprint(re.match(date, \"on 2024-02\"))\n\
+                  print(re.match(\"[a-z]+\", \"héllo world\")[\"end\"])\n\
+                  print(re.find_all(\"[0-9]+\", \"a1 b22 c333\"))\n\
+                  print(re.replace(\"(?P<key>[a-z]+)=([0-9]+)\", \"a=1, b=2\", \"$2=${key}\"))\n\
+                  print(re.replace(\"o\", \"foo boo\", \"0\", 2))\nprint(re.split(\" *, *\", \"a , b,c\"))\n\
+                  print(date.pattern == re.compile(date.pattern).pattern && re.compile(\"a\") == re.compile(\"a\"))";
+    assert_eq!(
+        run(source),
+        "<regex \"(?P<year>[0-9]{4})-(?P<month>[0-9]{2})(-([0-9]{2}))?\">\n\
+         {\"text\": \"2024-02\", \"start\": 3, \"end\": 10, \"groups\": [\"2024\", \"02\", none, none], \
+         \"named\": {\"year\": \"2024\", \"month\": \"02\"}}\n\
+         202402\nnone\n1\n[\"1\", \"22\", \"333\"]\n1=a, 2=b\nf00 boo\n[\"a\", \"b\", \"c\"]\n1\n"
+    );
+
+    assert!(error("import re\nre.compile(\"(\")").contains("re.compile() got an invalid pattern"));
+    assert!(
+        error("import re\nre.search(1, \"a\")")
+            .contains("re.search() expects a pattern string or regex, got integer")
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn processes_run_to_completion_or_stream_their_lines() {
+    let dir = temp_dir("process");
+    let source = format!(
+        "import process\nprint(process.run(\"sh\", [\"-c\", \"echo out; echo err >&2; exit 3\"]))\n\
+         print(process.run(\"cat\", [], {{\"stdin\": \"piped in\"}})[\"stdout\"])\n\
+         let shell = process.run(\"sh\", [\"-c\", \"pwd; echo $GREETING\"], {{\"cwd\": {:?}, \"env\": {{\"GREETING\": \"hi\"}}}})\n\
+         print(shell[\"stdout\"])\n\
+         for line in process.lines(\"sh\", [\"-c\", \"echo one; sleep 0.05; echo two\"]):\n    print(\"got \" + line)\n\
+         def first_two():\n    let seen = []\n    for line in process.lines(\"yes\"):\n        let seen = seen + [line]\n\
+         \x20       if seen == [\"y\", \"y\"]:\n            return seen\nprint(first_two())",
+        dir.display().to_string()
+    );
+    let dir = dir.canonicalize().unwrap();
+    assert_eq!(
+        run(&source),
+        format!(
+            "{{\"stdout\": \"out\\n\", \"stderr\": \"err\\n\", \"code\": 3}}\npiped in\n{}\nhi\n\n\
+             got one\ngot two\n[\"y\", \"y\"]\n",
+            dir.display()
+        )
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn process_errors_tell_a_missing_command_from_a_failing_one() {
+    assert!(
+        error("import process\nprocess.run(\"crabby-no-such-command\")")
+            .contains("process.run() cannot find the command 'crabby-no-such-command'")
+    );
+    assert!(
+        error("import process\nprocess.run(\"false\", [], {\"check\": 1})")
+            .contains("process.run(): 'false' exited with status 1")
+    );
+    assert!(
+        error("import process\nprocess.run(\"sleep\", [\"5\"], {\"timeout\": 50})")
+            .contains("'sleep' timed out after 50 ms")
+    );
+    assert!(
+        error("import process\nfor line in process.lines(\"sleep\", [\"5\"], {\"timeout\": 50}):\n    print(line)")
+            .contains("process.lines(): 'sleep' timed out after 50 ms")
+    );
+    assert!(
+        error("import process\nprocess.run(\"ls\", [], {\"shell\": 1})")
+            .contains("process.run() has no option 'shell'")
+    );
+
+    let kinds = "import process\n\
+                 print(try(process.run, \"crabby-no-such-command\")[\"kind\"])\n\
+                 print(try(process.run, \"false\", [], {\"check\": 1})[\"kind\"])\n\
+                 print(try(process.run, \"false\")[\"value\"][\"code\"])\n\
+                 print(try(process.run, \"sleep\", [\"5\"], {\"timeout\": 50})[\"kind\"])";
+    assert_eq!(run(kinds), "not_found\nexit_status\n1\ntimeout\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn timeouts_kill_what_the_command_started() {
+    let dir = temp_dir("process_group");
+    let marker = dir.join("late.txt");
+    let source = format!(
+        "import process\nprocess.run(\"sh\", [\"-c\", \"(sleep 1; touch {}) & wait\"], {{\"timeout\": 100}})",
+        marker.display()
+    );
+    assert!(error(&source).contains("timed out after 100 ms"));
+    std::thread::sleep(std::time::Duration::from_millis(1500));
+    assert!(!marker.exists(), "the background sleep outlived the timeout");
+
+    // The command exits in time, but leaves a child holding its output open
+    let started = std::time::Instant::now();
+    let source = "import process\nprocess.run(\"sh\", [\"-c\", \"sleep 5 &\"], {\"timeout\": 100})";
+    assert!(error(source).contains("timed out after 100 ms"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(3));
+}
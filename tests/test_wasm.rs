@@ -0,0 +1,207 @@
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use crabby::backend::wasm::{self, INTEGER_TAG, STRING_TAG};
+use crabby::compile;
+use crabby::runtime::Runtime;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use wasmi::{Caller, Engine, Extern, Instance, Linker, Memory, Module, Store};
+
+/// What a module printed, and the runtime error it stopped with
+#[derive(Default)]
+struct Host {
+    output: String,
+    error: Option<String>,
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Memory {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .expect("module exports its memory")
+}
+
+fn read(memory: &Memory, store: impl wasmi::AsContext, address: i32, len: i32) -> String {
+    let mut bytes = vec![0; len as usize];
+    memory
+        .read(store, address as usize, &mut bytes)
+        .expect("read memory");
+    String::from_utf8(bytes).expect("UTF-8 text")
+}
+
+fn compile(source: &str) -> Result<Vec<u8>, crabby::utils::CrabbyError> {
+    let module = compile::compile_source(source, &Runtime::new().global_names())?;
+    wasm::emit_wasm(&module)
+}
+
+/// Instantiates a module, which runs its top-level code
+fn instantiate(bytes: &[u8]) -> (Store<Host>, Result<Instance, wasmi::Error>) {
+    let engine = Engine::default();
+    let module = Module::new(&engine, bytes).expect("valid module");
+    let mut store = Store::new(&engine, Host::default());
+    let mut linker = Linker::<Host>::new(&engine);
+    linker
+        .func_wrap(
+            "crabby",
+            "print",
+            |mut caller: Caller<'_, Host>, address: i32, len: i32| {
+                let text = read(&memory(&caller), &caller, address, len);
+                caller.data_mut().output.push_str(&text);
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "crabby",
+            "error",
+            |mut caller: Caller<'_, Host>, address: i32, len: i32| {
+                let message = read(&memory(&caller), &caller, address, len);
+                caller.data_mut().error = Some(message);
+            },
+        )
+        .unwrap();
+    linker
+        .func_wrap(
+            "crabby",
+            "format_float",
+            |mut caller: Caller<'_, Host>, value: f64, address: i32| {
+                let text = value.to_string();
+                memory(&caller)
+                    .write(&mut caller, address as usize, text.as_bytes())
+                    .expect("write memory");
+                text.len() as i32
+            },
+        )
+        .unwrap();
+    let instance = linker.instantiate_and_start(&mut store, &module);
+    (store, instance)
+}
+
+/// The golden programs the WebAssembly backend supports (the others use
+/// format specs) must print the same and fail the same as on the interpreter
+#[test]
+fn wasm_modules_match_the_interpreter() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for name in ["closures", "errors", "numbers"] {
+        let program = golden.join(format!("{}.crab", name));
+        let bytes = compile(&fs::read_to_string(&program).unwrap()).unwrap();
+        let (store, instance) = instantiate(&bytes);
+
+        let interpreted = Command::new(env!("CARGO_BIN_EXE_crabby"))
+            .arg("--interpreter")
+            .arg(&program)
+            .output()
+            .expect("run crabby");
+        assert_eq!(
+            store.data().output,
+            String::from_utf8_lossy(&interpreted.stdout),
+            "output differs for {}",
+            name
+        );
+
+        let stderr = String::from_utf8_lossy(&interpreted.stderr);
+        match &store.data().error {
+            Some(message) => {
+                assert!(instance.is_err(), "{} trapped", name);
+                assert_eq!(
+                    stderr,
+                    format!("Error: Runtime error: {}\n", message),
+                    "error differs for {}",
+                    name
+                );
+            }
+            None => {
+                assert!(instance.is_ok(), "{} failed: {:?}", name, instance.err());
+                assert_eq!(stderr, "", "{} should not fail", name);
+            }
+        }
+    }
+}
+
+#[test]
+fn wasm_exports_public_functions() {
+    let bytes = compile(
+        "pub def add(a, b): {\n    return a + b\n}\n\
+         pub def greet(name): {\n    \"Hello, \" + name + \"!\"\n}\n\
+         def hidden(): {\n    return 1\n}\n\
+         print(\"loaded\")\n",
+    )
+    .unwrap();
+    let (mut store, instance) = instantiate(&bytes);
+    let instance = instance.unwrap();
+    assert_eq!(store.data().output, "loaded\n");
+    assert!(instance.get_func(&store, "hidden").is_none());
+
+    let int = instance
+        .get_typed_func::<i64, i32>(&store, "crabby_int")
+        .unwrap();
+    let tag = instance
+        .get_typed_func::<i32, i32>(&store, "crabby_tag")
+        .unwrap();
+    let int_value = instance
+        .get_typed_func::<i32, i64>(&store, "crabby_int_value")
+        .unwrap();
+    let add = instance
+        .get_typed_func::<(i32, i32), i32>(&store, "add")
+        .unwrap();
+
+    let (a, b) = (
+        int.call(&mut store, 40).unwrap(),
+        int.call(&mut store, 2).unwrap(),
+    );
+    let sum = add.call(&mut store, (a, b)).unwrap();
+    assert_eq!(tag.call(&mut store, sum).unwrap(), INTEGER_TAG);
+    assert_eq!(int_value.call(&mut store, sum).unwrap(), 42);
+
+    // Strings are built in the module's memory
+    let memory = instance.get_memory(&store, "memory").unwrap();
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "crabby_alloc")
+        .unwrap();
+    let string = instance
+        .get_typed_func::<(i32, i32), i32>(&store, "crabby_string")
+        .unwrap();
+    let string_ptr = instance
+        .get_typed_func::<i32, i32>(&store, "crabby_string_ptr")
+        .unwrap();
+    let string_len = instance
+        .get_typed_func::<i32, i32>(&store, "crabby_string_len")
+        .unwrap();
+    let greet = instance
+        .get_typed_func::<i32, i32>(&store, "greet")
+        .unwrap();
+
+    let address = alloc.call(&mut store, 4).unwrap();
+    memory.write(&mut store, address as usize, b"Wasm").unwrap();
+    let name = string.call(&mut store, (address, 4)).unwrap();
+    let greeting = greet.call(&mut store, name).unwrap();
+    assert_eq!(tag.call(&mut store, greeting).unwrap(), STRING_TAG);
+    let address = string_ptr.call(&mut store, greeting).unwrap();
+    let len = string_len.call(&mut store, greeting).unwrap();
+    assert_eq!(read(&memory, &store, address, len), "Hello, Wasm!");
+
+    // Values stay dynamically typed, and errors read as on the interpreter
+    let joined = add.call(&mut store, (name, a)).unwrap();
+    let address = string_ptr.call(&mut store, joined).unwrap();
+    let len = string_len.call(&mut store, joined).unwrap();
+    assert_eq!(read(&memory, &store, address, len), "Wasm40");
+    let none = instance
+        .get_typed_func::<(), i32>(&store, "crabby_none")
+        .unwrap()
+        .call(&mut store, ())
+        .unwrap();
+    assert!(add.call(&mut store, (none, a)).is_err());
+    assert_eq!(
+        store.data().error.as_deref(),
+        Some("Invalid operation between none and integer")
+    );
+}
+
+#[test]
+fn wasm_rejects_what_it_cannot_compile() {
+    let error = |source: &str| compile(source).expect_err("rejected").to_string();
+    assert!(error("import thing from \"./other.crab\"\n").contains("Imports are not supported"));
+    assert!(error("let n = 3\nprint(f\"{n:>4}\")\n").contains("Format specs are not supported"));
+    assert!(error("pub def memory(): {\n    1\n}\n").contains("'memory' is reserved"));
+}